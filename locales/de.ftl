@@ -0,0 +1,77 @@
+action-convert = konvertieren
+action-skip-compliant = überspringen (bereits im Rekordbox-Format)
+action-skip-untagged = überspringen (nicht zur Konvertierung markiert)
+action-skip-filtered = überspringen (passt nicht zum Filter)
+action-skip-unchanged = überspringen (seit der letzten Konvertierung unverändert)
+action-skip-existing = überspringen (Ausgabedatei existiert bereits)
+action-skip-in-collection = überspringen (bereits in der Rekordbox-Sammlung)
+action-skip-no-space = überspringen (passt nicht mehr auf das Gerät)
+action-unsupported = ablehnen (nicht unterstütztes Format)
+job-target = nach { $format }
+not-attempted = nicht versucht, ein früherer Song ist fehlgeschlagen
+progress-failed = { $failed } fehlgeschlagen
+progress-throughput = { $failed } fehlgeschlagen, { $mbps } MB/s
+
+dry-run-summary = { $songs ->
+        [one] { $songs } Song
+       *[other] { $songs } Songs
+    }: { $convert } zu konvertieren, { $skip } zu überspringen, { $unsupported } nicht unterstützt
+verify-summary = { $verified ->
+        [one] { $verified } Song
+       *[other] { $verified } Songs
+    } geprüft, { $failed } fehlerhaft
+
+confirm-overwrite = { $count } Dateien im Ausgabeverzeichnis würden überschrieben:
+confirm-more = ... und { $count } weitere
+confirm-continue = Fortfahren? [j/N]
+confirm-yes = j, ja, y, yes
+
+directory-songs = Songs
+directory-total = Gesamt
+
+report-title = Konvertierungsbericht
+report-counts = { $converted } konvertiert, { $copied } kopiert, { $skipped } übersprungen, { $failed } fehlgeschlagen
+report-sizes = Aus { $source } MB Quelldateien wurden { $output } MB, { $saved } MB gespart
+report-converted = Konvertiert
+report-skipped = Übersprungen
+report-failed = Fehlgeschlagen
+report-song = Song
+report-from = Von
+report-to = Nach
+report-copied = kopiert
+report-source-mb = Quelle MB
+report-output-mb = Ausgabe MB
+report-seconds = Sekunden
+report-measured-lufs = Gemessen LUFS
+report-output-lufs = Ausgabe LUFS
+report-gain-db = Verstärkung dB
+report-gain-dynamic = { $gain } (dynamisch)
+report-limited-db = Limiter dB
+report-reason = Grund
+report-error = Fehler
+
+merge-added = Hinzugefügt: { $name }
+merge-updated = Aktualisiert: { $name }: { $attributes }
+merge-summary = { $added } hinzugefügt, { $updated } aktualisiert, { $unchanged } unverändert, { $kept } andere Titel behalten
+
+history-updated = { $name }: { $plays ->
+        [one] einmal
+       *[other] { $plays } Mal
+    } gespielt, mit { $rating }/5 bewertet
+history-summary = { $updated } aktualisiert, { $unchanged } unverändert, { $unmatched } Titel der Bibliothek nicht hier konvertiert
+
+manifest-missing = fehlt
+manifest-size = Größe ist { $size }, erwartet { $expected }
+manifest-hash = Hash ist { $hash }, erwartet { $expected }
+manifest-unreadable = nicht lesbar: { $error }
+manifest-summary = { $checked ->
+        [one] { $checked } Datei
+       *[other] { $checked } Dateien
+    } geprüft, { $missing } fehlen, { $changed } verändert
+diff-changed = verändert
+diff-added = neu
+diff-removed = entfallen
+diff-summary = { $unchanged } unverändert, { $changed } verändert, { $added } neu, { $removed } entfallen
+tempo-half-time = { $bpm } BPM liegt weit außerhalb der { $min }-{ $max } BPM von { $playlist }, passt aber verdoppelt: mit halbem Tempo erkannt?
+tempo-double-time = { $bpm } BPM liegt weit außerhalb der { $min }-{ $max } BPM von { $playlist }, passt aber halbiert: mit doppeltem Tempo erkannt?
+tempo-misfiled = { $bpm } BPM liegt weit außerhalb der { $min }-{ $max } BPM von { $playlist }: in der falschen Playlist?
@@ -0,0 +1,79 @@
+# What will be done with a song
+action-convert = convert
+action-skip-compliant = skip (already Rekordbox format)
+action-skip-untagged = skip (not tagged for conversion)
+action-skip-filtered = skip (does not match the filter)
+action-skip-unchanged = skip (unchanged since last conversion)
+action-skip-existing = skip (output already exists)
+action-skip-in-collection = skip (already in the Rekordbox collection)
+action-skip-no-space = skip (does not fit on the device)
+action-unsupported = reject (unsupported format)
+job-target = to { $format }
+not-attempted = not attempted, an earlier song failed
+progress-failed = { $failed } failed
+progress-throughput = { $failed } failed, { $mbps } MB/s
+
+dry-run-summary = { $songs ->
+        [one] { $songs } song
+       *[other] { $songs } songs
+    }: { $convert } to convert, { $skip } to skip, { $unsupported } unsupported
+verify-summary = { $verified ->
+        [one] { $verified } song
+       *[other] { $verified } songs
+    } verified, { $failed } failed
+
+confirm-overwrite = { $count } files in the output directory would be overwritten:
+confirm-more = ... and { $count } more
+confirm-continue = Continue? [y/N]
+# Answers that mean yes, separated by commas
+confirm-yes = y, yes
+
+directory-songs = songs
+directory-total = Total
+
+report-title = Conversion report
+report-counts = { $converted } converted, { $copied } copied, { $skipped } skipped, { $failed } failed
+report-sizes = { $source } MB of source files became { $output } MB, saving { $saved } MB
+report-converted = Converted
+report-skipped = Skipped
+report-failed = Failed
+report-song = Song
+report-from = From
+report-to = To
+report-copied = copied
+report-source-mb = Source MB
+report-output-mb = Output MB
+report-seconds = Seconds
+report-measured-lufs = Measured LUFS
+report-output-lufs = Output LUFS
+report-gain-db = Gain dB
+report-gain-dynamic = { $gain } (dynamic)
+report-limited-db = Limiter dB
+report-reason = Reason
+report-error = Error
+
+merge-added = Added { $name }
+merge-updated = Updated { $name }: { $attributes }
+merge-summary = { $added } added, { $updated } updated, { $unchanged } unchanged, { $kept } other tracks kept
+
+history-updated = { $name }: played { $plays ->
+        [one] once
+       *[other] { $plays } times
+    }, rated { $rating }/5
+history-summary = { $updated } updated, { $unchanged } unchanged, { $unmatched } tracks of the library not converted here
+
+manifest-missing = missing
+manifest-size = size is { $size }, expected { $expected }
+manifest-hash = hash is { $hash }, expected { $expected }
+manifest-unreadable = unreadable: { $error }
+manifest-summary = { $checked ->
+        [one] { $checked } file
+       *[other] { $checked } files
+    } checked, { $missing } missing, { $changed } changed
+diff-changed = changed
+diff-added = new
+diff-removed = gone
+diff-summary = { $unchanged } unchanged, { $changed } changed, { $added } new, { $removed } gone
+tempo-half-time = { $bpm } BPM is far outside the { $min }-{ $max } BPM of { $playlist }, but fits at double time: detected at half its tempo?
+tempo-double-time = { $bpm } BPM is far outside the { $min }-{ $max } BPM of { $playlist }, but fits at half time: detected at double its tempo?
+tempo-misfiled = { $bpm } BPM is far outside the { $min }-{ $max } BPM of { $playlist }: in the wrong playlist?
@@ -0,0 +1,77 @@
+action-convert = convertir
+action-skip-compliant = omitir (ya está en formato de Rekordbox)
+action-skip-untagged = omitir (no está etiquetada para convertir)
+action-skip-filtered = omitir (no coincide con el filtro)
+action-skip-unchanged = omitir (sin cambios desde la última conversión)
+action-skip-existing = omitir (el archivo de salida ya existe)
+action-skip-in-collection = omitir (ya está en la colección de Rekordbox)
+action-skip-no-space = omitir (no cabe en el dispositivo)
+action-unsupported = rechazar (formato no compatible)
+job-target = a { $format }
+not-attempted = no se intentó, una canción anterior falló
+progress-failed = { $failed } fallidas
+progress-throughput = { $failed } fallidas, { $mbps } MB/s
+
+dry-run-summary = { $songs ->
+        [one] { $songs } canción
+       *[other] { $songs } canciones
+    }: { $convert } por convertir, { $skip } por omitir, { $unsupported } no compatibles
+verify-summary = { $verified ->
+        [one] { $verified } canción verificada
+       *[other] { $verified } canciones verificadas
+    }, { $failed } con errores
+
+confirm-overwrite = Se sobrescribirían { $count } archivos del directorio de salida:
+confirm-more = ... y { $count } más
+confirm-continue = ¿Continuar? [s/N]
+confirm-yes = s, si, sí, y, yes
+
+directory-songs = canciones
+directory-total = Total
+
+report-title = Informe de conversión
+report-counts = { $converted } convertidas, { $copied } copiadas, { $skipped } omitidas, { $failed } con errores
+report-sizes = { $source } MB de archivos originales quedaron en { $output } MB, un ahorro de { $saved } MB
+report-converted = Convertidas
+report-skipped = Omitidas
+report-failed = Con errores
+report-song = Canción
+report-from = De
+report-to = A
+report-copied = copiada
+report-source-mb = MB originales
+report-output-mb = MB convertidos
+report-seconds = Segundos
+report-measured-lufs = LUFS medidos
+report-output-lufs = LUFS de salida
+report-gain-db = Ganancia dB
+report-gain-dynamic = { $gain } (dinámica)
+report-limited-db = Limitador dB
+report-reason = Motivo
+report-error = Error
+
+merge-added = Añadida { $name }
+merge-updated = Actualizada { $name }: { $attributes }
+merge-summary = { $added } añadidas, { $updated } actualizadas, { $unchanged } sin cambios, { $kept } otras pistas conservadas
+
+history-updated = { $name }: reproducida { $plays ->
+        [one] una vez
+       *[other] { $plays } veces
+    }, valorada con { $rating }/5
+history-summary = { $updated } actualizadas, { $unchanged } sin cambios, { $unmatched } pistas de la biblioteca no convertidas aquí
+
+manifest-missing = falta
+manifest-size = el tamaño es { $size }, se esperaba { $expected }
+manifest-hash = el hash es { $hash }, se esperaba { $expected }
+manifest-unreadable = no se puede leer: { $error }
+manifest-summary = { $checked ->
+        [one] { $checked } archivo comprobado
+       *[other] { $checked } archivos comprobados
+    }, { $missing } faltan, { $changed } modificados
+diff-changed = modificado
+diff-added = nuevo
+diff-removed = desaparecido
+diff-summary = { $unchanged } sin cambios, { $changed } modificados, { $added } nuevos, { $removed } desaparecidos
+tempo-half-time = { $bpm } BPM está muy fuera de los { $min }-{ $max } BPM de { $playlist }, pero encaja al doble: ¿detectado a la mitad de su tempo?
+tempo-double-time = { $bpm } BPM está muy fuera de los { $min }-{ $max } BPM de { $playlist }, pero encaja a la mitad: ¿detectado al doble de su tempo?
+tempo-misfiled = { $bpm } BPM está muy fuera de los { $min }-{ $max } BPM de { $playlist }: ¿en la lista equivocada?
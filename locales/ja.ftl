@@ -0,0 +1,65 @@
+action-convert = 変換
+action-skip-compliant = スキップ (すでに Rekordbox 形式)
+action-skip-untagged = スキップ (変換タグなし)
+action-skip-filtered = スキップ (フィルターに不一致)
+action-skip-unchanged = スキップ (前回の変換から変更なし)
+action-skip-existing = スキップ (出力ファイルが既に存在)
+action-skip-in-collection = スキップ (すでに Rekordbox コレクションにあり)
+action-skip-no-space = スキップ (デバイスの空き容量不足)
+action-unsupported = 拒否 (非対応の形式)
+job-target = → { $format }
+not-attempted = 未実行 (前の曲が失敗したため)
+progress-failed = 失敗 { $failed } 件
+progress-throughput = 失敗 { $failed } 件、{ $mbps } MB/s
+
+dry-run-summary = { $songs } 曲: 変換 { $convert }、スキップ { $skip }、非対応 { $unsupported }
+verify-summary = { $verified } 曲を検証、失敗 { $failed }
+
+confirm-overwrite = 出力ディレクトリの { $count } 個のファイルが上書きされます:
+confirm-more = ... ほか { $count } 個
+confirm-continue = 続行しますか? [y/N]
+confirm-yes = y, yes, はい
+
+directory-songs = 曲
+directory-total = 合計
+
+report-title = 変換レポート
+report-counts = 変換 { $converted }、コピー { $copied }、スキップ { $skipped }、失敗 { $failed }
+report-sizes = 元ファイル { $source } MB が { $output } MB になり、{ $saved } MB 削減
+report-converted = 変換済み
+report-skipped = スキップ
+report-failed = 失敗
+report-song = 曲
+report-from = 元の形式
+report-to = 変換後
+report-copied = コピー
+report-source-mb = 元 MB
+report-output-mb = 出力 MB
+report-seconds = 秒
+report-measured-lufs = 測定 LUFS
+report-output-lufs = 出力 LUFS
+report-gain-db = ゲイン dB
+report-gain-dynamic = { $gain }（ダイナミック）
+report-limited-db = リミッター dB
+report-reason = 理由
+report-error = エラー
+
+merge-added = 追加: { $name }
+merge-updated = 更新: { $name }: { $attributes }
+merge-summary = 追加 { $added }、更新 { $updated }、変更なし { $unchanged }、その他の保持トラック { $kept }
+
+history-updated = { $name }: 再生 { $plays } 回、評価 { $rating }/5
+history-summary = 更新 { $updated }、変更なし { $unchanged }、ここで変換されていないライブラリのトラック { $unmatched }
+
+manifest-missing = 見つかりません
+manifest-size = サイズ { $size } (期待値 { $expected })
+manifest-hash = ハッシュ { $hash } (期待値 { $expected })
+manifest-unreadable = 読み取れません: { $error }
+manifest-summary = { $checked } ファイルを確認、欠落 { $missing }、変更 { $changed }
+diff-changed = 変更
+diff-added = 新規
+diff-removed = 削除
+diff-summary = 変更なし { $unchanged }、変更 { $changed }、新規 { $added }、削除 { $removed }
+tempo-half-time = { $bpm } BPM は { $playlist } の { $min }-{ $max } BPM から大きく外れていますが、倍速なら収まります: 半分のテンポで検出された?
+tempo-double-time = { $bpm } BPM は { $playlist } の { $min }-{ $max } BPM から大きく外れていますが、半速なら収まります: 倍のテンポで検出された?
+tempo-misfiled = { $bpm } BPM は { $playlist } の { $min }-{ $max } BPM から大きく外れています: プレイリストの間違い?
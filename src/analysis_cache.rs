@@ -0,0 +1,150 @@
+use crate::analyzers::{self, Analyzer, TrackAnalysis};
+use crate::probe_cache::ProbeCache;
+use crate::read_only;
+use crate::state::{self, write_atomic, Fnv};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The hash of a file's decoded audio, and what the file looked like when it was hashed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct FileEntry {
+    /// Modification time in seconds since the Unix epoch
+    modified: u64,
+    size: u64,
+    audio_hash: String,
+}
+
+/// What analyzers found in some audio, and which analyzers ran on it
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct AudioEntry {
+    analyzers: BTreeSet<String>,
+    analysis: TrackAnalysis,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheContents {
+    #[serde(default)]
+    files: BTreeMap<PathBuf, FileEntry>,
+    /// By the hash of the decoded audio
+    #[serde(default)]
+    audio: BTreeMap<String, AudioEntry>,
+}
+
+/// What analyzers found in songs analyzed by earlier runs, kept in a JSON file by the hash of
+/// the songs' decoded audio. Retagging, renaming or re-encoding a song losslessly leaves its
+/// audio alone, so it isn't analyzed again: only decoded, to hash it. Files that haven't changed
+/// since they were hashed aren't even decoded.
+pub struct AnalysisCache {
+    path: PathBuf,
+    contents: Mutex<CacheContents>,
+}
+
+impl AnalysisCache {
+    /// Reads the cache file, or starts an empty cache if there is none or it can't be read
+    pub fn open(path: &Path) -> Self {
+        let contents = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                tracing::warn!(?path, ?e, "Could not read analysis cache, starting over");
+                CacheContents::default()
+            }),
+            Err(_) => CacheContents::default(),
+        };
+        AnalysisCache {
+            path: path.to_path_buf(),
+            contents: Mutex::new(contents),
+        }
+    }
+
+    /// Where the cache is kept unless told otherwise: next to the probe cache
+    pub fn default_path() -> Option<PathBuf> {
+        ProbeCache::default_path().map(|path| path.with_file_name("analysis-cache.json"))
+    }
+
+    /// Runs the analyzers over a song, or takes what they found from the cache if they already
+    /// ran over the same audio. The analysis can hold what other analyzers found in the audio
+    /// before too.
+    pub fn analyze(&self, path: &Path, analyzers: &[&dyn Analyzer]) -> Result<TrackAnalysis> {
+        if analyzers.is_empty() {
+            return Ok(TrackAnalysis::default());
+        }
+        let (modified, size) = state::modified_and_size(path)?;
+        let known = self
+            .contents
+            .lock()
+            .unwrap()
+            .files
+            .get(path)
+            .filter(|entry| entry.modified == modified && entry.size == size)
+            .map(|entry| entry.audio_hash.clone());
+        let audio_hash = match known {
+            Some(hash) => hash,
+            None => {
+                let hash = audio_hash(path)?;
+                self.contents.lock().unwrap().files.insert(
+                    path.to_path_buf(),
+                    FileEntry {
+                        modified,
+                        size,
+                        audio_hash: hash.clone(),
+                    },
+                );
+                hash
+            }
+        };
+        let mut entry = self
+            .contents
+            .lock()
+            .unwrap()
+            .audio
+            .get(&audio_hash)
+            .cloned()
+            .unwrap_or_default();
+        let missing: Vec<&dyn Analyzer> = analyzers
+            .iter()
+            .copied()
+            .filter(|a| !entry.analyzers.contains(a.name()))
+            .collect();
+        if missing.is_empty() {
+            tracing::debug!(?path, "Took the analysis from the cache");
+            return Ok(entry.analysis);
+        }
+        entry.analysis.merge(analyzers::analyze(path, &missing)?);
+        entry
+            .analyzers
+            .extend(missing.iter().map(|a| a.name().to_string()));
+        self.contents
+            .lock()
+            .unwrap()
+            .audio
+            .insert(audio_hash, entry.clone());
+        Ok(entry.analysis)
+    }
+
+    /// Writes the cache file, leaving out files that no longer exist
+    pub fn save(&self) -> Result<()> {
+        if read_only::is_enabled() {
+            return Ok(());
+        }
+        let mut contents = self.contents.lock().unwrap();
+        contents.files.retain(|path, _| path.exists());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &serde_json::to_vec(&*contents)?)
+    }
+}
+
+/// Hashes a song's audio as the analyzers get it
+fn audio_hash(path: &Path) -> Result<String> {
+    let mut hash = Fnv::new();
+    analyzers::decode(path, |samples| {
+        for sample in samples {
+            hash.update(&sample.to_le_bytes());
+        }
+    })?;
+    Ok(hash.hex())
+}
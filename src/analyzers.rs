@@ -0,0 +1,387 @@
+use crate::analysis_cache::AnalysisCache;
+use crate::exporters::TrackRecord;
+use crate::fingerprint::Fingerprint;
+use crate::key::Key;
+use crate::spectrum::Spectrum;
+use crate::tempo::Tempo;
+use crate::workers;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+/// Rate songs are decoded at for analysis
+pub const SAMPLE_RATE: u32 = 44100;
+/// Songs are decoded to stereo for analysis, with samples interleaved left, right
+pub const CHANNELS: usize = 2;
+
+/// Frames decoded at a time and handed to every analysis
+const BLOCK_FRAMES: usize = 4096;
+
+/// What analyzing a track found. Each analyzer fills in its own fields and leaves the others
+/// alone, so the results of several analyzers merge into one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackAnalysis {
+    /// Integrated loudness, in LUFS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<f64>,
+    /// Highest sample, in dBFS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak: Option<f64>,
+    /// Frequency above which the song has next to nothing, in Hz. Lossy encoders cut off the
+    /// top of the spectrum, so a lossless file cut off at 16 kHz was made from an MP3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutoff_hz: Option<f64>,
+    /// Tempo, in BPM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bpm: Option<f64>,
+    /// Onsets a second, how busy the song's rhythm is, which tells a half time tempo from the
+    /// real one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onset_density: Option<f64>,
+    /// Musical key, like "Am" or "F#"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// How much better the key fits than any other but its relative major or minor, from 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_confidence: Option<f64>,
+    /// Chromaprint fingerprint, compressed and base64 encoded as AcoustID takes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+impl TrackAnalysis {
+    /// Takes what another analysis found, keeping what this one found that the other didn't
+    pub fn merge(&mut self, other: TrackAnalysis) {
+        fn take<T>(field: &mut Option<T>, other: Option<T>) {
+            if other.is_some() {
+                *field = other;
+            }
+        }
+        take(&mut self.loudness, other.loudness);
+        take(&mut self.peak, other.peak);
+        take(&mut self.cutoff_hz, other.cutoff_hz);
+        take(&mut self.bpm, other.bpm);
+        take(&mut self.onset_density, other.onset_density);
+        take(&mut self.key, other.key);
+        take(&mut self.key_confidence, other.key_confidence);
+        take(&mut self.fingerprint, other.fingerprint);
+    }
+}
+
+/// Something measured from a song's audio. Analyzers don't decode songs themselves: the song
+/// is decoded once and every analyzer picked is fed the same samples, so adding one only costs
+/// its own processing.
+pub trait Analyzer: Sync {
+    /// Name the analyzer is picked by on the command line
+    fn name(&self) -> &'static str;
+    /// What the analyzer measures, for --help and error messages
+    fn description(&self) -> &'static str;
+    /// Whether what the analyzer needs outside this program is installed, checked once before
+    /// a run so a missing tool disables the analyzer instead of failing every song
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Starts analyzing a track
+    fn start(&self) -> Box<dyn Analysis>;
+}
+
+/// An analyzer's work on one track
+pub trait Analysis: Send {
+    /// Takes the next block of decoded samples, interleaved stereo at SAMPLE_RATE
+    fn feed(&mut self, samples: &[f32]);
+    /// Writes what was found into the track's analysis
+    fn finish(self: Box<Self>, analysis: &mut TrackAnalysis);
+}
+
+/// Every analyzer that can be run
+pub static ANALYZERS: [&dyn Analyzer; 5] = [&Loudness, &Spectrum, &Tempo, &Key, &Fingerprint];
+
+/// An analyzer by its name
+pub fn find(name: &str) -> Result<&'static dyn Analyzer> {
+    ANALYZERS
+        .iter()
+        .copied()
+        .find(|a| a.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = ANALYZERS.iter().map(|a| a.name()).collect();
+            anyhow!(
+                "No analyzer called {}, choose from: {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// The analyzers that can run here, leaving out, with a warning, the ones missing something
+/// they need
+pub fn available(analyzers: &[&'static dyn Analyzer]) -> Vec<&'static dyn Analyzer> {
+    analyzers
+        .iter()
+        .copied()
+        .filter(|analyzer| match analyzer.check() {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(
+                    analyzer = analyzer.name(),
+                    "Running without the {} analyzer: {}",
+                    analyzer.name(),
+                    e
+                );
+                false
+            }
+        })
+        .collect()
+}
+
+/// Decodes a song once with ffmpeg and runs every analyzer over it
+pub fn analyze(path: &Path, analyzers: &[&dyn Analyzer]) -> Result<TrackAnalysis> {
+    let mut analysis = TrackAnalysis::default();
+    if analyzers.is_empty() {
+        return Ok(analysis);
+    }
+    let mut running: Vec<Box<dyn Analysis>> = analyzers.iter().map(|a| a.start()).collect();
+    decode(path, |samples| {
+        for analysis in running.iter_mut() {
+            analysis.feed(samples);
+        }
+    })?;
+    for running in running {
+        running.finish(&mut analysis);
+    }
+    Ok(analysis)
+}
+
+/// Decodes a song with ffmpeg, handing the samples to `feed` a block at a time, interleaved
+/// stereo at SAMPLE_RATE
+pub fn decode<F>(path: &Path, mut feed: F) -> Result<()>
+where
+    F: FnMut(&[f32]),
+{
+    let mut ffmpeg = Command::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(path)
+        .arg("-map")
+        .arg("0:a:0")
+        .arg("-f")
+        .arg("f32le")
+        .arg("-ac")
+        .arg(CHANNELS.to_string())
+        .arg("-ar")
+        .arg(SAMPLE_RATE.to_string())
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = ffmpeg.stdout.take().expect("stdout is piped");
+    // Read alongside the samples, so a song that makes ffmpeg print a lot can't fill the pipe
+    // and stall decoding
+    let mut stderr = ffmpeg.stderr.take().expect("stderr is piped");
+    let errors = std::thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+    let mut bytes = vec![0u8; BLOCK_FRAMES * CHANNELS * 4];
+    let mut samples = Vec::with_capacity(BLOCK_FRAMES * CHANNELS);
+    // Reads can end partway through a sample, whose first bytes wait here for the rest
+    let mut filled = 0;
+    loop {
+        let n_read = stdout.read(&mut bytes[filled..])?;
+        if n_read == 0 {
+            break;
+        }
+        filled += n_read;
+        let whole = filled - filled % 4;
+        samples.clear();
+        samples.extend(
+            bytes[..whole]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+        feed(&samples);
+        bytes.copy_within(whole..filled, 0);
+        filled -= whole;
+    }
+    let status = ffmpeg.wait()?;
+    let errors = errors.join().unwrap_or_default();
+    if !status.success() {
+        return Err(anyhow!("Could not decode {:?}: {}", path, errors));
+    }
+    Ok(())
+}
+
+/// Analyzes songs in parallel, returning each song's analysis in the order of the paths. Songs
+/// whose audio is in the cache aren't analyzed again.
+pub fn analyze_all(
+    paths: Vec<PathBuf>,
+    analyzers: &[&'static dyn Analyzer],
+    n_jobs: usize,
+    cache: Option<Arc<AnalysisCache>>,
+) -> Vec<Result<TrackAnalysis>> {
+    let analyzers = analyzers.to_vec();
+    workers::map_parallel(paths, n_jobs, move |path| match &cache {
+        Some(cache) => cache.analyze(&path, &analyzers),
+        None => analyze(&path, &analyzers),
+    })
+}
+
+/// Analyzes exported tracks and merges the results into them. Tracks that can't be analyzed
+/// are logged and exported without.
+pub fn analyze_tracks(
+    tracks: &mut [TrackRecord],
+    analyzers: &[&'static dyn Analyzer],
+    cache: Option<Arc<AnalysisCache>>,
+) {
+    if analyzers.is_empty() {
+        return;
+    }
+    let paths = tracks.iter().map(|t| t.path.clone()).collect();
+    let results = analyze_all(paths, analyzers, workers::default_jobs(), cache);
+    for (track, result) in tracks.iter_mut().zip(results) {
+        match result {
+            Ok(analysis) => track.analysis = analysis,
+            Err(e) => tracing::warn!(path = ?track.path, ?e, "Could not analyze song"),
+        }
+    }
+}
+
+/// Loudness as EBU R128 measures it, and sample peak
+pub struct Loudness;
+
+impl Analyzer for Loudness {
+    fn name(&self) -> &'static str {
+        "loudness"
+    }
+
+    fn description(&self) -> &'static str {
+        "integrated loudness in LUFS and sample peak"
+    }
+
+    fn start(&self) -> Box<dyn Analysis> {
+        Box::new(LoudnessAnalysis::new(SAMPLE_RATE))
+    }
+}
+
+/// Length of the steps gating blocks advance by, in seconds. Blocks are 400 ms long, four
+/// steps, so they overlap by 75%.
+const STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+/// Blocks quieter than this, in LUFS, are left out as silence
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this many LU below the loudness of the blocks above the absolute gate are left out
+const RELATIVE_GATE: f64 = -10.0;
+
+/// A biquad filter, in direct form I
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+struct LoudnessAnalysis {
+    /// K-weighting: a high shelf for the head, then a high pass, per channel
+    filters: [[Biquad; 2]; CHANNELS],
+    step_frames: usize,
+    /// Sum of the channels' weighted mean squares over each finished step
+    steps: Vec<f64>,
+    step_energy: f64,
+    step_filled: usize,
+    peak: f32,
+}
+
+impl LoudnessAnalysis {
+    /// K-weighting filter coefficients for a sample rate, from the analog filters of ITU-R
+    /// BS.1770, so rates other than 48 kHz get the same response
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+        let k = (std::f64::consts::PI * 1681.974450955533 / rate).tan();
+        let q = 0.7071752369554196;
+        let vh = 10f64.powf(3.999843853973347 / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            ..Default::default()
+        };
+        let k = (std::f64::consts::PI * 38.13547087602444 / rate).tan();
+        let q = 0.5003270373238773;
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            ..Default::default()
+        };
+        LoudnessAnalysis {
+            filters: [[shelf, high_pass]; CHANNELS],
+            step_frames: (rate * STEP_SECONDS).round() as usize,
+            steps: vec![],
+            step_energy: 0.0,
+            step_filled: 0,
+            peak: 0.0,
+        }
+    }
+}
+
+impl Analysis for LoudnessAnalysis {
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(self.filters.iter_mut()) {
+                self.peak = self.peak.max(sample.abs());
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.step_energy += weighted * weighted;
+            }
+            self.step_filled += 1;
+            if self.step_filled == self.step_frames {
+                self.steps.push(self.step_energy / self.step_frames as f64);
+                self.step_energy = 0.0;
+                self.step_filled = 0;
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>, analysis: &mut TrackAnalysis) {
+        let to_lufs = |energy: f64| -0.691 + 10.0 * energy.log10();
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(STEPS_PER_BLOCK)
+            .map(|w| w.iter().sum::<f64>() / STEPS_PER_BLOCK as f64)
+            .filter(|energy| to_lufs(*energy) > ABSOLUTE_GATE)
+            .collect();
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+        if !blocks.is_empty() {
+            let threshold = to_lufs(mean(&blocks)) + RELATIVE_GATE;
+            let gated: Vec<f64> = blocks
+                .into_iter()
+                .filter(|energy| to_lufs(*energy) > threshold)
+                .collect();
+            analysis.loudness = Some(to_lufs(mean(&gated)));
+        }
+        if self.peak > 0.0 {
+            analysis.peak = Some(20.0 * (self.peak as f64).log10());
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::analyzers::{self, CHANNELS, SAMPLE_RATE};
+use crate::song_info::SongInfo;
+use crate::state;
+use crate::usb_layout;
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entries per second of the scrolling waveform, as CDJs expect
+const DETAIL_ENTRIES_PER_SECOND: usize = 150;
+/// Columns of the waveform preview shown over the whole track, and of the tiny preview older
+/// players show
+const PREVIEW_COLUMNS: usize = 400;
+const TINY_PREVIEW_COLUMNS: usize = 100;
+/// Tallest column of the waveforms, in the bits each has for its height
+const MAX_HEIGHT: f32 = 31.0;
+const TINY_MAX_HEIGHT: f32 = 15.0;
+/// Most white a column can be, for high frequency content
+const MAX_WHITENESS: f32 = 7.0;
+
+/// The envelope of a decoded song, one entry per 1/150 s: the entry's peak, and how much of it
+/// is high frequencies, from 0 to 1
+#[derive(Clone, Debug, Default)]
+pub struct Waveform {
+    entries: Vec<(f32, f32)>,
+    /// The entry being filled: its peak, energy, energy of its differences and frames
+    peak: f32,
+    energy: f32,
+    difference_energy: f32,
+    previous: f32,
+    n_frames: usize,
+}
+
+impl Waveform {
+    /// Takes the next block of interleaved stereo samples
+    pub fn feed(&mut self, samples: &[f32]) {
+        let frames_per_entry = SAMPLE_RATE as usize / DETAIL_ENTRIES_PER_SECOND;
+        for frame in samples.chunks_exact(CHANNELS) {
+            let mono = frame.iter().sum::<f32>() / CHANNELS as f32;
+            self.peak = self.peak.max(mono.abs());
+            self.energy += mono * mono;
+            let difference = mono - self.previous;
+            self.difference_energy += difference * difference;
+            self.previous = mono;
+            self.n_frames += 1;
+            if self.n_frames == frames_per_entry {
+                self.finish_entry();
+            }
+        }
+    }
+
+    fn finish_entry(&mut self) {
+        // The differences of a sine have (2 sin(pi f / rate))^2 times its energy, so this goes
+        // from 0 for the lowest frequencies to 1 at half the sample rate
+        let brightness = if self.energy > 0.0 {
+            (self.difference_energy / (4.0 * self.energy)).sqrt()
+        } else {
+            0.0
+        };
+        self.entries.push((self.peak.min(1.0), brightness.min(1.0)));
+        self.peak = 0.0;
+        self.energy = 0.0;
+        self.difference_energy = 0.0;
+        self.n_frames = 0;
+    }
+
+    /// Length of the song in milliseconds
+    fn duration_ms(&self) -> u32 {
+        (self.entries.len() * 1000 / DETAIL_ENTRIES_PER_SECOND) as u32
+    }
+
+    /// Groups the entries into columns, each the highest peak and mean brightness of its entries
+    fn columns(&self, n_columns: usize) -> Vec<(f32, f32)> {
+        (0..n_columns)
+            .map(|column| {
+                let start = column * self.entries.len() / n_columns;
+                let end = ((column + 1) * self.entries.len() / n_columns).max(start + 1);
+                let entries = self.entries.get(start..end).unwrap_or_default();
+                if entries.is_empty() {
+                    return (0.0, 0.0);
+                }
+                let peak = entries.iter().map(|e| e.0).fold(0.0, f32::max);
+                let brightness = entries.iter().map(|e| e.1).sum::<f32>() / entries.len() as f32;
+                (peak, brightness)
+            })
+            .collect()
+    }
+
+    /// When the first beat is, in milliseconds: the first entry that gets to half the song's
+    /// peak, as intros tend to start on a beat
+    fn first_beat_ms(&self) -> u32 {
+        let peak = self.entries.iter().map(|e| e.0).fold(0.0, f32::max);
+        let first = self
+            .entries
+            .iter()
+            .position(|e| e.0 >= peak / 2.0)
+            .unwrap_or(0);
+        (first * 1000 / DETAIL_ENTRIES_PER_SECOND) as u32
+    }
+}
+
+/// A waveform column as CDJs store it: the height in the low 5 bits and whiteness in the top 3
+fn column_byte((peak, brightness): (f32, f32)) -> u8 {
+    let height = (peak * MAX_HEIGHT).round() as u8;
+    let whiteness = ((brightness * 2.0).min(1.0) * MAX_WHITENESS).round() as u8;
+    whiteness << 5 | height
+}
+
+/// The BPM a song is tagged with, for the beat grid
+pub fn tagged_bpm(song: &SongInfo) -> Option<f64> {
+    song.get_tag("bpm")
+        .or_else(|| song.get_tag("TBPM"))
+        .and_then(|bpm| bpm.trim().parse::<f64>().ok())
+        .filter(|bpm| *bpm > 0.0)
+}
+
+/// Where on a USB stick the analysis files of a track go: a folder under PIONEER/USBANLZ named
+/// after a hash of the track's path on the stick, like /PIONEER/USBANLZ/P03A/1B2C4D5E/ANLZ0000.DAT,
+/// as Rekordbox lays them out. This is what the track's row in export.pdb points to.
+pub fn device_analysis_path(device_path: &str) -> String {
+    // FNV-1a, which stays the same across runs and builds
+    let hash = device_path.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    format!(
+        "/{}/USBANLZ/P{:03X}/{:08X}/ANLZ0000.DAT",
+        usb_layout::PIONEER_DIR,
+        hash >> 24,
+        hash
+    )
+}
+
+/// The .DAT and .EXT analysis files of a track in an output directory laid out like a USB stick
+pub fn paths(output_dir: &Path, device_path: &str) -> (PathBuf, PathBuf) {
+    let dat_path = output_dir.join(device_analysis_path(device_path).trim_start_matches('/'));
+    let ext_path = dat_path.with_extension("EXT");
+    (dat_path, ext_path)
+}
+
+/// Writes the Pioneer ANLZ analysis files of a converted track into PIONEER/USBANLZ of an
+/// output directory laid out like a USB stick: a .DAT file with the waveform previews and, if
+/// the BPM is known, a beat grid, and an .EXT file with the scrolling waveform. Players load
+/// them through the track's row in the export.pdb written by `usb_layout::write_device_library`.
+/// `device_path` is where the track is on the stick, like /Contents/Artist/Album/song.mp3.
+/// The beat grid assumes a steady tempo from the first loud beat, so it is a starting point
+/// to fix in Rekordbox rather than an analysis.
+pub fn write(
+    track_path: &Path,
+    output_dir: &Path,
+    device_path: &str,
+    bpm: Option<f64>,
+) -> Result<()> {
+    let mut waveform = Waveform::default();
+    analyzers::decode(track_path, |samples| waveform.feed(samples))?;
+    let (dat_path, ext_path) = paths(output_dir, device_path);
+    if let Some(folder) = dat_path.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let path = path_section(device_path);
+    let mut dat = vec![path.clone()];
+    dat.push(preview_section(
+        b"PWAV",
+        waveform
+            .columns(PREVIEW_COLUMNS)
+            .into_iter()
+            .map(column_byte)
+            .collect(),
+    ));
+    dat.push(preview_section(
+        b"PWV2",
+        waveform
+            .columns(TINY_PREVIEW_COLUMNS)
+            .into_iter()
+            .map(|(peak, _)| (peak * TINY_MAX_HEIGHT).round() as u8)
+            .collect(),
+    ));
+    if let Some(bpm) = bpm {
+        dat.push(beat_grid_section(
+            bpm,
+            waveform.first_beat_ms(),
+            waveform.duration_ms(),
+        ));
+    }
+    let detail: Vec<u8> = waveform.entries.iter().copied().map(column_byte).collect();
+    let ext = vec![path, detail_section(detail)];
+    state::write_atomic(&dat_path, &file(&dat))?;
+    state::write_atomic(&ext_path, &file(&ext))?;
+    Ok(())
+}
+
+/// An ANLZ file: a PMAI header with the length of the whole file, then the sections. Everything
+/// is big endian.
+fn file(sections: &[Vec<u8>]) -> Vec<u8> {
+    const HEADER_LENGTH: u32 = 0x1c;
+    let length = HEADER_LENGTH as usize + sections.iter().map(Vec::len).sum::<usize>();
+    let mut file = Vec::with_capacity(length);
+    file.extend_from_slice(b"PMAI");
+    file.extend_from_slice(&HEADER_LENGTH.to_be_bytes());
+    file.extend_from_slice(&(length as u32).to_be_bytes());
+    for value in [1u32, 0x10000, 0x10000, 0] {
+        file.extend_from_slice(&value.to_be_bytes());
+    }
+    for section in sections {
+        file.extend_from_slice(section);
+    }
+    file
+}
+
+/// A section: its tag, the length of its header, its whole length, the rest of its header, and
+/// its contents
+fn section(tag: &[u8; 4], header: &[u32], contents: &[u8]) -> Vec<u8> {
+    let header_length = 12 + 4 * header.len();
+    let length = header_length + contents.len();
+    let mut section = Vec::with_capacity(length);
+    section.extend_from_slice(tag);
+    section.extend_from_slice(&(header_length as u32).to_be_bytes());
+    section.extend_from_slice(&(length as u32).to_be_bytes());
+    for value in header {
+        section.extend_from_slice(&value.to_be_bytes());
+    }
+    section.extend_from_slice(contents);
+    section
+}
+
+/// The path of the track the analysis is of, in UTF-16 with a terminating zero
+fn path_section(device_path: &str) -> Vec<u8> {
+    let path: Vec<u8> = device_path
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_be_bytes())
+        .collect();
+    section(b"PPTH", &[path.len() as u32], &path)
+}
+
+fn preview_section(tag: &[u8; 4], columns: Vec<u8>) -> Vec<u8> {
+    section(tag, &[columns.len() as u32, 0x10000], &columns)
+}
+
+fn detail_section(entries: Vec<u8>) -> Vec<u8> {
+    section(b"PWV3", &[1, entries.len() as u32, 0x960000], &entries)
+}
+
+/// Beats at a steady tempo from the first beat to the end, counted 1 to 4 through each bar
+fn beat_grid_section(bpm: f64, first_beat_ms: u32, duration_ms: u32) -> Vec<u8> {
+    let beat_ms = 60_000.0 / bpm;
+    let mut beats = vec![];
+    let mut n_beats = 0u32;
+    loop {
+        let time = first_beat_ms as f64 + n_beats as f64 * beat_ms;
+        if time >= duration_ms as f64 {
+            break;
+        }
+        beats.extend_from_slice(&((n_beats % 4 + 1) as u16).to_be_bytes());
+        beats.extend_from_slice(&((bpm * 100.0).round() as u16).to_be_bytes());
+        beats.extend_from_slice(&(time.round() as u32).to_be_bytes());
+        n_beats += 1;
+    }
+    section(b"PQTZ", &[0, 0x80000, n_beats], &beats)
+}
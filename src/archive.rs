@@ -0,0 +1,265 @@
+use crate::manifest::Manifest;
+use crate::read_only;
+use crate::routing;
+use crate::song_info::{AudioFormatType, SongInfo, SupportedAudioFormat};
+use crate::state;
+use crate::targets::ArchiveTarget;
+use crate::throttle::ReadThrottle;
+use crate::workers;
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+/// WAV codecs FLAC holds every sample of. Float and 32 bit WAVs are archived as they are.
+const FLAC_CODECS: [&str; 2] = ["pcm_s16le", "pcm_s24le"];
+
+/// A tree that every song a run picks is stored in untouched, apart from the songs converted
+/// for DJing, to keep the originals for the long term
+#[derive(Clone, Debug)]
+pub struct Archive {
+    pub dir: PathBuf,
+    /// Mirror each song's folder relative to this root in the archive. Songs outside it, or all
+    /// songs without one, are stored under their full path.
+    pub source_root: Option<PathBuf>,
+    pub target: ArchiveTarget,
+    /// What songs and the archive are read through, the same throttle conversions read through
+    pub read_throttle: Option<Arc<ReadThrottle>>,
+}
+
+/// Results of archiving a set of songs
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveSummary {
+    pub n_stored: usize,
+    pub n_unchanged: usize,
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl Archive {
+    /// Where a song is stored in the archive
+    pub fn path_for(&self, song: &SongInfo) -> Result<PathBuf> {
+        let source_dir = song
+            .get_song_path()
+            .parent()
+            .ok_or_else(|| anyhow!("{} has no folder", song.get_song_path().display()))?;
+        let dir = match &self.source_root {
+            Some(root) if source_dir.starts_with(root) => {
+                routing::mirrored_dir(song, root, &self.dir)
+            }
+            // Songs from a collection or playlist can share a name across folders, so only
+            // their full path keeps them apart
+            _ => self.dir.join(relative_to_root(source_dir)),
+        };
+        let name = song.get_song_name()?;
+        // A transcoded WAV keeps its extension, so it can't land on a FLAC of the same name
+        Ok(if self.transcodes(song) {
+            dir.join(format!("{}.flac", name))
+        } else {
+            dir.join(name)
+        })
+    }
+
+    fn transcodes(&self, song: &SongInfo) -> bool {
+        self.target == ArchiveTarget::Flac
+            && matches!(
+                song.get_format(),
+                AudioFormatType::Lossless(SupportedAudioFormat::WAV)
+            )
+            && FLAC_CODECS.contains(&song.get_codec())
+    }
+
+    /// Whether the archived copy of a song holds the same audio as the song: the same bytes for
+    /// a copy, or the same decoded samples for a FLAC transcode
+    fn holds(&self, song: &SongInfo, path: &Path) -> Result<bool> {
+        let source = song.get_song_path();
+        let throttle = self.read_throttle.as_deref();
+        if self.transcodes(song) {
+            Ok(samples_md5(source, song.get_codec(), throttle)?
+                == samples_md5(path, song.get_codec(), throttle)?)
+        } else {
+            Ok(state::hash_file_throttled(source, throttle)?
+                == state::hash_file_throttled(path, throttle)?)
+        }
+    }
+
+    /// Stores a song in the archive, unless it is there already and holds the same audio.
+    /// Returns whether it was stored.
+    pub fn store(&self, song: &SongInfo) -> Result<bool> {
+        let source = song.get_song_path();
+        let path = self.path_for(song)?;
+        let (source_modified, source_size) = state::modified_and_size(source)?;
+        if let Ok((modified, size)) = state::modified_and_size(&path) {
+            if modified >= source_modified
+                && (self.transcodes(song) || size == source_size)
+                && self.holds(song, &path)?
+            {
+                return Ok(false);
+            }
+        }
+        read_only::check_writable(&path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written next to its destination and moved there once checked, so the archive never
+        // holds a partial or damaged copy
+        let mut partial = path.clone().into_os_string();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+        let throttle = self.read_throttle.as_deref();
+        let stored = if self.transcodes(song) {
+            transcode_to_flac(source, &partial, song.get_codec(), throttle)
+        } else {
+            copy_checked(source, &partial, throttle)
+        };
+        if let Err(e) = stored {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        fs::rename(&partial, &path)?;
+        tracing::debug!(?source, ?path, "Archived");
+        Ok(true)
+    }
+
+    /// Stores songs in the archive, `n_jobs` at once, then updates the archive's manifest with
+    /// the checksum of every file in it, for verify-manifest to check the archive against later
+    pub fn store_all(&self, songs: Vec<SongInfo>, n_jobs: usize) -> ArchiveSummary {
+        let archive = self.clone();
+        let results = workers::map_parallel(songs, n_jobs, move |song| {
+            let result = archive.store(&song);
+            (song.get_song_path().clone(), result)
+        });
+        let mut summary = ArchiveSummary::default();
+        for (source, result) in results {
+            match result {
+                Ok(true) => summary.n_stored += 1,
+                Ok(false) => summary.n_unchanged += 1,
+                Err(e) => {
+                    tracing::error!(?source, ?e, "Could not archive song");
+                    summary.failures.push((source, format!("{:#}", e)));
+                }
+            }
+        }
+        if let Err(e) = Manifest::update(&self.dir, self.read_throttle.as_deref()) {
+            tracing::warn!(?e, "Could not update the archive's manifest");
+        }
+        tracing::info!(
+            n_stored = summary.n_stored,
+            n_unchanged = summary.n_unchanged,
+            n_failed = summary.failures.len(),
+            "Archived songs into {}",
+            self.dir.display()
+        );
+        summary
+    }
+}
+
+/// A folder's path with its root and any drive prefix taken off, to join onto the archive's
+fn relative_to_root(dir: &Path) -> PathBuf {
+    dir.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_os_string()),
+            Component::Prefix(prefix) => Some(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .trim_end_matches(':')
+                    .into(),
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Copies a file, checking the copy has the same contents
+fn copy_checked(source: &Path, destination: &Path, throttle: Option<&ReadThrottle>) -> Result<()> {
+    match throttle {
+        Some(throttle) => {
+            io::copy(&mut throttle.open(source)?, &mut File::create(destination)?)?;
+        }
+        None => {
+            fs::copy(source, destination)?;
+        }
+    }
+    if state::hash_file_throttled(source, throttle)?
+        != state::hash_file_throttled(destination, throttle)?
+    {
+        return Err(anyhow!("The copy of {} differs from it", source.display()));
+    }
+    Ok(())
+}
+
+/// Encodes a WAV file as FLAC with its tags, checking the FLAC decodes to the same samples
+fn transcode_to_flac(
+    source: &Path,
+    destination: &Path,
+    codec: &str,
+    throttle: Option<&ReadThrottle>,
+) -> Result<()> {
+    let slot = throttle.map(ReadThrottle::slot);
+    let output = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .arg("-v")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(source)
+        .arg("-map")
+        .arg("0:a:0")
+        .arg("-map_metadata")
+        .arg("0")
+        .arg("-c:a")
+        .arg("flac")
+        .arg("-compression_level")
+        .arg("8")
+        .arg("-f")
+        .arg("flac")
+        .arg(destination)
+        .output()
+        .context("Could not run ffmpeg")?;
+    drop(slot);
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Could not encode {} as FLAC: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    if samples_md5(source, codec, throttle)? != samples_md5(destination, codec, throttle)? {
+        return Err(anyhow!(
+            "The FLAC encoding of {} doesn't decode to the same samples",
+            source.display()
+        ));
+    }
+    Ok(())
+}
+
+/// The MD5 of a file's samples decoded as the WAV codec they came from
+fn samples_md5(path: &Path, codec: &str, throttle: Option<&ReadThrottle>) -> Result<String> {
+    let _slot = throttle.map(ReadThrottle::slot);
+    let output = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(path)
+        .arg("-map")
+        .arg("0:a:0")
+        .arg("-c:a")
+        .arg(codec)
+        .arg("-f")
+        .arg("md5")
+        .arg("-")
+        .output()
+        .with_context(|| format!("Could not decode {}", path.display()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.trim().strip_prefix("MD5=") {
+        Some(md5) if output.status.success() => Ok(md5.to_string()),
+        _ => Err(anyhow!(
+            "Could not decode {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
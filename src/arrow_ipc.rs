@@ -0,0 +1,707 @@
+use crate::state;
+use anyhow::Result;
+use std::path::Path;
+
+/// Starts and ends an Arrow IPC file, padded to 8 bytes at the start
+const MAGIC: &[u8; 6] = b"ARROW1";
+/// Marks the start of every message
+const CONTINUATION: u32 = 0xffff_ffff;
+/// Arrow's MetadataVersion.V5
+const METADATA_VERSION: i16 = 4;
+
+/// Arrow's MessageHeader union members
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+/// Arrow's Type union members
+const TYPE_INT: u8 = 2;
+const TYPE_FLOATING_POINT: u8 = 3;
+const TYPE_UTF8: u8 = 5;
+const TYPE_BOOL: u8 = 6;
+const TYPE_TIMESTAMP: u8 = 10;
+
+/// Arrow's Precision.DOUBLE
+const DOUBLE_PRECISION: i16 = 2;
+/// Arrow's TimeUnit.SECOND
+const SECONDS: i16 = 0;
+
+/// The values of a column, None where a value is missing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Utf8(Vec<Option<String>>),
+    Int64(Vec<Option<i64>>),
+    Float64(Vec<Option<f64>>),
+    Bool(Vec<Option<bool>>),
+    /// Seconds since the Unix epoch, without a time zone
+    Timestamp(Vec<Option<i64>>),
+}
+
+impl Column {
+    fn len(&self) -> usize {
+        match self {
+            Column::Utf8(values) => values.len(),
+            Column::Int64(values) | Column::Timestamp(values) => values.len(),
+            Column::Float64(values) => values.len(),
+            Column::Bool(values) => values.len(),
+        }
+    }
+
+    /// The Type union member and table the schema describes the column with
+    fn arrow_type(&self) -> (u8, Table) {
+        match self {
+            Column::Utf8(_) => (TYPE_UTF8, Table::default()),
+            Column::Int64(_) => (
+                TYPE_INT,
+                Table::default()
+                    .with(0, Field::I32(64))
+                    .with(1, Field::Bool(true)),
+            ),
+            Column::Float64(_) => (
+                TYPE_FLOATING_POINT,
+                Table::default().with(0, Field::I16(DOUBLE_PRECISION)),
+            ),
+            Column::Bool(_) => (TYPE_BOOL, Table::default()),
+            Column::Timestamp(_) => (
+                TYPE_TIMESTAMP,
+                Table::default().with(0, Field::I16(SECONDS)),
+            ),
+        }
+    }
+
+    /// Which values are there, as a bitmap, and how many are missing
+    fn validity(&self) -> (Vec<u8>, usize) {
+        let present: Vec<bool> = match self {
+            Column::Utf8(values) => values.iter().map(Option::is_some).collect(),
+            Column::Int64(values) | Column::Timestamp(values) => {
+                values.iter().map(Option::is_some).collect()
+            }
+            Column::Float64(values) => values.iter().map(Option::is_some).collect(),
+            Column::Bool(values) => values.iter().map(Option::is_some).collect(),
+        };
+        let n_missing = present.iter().filter(|p| !**p).count();
+        (bitmap(present.into_iter()), n_missing)
+    }
+
+    /// The buffers holding the values, after the validity bitmap
+    fn value_buffers(&self) -> Vec<Vec<u8>> {
+        match self {
+            Column::Utf8(values) => {
+                let mut offsets = 0i32.to_le_bytes().to_vec();
+                let mut data = vec![];
+                for value in values {
+                    data.extend_from_slice(value.as_deref().unwrap_or_default().as_bytes());
+                    offsets.extend_from_slice(&(data.len() as i32).to_le_bytes());
+                }
+                vec![offsets, data]
+            }
+            Column::Int64(values) | Column::Timestamp(values) => vec![values
+                .iter()
+                .flat_map(|v| v.unwrap_or_default().to_le_bytes())
+                .collect()],
+            Column::Float64(values) => vec![values
+                .iter()
+                .flat_map(|v| v.unwrap_or_default().to_le_bytes())
+                .collect()],
+            Column::Bool(values) => vec![bitmap(values.iter().map(|v| v.unwrap_or_default()))],
+        }
+    }
+}
+
+/// Bits packed least significant first, as Arrow keeps validity and booleans
+fn bitmap(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (i, bit) in bits.enumerate() {
+        if i.is_multiple_of(8) {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().unwrap() |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+/// Writes named columns of the same length as an Arrow IPC file, also known as Feather, in one
+/// record batch, which Polars, pandas and DuckDB load straight into a table
+pub fn write(path: &Path, columns: &[(&str, Column)]) -> Result<()> {
+    state::write_atomic(path, &to_bytes(columns))
+}
+
+/// An Arrow IPC file of named columns of the same length
+pub fn to_bytes(columns: &[(&str, Column)]) -> Vec<u8> {
+    let mut file = MAGIC.to_vec();
+    file.extend_from_slice(&[0, 0]);
+    let schema = || {
+        let fields = columns
+            .iter()
+            .map(|(name, column)| {
+                let (type_type, arrow_type) = column.arrow_type();
+                Table::default()
+                    .with(0, Field::String(name.to_string()))
+                    .with(1, Field::Bool(true))
+                    .with(2, Field::U8(type_type))
+                    .with(3, Field::Table(arrow_type))
+                    .with(5, Field::Tables(vec![]))
+            })
+            .collect();
+        // Little endian
+        Table::default()
+            .with(0, Field::I16(0))
+            .with(1, Field::Tables(fields))
+    };
+    write_message(&mut file, HEADER_SCHEMA, schema(), &[]);
+
+    let n_rows = columns.first().map_or(0, |(_, column)| column.len());
+    let mut body = vec![];
+    let mut nodes = vec![];
+    let mut buffers = vec![];
+    for (_, column) in columns {
+        let (validity, n_missing) = column.validity();
+        nodes.extend_from_slice(&(column.len() as i64).to_le_bytes());
+        nodes.extend_from_slice(&(n_missing as i64).to_le_bytes());
+        for buffer in std::iter::once(validity).chain(column.value_buffers()) {
+            buffers.extend_from_slice(&(body.len() as i64).to_le_bytes());
+            buffers.extend_from_slice(&(buffer.len() as i64).to_le_bytes());
+            body.extend_from_slice(&buffer);
+            pad(&mut body, 8);
+        }
+    }
+    let record_batch = Table::default()
+        .with(0, Field::I64(n_rows as i64))
+        .with(1, Field::Structs(nodes, columns.len()))
+        .with(2, Field::Structs(buffers.clone(), buffers.len() / 16));
+    let block = write_message(&mut file, HEADER_RECORD_BATCH, record_batch, &body);
+    // End of stream
+    file.extend_from_slice(&CONTINUATION.to_le_bytes());
+    file.extend_from_slice(&0u32.to_le_bytes());
+
+    let footer = Table::default()
+        .with(0, Field::I16(METADATA_VERSION))
+        .with(1, Field::Table(schema()))
+        .with(2, Field::Structs(vec![], 0))
+        .with(3, Field::Structs(block, 1));
+    let footer = finish(&footer);
+    file.extend_from_slice(&footer);
+    file.extend_from_slice(&(footer.len() as i32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    file
+}
+
+/// Appends an encapsulated message: its metadata, padded to 8 bytes, then its body. Returns the
+/// footer's Block struct pointing at it.
+fn write_message(file: &mut Vec<u8>, header_type: u8, header: Table, body: &[u8]) -> Vec<u8> {
+    let offset = file.len();
+    let message = Table::default()
+        .with(0, Field::I16(METADATA_VERSION))
+        .with(1, Field::U8(header_type))
+        .with(2, Field::Table(header))
+        .with(3, Field::I64(body.len() as i64));
+    let mut metadata = finish(&message);
+    pad(&mut metadata, 8);
+    file.extend_from_slice(&CONTINUATION.to_le_bytes());
+    file.extend_from_slice(&(metadata.len() as i32).to_le_bytes());
+    file.extend_from_slice(&metadata);
+    file.extend_from_slice(body);
+    let mut block = (offset as i64).to_le_bytes().to_vec();
+    block.extend_from_slice(&(8 + metadata.len() as i32).to_le_bytes());
+    block.extend_from_slice(&[0; 4]);
+    block.extend_from_slice(&(body.len() as i64).to_le_bytes());
+    block
+}
+
+fn pad(bytes: &mut Vec<u8>, alignment: usize) {
+    while !bytes.len().is_multiple_of(alignment) {
+        bytes.push(0);
+    }
+}
+
+/// A flatbuffer table, the fields set by their slot in the schema
+#[derive(Default)]
+struct Table {
+    fields: Vec<(usize, Field)>,
+}
+
+impl Table {
+    fn with(mut self, slot: usize, field: Field) -> Self {
+        self.fields.push((slot, field));
+        self
+    }
+}
+
+enum Field {
+    U8(u8),
+    Bool(bool),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Table(Table),
+    String(String),
+    Tables(Vec<Table>),
+    /// A vector of structs, as their bytes and how many there are. Arrow's structs all hold
+    /// 64 bit numbers, so they are 8 byte aligned.
+    Structs(Vec<u8>, usize),
+}
+
+impl Field {
+    /// Bytes the field takes in its table: its value, or the offset to it
+    fn inline_size(&self) -> usize {
+        match self {
+            Field::U8(_) | Field::Bool(_) => 1,
+            Field::I16(_) => 2,
+            Field::I64(_) => 8,
+            _ => 4,
+        }
+    }
+}
+
+/// Encodes a flatbuffer with a table at its root. Flatbuffers are usually built back to front;
+/// this lays every table's vtable out just before it and whatever it points to after it, as
+/// offsets to strings, tables and vectors only have to point forward.
+fn finish(root: &Table) -> Vec<u8> {
+    let mut bytes = vec![0; 4];
+    let root = write_table(&mut bytes, root);
+    patch_offset(&mut bytes, 0, root);
+    bytes
+}
+
+fn patch_offset(bytes: &mut [u8], at: usize, target: usize) {
+    bytes[at..at + 4].copy_from_slice(&((target - at) as u32).to_le_bytes());
+}
+
+fn write_table(bytes: &mut Vec<u8>, table: &Table) -> usize {
+    // Largest fields first, so each is aligned without padding in between
+    let mut fields: Vec<&(usize, Field)> = table.fields.iter().collect();
+    fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.inline_size()));
+    let mut field_offsets = vec![];
+    let mut size: usize = 4;
+    for (slot, field) in fields.iter() {
+        let field_size = field.inline_size();
+        size = size.div_ceil(field_size) * field_size;
+        field_offsets.push((*slot, size));
+        size += field_size;
+    }
+    let n_slots = table
+        .fields
+        .iter()
+        .map(|(slot, _)| slot + 1)
+        .max()
+        .unwrap_or(0);
+
+    pad(bytes, 2);
+    let vtable = bytes.len();
+    bytes.extend_from_slice(&(4 + 2 * n_slots as u16).to_le_bytes());
+    bytes.extend_from_slice(&(size as u16).to_le_bytes());
+    for slot in 0..n_slots {
+        let offset = field_offsets
+            .iter()
+            .find(|(s, _)| *s == slot)
+            .map_or(0, |(_, offset)| *offset);
+        bytes.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+    pad(bytes, 8);
+    let start = bytes.len();
+    bytes.resize(start + size, 0);
+    bytes[start..start + 4].copy_from_slice(&((start - vtable) as i32).to_le_bytes());
+
+    let mut children = vec![];
+    for ((_, field), (_, offset)) in fields.iter().zip(field_offsets) {
+        let at = start + offset;
+        let inline = match field {
+            Field::U8(value) => vec![*value],
+            Field::Bool(value) => vec![*value as u8],
+            Field::I16(value) => value.to_le_bytes().to_vec(),
+            Field::I32(value) => value.to_le_bytes().to_vec(),
+            Field::I64(value) => value.to_le_bytes().to_vec(),
+            child => {
+                children.push((at, child));
+                continue;
+            }
+        };
+        bytes[at..at + inline.len()].copy_from_slice(&inline);
+    }
+    for (at, child) in children {
+        let target = match child {
+            Field::Table(table) => write_table(bytes, table),
+            Field::String(string) => {
+                pad(bytes, 4);
+                let target = bytes.len();
+                bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(string.as_bytes());
+                bytes.push(0);
+                target
+            }
+            Field::Tables(tables) => {
+                pad(bytes, 4);
+                let target = bytes.len();
+                bytes.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+                bytes.resize(target + 4 + 4 * tables.len(), 0);
+                for (i, table) in tables.iter().enumerate() {
+                    let element = target + 4 + 4 * i;
+                    let table = write_table(bytes, table);
+                    patch_offset(bytes, element, table);
+                }
+                target
+            }
+            Field::Structs(structs, n) => {
+                // The structs after the length have to be 8 byte aligned
+                pad(bytes, 4);
+                if bytes.len().is_multiple_of(8) {
+                    bytes.extend_from_slice(&[0; 4]);
+                }
+                let target = bytes.len();
+                bytes.extend_from_slice(&(*n as u32).to_le_bytes());
+                bytes.extend_from_slice(structs);
+                target
+            }
+            _ => unreachable!("scalars are written inline"),
+        };
+        patch_offset(bytes, at, target);
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    fn i64_at(bytes: &[u8], at: usize) -> i64 {
+        i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    /// A flatbuffer table, read the way Arrow readers read it
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        at: usize,
+        vtable: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn root(bytes: &'a [u8]) -> Self {
+            Reader::table(bytes, u32_at(bytes, 0))
+        }
+
+        fn table(bytes: &'a [u8], at: usize) -> Self {
+            let back = i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+            let vtable = (at as i64 - back as i64) as usize;
+            assert_eq!(vtable % 2, 0, "vtable at {} isn't aligned", vtable);
+            Reader { bytes, at, vtable }
+        }
+
+        fn field(&self, slot: usize) -> Option<usize> {
+            if 4 + 2 * slot >= u16_at(self.bytes, self.vtable) {
+                return None;
+            }
+            match u16_at(self.bytes, self.vtable + 4 + 2 * slot) {
+                0 => None,
+                offset => {
+                    assert!(offset < u16_at(self.bytes, self.vtable + 2));
+                    Some(self.at + offset)
+                }
+            }
+        }
+
+        fn u8(&self, slot: usize) -> u8 {
+            self.field(slot).map_or(0, |at| self.bytes[at])
+        }
+
+        fn i16(&self, slot: usize) -> i16 {
+            self.field(slot).map_or(0, |at| {
+                assert_eq!(at % 2, 0);
+                i16::from_le_bytes(self.bytes[at..at + 2].try_into().unwrap())
+            })
+        }
+
+        fn i32(&self, slot: usize) -> i32 {
+            self.field(slot).map_or(0, |at| {
+                assert_eq!(at % 4, 0);
+                i32::from_le_bytes(self.bytes[at..at + 4].try_into().unwrap())
+            })
+        }
+
+        fn i64(&self, slot: usize) -> i64 {
+            self.field(slot).map_or(0, |at| {
+                assert_eq!(at % 8, 0);
+                i64_at(self.bytes, at)
+            })
+        }
+
+        fn indirect(&self, slot: usize) -> usize {
+            let at = self.field(slot).expect("missing field");
+            let target = at + u32_at(self.bytes, at);
+            assert_eq!(target % 4, 0);
+            target
+        }
+
+        fn child(&self, slot: usize) -> Reader<'a> {
+            Reader::table(self.bytes, self.indirect(slot))
+        }
+
+        fn string(&self, slot: usize) -> &'a str {
+            let at = self.indirect(slot);
+            let len = u32_at(self.bytes, at);
+            assert_eq!(self.bytes[at + 4 + len], 0, "string isn't null terminated");
+            std::str::from_utf8(&self.bytes[at + 4..at + 4 + len]).unwrap()
+        }
+
+        /// Where a vector's elements start, and how many there are
+        fn vector(&self, slot: usize) -> (usize, usize) {
+            let at = self.indirect(slot);
+            (at + 4, u32_at(self.bytes, at))
+        }
+
+        fn children(&self, slot: usize) -> Vec<Reader<'a>> {
+            let (start, n) = self.vector(slot);
+            (0..n)
+                .map(|i| {
+                    let element = start + 4 * i;
+                    Reader::table(self.bytes, element + u32_at(self.bytes, element))
+                })
+                .collect()
+        }
+
+        /// A vector of structs of 64 bit numbers, which have to be 8 byte aligned
+        fn structs(&self, slot: usize, n_numbers: usize) -> Vec<Vec<i64>> {
+            let (start, n) = self.vector(slot);
+            assert_eq!(start % 8, 0, "structs at {} aren't 8 byte aligned", start);
+            (0..n)
+                .map(|i| {
+                    let at = start + 8 * n_numbers * i;
+                    (0..n_numbers)
+                        .map(|j| i64_at(self.bytes, at + 8 * j))
+                        .collect()
+                })
+                .collect()
+        }
+    }
+
+    /// The name and Type union member of each field of a schema
+    fn schema_fields(schema: &Reader) -> Vec<(String, u8)> {
+        assert_eq!(schema.i16(0), 0, "not little endian");
+        schema
+            .children(1)
+            .iter()
+            .map(|field| {
+                let arrow_type = field.child(3);
+                match field.u8(2) {
+                    TYPE_INT => {
+                        assert_eq!(arrow_type.i32(0), 64);
+                        assert_eq!(arrow_type.u8(1), 1, "not signed");
+                    }
+                    TYPE_FLOATING_POINT => assert_eq!(arrow_type.i16(0), DOUBLE_PRECISION),
+                    TYPE_TIMESTAMP => assert_eq!(arrow_type.i16(0), SECONDS),
+                    TYPE_UTF8 | TYPE_BOOL => {}
+                    other => panic!("unexpected type {}", other),
+                }
+                assert_eq!(field.u8(1), 1, "not nullable");
+                assert_eq!(field.vector(5).1, 0, "has children");
+                (field.string(0).to_string(), field.u8(2))
+            })
+            .collect()
+    }
+
+    fn bit(bytes: &[u8], i: usize) -> bool {
+        bytes[i / 8] & (1 << (i % 8)) != 0
+    }
+
+    fn column(arrow_type: u8, n_rows: usize, buffers: &[&[u8]]) -> Column {
+        let present = |i: usize| bit(buffers[0], i);
+        let numbers = |i: usize| i64_at(buffers[1], 8 * i);
+        match arrow_type {
+            TYPE_UTF8 => {
+                let offset = |i: usize| u32_at(buffers[1], 4 * i);
+                assert_eq!(offset(0), 0);
+                assert_eq!(offset(n_rows), buffers[2].len());
+                Column::Utf8(
+                    (0..n_rows)
+                        .map(|i| {
+                            let value = &buffers[2][offset(i)..offset(i + 1)];
+                            present(i).then(|| String::from_utf8(value.to_vec()).unwrap())
+                        })
+                        .collect(),
+                )
+            }
+            TYPE_INT => Column::Int64(
+                (0..n_rows)
+                    .map(|i| present(i).then(|| numbers(i)))
+                    .collect(),
+            ),
+            TYPE_TIMESTAMP => Column::Timestamp(
+                (0..n_rows)
+                    .map(|i| present(i).then(|| numbers(i)))
+                    .collect(),
+            ),
+            TYPE_FLOATING_POINT => Column::Float64(
+                (0..n_rows)
+                    .map(|i| present(i).then(|| f64::from_bits(numbers(i) as u64)))
+                    .collect(),
+            ),
+            TYPE_BOOL => Column::Bool(
+                (0..n_rows)
+                    .map(|i| present(i).then(|| bit(buffers[1], i)))
+                    .collect(),
+            ),
+            other => panic!("unexpected type {}", other),
+        }
+    }
+
+    /// Reads a file back, checking its framing and alignment as it goes
+    fn decode(file: &[u8]) -> Vec<(String, Column)> {
+        assert_eq!(&file[..8], b"ARROW1\0\0");
+        assert_eq!(&file[file.len() - 6..], MAGIC);
+
+        let mut at = 8;
+        let mut fields = vec![];
+        let mut columns = vec![];
+        let mut batches = vec![];
+        loop {
+            assert_eq!(at % 8, 0, "message at {} isn't 8 byte aligned", at);
+            assert_eq!(u32_at(file, at), CONTINUATION as usize);
+            let metadata_len = u32_at(file, at + 4);
+            if metadata_len == 0 {
+                at += 8;
+                break;
+            }
+            assert_eq!(metadata_len % 8, 0, "metadata isn't padded to 8 bytes");
+            let metadata = &file[at + 8..at + 8 + metadata_len];
+            let message = Reader::root(metadata);
+            assert_eq!(message.i16(0), METADATA_VERSION);
+            let body_len = message.i64(3) as usize;
+            let body = &file[at + 8 + metadata_len..at + 8 + metadata_len + body_len];
+            match message.u8(1) {
+                HEADER_SCHEMA => {
+                    assert_eq!(body_len, 0);
+                    fields = schema_fields(&message.child(2));
+                }
+                HEADER_RECORD_BATCH => {
+                    batches.push(vec![at as i64, (8 + metadata_len) as i64, body_len as i64]);
+                    let batch = message.child(2);
+                    let n_rows = batch.i64(0) as usize;
+                    let nodes = batch.structs(1, 2);
+                    let buffers = batch.structs(2, 2);
+                    assert_eq!(nodes.len(), fields.len());
+                    let mut buffers = buffers.iter().map(|buffer| {
+                        let (offset, len) = (buffer[0] as usize, buffer[1] as usize);
+                        assert_eq!(offset % 8, 0, "buffer at {} isn't 8 byte aligned", offset);
+                        assert!(offset + len <= body_len, "buffer past the end of the body");
+                        &body[offset..offset + len]
+                    });
+                    for ((name, arrow_type), node) in fields.iter().zip(nodes) {
+                        assert_eq!(node[0] as usize, n_rows);
+                        let n_buffers = if *arrow_type == TYPE_UTF8 { 3 } else { 2 };
+                        let column_buffers: Vec<&[u8]> = buffers.by_ref().take(n_buffers).collect();
+                        let column = column(*arrow_type, n_rows, &column_buffers);
+                        let (_, n_missing) = column.validity();
+                        assert_eq!(node[1] as usize, n_missing);
+                        columns.push((name.clone(), column));
+                    }
+                    assert!(buffers.next().is_none(), "buffers left over");
+                }
+                other => panic!("unexpected message {}", other),
+            }
+            at += 8 + metadata_len + body_len;
+        }
+
+        let footer_len = u32_at(file, file.len() - 10);
+        assert_eq!(
+            at + footer_len + 10,
+            file.len(),
+            "footer doesn't follow the stream"
+        );
+        let footer = Reader::root(&file[at..at + footer_len]);
+        assert_eq!(footer.i16(0), METADATA_VERSION);
+        assert_eq!(schema_fields(&footer.child(1)), fields);
+        assert_eq!(footer.structs(2, 3).len(), 0);
+        let blocks: Vec<Vec<i64>> = footer
+            .structs(3, 3)
+            .into_iter()
+            // The metadata length is an i32 followed by padding
+            .map(|block| vec![block[0], block[1] & 0xffff_ffff, block[2]])
+            .collect();
+        assert_eq!(blocks, batches);
+        columns
+    }
+
+    #[test]
+    fn every_type_reads_back() {
+        let columns = vec![
+            (
+                "path",
+                Column::Utf8(vec![
+                    Some("/music/Café.flac".to_string()),
+                    None,
+                    Some(String::new()),
+                    Some("a".to_string()),
+                ]),
+            ),
+            (
+                "size",
+                Column::Int64(vec![Some(1), Some(-2), None, Some(i64::MAX)]),
+            ),
+            (
+                "loudness",
+                Column::Float64(vec![None, Some(-8.5), Some(0.0), Some(1e-3)]),
+            ),
+            (
+                "is_stem",
+                Column::Bool(vec![Some(true), Some(false), None, Some(true)]),
+            ),
+            (
+                "modified",
+                Column::Timestamp(vec![Some(1_700_000_000), None, None, Some(0)]),
+            ),
+        ];
+        let decoded = decode(&to_bytes(&columns));
+        let expected: Vec<(String, Column)> = columns
+            .into_iter()
+            .map(|(name, column)| (name.to_string(), column))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn bitmaps_span_bytes() {
+        let values: Vec<Option<bool>> = (0..19)
+            .map(|i| (i % 3 != 0).then_some(i % 2 == 0))
+            .collect();
+        let columns = vec![("flag", Column::Bool(values.clone()))];
+        assert_eq!(
+            decode(&to_bytes(&columns)),
+            vec![("flag".to_string(), Column::Bool(values))]
+        );
+    }
+
+    #[test]
+    fn no_rows() {
+        let columns = vec![
+            ("title", Column::Utf8(vec![])),
+            ("bpm", Column::Float64(vec![])),
+        ];
+        assert_eq!(
+            decode(&to_bytes(&columns)),
+            vec![
+                ("title".to_string(), Column::Utf8(vec![])),
+                ("bpm".to_string(), Column::Float64(vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn framing() {
+        let file = to_bytes(&[("n", Column::Int64(vec![Some(7)]))]);
+        // Magic padded to 8 bytes, then the schema message
+        assert_eq!(&file[..12], b"ARROW1\0\0\xff\xff\xff\xff");
+        // The file ends with the end of stream marker, the footer, its length and the magic
+        let footer_len = u32_at(&file, file.len() - 10);
+        let eos = file.len() - 10 - footer_len - 8;
+        assert_eq!(&file[eos..eos + 8], b"\xff\xff\xff\xff\0\0\0\0");
+    }
+}
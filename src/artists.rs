@@ -0,0 +1,89 @@
+/// Phrases that only ever join several artists into one artist field, checked
+/// case-insensitively. Surrounding spaces are part of the phrase so names like "Xavier" or "Vast"
+/// aren't split.
+const ARTIST_DELIMITERS: [&str; 9] = [
+    " featuring ",
+    " feat. ",
+    " feat ",
+    " ft. ",
+    " ft ",
+    " vs. ",
+    " vs ",
+    " x ",
+    ";",
+];
+
+/// Phrases that join several artists, but are also part of single acts' names like "AC/DC" or
+/// "Earth, Wind & Fire", so they are only split on when asked to
+const LOOSE_ARTIST_DELIMITERS: [&str; 3] = [" & ", ",", "/"];
+
+/// Splits an artist field like "A feat. B; C x D" into the individual artists. With `loose`, "&",
+/// "," and "/" split it too.
+pub fn split_artists(artist: &str, loose: bool) -> Vec<String> {
+    let loose_delimiters: &[&str] = if loose { &LOOSE_ARTIST_DELIMITERS } else { &[] };
+    let mut artists = vec![artist.to_string()];
+    for delimiter in ARTIST_DELIMITERS.iter().chain(loose_delimiters) {
+        artists = artists
+            .iter()
+            .flat_map(|a| split_case_insensitive(a, delimiter))
+            .collect();
+    }
+    artists
+        .into_iter()
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+fn split_case_insensitive(s: &str, delimiter: &str) -> Vec<String> {
+    // Lowercasing ASCII keeps byte offsets the same, so they can be used on the original
+    let lower = s.to_ascii_lowercase();
+    let mut parts = vec![];
+    let mut start = 0;
+    while let Some(i) = lower[start..].find(delimiter) {
+        parts.push(s[start..start + i].to_string());
+        start += i + delimiter.len();
+    }
+    parts.push(s[start..].to_string());
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collaboration_markers() {
+        assert_eq!(
+            split_artists("A feat. B; C x D", false),
+            vec!["A", "B", "C", "D"]
+        );
+        assert_eq!(split_artists("A FT B vs. C", false), vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn names_containing_markers() {
+        assert_eq!(split_artists("Xavier", false), vec!["Xavier"]);
+        assert_eq!(split_artists("Vast Aire", false), vec!["Vast Aire"]);
+        assert_eq!(split_artists("Daft Punk", false), vec!["Daft Punk"]);
+    }
+
+    #[test]
+    fn single_acts_kept_whole() {
+        assert_eq!(split_artists("AC/DC", false), vec!["AC/DC"]);
+        assert_eq!(
+            split_artists("Earth, Wind & Fire", false),
+            vec!["Earth, Wind & Fire"]
+        );
+        assert_eq!(
+            split_artists("Earth, Wind & Fire feat. The Emotions", false),
+            vec!["Earth, Wind & Fire", "The Emotions"]
+        );
+    }
+
+    #[test]
+    fn loose_splits_ampersands_commas_and_slashes() {
+        assert_eq!(split_artists("A & B, C/D", true), vec!["A", "B", "C", "D"]);
+        assert_eq!(split_artists("AC/DC", true), vec!["AC", "DC"]);
+    }
+}
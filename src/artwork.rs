@@ -0,0 +1,286 @@
+use crate::read_only;
+use crate::song_info::SongInfo;
+use crate::throttle::ReadThrottle;
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// How embedded artwork is processed during conversion
+#[derive(Clone, Debug)]
+pub struct ArtworkOptions {
+    /// Largest width or height in pixels. CDJs choke on large artwork, 800 is safe
+    pub max_size: u32,
+    /// JPEG quality from 1 (worst) to 100 (best)
+    pub quality: u8,
+    /// Directory to save the original artwork in before it is resized
+    pub archive_dir: Option<PathBuf>,
+}
+
+impl Default for ArtworkOptions {
+    fn default() -> Self {
+        ArtworkOptions {
+            max_size: 800,
+            quality: 90,
+            archive_dir: None,
+        }
+    }
+}
+
+impl ArtworkOptions {
+    /// ffmpeg output options that re-encode the artwork stream as a JPEG no larger than max_size,
+    /// keeping its aspect ratio and never scaling it up
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        // mjpeg's qscale goes from 2 (best) to 31 (worst)
+        let qscale = 31 - (u32::from(self.quality.clamp(1, 100)) - 1) * 29 / 99;
+        vec![
+            "-c:v".to_string(),
+            "mjpeg".to_string(),
+            "-q:v".to_string(),
+            qscale.to_string(),
+            "-vf".to_string(),
+            format!(
+                "scale='min({0},iw)':'min({0},ih)':force_original_aspect_ratio=decrease",
+                self.max_size
+            ),
+            "-disposition:v".to_string(),
+            "attached_pic".to_string(),
+        ]
+    }
+
+    /// Copies a song's original artwork, untouched, into the archive directory
+    pub fn archive(&self, song: &SongInfo, song_name: &str) -> Result<()> {
+        let (archive_dir, codec) = match (&self.archive_dir, song.get_artwork_codec()) {
+            (Some(dir), Some(codec)) => (dir, codec),
+            _ => return Ok(()),
+        };
+        let extension = match codec {
+            "mjpeg" => "jpg",
+            other => other,
+        };
+        read_only::check_writable(archive_dir)?;
+        fs::create_dir_all(archive_dir)?;
+        let output = Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(song.get_song_path())
+            .arg("-map")
+            .arg("0:v:0")
+            .arg("-c")
+            .arg("copy")
+            .arg("-f")
+            .arg("image2")
+            .arg(archive_dir.join(format!("{}.{}", song_name, extension)))
+            .output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Could not archive artwork of {:?}: {}",
+                song.get_song_path(),
+                String::from_utf8_lossy(&output.stderr)
+            ))
+        }
+    }
+}
+
+/// File names, without extension, that folder artwork goes by, in order of preference
+const FOLDER_IMAGE_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+
+/// Finds a cover image such as cover.jpg or folder.png next to a song
+pub fn folder_image(song: &SongInfo) -> Option<PathBuf> {
+    let dir = song.get_song_path().parent()?;
+    let images: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .and_then(|e| e.to_str())
+                .map(|e| ["jpg", "jpeg", "png"].contains(&e.to_lowercase().as_str()))
+                .unwrap_or(false)
+        })
+        .collect();
+    FOLDER_IMAGE_NAMES.iter().find_map(|name| {
+        images
+            .iter()
+            .find(|p| {
+                p.file_stem()
+                    .and_then(|s| s.to_str())
+                    .map(|s| s.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+            })
+            .cloned()
+    })
+}
+
+/// Largest number of differing bits for two perceptual hashes to count as the same artwork
+const MAX_HASH_DISTANCE: u32 = 4;
+
+/// Computes an average hash of a song's artwork: the artwork is shrunk to 8x8 grayscale and
+/// each bit says whether a pixel is brighter than the mean. Re-encoded or slightly resized
+/// copies of the same cover end up with the same, or a very close, hash.
+pub fn perceptual_hash(song: &SongInfo) -> Result<u64> {
+    let output = Command::new("ffmpeg")
+        .arg("-i")
+        .arg(song.get_song_path())
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-vf")
+        .arg("scale=8:8:flags=area,format=gray")
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-f")
+        .arg("rawvideo")
+        .arg("-")
+        .output()?;
+    if output.stdout.len() != 64 {
+        return Err(anyhow!(
+            "Could not hash artwork of {:?}",
+            song.get_song_path()
+        ));
+    }
+    let mean = output.stdout.iter().map(|p| u32::from(*p)).sum::<u32>() / 64;
+    Ok(output
+        .stdout
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| u32::from(**p) > mean)
+        .fold(0u64, |hash, (i, _)| hash | (1 << i)))
+}
+
+/// The release a song is on, by its album and album artist (or artist), lowercased. Songs
+/// without an album aren't on a release.
+fn release(song: &SongInfo) -> Option<String> {
+    let album = song.get_tag("album")?;
+    let artist = song
+        .get_tag("album_artist")
+        .or_else(|| song.get_tag("albumartist"))
+        .or_else(|| song.get_tag("artist"))
+        .unwrap_or_default();
+    Some(format!("{}\u{0}{}", artist.trim(), album.trim()).to_lowercase())
+}
+
+/// An optimized image and the artwork it was made from
+struct CachedImage {
+    release: Option<String>,
+    hash: u64,
+    path: PathBuf,
+}
+
+/// Optimized artwork shared between the songs of a release, so all its tracks are processed
+/// once and show the exact same cover on the player. Artwork is only shared within a release,
+/// since covers made from one label template hash alike.
+#[derive(Default)]
+pub struct ArtworkCache {
+    images: Mutex<Vec<CachedImage>>,
+    /// Artwork hashes by source file, known from earlier runs or hashed in this one
+    hashes: Mutex<BTreeMap<PathBuf, u64>>,
+}
+
+impl ArtworkCache {
+    pub fn new() -> Self {
+        ArtworkCache::default()
+    }
+
+    /// Remembers the artwork hashes of source files that haven't changed since they were hashed,
+    /// so they aren't hashed again
+    pub fn remember_hashes(&self, hashes: impl IntoIterator<Item = (PathBuf, u64)>) {
+        self.hashes.lock().unwrap().extend(hashes);
+    }
+
+    /// The artwork hashes of every source file known, to keep for later runs
+    pub fn hashes(&self) -> BTreeMap<PathBuf, u64> {
+        self.hashes.lock().unwrap().clone()
+    }
+
+    fn hash(&self, song: &SongInfo, throttle: Option<&ReadThrottle>) -> Result<u64> {
+        let known = self
+            .hashes
+            .lock()
+            .unwrap()
+            .get(song.get_song_path())
+            .copied();
+        match known {
+            Some(hash) => Ok(hash),
+            None => {
+                let hash = {
+                    let _slot = throttle.map(ReadThrottle::slot);
+                    perceptual_hash(song)?
+                };
+                self.hashes
+                    .lock()
+                    .unwrap()
+                    .insert(song.get_song_path().clone(), hash);
+                Ok(hash)
+            }
+        }
+    }
+
+    /// Returns the optimized image for a song's artwork, creating it if no song of the same
+    /// release with the same artwork has been converted yet. The song is read through the read
+    /// throttle if there is one.
+    pub fn optimized(
+        &self,
+        song: &SongInfo,
+        options: &ArtworkOptions,
+        throttle: Option<&ReadThrottle>,
+    ) -> Result<PathBuf> {
+        let hash = self.hash(song, throttle)?;
+        let release = release(song);
+        // Holding the lock while the image is created keeps two songs of the same release from
+        // both creating it
+        let mut images = self.images.lock().unwrap();
+        if let Some(image) = images.iter().find(|image| {
+            image.release.is_some()
+                && image.release == release
+                && (image.hash ^ hash).count_ones() <= MAX_HASH_DISTANCE
+        }) {
+            tracing::debug!(song = ?song.get_song_path(), path = ?image.path, "Reusing optimized artwork");
+            return Ok(image.path.clone());
+        }
+        let path = std::env::temp_dir().join(format!(
+            "rekordbox-artwork-{}-{}-{:016x}.jpg",
+            std::process::id(),
+            images.len(),
+            hash
+        ));
+        let slot = throttle.map(ReadThrottle::slot);
+        let output = Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(song.get_song_path())
+            .arg("-map")
+            .arg("0:v:0")
+            .args(options.ffmpeg_args())
+            .arg("-frames:v")
+            .arg("1")
+            .arg("-f")
+            .arg("image2")
+            .arg(&path)
+            .output()?;
+        drop(slot);
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Could not optimize artwork of {:?}: {}",
+                song.get_song_path(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        images.push(CachedImage {
+            release,
+            hash,
+            path: path.clone(),
+        });
+        Ok(path)
+    }
+}
+
+impl Drop for ArtworkCache {
+    fn drop(&mut self) {
+        for image in self.images.lock().unwrap().iter() {
+            let _ = fs::remove_file(&image.path);
+        }
+    }
+}
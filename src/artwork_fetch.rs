@@ -0,0 +1,222 @@
+use crate::song_info::SongInfo;
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where fetched artwork came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArtworkProvider {
+    CoverArtArchive,
+    ITunes,
+}
+
+impl std::fmt::Display for ArtworkProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArtworkProvider::CoverArtArchive => write!(f, "coverartarchive"),
+            ArtworkProvider::ITunes => write!(f, "itunes"),
+        }
+    }
+}
+
+/// Artwork downloaded for a song, waiting to be embedded
+#[derive(Clone, Debug)]
+pub struct FetchedArtwork {
+    pub path: PathBuf,
+    pub provider: ArtworkProvider,
+    pub url: String,
+}
+
+impl FetchedArtwork {
+    /// Tag value recording where the artwork came from
+    pub fn provenance(&self) -> String {
+        format!("{}:{}", self.provider, self.url)
+    }
+}
+
+impl Drop for FetchedArtwork {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Helper struct that represents an iTunes Search API response
+#[derive(Clone, Debug, Deserialize)]
+struct ITunesResponse {
+    results: Vec<ITunesResult>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ITunesResult {
+    artwork_url100: Option<String>,
+    artist_name: Option<String>,
+    collection_name: Option<String>,
+    track_name: Option<String>,
+}
+
+/// Results of an iTunes search looked through for one that matches the song's tags
+const ITUNES_RESULTS: usize = 10;
+
+/// Lower case letters and digits of a name, so "Daft Punk" matches "DAFT PUNK" or "Daft-Punk"
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Whether a name from a search result is the one in a tag, or one of them holds the other, as
+/// "Discovery" and "Discovery (Remastered)" do
+fn names_match(tag: &str, result: Option<&str>) -> bool {
+    let (tag, result) = (normalize(tag), normalize(result.unwrap_or_default()));
+    !tag.is_empty() && !result.is_empty() && (tag.contains(&result) || result.contains(&tag))
+}
+
+/// Fetches artwork for songs without any, trying the Cover Art Archive first and then the iTunes
+/// Search API. Requests are spaced out so the providers' rate limits are respected even when many
+/// songs are converted in parallel.
+pub struct ArtworkFetcher {
+    min_interval: Duration,
+    last_request: Mutex<Option<Instant>>,
+    /// Number of images downloaded, which keeps their temporary files apart
+    n_downloaded: AtomicUsize,
+}
+
+impl ArtworkFetcher {
+    pub fn new(min_interval: Duration) -> Self {
+        ArtworkFetcher {
+            min_interval,
+            last_request: Mutex::new(None),
+            n_downloaded: AtomicUsize::new(0),
+        }
+    }
+
+    /// Downloads artwork for a song, or returns None if no provider has any
+    pub fn fetch(&self, song: &SongInfo) -> Result<Option<FetchedArtwork>> {
+        if let Some(release_id) = song
+            .get_tag("MUSICBRAINZ_ALBUMID")
+            .or_else(|| song.get_tag("MusicBrainz Album Id"))
+        {
+            let url = format!(
+                "https://coverartarchive.org/release/{}/front-500",
+                release_id
+            );
+            if let Some(artwork) = self.download(song, &url, ArtworkProvider::CoverArtArchive)? {
+                return Ok(Some(artwork));
+            }
+        }
+        let artist = song.get_tag("artist");
+        let (term, entity) = match (song.get_tag("album"), song.get_tag("title")) {
+            (Some(album), _) => (album, "album"),
+            (None, Some(title)) => (title, "song"),
+            (None, None) => return Ok(None),
+        };
+        let query = match artist {
+            Some(artist) => format!("{} {}", artist, term),
+            None => term.to_string(),
+        };
+        let search_url = format!(
+            "https://itunes.apple.com/search?term={}&entity={}&limit={}",
+            percent_encode(&query),
+            entity,
+            ITUNES_RESULTS
+        );
+        let response = self.get(&search_url)?;
+        let response: ITunesResponse = match response {
+            Some(body) => serde_json::from_slice(&body)?,
+            None => return Ok(None),
+        };
+        // Searches return whatever is closest, so only a result with the song's artist and album
+        // or title is taken
+        let matching = response.results.into_iter().find(|r| {
+            let name = match entity {
+                "album" => r.collection_name.as_deref(),
+                _ => r.track_name.as_deref(),
+            };
+            names_match(term, name)
+                && artist.is_none_or(|artist| names_match(artist, r.artist_name.as_deref()))
+        });
+        match matching.and_then(|r| r.artwork_url100) {
+            // The URL asks for a 100x100 thumbnail, but larger sizes are served too
+            Some(url) => self.download(
+                song,
+                &url.replace("100x100bb", "600x600bb"),
+                ArtworkProvider::ITunes,
+            ),
+            None => Ok(None),
+        }
+    }
+
+    /// Waits until enough time has passed since the last request
+    fn wait_turn(&self) {
+        let mut last_request = self.last_request.lock().unwrap();
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval {
+                thread::sleep(self.min_interval - elapsed);
+            }
+        }
+        *last_request = Some(Instant::now());
+    }
+
+    /// Fetches a URL with curl, returning None if the server has nothing there
+    fn get(&self, url: &str) -> Result<Option<Vec<u8>>> {
+        self.wait_turn();
+        let output = Command::new("curl")
+            .arg("-sfL")
+            .arg("--max-time")
+            .arg("30")
+            .arg(url)
+            .output()?;
+        if output.status.success() {
+            Ok(Some(output.stdout))
+        } else {
+            tracing::debug!(?url, status = ?output.status, "No artwork found");
+            Ok(None)
+        }
+    }
+
+    fn download(
+        &self,
+        song: &SongInfo,
+        url: &str,
+        provider: ArtworkProvider,
+    ) -> Result<Option<FetchedArtwork>> {
+        let image = match self.get(url)? {
+            Some(image) if !image.is_empty() => image,
+            _ => return Ok(None),
+        };
+        // Songs of the same name from different folders can be fetched at the same time
+        let path = std::env::temp_dir().join(format!(
+            "rekordbox-fetched-artwork-{}-{}.img",
+            std::process::id(),
+            self.n_downloaded.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, image)?;
+        tracing::info!(song = ?song.get_song_path(), %provider, ?url, "Fetched artwork");
+        Ok(Some(FetchedArtwork {
+            path,
+            provider,
+            url: url.to_string(),
+        }))
+    }
+}
+
+/// Percent-encodes a query string value
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
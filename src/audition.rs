@@ -0,0 +1,85 @@
+use crate::song_info;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small xorshift generator, good enough for picking excerpts to listen to
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        // The state must never be zero
+        Xorshift(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Random number in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The most recently modified files in a directory, newest first
+pub fn recent_files(dir: &Path, count: usize) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    crate::converter::build_list_of_files(dir, &mut files)?;
+    let mut files: Vec<(SystemTime, PathBuf)> = files
+        .into_iter()
+        .filter_map(|f| {
+            let modified = fs::metadata(&f).and_then(|m| m.modified()).ok()?;
+            Some((modified, f))
+        })
+        .collect();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok(files.into_iter().take(count).map(|(_, f)| f).collect())
+}
+
+/// Plays an excerpt of a song through the default audio device with ffplay
+pub fn play_excerpt(path: &Path, start: f64, seconds: f64) -> Result<()> {
+    let status = Command::new("ffplay")
+        .arg("-nodisp")
+        .arg("-autoexit")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-ss")
+        .arg(format!("{:.3}", start))
+        .arg("-t")
+        .arg(format!("{:.3}", seconds))
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("ffplay could not play {:?}", path));
+    }
+    Ok(())
+}
+
+/// Plays an excerpt of the given length from a random point in each song
+pub fn audition(paths: &[PathBuf], seconds: f64) -> Result<()> {
+    let mut rng = Xorshift::from_time();
+    for path in paths {
+        let song = match song_info::from_file(path) {
+            Ok(song) => song,
+            Err(e) => {
+                tracing::warn!(?path, ?e, "Skipping file that is not a song");
+                continue;
+            }
+        };
+        let latest_start = (song.get_duration() - seconds).max(0.0);
+        let start = rng.next_f64() * latest_start;
+        println!("{} @ {:.0}s", path.display(), start);
+        play_excerpt(path, start, seconds)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use crate::bpm_octave::REVIEW_FILE_NAME;
+use crate::key;
+use crate::manifest::MANIFEST_FILE_NAME;
+use crate::read_only;
+use crate::rekordbox_xml::XML_FILE_NAME;
+use crate::speed;
+use crate::state::{JOURNAL_FILE_NAME, STATE_FILE_NAME};
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Files kept in an output directory that aren't audio and can't be recreated from it
+pub const ARTIFACTS: [&str; 7] = [
+    STATE_FILE_NAME,
+    JOURNAL_FILE_NAME,
+    XML_FILE_NAME,
+    MANIFEST_FILE_NAME,
+    REVIEW_FILE_NAME,
+    key::REVIEW_FILE_NAME,
+    speed::REVIEW_FILE_NAME,
+];
+
+/// Formats a time as a UTC timestamp like 20240131-235959, for file names
+pub fn timestamp(time: SystemTime) -> String {
+    let (year, month, day, secs_of_day) = civil_time(time);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Formats the UTC date of a time like 2024-01-31
+pub fn date(time: SystemTime) -> String {
+    let (year, month, day, _) = civil_time(time);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The UTC year, month, day and seconds into the day of a time
+fn civil_time(time: SystemTime) -> (i64, i64, i64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant's days_from_civil inverse
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, secs_of_day)
+}
+
+/// Archives the artifacts of an output directory into a timestamped tarball in backup_dir
+pub fn backup(output_dir: &Path, backup_dir: &Path) -> Result<PathBuf> {
+    let present: Vec<&str> = ARTIFACTS
+        .iter()
+        .copied()
+        .filter(|name| output_dir.join(name).exists())
+        .collect();
+    if present.is_empty() {
+        return Err(anyhow!("Nothing to back up in {}", output_dir.display()));
+    }
+    read_only::check_writable(backup_dir)?;
+    std::fs::create_dir_all(backup_dir)?;
+    let archive = backup_dir.join(format!(
+        "rekordbox-backup-{}.tar.gz",
+        timestamp(SystemTime::now())
+    ));
+    let output = Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(output_dir)
+        .args(&present)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "tar could not create {:?}: {}",
+            archive,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    tracing::info!(?archive, files = ?present, "Backed up artifacts");
+    Ok(archive)
+}
+
+/// Extracts a backup made by `backup` into an output directory, replacing its artifacts
+pub fn restore(archive: &Path, output_dir: &Path) -> Result<()> {
+    read_only::check_writable(output_dir)?;
+    std::fs::create_dir_all(output_dir)?;
+    let output = Command::new("tar")
+        .arg("-xzf")
+        .arg(archive)
+        .arg("-C")
+        .arg(output_dir)
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "tar could not extract {:?}: {}",
+            archive,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    tracing::info!(?archive, ?output_dir, "Restored artifacts");
+    Ok(())
+}
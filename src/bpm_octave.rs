@@ -0,0 +1,241 @@
+use crate::exporters::TrackRecord;
+use crate::song_info::SongInfo;
+use crate::state;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File in the output directory listing what the BPM of each analyzed song was resolved to, where
+/// a BPM can be overridden
+pub const REVIEW_FILE_NAME: &str = "bpm-review.json";
+
+/// Onsets a beat above which a slow tempo is taken to be half the real one, as a track with
+/// that much going on every beat is at double the tempo, and below which a fast tempo is taken
+/// to be double the real one
+const DENSE_ONSETS_PER_BEAT: f64 = 3.0;
+const SPARSE_ONSETS_PER_BEAT: f64 = 1.0;
+/// Tempos below and above which onset density is looked at
+const SLOW_BPM: f64 = 90.0;
+const FAST_BPM: f64 = 150.0;
+
+/// Genres whose tempo is known well enough to pick between half and double time, matched as
+/// parts of the genre tag, in order
+const GENRE_RANGES: [(&str, f64, f64); 14] = [
+    ("drum and bass", 160.0, 185.0),
+    ("drum & bass", 160.0, 185.0),
+    ("drum n bass", 160.0, 185.0),
+    ("dnb", 160.0, 185.0),
+    ("jungle", 155.0, 180.0),
+    ("footwork", 150.0, 170.0),
+    ("dubstep", 135.0, 150.0),
+    ("grime", 135.0, 150.0),
+    ("hip hop", 75.0, 115.0),
+    ("hip-hop", 75.0, 115.0),
+    ("reggaeton", 85.0, 110.0),
+    ("house", 115.0, 130.0),
+    ("trance", 125.0, 145.0),
+    ("techno", 120.0, 150.0),
+];
+
+/// A tempo range for the songs of a genre or artist, declared in a BPM rules file
+#[derive(Clone, Debug, Deserialize)]
+pub struct BpmRule {
+    /// Part of the genre tag, in any case
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// Part of the artist tag, in any case
+    #[serde(default)]
+    pub artist: Option<String>,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl BpmRule {
+    fn matches(&self, song: &SongInfo) -> bool {
+        let contains = |tag: &str, part: &Option<String>| match part {
+            Some(part) => song
+                .get_tag(tag)
+                .is_some_and(|value| value.to_lowercase().contains(&part.to_lowercase())),
+            None => true,
+        };
+        contains("genre", &self.genre) && contains("artist", &self.artist)
+    }
+}
+
+/// Reads the BPM rules declared in a JSON file, a list like
+/// [{"genre": "dubstep", "min": 135, "max": 150}], the first matching a song applying
+pub fn load_rules(path: &Path) -> Result<Vec<BpmRule>> {
+    let contents = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let rules: Vec<BpmRule> = serde_json::from_slice(&contents)
+        .with_context(|| format!("{} isn't a list of BPM rules", path.display()))?;
+    for rule in rules.iter() {
+        if rule.genre.is_none() && rule.artist.is_none() {
+            return Err(anyhow!("BPM rules need a genre or an artist to match"));
+        }
+        // A range of less than an octave can only fit one of half, single and double time
+        if rule.min <= 0.0 || rule.max < rule.min || rule.max >= 2.0 * rule.min {
+            return Err(anyhow!(
+                "The BPM range {}-{} must be positive and less than an octave wide",
+                rule.min,
+                rule.max
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+/// What took a song's BPM to half or double the detected one, if anything
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Nothing said otherwise, so the detected BPM stands
+    Detected,
+    /// A rule from the BPM rules file
+    Rule,
+    /// The tempo its genre is usually in
+    Genre,
+    /// How many onsets it has a beat
+    OnsetDensity,
+}
+
+/// The BPM picked for a song, out of the detected one, half of it and double it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Resolution {
+    pub detected: f64,
+    pub bpm: f64,
+    pub reason: Reason,
+}
+
+/// Picks between half, single and double the detected BPM of a song: by the first rule matching
+/// it, else by the usual tempo of its genre, else by how busy its rhythm is
+pub fn resolve(
+    detected: f64,
+    song: &SongInfo,
+    onset_density: Option<f64>,
+    rules: &[BpmRule],
+) -> Resolution {
+    let in_range = |min: f64, max: f64| {
+        [detected, detected * 2.0, detected / 2.0]
+            .iter()
+            .copied()
+            .find(|bpm| (min..=max).contains(bpm))
+    };
+    let resolution = |bpm: f64, reason: Reason| Resolution {
+        detected,
+        bpm,
+        reason: if bpm == detected {
+            Reason::Detected
+        } else {
+            reason
+        },
+    };
+    if let Some(rule) = rules.iter().find(|rule| rule.matches(song)) {
+        if let Some(bpm) = in_range(rule.min, rule.max) {
+            return resolution(bpm, Reason::Rule);
+        }
+    }
+    let genre = song.get_tag("genre").unwrap_or_default().to_lowercase();
+    if let Some((_, min, max)) = GENRE_RANGES
+        .iter()
+        .find(|(part, _, _)| genre.contains(part))
+    {
+        if let Some(bpm) = in_range(*min, *max) {
+            return resolution(bpm, Reason::Genre);
+        }
+    }
+    if let Some(density) = onset_density {
+        let onsets_per_beat = density * 60.0 / detected;
+        if detected < SLOW_BPM && onsets_per_beat >= DENSE_ONSETS_PER_BEAT {
+            return resolution(detected * 2.0, Reason::OnsetDensity);
+        }
+        if detected > FAST_BPM && onsets_per_beat < SPARSE_ONSETS_PER_BEAT {
+            return resolution(detected / 2.0, Reason::OnsetDensity);
+        }
+    }
+    resolution(detected, Reason::Detected)
+}
+
+/// A song in the review file: what its BPM was resolved to, and the BPM to use instead, if set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    #[serde(flatten)]
+    pub resolution: Resolution,
+    #[serde(default, rename = "override", skip_serializing_if = "Option::is_none")]
+    pub override_bpm: Option<f64>,
+}
+
+/// The BPM each analyzed song of an output directory was resolved to, by source path. Setting
+/// "override" on a song fixes its BPM for later exports and conversions.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Review {
+    pub songs: BTreeMap<PathBuf, ReviewEntry>,
+}
+
+impl Review {
+    /// The review file of an output directory, empty if there is none yet
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(REVIEW_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Review::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("{} isn't a BPM review file", path.display()))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        state::write_atomic(
+            &output_dir.join(REVIEW_FILE_NAME),
+            &serde_json::to_vec_pretty(self)?,
+        )
+    }
+
+    /// The BPM a song was overridden to
+    pub fn override_for(&self, source: &Path) -> Option<f64> {
+        self.songs.get(source)?.override_bpm
+    }
+}
+
+/// Resolves the detected BPM of analyzed tracks to half or double time where the rules, their
+/// genre or their rhythm say so, or to the override in the output directory's review file, and
+/// records what each was resolved to in it
+pub fn disambiguate(
+    tracks: &mut [TrackRecord],
+    output_dir: &Path,
+    rules: &[BpmRule],
+) -> Result<()> {
+    let mut review = Review::load(output_dir)?;
+    let before = review.clone();
+    for track in tracks.iter_mut() {
+        let detected = match track.analysis.bpm {
+            Some(bpm) => bpm,
+            None => continue,
+        };
+        let override_bpm = review.override_for(&track.source);
+        let resolution = resolve(detected, &track.song, track.analysis.onset_density, rules);
+        track.analysis.bpm = Some(override_bpm.unwrap_or(resolution.bpm));
+        if resolution.reason != Reason::Detected {
+            tracing::info!(
+                path = ?track.path,
+                detected,
+                bpm = resolution.bpm,
+                reason = ?resolution.reason,
+                "Resolved the tempo"
+            );
+        }
+        review.songs.insert(
+            track.source.clone(),
+            ReviewEntry {
+                resolution,
+                override_bpm,
+            },
+        );
+    }
+    if review != before {
+        review.save(output_dir)?;
+    }
+    Ok(())
+}
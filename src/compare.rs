@@ -0,0 +1,184 @@
+use crate::loudness;
+use crate::read_only;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Encoder settings that can be compared against each other
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum EncoderProfile {
+    /// MP3 at a constant 320 kbps
+    #[value(name = "mp3-320")]
+    Mp3Cbr320,
+    /// MP3 at LAME's highest VBR quality, V0
+    #[value(name = "mp3-v0")]
+    Mp3V0,
+    /// AAC at 256 kbps
+    #[value(name = "aac-256")]
+    Aac256,
+    /// 16 bit AIFF
+    Aiff,
+}
+
+impl EncoderProfile {
+    fn extension(&self) -> &'static str {
+        match self {
+            EncoderProfile::Mp3Cbr320 | EncoderProfile::Mp3V0 => "mp3",
+            EncoderProfile::Aac256 => "m4a",
+            EncoderProfile::Aiff => "aiff",
+        }
+    }
+
+    fn ffmpeg_args(&self) -> &'static [&'static str] {
+        match self {
+            EncoderProfile::Mp3Cbr320 => &["-c:a", "libmp3lame", "-b:a", "320k"],
+            EncoderProfile::Mp3V0 => &["-c:a", "libmp3lame", "-q:a", "0"],
+            EncoderProfile::Aac256 => &["-c:a", "aac", "-b:a", "256k"],
+            EncoderProfile::Aiff => &["-c:a", "pcm_s16be"],
+        }
+    }
+
+    fn file_stem(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+}
+
+/// An encoding of the sample with one profile
+#[derive(Clone, Debug)]
+pub struct EncodedSample {
+    pub profile: EncoderProfile,
+    pub encoded: PathBuf,
+    /// Decoded copy with its loudness matched to the source
+    pub level_matched: PathBuf,
+    pub size: u64,
+}
+
+/// Results of comparing two encoder profiles on a sample
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub a: EncodedSample,
+    pub b: EncodedSample,
+    /// The level matched encodings subtracted from each other
+    pub difference: PathBuf,
+    /// RMS level of the difference in dBFS. The lower, the closer the encodings are
+    pub difference_rms_db: f64,
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for sample in [&self.a, &self.b] {
+            writeln!(
+                f,
+                "{:?}: {} ({:.1} MB)",
+                sample.profile,
+                sample.encoded.display(),
+                sample.size as f64 / 1e6
+            )?;
+        }
+        write!(
+            f,
+            "Difference: {} (RMS {:.1} dBFS)",
+            self.difference.display(),
+            self.difference_rms_db
+        )
+    }
+}
+
+fn run_ffmpeg(command: &mut Command) -> Result<String> {
+    let output = command.output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        return Err(anyhow!("ffmpeg failed: {}", stderr));
+    }
+    Ok(stderr)
+}
+
+/// Encodes the sample with a profile, then decodes it to a float WAV at the source's loudness
+fn encode(sample: &Path, profile: EncoderProfile, output_dir: &Path) -> Result<EncodedSample> {
+    let encoded = output_dir.join(format!("{}.{}", profile.file_stem(), profile.extension()));
+    run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(sample)
+            .arg("-map")
+            .arg("0:a:0")
+            .args(profile.ffmpeg_args())
+            .arg(&encoded),
+    )?;
+    let source_lufs = loudness::measure(sample, &[], -14.0)?.integrated;
+    let encoded_lufs = loudness::measure(&encoded, &[], -14.0)?.integrated;
+    let level_matched = output_dir.join(format!("{}-matched.wav", profile.file_stem()));
+    run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(&encoded)
+            .arg("-af")
+            .arg(format!("volume={:.3}dB", source_lufs - encoded_lufs))
+            .arg("-c:a")
+            .arg("pcm_f32le")
+            .arg(&level_matched),
+    )?;
+    Ok(EncodedSample {
+        profile,
+        size: fs::metadata(&encoded)?.len(),
+        encoded,
+        level_matched,
+    })
+}
+
+/// Encodes a sample with two profiles and subtracts the level matched results from each other,
+/// for judging how far apart two encoder settings are. Both encodings must be stereo.
+pub fn compare(
+    sample: &Path,
+    a: EncoderProfile,
+    b: EncoderProfile,
+    output_dir: &Path,
+) -> Result<Comparison> {
+    read_only::check_writable(output_dir)?;
+    fs::create_dir_all(output_dir)?;
+    let a = encode(sample, a, output_dir)?;
+    let b = encode(sample, b, output_dir)?;
+    let difference = output_dir.join("difference.wav");
+    run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(&a.level_matched)
+            .arg("-i")
+            .arg(&b.level_matched)
+            .arg("-filter_complex")
+            .arg("[0:a][1:a]amerge=inputs=2,pan=stereo|c0=c0-c2|c1=c1-c3")
+            .arg("-c:a")
+            .arg("pcm_f32le")
+            .arg(&difference),
+    )?;
+    let stats = run_ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-hide_banner")
+            .arg("-nostats")
+            .arg("-i")
+            .arg(&difference)
+            .arg("-af")
+            .arg("astats=measure_perchannel=none")
+            .arg("-f")
+            .arg("null")
+            .arg("-"),
+    )?;
+    // astats ends with the overall statistics
+    let difference_rms_db = stats
+        .lines()
+        .rev()
+        .find_map(|l| l.split("RMS level dB:").nth(1))
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .ok_or_else(|| anyhow!("astats printed no RMS level for {:?}", difference))?;
+    Ok(Comparison {
+        a,
+        b,
+        difference,
+        difference_rms_db,
+    })
+}
@@ -0,0 +1,51 @@
+use crate::i18n;
+use anyhow::{bail, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+/// Number of files an operation may delete or overwrite without asking first
+pub const THRESHOLD: usize = 50;
+
+/// Number of files listed when asking
+const MAX_LISTED: usize = 10;
+
+/// Asks on the terminal whether to go ahead with an operation, after printing a summary of it.
+/// Without a terminal to ask on, the operation is refused.
+pub fn confirm(summary: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!(
+            "{}\nNot asking without a terminal, pass --yes to go ahead",
+            summary
+        );
+    }
+    let mut stderr = io::stderr();
+    write!(
+        stderr,
+        "{}\n{} ",
+        summary,
+        i18n::message("confirm-continue", &[])
+    )?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(i18n::message("confirm-yes", &[])
+        .split(',')
+        .any(|yes| yes.trim() == answer))
+}
+
+/// Summary of files an operation would touch, listing the first few of them after a heading
+pub fn summarize(heading: &str, files: &[PathBuf]) -> String {
+    let mut summary = heading.to_string();
+    for file in files.iter().take(MAX_LISTED) {
+        summary.push_str(&format!("\n  {}", file.display()));
+    }
+    if files.len() > MAX_LISTED {
+        let more = i18n::message(
+            "confirm-more",
+            &[("count", (files.len() - MAX_LISTED).into())],
+        );
+        summary.push_str(&format!("\n  {}", more));
+    }
+    summary
+}
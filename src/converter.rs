@@ -0,0 +1,2037 @@
+use crate::analysis_cache::AnalysisCache;
+use crate::analyzers::{self, Analyzer, TrackAnalysis};
+use crate::anlz;
+use crate::archive::Archive;
+use crate::artists;
+use crate::artwork::{self, ArtworkCache, ArtworkOptions};
+use crate::artwork_fetch::ArtworkFetcher;
+use crate::bpm_octave::{self, BpmRule, Review};
+use crate::dates::{self, YearSource};
+use crate::device::{self, Filesystem, WhenFull};
+use crate::exporters;
+use crate::i18n;
+use crate::imported_analysis::{self, ImportedAnalysis, MikExport};
+use crate::key::{self, DetectedKey, Key};
+use crate::loudness::{self, LoudnessPreset};
+use crate::manifest::Manifest;
+use crate::naming;
+use crate::pdb::Collection;
+use crate::probe_cache::ProbeCache;
+use crate::processors::{Processor, Stage, Workspace};
+use crate::read_only;
+use crate::record_pool::PoolParser;
+use crate::rekordbox_playlist::PlaylistSelection;
+use crate::report::{self, Decision, FileReport};
+use crate::routing::{self, RouteBy, RouteMap};
+use crate::sanitize::NameSanitizer;
+use crate::scan_events::{self, ScanEvents};
+use crate::separation::{self, Part, SeparationTool};
+use crate::song_info::{self, AudioFormatType, ProbeBackend, SongInfo, SupportedAudioFormat};
+use crate::speed::{self, SpeedCorrection};
+use crate::state::{self, ConversionState, Journal};
+use crate::stems::{self, StemMode, StemOutput};
+use crate::tag_filter::TagFilter;
+use crate::tagging;
+use crate::targets::{self, LosslessTarget, LossyTarget};
+use crate::tempo::Tempo;
+use crate::throttle::ReadThrottle;
+use crate::usb_layout;
+use crate::variants::{self, EditPolicy, ExplicitPolicy};
+use crate::verify;
+use crate::waveform;
+use crate::workers::{self, AdaptiveLimit};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeMap, HashMap};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{
+    cmp, fs,
+    path::{Path, PathBuf},
+};
+
+/// Asked with the files a run would overwrite before converting, returns whether to overwrite
+/// them
+pub type ConfirmOverwrite = dyn Fn(&[PathBuf]) -> Result<bool> + Send + Sync;
+
+/// Settings that apply to every song converted in a run
+pub struct ConvertOptions {
+    pub conversion_tag: String,
+    /// Only convert songs whose tags match this expression
+    pub filter: Option<TagFilter>,
+    /// Only convert the tracks of this Rekordbox playlist
+    pub playlist: Option<PlaylistSelection>,
+    /// Skip songs already in this Rekordbox collection
+    pub collection: Option<Collection>,
+    pub pool_parser: Option<Box<dyn PoolParser>>,
+    pub explicit_policy: ExplicitPolicy,
+    pub edit_policy: EditPolicy,
+    pub loudness_preset: LoudnessPreset,
+    /// Loudness target in LUFS that overrides the preset's
+    pub normalize: Option<f64>,
+    pub limiter_ceiling: Option<f64>,
+    /// Write ReplayGain track gain and peak tags, from the loudness the song has after
+    /// conversion
+    pub replaygain: bool,
+    pub cleanup: bool,
+    pub cleanup_dirs: Vec<PathBuf>,
+    pub speed_correction: Option<SpeedCorrection>,
+    /// The output directory's speed review file, whose overrides replace speed_correction for
+    /// single songs
+    pub speed_review: speed::Review,
+    pub stem_mode: StemMode,
+    /// If set, artist fields listing several artists are rewritten to join them with this
+    pub artist_separator: Option<String>,
+    /// Also split artist fields on "&", "," and "/"
+    pub split_artists_loosely: bool,
+    pub year_source: YearSource,
+    /// Name output files after this template, e.g. "{artist} - {title}", instead of after the
+    /// source file
+    pub name_template: Option<String>,
+    /// Make output file and folder names safe for FAT32 and exFAT USB sticks
+    pub sanitizer: Option<NameSanitizer>,
+    /// Mirror each song's directory relative to this root in the output directory, instead of
+    /// writing every song to the top of it
+    pub source_root: Option<PathBuf>,
+    /// Sort converted songs into subfolders by this metadata
+    pub route_by: Option<RouteBy>,
+    /// Folders songs are routed to in place of those named after their tags
+    pub route_map: RouteMap,
+    /// Lay the output directory out like a Rekordbox USB stick, with songs in
+    /// Contents/<Artist>/<Album> and a PIONEER folder, in place of source_root and route_by
+    pub usb_layout: bool,
+    /// Check the output directory is on a device players can read with room for the songs
+    /// before converting, and what to do if they don't fit
+    pub device_preflight: Option<WhenFull>,
+    /// Resize embedded artwork, or leave it to ffmpeg if None
+    pub artwork: Option<ArtworkOptions>,
+    /// Embed a cover image from the song's folder, such as cover.jpg, in songs that have no
+    /// artwork
+    pub folder_artwork: bool,
+    /// Download artwork for songs that have none
+    pub artwork_fetcher: Option<ArtworkFetcher>,
+    /// Share one optimized image between songs with the same artwork when resizing it
+    pub artwork_cache: Option<ArtworkCache>,
+    /// Number of songs probed at once. Probing is mostly waiting on the disk or network
+    pub probe_jobs: usize,
+    /// How files are read to find out their format and tags
+    pub probe_backend: ProbeBackend,
+    /// Reuse what probing found out about files that haven't changed since an earlier run
+    pub probe_cache: Option<ProbeCache>,
+    /// Reuse what tempo and key detection found in audio analyzed by an earlier run
+    pub analysis_cache: Option<AnalysisCache>,
+    /// Number of songs converted at once. Converting is CPU bound
+    pub convert_jobs: usize,
+    /// Treat convert_jobs as a maximum, and convert fewer songs at once while other programs
+    /// keep the machine busy
+    pub adaptive_jobs: bool,
+    /// Show a progress bar while converting
+    pub show_progress: bool,
+    /// Show progress as a line per finished song instead of a bar that redraws itself, for
+    /// screen readers and log collectors
+    pub plain_progress: bool,
+    /// Render a waveform PNG of each converted song into a waveforms folder in the output
+    /// directory
+    pub export_waveforms: bool,
+    /// Write Pioneer ANLZ waveform and beat grid files into PIONEER/USBANLZ, and a device
+    /// library pointing to them into PIONEER/rekordbox. Only applies with usb_layout.
+    pub anlz: bool,
+    /// Detect the tempo of songs without a BPM tag and tag the converted files with it
+    pub detect_bpm: bool,
+    /// Rules resolving detected tempos between half and double time
+    pub bpm_rules: Vec<BpmRule>,
+    /// The output directory's BPM review file, whose overrides replace detected tempos
+    pub bpm_review: Review,
+    /// Detect the key of songs without a key tag and tag the converted files with it, when
+    /// detected with at least min_key_confidence
+    pub detect_key: bool,
+    pub min_key_confidence: f64,
+    /// Keys and tempos Mixed In Key analyzed, tagged in place of detected ones
+    pub mik_export: Option<MikExport>,
+    /// Write byte-identical files whenever the same source is converted with the same settings
+    pub deterministic: bool,
+    /// External tools run on every converted song, in order
+    pub processors: Vec<Processor>,
+    /// Tool to write acapella and instrumental rehearsal versions of converted songs with
+    pub separation: Option<SeparationTool>,
+    /// Tag songs need set to 1 to get rehearsal versions. If empty every converted song does
+    pub separation_tag: String,
+    /// Folder mirroring the output directory that rehearsal versions go in, Rehearsal in the
+    /// output directory if not given
+    pub separation_dir: Option<PathBuf>,
+    /// Highest sample rate to output, songs above it are resampled
+    pub max_sample_rate: usize,
+    /// Highest bit depth of lossless output, songs above it are reduced to it
+    pub max_bit_depth: usize,
+    /// Format lossless songs are converted to
+    pub lossless_target: LosslessTarget,
+    /// Format lossy songs are converted to
+    pub lossy_target: LossyTarget,
+    /// Limit how fast songs are read from the source, for sources on a slow network
+    pub read_throttle: Option<Arc<ReadThrottle>>,
+    /// Put songs that are already in a Rekordbox format into the output directory too, so it
+    /// holds the complete set
+    pub copy_compliant: Option<CopyMode>,
+    /// Also store every song picked, untouched, in this archive
+    pub archive: Option<Archive>,
+    /// Skip songs converted by a previous run that haven't changed since. Converted songs are
+    /// kept track of in a state file in the output directory either way
+    pub incremental: bool,
+    /// Pick up a run into the output directory that was cut short: skip the songs it finished,
+    /// and remove the outputs it may have left half written so they are converted again
+    pub resume: bool,
+    /// Asked before overwriting files already in the output directory. Songs whose files aren't
+    /// to be overwritten are skipped. If None, existing files are overwritten
+    pub confirm_overwrite: Option<Box<ConfirmOverwrite>>,
+    /// Stop converting at the first song that fails, skipping the ones not started yet
+    pub fail_fast: bool,
+    /// Decode converted files after writing them, failing songs whose output is corrupt or
+    /// truncated
+    pub verify: bool,
+    /// Set the conversion tag to 0 in the source file of every song converted, so later runs
+    /// don't pick it again
+    pub untag_source: bool,
+}
+
+impl ConvertOptions {
+    /// Whether songs go through any audio filters, in which case even songs that are already in
+    /// a Rekordbox format need to be re-encoded
+    pub fn filters_audio(&self, song: &SongInfo) -> bool {
+        self.loudness_target().is_some()
+            || self.cleans_up(song)
+            || self.speed_correction_for(song).is_some()
+    }
+
+    /// The speed correction a song is played back with: its override in the speed review file,
+    /// or else --speed-correct
+    pub fn speed_correction_for(&self, song: &SongInfo) -> Option<SpeedCorrection> {
+        self.speed_review
+            .override_for(song.get_song_path())
+            .or(self.speed_correction)
+            .filter(SpeedCorrection::changes_speed)
+    }
+
+    /// Integrated loudness to normalize songs to, if any
+    pub fn loudness_target(&self) -> Option<f64> {
+        self.normalize
+            .or_else(|| self.loudness_preset.target_lufs())
+    }
+
+    /// A hash of the settings that change the audio of a song's converted files, so songs
+    /// converted with other settings are converted again
+    pub fn output_settings(&self, song: &SongInfo) -> String {
+        let settings = format!(
+            "{:?} {:?} {} {} {:?} {:?} {:?} {} {:?} {:?}",
+            self.lossless_target,
+            self.lossy_target,
+            self.max_sample_rate,
+            self.max_bit_depth,
+            self.loudness_target(),
+            self.limiter_ceiling,
+            self.speed_correction_for(song),
+            self.cleanup,
+            self.cleanup_dirs,
+            self.stem_mode,
+        );
+        format!("{:016x}", state::hash_bytes(settings.as_bytes()))
+    }
+
+    /// The tool to write rehearsal versions of a song with, if it gets them
+    pub fn separation_for(&self, song: &SongInfo) -> Option<SeparationTool> {
+        let tagged = self.separation_tag.is_empty()
+            || song
+                .get_tags()
+                .as_ref()
+                .and_then(|t| t.get(&self.separation_tag))
+                .is_some_and(|tag| tag == "1");
+        // Stem files already have their parts apart
+        self.separation.filter(|_| tagged && !song.is_stem())
+    }
+
+    /// Whether the DC offset and rumble cleanup applies to a song
+    pub fn cleans_up(&self, song: &SongInfo) -> bool {
+        self.cleanup
+            || self
+                .cleanup_dirs
+                .iter()
+                .any(|dir| song.get_song_path().starts_with(dir))
+    }
+}
+
+impl Default for ConvertOptions {
+    /// Converts every song in the directory, without touching its audio beyond what Rekordbox needs
+    fn default() -> Self {
+        ConvertOptions {
+            conversion_tag: String::new(),
+            filter: None,
+            playlist: None,
+            collection: None,
+            pool_parser: None,
+            explicit_policy: ExplicitPolicy::Both,
+            edit_policy: EditPolicy::All,
+            loudness_preset: LoudnessPreset::Archive,
+            normalize: None,
+            limiter_ceiling: None,
+            replaygain: false,
+            cleanup: false,
+            cleanup_dirs: vec![],
+            speed_correction: None,
+            speed_review: speed::Review::default(),
+            stem_mode: StemMode::Master,
+            artist_separator: None,
+            split_artists_loosely: false,
+            year_source: YearSource::Original,
+            name_template: None,
+            sanitizer: None,
+            source_root: None,
+            route_by: None,
+            route_map: RouteMap::default(),
+            usb_layout: false,
+            device_preflight: None,
+            artwork: None,
+            folder_artwork: false,
+            artwork_fetcher: None,
+            artwork_cache: None,
+            probe_jobs: workers::default_jobs(),
+            probe_backend: ProbeBackend::Ffprobe,
+            probe_cache: None,
+            analysis_cache: None,
+            convert_jobs: workers::default_jobs(),
+            adaptive_jobs: false,
+            show_progress: false,
+            plain_progress: false,
+            export_waveforms: false,
+            anlz: false,
+            detect_bpm: false,
+            bpm_rules: vec![],
+            bpm_review: Review::default(),
+            detect_key: false,
+            min_key_confidence: key::DEFAULT_MIN_CONFIDENCE,
+            mik_export: None,
+            deterministic: false,
+            processors: vec![],
+            separation: None,
+            separation_tag: String::new(),
+            separation_dir: None,
+            max_sample_rate: 44100,
+            max_bit_depth: 16,
+            lossless_target: LosslessTarget::Aiff,
+            lossy_target: LossyTarget::Mp3,
+            read_throttle: None,
+            copy_compliant: None,
+            archive: None,
+            incremental: false,
+            resume: false,
+            confirm_overwrite: None,
+            fail_fast: false,
+            verify: false,
+            untag_source: false,
+        }
+    }
+}
+
+/// How songs that don't need converting are put into the output directory
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum CopyMode {
+    /// Copy the file
+    Copy,
+    /// Hard link the file, falling back to a copy across file systems
+    Hardlink,
+}
+
+/// What will be done with a song
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Convert the song into a Rekordbox friendly format
+    Convert,
+    /// Skip the song, it is already in a Rekordbox friendly format
+    SkipCompliant,
+    /// Skip the song, it isn't tagged for conversion
+    SkipUntagged,
+    /// Skip the song, its tags don't match the filter
+    SkipFiltered,
+    /// Skip the song, it was converted by a previous run and hasn't changed since
+    SkipUnchanged,
+    /// Skip the song, its output already exists and overwriting it wasn't confirmed
+    SkipExisting,
+    /// Skip the song, it is already in the Rekordbox collection
+    SkipInCollection,
+    /// Skip the song, it wouldn't fit on the device
+    SkipNoSpace,
+    /// Reject the song, its format isn't supported
+    Unsupported,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let id = match self {
+            Action::Convert => "action-convert",
+            Action::SkipCompliant => "action-skip-compliant",
+            Action::SkipUntagged => "action-skip-untagged",
+            Action::SkipFiltered => "action-skip-filtered",
+            Action::SkipUnchanged => "action-skip-unchanged",
+            Action::SkipExisting => "action-skip-existing",
+            Action::SkipInCollection => "action-skip-in-collection",
+            Action::SkipNoSpace => "action-skip-no-space",
+            Action::Unsupported => "action-unsupported",
+        };
+        write!(f, "{}", i18n::message(id, &[]))
+    }
+}
+
+/// A song and what will be done with it
+#[derive(Clone, Debug)]
+pub struct ConversionJob {
+    pub song: SongInfo,
+    pub action: Action,
+    /// The format the song is converted to, if it is supported
+    pub output_format: Option<SupportedAudioFormat>,
+}
+
+impl std::fmt::Display for ConversionJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.song.get_song_path().display(),
+            self.action
+        )?;
+        if let (Action::Convert, Some(format)) = (&self.action, &self.output_format) {
+            let format = format.to_string().to_uppercase();
+            write!(
+                f,
+                " {}",
+                i18n::message("job-target", &[("format", format.into())])
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of what happened to the songs of a run
+#[derive(Clone, Debug, Default)]
+pub struct ConversionSummary {
+    pub n_iterated: usize,
+    pub n_converted: usize,
+    /// Songs already in a Rekordbox format that were copied into the output directory
+    pub n_copied: usize,
+    pub n_skipped: usize,
+    pub n_failed: usize,
+    /// Songs that couldn't be stored in the archive
+    pub n_archive_failed: usize,
+    /// Source files of the songs that were converted or copied
+    pub converted: Vec<PathBuf>,
+    /// What happened to each song, in the order the songs finished
+    pub files: Vec<FileReport>,
+}
+
+/// Runs the stages of a conversion: scanning a directory for files, probing them, planning what
+/// to do with each song and converting them. `run` does all of them in one go.
+pub struct Converter {
+    options: Arc<ConvertOptions>,
+}
+
+impl Converter {
+    pub fn new(options: ConvertOptions) -> Self {
+        Converter {
+            options: Arc::new(options),
+        }
+    }
+
+    pub fn get_options(&self) -> &ConvertOptions {
+        &self.options
+    }
+
+    /// Lists every file under a directory, or only the tracks of the playlist if one was given
+    pub fn scan(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        build_list_of_files(dir, &mut files)?;
+        if let Some(playlist) = &self.options.playlist {
+            files = playlist.select(&files);
+            tracing::info!(
+                playlist = playlist.name.as_str(),
+                n_found = files.len(),
+                n_tracks = playlist.locations.len(),
+                "Converting the tracks of the playlist"
+            );
+        }
+        Ok(files)
+    }
+
+    /// Scans a directory in the background, like scan then probe and plan, handing out what it
+    /// finds as it finds it, so a library of any size can be shown while it is scanned
+    pub fn scan_events(&self, dir: &Path) -> ScanEvents {
+        scan_events::start(dir.to_path_buf(), self.options.clone())
+    }
+
+    /// The files of the playlist, for converting one without an input directory to look for
+    /// its tracks in
+    pub fn playlist_files(&self) -> Result<Vec<PathBuf>> {
+        match &self.options.playlist {
+            Some(playlist) => Ok(playlist.existing()),
+            None => Err(anyhow!("Give an input directory or a playlist to convert")),
+        }
+    }
+
+    /// Whether a file would be picked by scan, for files that turn up after it
+    pub fn selects(&self, file: &Path) -> bool {
+        match &self.options.playlist {
+            Some(playlist) => playlist.contains(file),
+            None => true,
+        }
+    }
+
+    /// Probes files, leaving out any that aren't audio
+    pub fn probe(&self, files: &[PathBuf]) -> Vec<SongInfo> {
+        let options = self.options.clone();
+        let songs = workers::map_parallel(files.to_vec(), self.options.probe_jobs, move |file| {
+            let _slot = options.read_throttle.as_ref().map(|t| t.slot());
+            song_info::from_file_cached(
+                file.as_path(),
+                options.probe_cache.as_ref(),
+                options.probe_backend,
+            )
+            .ok()
+        })
+        .into_iter()
+        .flatten()
+        .collect();
+        if let Some(cache) = &self.options.probe_cache {
+            if let Err(e) = cache.save() {
+                tracing::warn!(?e, "Could not save probe cache");
+            }
+        }
+        songs
+    }
+
+    /// Decides what to do with each song, after picking between versions and edits of the same
+    /// track
+    pub fn plan(&self, songs: Vec<SongInfo>) -> Vec<ConversionJob> {
+        let position: HashMap<PathBuf, usize> = songs
+            .iter()
+            .enumerate()
+            .map(|(i, song)| (song.get_song_path().clone(), i))
+            .collect();
+        // Versions and edits are only picked between among the selected songs, so a version
+        // that wouldn't be converted anyway never pushes out one that would
+        let (selected, mut songs): (Vec<SongInfo>, Vec<SongInfo>) = songs
+            .into_iter()
+            .partition(|song| is_selected(song, &self.options));
+        let selected = variants::select_explicitness(selected, self.options.explicit_policy);
+        songs.extend(variants::select_edits(selected, self.options.edit_policy));
+        songs.sort_by_key(|song| position[song.get_song_path()]);
+        songs
+            .into_iter()
+            .map(|song| ConversionJob {
+                action: plan_song(&song, &self.options),
+                output_format: output_format(&song, &self.options),
+                song,
+            })
+            .collect()
+    }
+
+    /// Carries out the planned jobs in parallel
+    pub fn convert(&self, jobs: Vec<ConversionJob>, output_dir: &Path) -> ConversionSummary {
+        convert_songs_parallel(jobs, output_dir, self.options.clone(), None)
+    }
+
+    /// Prints what would be done with every song, without converting anything
+    pub fn dry_run(&self, input_dir: &Path) -> Result<ConversionSummary> {
+        let files = self.scan(input_dir)?;
+        self.dry_run_files(&files)
+    }
+
+    /// Prints what converting a list of files would do, like dry_run
+    pub fn dry_run_files(&self, files: &[PathBuf]) -> Result<ConversionSummary> {
+        let songs = self.probe(files);
+        let jobs = self.plan(songs);
+        let mut summary = ConversionSummary::default();
+        for job in jobs.iter() {
+            println!("{}", job);
+            summary.n_iterated += 1;
+            match job.action {
+                Action::Convert => summary.n_converted += 1,
+                Action::SkipCompliant
+                | Action::SkipUntagged
+                | Action::SkipFiltered
+                | Action::SkipUnchanged
+                | Action::SkipExisting
+                | Action::SkipInCollection
+                | Action::SkipNoSpace => summary.n_skipped += 1,
+                Action::Unsupported => summary.n_failed += 1,
+            }
+        }
+        println!(
+            "{}",
+            i18n::message(
+                "dry-run-summary",
+                &[
+                    ("songs", summary.n_iterated.into()),
+                    ("convert", summary.n_converted.into()),
+                    ("skip", summary.n_skipped.into()),
+                    ("unsupported", summary.n_failed.into()),
+                ]
+            )
+        );
+        Ok(summary)
+    }
+
+    /// Scans, probes, plans and converts everything in the input directory
+    pub fn run(&self, input_dir: &Path, output_dir: &Path) -> Result<ConversionSummary> {
+        let files = self.scan(input_dir)?;
+        let summary = self.run_files(&files, output_dir)?;
+        self.write_playlist(&files, output_dir)?;
+        Ok(summary)
+    }
+
+    /// When the songs were picked by a playlist, writes it into the output directory as an M3U8
+    /// of the files its tracks were converted into, in order, so the set is ready to play.
+    /// Tracks already in a Rekordbox format and not copied are listed where they are, and tracks
+    /// that failed are left out. Returns the playlist written, if any.
+    pub fn write_playlist(&self, files: &[PathBuf], output_dir: &Path) -> Result<Option<PathBuf>> {
+        let playlist = match &self.options.playlist {
+            Some(playlist) => playlist,
+            None => return Ok(None),
+        };
+        let state = ConversionState::load(output_dir)?;
+        let output = |file: &PathBuf| {
+            state
+                .get(file)
+                .and_then(|record| record.outputs.iter().find(|o| o.is_file()))
+                .cloned()
+        };
+        let unconverted: Vec<PathBuf> = files
+            .iter()
+            .filter(|f| output(f).is_none())
+            .cloned()
+            .collect();
+        let compliant: Vec<PathBuf> = self
+            .probe(&unconverted)
+            .iter()
+            .filter(|song| plan_song(song, &self.options) == Action::SkipCompliant)
+            .map(|song| song.get_song_path().clone())
+            .collect();
+        let mut m3u = String::from("#EXTM3U\n");
+        for file in files {
+            let entry = match output(file) {
+                // Relative, so the playlist still works when the output directory is copied
+                Some(output) => output
+                    .strip_prefix(output_dir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(output),
+                None if compliant.contains(file) => file.canonicalize()?,
+                None => {
+                    tracing::warn!(
+                        ?file,
+                        "Leaving a track that wasn't converted out of the playlist"
+                    );
+                    continue;
+                }
+            };
+            m3u.push_str(&format!("{}\n", entry.display()));
+        }
+        let path = output_dir.join(exporters::file_name(&playlist.name, "m3u8"));
+        state::write_atomic(&path, m3u.as_bytes())?;
+        tracing::info!(?path, "Wrote the playlist of the converted files");
+        Ok(Some(path))
+    }
+
+    /// Probes, plans and converts a list of files
+    pub fn run_files(&self, files: &[PathBuf], output_dir: &Path) -> Result<ConversionSummary> {
+        if !output_dir.is_dir() {
+            return Err(anyhow!(
+                "Provided output path is not a directory: {}",
+                output_dir.display()
+            ));
+        }
+        let songs = self.probe(files);
+        let versions = variants::explicitness_links(
+            songs.iter().filter(|song| is_selected(song, &self.options)),
+        );
+        let mut jobs = self.plan(songs);
+        let interrupted = state::interrupted_run(output_dir)?;
+        let finished = match (&interrupted, self.options.resume) {
+            (Some(run), true) => {
+                for output in run.partial_outputs.iter().filter(|p| p.exists()) {
+                    tracing::info!(?output, "Removing output the interrupted run didn't finish");
+                    read_only::check_writable(output)?;
+                    fs::remove_file(output)?;
+                }
+                run.finished.clone()
+            }
+            (Some(_), false) => {
+                tracing::warn!(
+                    "The last run into this directory was cut short, pass --resume to pick up \
+                     where it stopped"
+                );
+                Default::default()
+            }
+            (None, _) => Default::default(),
+        };
+        let state = if self.options.incremental {
+            Some(ConversionState::load(output_dir)?)
+        } else {
+            None
+        };
+        for job in jobs.iter_mut() {
+            let writes_output = job.action == Action::Convert
+                || (job.action == Action::SkipCompliant && self.options.copy_compliant.is_some());
+            let source = job.song.get_song_path();
+            let unchanged = finished.contains(source)
+                || match &state {
+                    Some(state) if writes_output => {
+                        let outputs = output_paths(job, output_dir, &self.options)?;
+                        let settings = self.options.output_settings(&job.song);
+                        let throttle = self.options.read_throttle.as_deref();
+                        state.is_up_to_date(source, &outputs, &settings, throttle)
+                    }
+                    _ => false,
+                };
+            if writes_output && unchanged {
+                job.action = Action::SkipUnchanged;
+            }
+        }
+        self.check_output_collisions(&jobs, output_dir, state.as_ref())?;
+        if let Some(cache) = &self.options.artwork_cache {
+            // Artwork hashed by earlier runs, unless the source changed since
+            let known = ConversionState::load(output_dir)?;
+            cache.remember_hashes(jobs.iter().filter_map(|job| {
+                let source = job.song.get_song_path();
+                let hash = known.get(source)?.artwork_hash.as_ref()?;
+                let hash = u64::from_str_radix(hash, 16).ok()?;
+                known
+                    .is_unchanged(source, self.options.read_throttle.as_deref())
+                    .then(|| (source.clone(), hash))
+            }));
+        }
+        if let Some(confirm) = &self.options.confirm_overwrite {
+            self.keep_existing(&mut jobs, output_dir, confirm.as_ref())?;
+        }
+        if let Some(when_full) = self.options.device_preflight {
+            self.fit_on_device(&mut jobs, output_dir, when_full)?;
+        }
+        let journal = Arc::new(Journal::open(output_dir)?);
+        if self.options.usb_layout {
+            usb_layout::scaffold(output_dir)?;
+        }
+        // Every song picked is archived, whether or not this run converts it, and before
+        // converting, which may retag the sources
+        let n_archive_failed = match &self.options.archive {
+            Some(archive) => {
+                let to_archive: Vec<SongInfo> = jobs
+                    .iter()
+                    .filter(|job| {
+                        !matches!(
+                            job.action,
+                            Action::SkipUntagged
+                                | Action::SkipFiltered
+                                | Action::SkipInCollection
+                                | Action::Unsupported
+                        )
+                    })
+                    .map(|job| job.song.clone())
+                    .collect();
+                archive
+                    .store_all(to_archive, self.options.convert_jobs)
+                    .failures
+                    .len()
+            }
+            None => 0,
+        };
+        let mut summary =
+            convert_songs_parallel(jobs, output_dir, self.options.clone(), Some(journal));
+        summary.n_archive_failed = n_archive_failed;
+        // Fold the journal of this run into the state file, linking the clean and explicit
+        // versions of each track and keeping artwork hashes
+        let mut state = ConversionState::load(output_dir)?;
+        for (source, other_versions) in versions {
+            state.set_other_versions(&source, other_versions);
+        }
+        if let Some(cache) = &self.options.artwork_cache {
+            for (source, hash) in cache.hashes() {
+                state.set_artwork_hash(&source, format!("{:016x}", hash));
+            }
+        }
+        state.save(output_dir)?;
+        if let Some(cache) = &self.options.analysis_cache {
+            if let Err(e) = cache.save() {
+                tracing::warn!(?e, "Could not save analysis cache");
+            }
+        }
+        if self.options.detect_key {
+            let detected = summary
+                .files
+                .iter()
+                .filter_map(|f| Some((&f.source, f.key.as_ref()?)));
+            let n_to_review = key::Review::update(output_dir, detected)?;
+            if n_to_review > 0 {
+                tracing::warn!(
+                    n_to_review,
+                    review = ?output_dir.join(key::REVIEW_FILE_NAME),
+                    "Some keys were detected with too little confidence to tag, check them by ear"
+                );
+            }
+        }
+        if self.options.usb_layout && self.options.anlz {
+            let written = exporters::tracks(output_dir, self.options.probe_backend)
+                .and_then(|tracks| usb_layout::write_device_library(output_dir, &tracks));
+            if let Err(e) = written {
+                tracing::warn!(?e, "Could not write the device library");
+            }
+        }
+        if let Err(e) = Manifest::update(output_dir, self.options.read_throttle.as_deref()) {
+            tracing::warn!(?e, "Could not update the manifest");
+        }
+        Ok(summary)
+    }
+
+    /// Fails if two songs would be written to the same file, which a name template like
+    /// "{artist} - {title}" does for the original and extended mix of a track, or if a song
+    /// would overwrite what another song was converted into by an earlier run. Paths are
+    /// compared ignoring case, as players' file systems do.
+    fn check_output_collisions(
+        &self,
+        jobs: &[ConversionJob],
+        output_dir: &Path,
+        state: Option<&ConversionState>,
+    ) -> Result<()> {
+        let planned = jobs
+            .iter()
+            .map(|job| {
+                Ok((
+                    job.song.get_song_path(),
+                    output_paths(job, output_dir, &self.options)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut writers: HashMap<String, &Path> = HashMap::new();
+        // Outputs of earlier runs, apart from those of the songs this run writes again
+        if let Some(state) = state {
+            for (source, record) in state.sources().filter(|(source, _)| {
+                source.exists()
+                    && !planned
+                        .iter()
+                        .any(|(s, paths)| s == source && !paths.is_empty())
+            }) {
+                for output in record.outputs.iter() {
+                    writers.insert(output.to_string_lossy().to_lowercase(), source);
+                }
+            }
+        }
+        let mut collisions = vec![];
+        for (source, paths) in planned.iter() {
+            let source: &Path = source;
+            for path in paths {
+                match writers.insert(path.to_string_lossy().to_lowercase(), source) {
+                    Some(other) if other != source => {
+                        collisions.push(format!(
+                            "{} (from {} and {})",
+                            path.display(),
+                            other.display(),
+                            source.display()
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{} output files would be written from more than one song, give the name template a \
+             tag that tells them apart, like {{filename}}:\n{}",
+            collisions.len(),
+            collisions.join("\n")
+        ))
+    }
+
+    /// Asks whether to overwrite the files in the output directory that jobs would overwrite,
+    /// and skips those jobs if not
+    fn keep_existing(
+        &self,
+        jobs: &mut [ConversionJob],
+        output_dir: &Path,
+        confirm: &ConfirmOverwrite,
+    ) -> Result<()> {
+        let mut existing = Vec::with_capacity(jobs.len());
+        for job in jobs.iter() {
+            let paths = output_paths(job, output_dir, &self.options)?;
+            existing.push(paths.into_iter().filter(|p| p.exists()).collect::<Vec<_>>());
+        }
+        let collisions: Vec<PathBuf> = existing.iter().flatten().cloned().collect();
+        if collisions.is_empty() || confirm(&collisions)? {
+            return Ok(());
+        }
+        for (job, existing) in jobs.iter_mut().zip(existing) {
+            if !existing.is_empty() {
+                job.action = Action::SkipExisting;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the output directory is on a device players can read, and that what the jobs
+    /// will write fits on it, skipping the jobs that don't when trimming
+    fn fit_on_device(
+        &self,
+        jobs: &mut [ConversionJob],
+        output_dir: &Path,
+        when_full: WhenFull,
+    ) -> Result<()> {
+        let filesystem = Filesystem::of(output_dir)?;
+        filesystem.check_readable_by_players()?;
+        let estimates = jobs
+            .iter()
+            .map(|job| estimated_size(job, output_dir, &self.options))
+            .collect::<Result<Vec<_>>>()?;
+        let fits = device::fit(&estimates, &filesystem, when_full)?;
+        let mut n_trimmed = 0;
+        for (job, fits) in jobs.iter_mut().zip(fits) {
+            if !fits {
+                job.action = Action::SkipNoSpace;
+                n_trimmed += 1;
+            }
+        }
+        if n_trimmed > 0 {
+            tracing::warn!(n_trimmed, "Skipping the songs that don't fit on the device");
+        }
+        Ok(())
+    }
+}
+
+/// Function iterates through the directory and grabs file paths
+pub fn build_list_of_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    walk_files(dir, &mut |path| {
+        files.push(path);
+        true
+    })?;
+    Ok(())
+}
+
+/// Hands every file under a directory to `visit` as it is found, until `visit` returns false.
+/// Returns whether the whole directory was walked.
+pub fn walk_files(dir: &Path, visit: &mut dyn FnMut(PathBuf) -> bool) -> Result<bool> {
+    if dir.is_dir() {
+        if let Ok(entries) = fs::read_dir(dir) {
+            // Iterate through entries in the directory
+            for entry in entries {
+                if let Ok(e) = entry {
+                    let path = e.path();
+                    // If entry is a directory, recursively search through it
+                    if path.is_dir() {
+                        if !walk_files(path.as_path(), visit)? {
+                            return Ok(false);
+                        }
+                    } else if !visit(path) {
+                        return Ok(false);
+                    }
+                } else {
+                    tracing::error!("I/O error while reading directory entry: {:?}", entry)
+                }
+            }
+        } else {
+            tracing::error!("Error reading directory: {}", dir.display());
+        }
+        Ok(true)
+    } else {
+        Err(anyhow!("{} is not a directory!", dir.display()))
+    }
+}
+
+/// The format a song is converted to: lossless songs become the lossless target (AIFF by
+/// default) and lossy songs the lossy target (MP3 by default)
+pub fn output_format(song: &SongInfo, options: &ConvertOptions) -> Option<SupportedAudioFormat> {
+    match song.get_format() {
+        AudioFormatType::Lossless(_) => Some(options.lossless_target.format()),
+        AudioFormatType::Lossy(_) => Some(options.lossy_target.format()),
+        AudioFormatType::Unsupported => None,
+    }
+}
+
+/// Why a song isn't selected for conversion by its tags, if it isn't
+fn deselected(song: &SongInfo, options: &ConvertOptions) -> Option<Action> {
+    let conversion_tag = options.conversion_tag.as_str();
+    // If we are given a conversion tag, if a song does not have the specified conversion tag set
+    // to 1 move on to the next song
+    if !conversion_tag.is_empty() {
+        match song.get_tags().as_ref().and_then(|t| t.get(conversion_tag)) {
+            Some(tag) if tag == "1" => (),
+            // If song does not have conversion tag or it isn't 1, skip
+            _ => return Some(Action::SkipUntagged),
+        }
+    }
+    if options
+        .filter
+        .as_ref()
+        .is_some_and(|filter| !filter.matches(song))
+    {
+        return Some(Action::SkipFiltered);
+    }
+    None
+}
+
+/// Whether a song is supported and selected for conversion by its tags
+fn is_selected(song: &SongInfo, options: &ConvertOptions) -> bool {
+    !matches!(song.get_format(), AudioFormatType::Unsupported)
+        && deselected(song, options).is_none()
+}
+
+/// Decides whether a song needs converting
+pub fn plan_song(song: &SongInfo, options: &ConvertOptions) -> Action {
+    if matches!(song.get_format(), AudioFormatType::Unsupported) {
+        return Action::Unsupported;
+    }
+    if let Some(skip) = deselected(song, options) {
+        return skip;
+    }
+    if options
+        .collection
+        .as_ref()
+        .is_some_and(|collection| collection.contains(song))
+    {
+        return Action::SkipInCollection;
+    }
+    // If a song satisfies Rekordbox audio format, we can skip
+    if *song.get_sample_rate() <= options.max_sample_rate
+        && song.is_rekordbox_format()
+        && !options.filters_audio(song)
+        && !song.is_stem()
+    {
+        match song.get_format() {
+            AudioFormatType::Lossless(_) if *song.get_bit_info() <= options.max_bit_depth => {
+                return Action::SkipCompliant
+            }
+            AudioFormatType::Lossy(_) if *song.get_bit_info() <= 320000 => {
+                return Action::SkipCompliant
+            }
+            _ => (),
+        }
+    }
+    Action::Convert
+}
+
+/// What converting a song found out about it and did to it, for the report
+#[derive(Clone, Debug, Default)]
+pub struct ConvertedSong {
+    pub key: Option<DetectedKey>,
+    pub loudness: Option<loudness::Normalization>,
+}
+
+// Perhaps implement concurrency to speed up conversions
+/// Converts a song into the output directory. Returns the key detected for it, if one was, and
+/// how its loudness was normalized, if it was.
+pub fn convert_song(
+    song: &SongInfo,
+    output_dir: &Path,
+    options: &ConvertOptions,
+) -> Result<ConvertedSong> {
+    let conversion_tag = options.conversion_tag.as_str();
+    // Songs that don't need converting are filtered out by plan_song, so only the unsupported
+    // format has to be guarded against here
+    match song.get_format() {
+        AudioFormatType::Unsupported => Err(anyhow!(
+            "{} has an unsupported file format!",
+            song.get_song_path().to_string_lossy()
+        )),
+        _ => {
+            let song_name = song.get_song_name()?;
+            let output_name = output_name(song, options)?;
+            let staged = match &options.read_throttle {
+                Some(throttle) => Some(throttle.stage(song.get_song_path())?),
+                None => None,
+            };
+            // Where the audio is read from, the local copy if the song was staged
+            let input_path = staged
+                .as_ref()
+                .map(|s| s.path.as_path())
+                .unwrap_or_else(|| song.get_song_path().as_path());
+            let mut workspace = if options.processors.is_empty() {
+                None
+            } else {
+                Some(Workspace::new()?)
+            };
+            let processed = match &mut workspace {
+                Some(workspace) => workspace.run_stage(
+                    &options.processors,
+                    Stage::BeforeEncode,
+                    input_path,
+                    "wav",
+                )?,
+                None => None,
+            };
+            // Where the audio is measured and encoded from. Tags and artwork are still read from
+            // the song itself
+            let audio_path = processed.as_deref().unwrap_or(input_path);
+
+            let output_format = match output_format(song, options) {
+                Some(format) => format,
+                None => return Ok(ConvertedSong::default()), //can't occur as this code block only gets evaluated if the audio format is supported
+            };
+            let output_bit_info;
+            let output_bit_type;
+            let output_sample_rate = cmp::min(*song.get_sample_rate(), options.max_sample_rate);
+            // Float and some legacy formats don't report a bit depth
+            let output_bit_depth = match *song.get_bit_info() {
+                0 => 16,
+                bits => cmp::min(bits, options.max_bit_depth),
+            };
+            let output_codec = match song.get_format() {
+                AudioFormatType::Lossless(_) => targets::codec(&output_format, output_bit_depth),
+                _ => targets::codec(&output_format, 16),
+            };
+            let output_extension = targets::extension(&output_format);
+            let output_name = sanitized_output_name(song, options, output_name, &output_extension);
+            match song.get_format() {
+                AudioFormatType::Lossless(_) => {
+                    output_bit_type = "-sample_fmt";
+                    output_bit_info = targets::sample_fmt(output_bit_depth).to_string();
+                }
+                // Opus streams often don't report a bit rate, and are converted at the highest
+                // bit rate CDJs play
+                AudioFormatType::Lossy(SupportedAudioFormat::OPUS) => {
+                    output_bit_type = "-b:a";
+                    output_bit_info = String::from("320k");
+                }
+                AudioFormatType::Lossy(_) => {
+                    output_bit_type = "-b:a";
+                    output_bit_info = format!("{}k", cmp::min(*song.get_bit_info(), 320000) / 100);
+                }
+                _ => return Ok(ConvertedSong::default()), //can't occur as this code block only gets evaluated if the audio format is supported
+            }
+            if options.export_waveforms {
+                let png_path = output_dir
+                    .join("waveforms")
+                    .join(format!("{}.png", output_name));
+                if let Err(e) = waveform::render(audio_path, &png_path) {
+                    tracing::warn!(?song_name, ?e, "Could not export waveform");
+                }
+            }
+            // Where ANLZ files say the song is: on a stick holding the output directory
+            let library_root = output_dir;
+            let output_dir = song_output_dir(song, output_dir, options);
+            read_only::check_writable(&output_dir)?;
+            fs::create_dir_all(&output_dir)?;
+            let outputs = song_outputs(song, &output_dir, &output_name, &output_extension, options);
+
+            let mut audio_filters = vec![];
+            if options.cleans_up(song) {
+                // A one pole filter at 5 Hz blocks DC, then a steeper one removes rumble
+                audio_filters.push("highpass=f=5:poles=1".to_string());
+                audio_filters.push("highpass=f=20:poles=2".to_string());
+            }
+            if let Some(correction) = options.speed_correction_for(song) {
+                audio_filters.push(correction.filter(*song.get_sample_rate()));
+            }
+            // Integrated loudness and true peak of the converted song, for ReplayGain tags
+            let mut output_loudness = None;
+            let mut normalization = None;
+            if let Some(target) = options.loudness_target() {
+                let measured = loudness::measure(audio_path, &audio_filters, target)?;
+                tracing::info!(
+                    ?song_name,
+                    preset = ?options.loudness_preset,
+                    measured_lufs = measured.integrated,
+                    target_lufs = target,
+                    applied_gain_db = measured.gain_to(target),
+                    "Normalizing loudness"
+                );
+                audio_filters.push(loudness::loudnorm_filter(target, &measured));
+                let mut gain_reduction_db = None;
+                if let Some(ceiling) = options.limiter_ceiling {
+                    if measured.gain_to(target) > 0.0 && loudness::limits(ceiling) {
+                        let reduction =
+                            loudness::expected_gain_reduction(&measured, target, ceiling);
+                        tracing::info!(
+                            ?song_name,
+                            ceiling_dbtp = ceiling,
+                            gain_reduction_db = reduction,
+                            "Limiting boosted song"
+                        );
+                        audio_filters.push(loudness::limiter_filter(ceiling));
+                        gain_reduction_db = Some(reduction).filter(|&r| r > 0.0);
+                    }
+                }
+                normalization = Some(loudness::Normalization {
+                    measured_lufs: measured.integrated,
+                    target_lufs: target,
+                    gain_db: measured.gain_to(target),
+                    output_lufs: None,
+                    dynamic: false,
+                    gain_reduction_db,
+                });
+                output_loudness = Some(loudness::normalized_loudness(
+                    &measured,
+                    target,
+                    options.limiter_ceiling,
+                ));
+            } else if options.replaygain {
+                let measured =
+                    loudness::measure(audio_path, &audio_filters, loudness::REPLAYGAIN_REFERENCE)?;
+                output_loudness = Some((measured.integrated, measured.true_peak));
+            }
+            let replaygain_tags = match output_loudness {
+                Some((integrated, true_peak)) if options.replaygain => {
+                    loudness::replaygain_tags(integrated, true_peak)
+                }
+                _ => vec![],
+            };
+
+            // Keys and tempos from Mixed In Key or KeyFinder are tagged instead of detected
+            let imported =
+                if options.detect_bpm || options.detect_key || options.mik_export.is_some() {
+                    imported_analysis::for_song(song, options.mik_export.as_ref())
+                } else {
+                    ImportedAnalysis::default()
+                };
+            let tagged_bpm = anlz::tagged_bpm(song);
+            let imported_bpm = imported.bpm.filter(|_| tagged_bpm.is_none());
+            let imported_key = imported.key.filter(|_| key::tagged_key(song).is_none());
+            let overridden_bpm = options.bpm_review.override_for(song.get_song_path());
+            let detect_bpm = options.detect_bpm && tagged_bpm.is_none() && imported_bpm.is_none();
+            // The song is decoded once for everything detected
+            let mut detectors: Vec<&dyn Analyzer> = vec![];
+            if detect_bpm && overridden_bpm.is_none() {
+                detectors.push(&Tempo);
+            }
+            if options.detect_key && key::tagged_key(song).is_none() && imported_key.is_none() {
+                detectors.push(&Key);
+            }
+            let analysis = match &options.analysis_cache {
+                Some(cache) => cache.analyze(audio_path, &detectors),
+                None => analyzers::analyze(audio_path, &detectors),
+            };
+            let analysis = match analysis {
+                Ok(analysis) => analysis,
+                Err(e) => {
+                    tracing::warn!(?song_name, ?e, "Could not detect tempo or key");
+                    TrackAnalysis::default()
+                }
+            };
+            let detected_bpm = if detect_bpm {
+                overridden_bpm.or_else(|| {
+                    analysis.bpm.map(|detected| {
+                        bpm_octave::resolve(
+                            detected,
+                            song,
+                            analysis.onset_density,
+                            &options.bpm_rules,
+                        )
+                        .bpm
+                    })
+                })
+            } else {
+                imported_bpm
+            };
+            let detected_key = match (&analysis.key, analysis.key_confidence) {
+                (Some(key), Some(confidence)) => Some(DetectedKey {
+                    key: key.clone(),
+                    confidence,
+                    tagged: confidence >= options.min_key_confidence,
+                }),
+                _ => None,
+            };
+            if let Some(detected) = detected_key.as_ref().filter(|k| !k.tagged) {
+                tracing::warn!(
+                    ?song_name,
+                    key = detected.key.as_str(),
+                    confidence = detected.confidence,
+                    "Not tagging a key detected with little confidence, listing it for review"
+                );
+            }
+
+            let pool_info = options
+                .pool_parser
+                .as_ref()
+                .and_then(|p| p.parse(song.get_song_path()));
+            let artist = song
+                .get_tag("artist")
+                .map(|a| a.to_string())
+                .or_else(|| pool_info.as_ref().and_then(|p| p.artist.clone()));
+
+            let resize_artwork = options.artwork.as_ref().filter(|_| song.has_artwork());
+            if let Some(artwork) = resize_artwork {
+                artwork.archive(song, &output_name)?;
+            }
+            let folder_artwork = if options.folder_artwork && !song.has_artwork() {
+                artwork::folder_image(song)
+            } else {
+                None
+            };
+            let fetched_artwork = match &options.artwork_fetcher {
+                Some(fetcher) if !song.has_artwork() && folder_artwork.is_none() => {
+                    fetcher.fetch(song).unwrap_or_else(|e| {
+                        tracing::warn!(?song_name, ?e, "Could not fetch artwork");
+                        None
+                    })
+                }
+                _ => None,
+            };
+            // Artwork from outside the song, and where it came from
+            let external_artwork = match (&fetched_artwork, &folder_artwork) {
+                (Some(fetched), _) => Some((fetched.path.as_path(), fetched.provenance())),
+                (None, Some(path)) => Some((path.as_path(), format!("folder:{}", path.display()))),
+                (None, None) => None,
+            };
+            let cached_artwork = match (resize_artwork, &options.artwork_cache) {
+                (Some(artwork), Some(cache)) => {
+                    Some(cache.optimized(song, artwork, options.read_throttle.as_deref())?)
+                }
+                _ => None,
+            };
+
+            for output in outputs {
+                let mut convert_command = Command::new("ffmpeg");
+                convert_command.arg("-y").arg("-i").arg(input_path);
+                if let Some((path, _)) = &external_artwork {
+                    convert_command.arg("-i").arg(path);
+                } else if let Some(cached) = &cached_artwork {
+                    convert_command.arg("-i").arg(cached);
+                }
+                // Processed audio comes after the song and its artwork, so those keep their inputs
+                let audio_stream = match &processed {
+                    Some(path) => {
+                        convert_command.arg("-i").arg(path);
+                        let n_artwork_inputs =
+                            (external_artwork.is_some() || cached_artwork.is_some()) as usize;
+                        format!("{}:a:0", 1 + n_artwork_inputs)
+                    }
+                    None => output.stream.clone().unwrap_or_else(|| "0:a:0".to_string()),
+                };
+                convert_command
+                    .arg("-acodec")
+                    .arg(output_codec)
+                    .arg("-ar")
+                    .arg(format!("{}", output_sample_rate))
+                    .args(targets::muxer_args(&output_format));
+                if options.deterministic {
+                    convert_command.args(targets::deterministic_args(&output_format));
+                }
+                if !audio_filters.is_empty() {
+                    convert_command.arg("-af").arg(audio_filters.join(","));
+                }
+
+                let mut metadata = vec![("REKORDBOX".to_string(), "1".to_string())];
+                if !conversion_tag.is_empty() {
+                    metadata.push((conversion_tag.to_string(), "0".to_string()));
+                }
+                // Fill in tags from the record pool's naming convention. Artist and title already in
+                // the file take precedence over what we can guess from its name.
+                if let Some(pool_info) = &pool_info {
+                    for (key, value) in pool_info.to_metadata() {
+                        if (key == "artist" || key == "title") && song.get_tag(&key).is_some() {
+                            continue;
+                        }
+                        metadata.push((key, value));
+                    }
+                }
+                if let Some(bpm) = detected_bpm {
+                    // ID3 keeps the tempo as a whole number
+                    metadata.push((
+                        targets::bpm_tag(&output_format).to_string(),
+                        format!("{:.0}", bpm),
+                    ));
+                }
+                let key_to_tag = imported_key.clone().or_else(|| {
+                    detected_key
+                        .as_ref()
+                        .filter(|k| k.tagged)
+                        .map(|k| k.key.clone())
+                });
+                if let Some(key) = key_to_tag {
+                    metadata.push((targets::key_tag(&output_format).to_string(), key));
+                }
+                metadata.extend(replaygain_tags.iter().cloned());
+                metadata.extend(dates::date_metadata(song, options.year_source));
+                // Written after the record pool tags so the normalized artist wins
+                if let (Some(separator), Some(artist)) = (&options.artist_separator, &artist) {
+                    let artists = artists::split_artists(artist, options.split_artists_loosely);
+                    if artists.len() > 1 {
+                        metadata.push(("artist".to_string(), artists.join(separator)));
+                        metadata.push(("ARTISTS".to_string(), artists.join("; ")));
+                    }
+                }
+                if let Some((_, source)) = &external_artwork {
+                    metadata.push(("ARTWORK_SOURCE".to_string(), source.clone()));
+                }
+                if let Some(stem) = output.stem {
+                    metadata.push(("STEM".to_string(), stem.to_string()));
+                }
+                convert_command.args(metadata_args(song, metadata, options.deterministic));
+
+                if external_artwork.is_some() {
+                    convert_command
+                        .arg("-map")
+                        .arg(&audio_stream)
+                        .arg("-map")
+                        .arg("1:0");
+                    match &options.artwork {
+                        Some(artwork) => convert_command.args(artwork.ffmpeg_args()),
+                        None => convert_command
+                            .arg("-c:v")
+                            .arg("copy")
+                            .arg("-disposition:v")
+                            .arg("attached_pic"),
+                    };
+                } else if cached_artwork.is_some() {
+                    convert_command
+                        .arg("-map")
+                        .arg(&audio_stream)
+                        .arg("-map")
+                        .arg("1:0")
+                        .arg("-c:v")
+                        .arg("copy")
+                        .arg("-disposition:v")
+                        .arg("attached_pic");
+                } else if let Some(artwork) = resize_artwork {
+                    // Mapping the artwork means the audio has to be mapped explicitly too
+                    convert_command
+                        .arg("-map")
+                        .arg(&audio_stream)
+                        .arg("-map")
+                        .arg("0:v:0")
+                        .args(artwork.ffmpeg_args());
+                } else if song.has_artwork() && targets::embeds_artwork(&output_format) {
+                    // Left to itself ffmpeg would re-encode the artwork as a plain video
+                    // stream, or drop it, so it is copied over as the cover explicitly
+                    convert_command
+                        .arg("-map")
+                        .arg(&audio_stream)
+                        .arg("-map")
+                        .arg("0:v:0")
+                        .arg("-c:v")
+                        .arg("copy")
+                        .arg("-disposition:v")
+                        .arg("attached_pic");
+                } else if processed.is_some() || output.stream.is_some() {
+                    convert_command.arg("-map").arg(&audio_stream);
+                }
+                convert_command
+                    .arg(output_bit_type)
+                    .arg(&output_bit_info)
+                    .arg(&output.path);
+                // If we ran into an error when converting the file, log it and then move on to the next file
+                let result = convert_command.output()?;
+                if !result.status.success() {
+                    return Err(anyhow!(
+                        "ffmpeg failed to convert {}: {}",
+                        song.get_song_path().to_string_lossy(),
+                        stderr_excerpt(&result.stderr)
+                    ));
+                }
+                if let Some(normalization) = &mut normalization {
+                    normalization.update_from_loudnorm(&result.stderr);
+                    if normalization.dynamic {
+                        tracing::warn!(
+                            ?song_name,
+                            "loudnorm normalized dynamically, as the gain would have pushed peaks \
+                             over its ceiling"
+                        );
+                    }
+                }
+                if let Some(workspace) = &mut workspace {
+                    let processed = workspace.run_stage(
+                        &options.processors,
+                        Stage::AfterEncode,
+                        &output.path,
+                        &output_extension,
+                    )?;
+                    if let Some(processed) = processed {
+                        fs::copy(&processed, &output.path)?;
+                    }
+                }
+                if options.anlz {
+                    let device_path = usb_layout::device_path(&output.path, library_root);
+                    if let Err(e) = anlz::write(
+                        &output.path,
+                        library_root,
+                        &device_path,
+                        tagged_bpm.or(detected_bpm),
+                    ) {
+                        tracing::warn!(?song_name, ?e, "Could not write ANLZ files");
+                    }
+                }
+            }
+            if let Some(tool) = options.separation_for(song) {
+                let rehearsal_root = options
+                    .separation_dir
+                    .clone()
+                    .unwrap_or_else(|| library_root.join(separation::REHEARSAL_DIR));
+                let rehearsal_dir = rehearsal_root
+                    .join(output_dir.strip_prefix(library_root).unwrap_or(&output_dir));
+                let paths: Vec<(Part, PathBuf)> = [Part::Acapella, Part::Instrumental]
+                    .iter()
+                    .map(|part| {
+                        let name =
+                            format!("{} ({}).{}", output_name, part.name(), output_extension);
+                        (*part, rehearsal_dir.join(name))
+                    })
+                    .collect();
+                let mut encode_args: Vec<String> = vec![
+                    "-acodec".to_string(),
+                    output_codec.to_string(),
+                    "-ar".to_string(),
+                    output_sample_rate.to_string(),
+                ];
+                encode_args.extend(
+                    targets::muxer_args(&output_format)
+                        .iter()
+                        .map(|a| a.to_string()),
+                );
+                encode_args.push(output_bit_type.to_string());
+                encode_args.push(output_bit_info.clone());
+                // Rehearsal versions are extras, so the song itself still counts as converted
+                if let Err(e) = write_rehearsal_versions(
+                    song,
+                    tool,
+                    input_path,
+                    audio_path,
+                    &paths,
+                    &encode_args,
+                ) {
+                    tracing::warn!(?song_name, ?e, "Could not write rehearsal versions");
+                }
+            }
+            Ok(ConvertedSong {
+                key: detected_key,
+                loudness: normalization,
+            })
+        }
+    }
+}
+
+/// Separates a song's vocals from the rest and encodes both into their paths, with the song's
+/// tags and the part added to the title and in a STEM tag. `tags_path` is the song to take the
+/// tags from and `audio_path` the audio to separate.
+fn write_rehearsal_versions(
+    song: &SongInfo,
+    tool: SeparationTool,
+    tags_path: &Path,
+    audio_path: &Path,
+    paths: &[(Part, PathBuf)],
+    encode_args: &[String],
+) -> Result<()> {
+    let workspace = Workspace::new()?;
+    let separated = tool.separate(audio_path, workspace.dir())?;
+    let title = match song.get_tag("title") {
+        Some(title) => title.to_string(),
+        None => song.get_song_name()?,
+    };
+    for ((part, separated), (_, path)) in separated.iter().zip(paths) {
+        read_only::check_writable(path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let result = Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-i")
+            .arg(separated)
+            .arg("-i")
+            .arg(tags_path)
+            .arg("-map")
+            .arg("0:a:0")
+            .arg("-map_metadata")
+            .arg("1")
+            .args(encode_args)
+            .arg("-metadata")
+            .arg(format!("title={} ({})", title, part.name()))
+            .arg("-metadata")
+            .arg(format!("STEM={}", part.name()))
+            .arg(path)
+            .output()?;
+        if !result.status.success() {
+            return Err(anyhow!(
+                "ffmpeg failed to encode {}: {}",
+                path.display(),
+                stderr_excerpt(&result.stderr)
+            ));
+        }
+        tracing::info!(?path, "Wrote rehearsal version");
+    }
+    Ok(())
+}
+
+/// Tags that say when or by what a file was written, which deterministic output leaves out
+const RUN_SPECIFIC_TAGS: [&str; 5] = [
+    "encoder",
+    "creation_time",
+    "major_brand",
+    "minor_version",
+    "compatible_brands",
+];
+
+/// The -metadata arguments writing a song's tags. ffmpeg copies the source's tags first and
+/// the given ones are set over them. Deterministic output has every tag written from here
+/// instead, the source's included, sorted by name and without the ones that record when or
+/// by what the source was made.
+fn metadata_args(
+    song: &SongInfo,
+    metadata: Vec<(String, String)>,
+    deterministic: bool,
+) -> Vec<String> {
+    let mut args = vec![];
+    if !deterministic {
+        for (key, value) in metadata {
+            args.push("-metadata".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        return args;
+    }
+    let mut tags: BTreeMap<String, String> = BTreeMap::new();
+    if let Some(source) = song.get_tags().as_ref().and_then(|t| t.as_object()) {
+        for (key, value) in source {
+            if let Some(value) = value.as_str() {
+                tags.insert(key.clone(), value.to_string());
+            }
+        }
+    }
+    tags.retain(|key, _| {
+        !RUN_SPECIFIC_TAGS
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(key))
+    });
+    for (key, value) in metadata {
+        // Containers disagree on capitalization, so the source's ARTIST is replaced by artist
+        tags.retain(|existing, _| !existing.eq_ignore_ascii_case(&key));
+        tags.insert(key, value);
+    }
+    args.push("-map_metadata".to_string());
+    args.push("-1".to_string());
+    for (key, value) in tags {
+        args.push("-metadata".to_string());
+        args.push(format!("{}={}", key, value));
+    }
+    args
+}
+
+/// The name of a song's output files, without extension. Without a name template this is the
+/// source file name.
+fn output_name(song: &SongInfo, options: &ConvertOptions) -> Result<String> {
+    match &options.name_template {
+        Some(template) => Ok(naming::render(template, song)),
+        None => song.get_song_name(),
+    }
+}
+
+/// A song's output name, made safe for the file system if output names are sanitized
+fn sanitized_output_name(
+    song: &SongInfo,
+    options: &ConvertOptions,
+    output_name: String,
+    extension: &str,
+) -> String {
+    match &options.sanitizer {
+        Some(sanitizer) => {
+            // Room for the extension, and the suffix naming the stem of stem outputs
+            let suffix = if song.is_stem() { " (Vocals)".len() } else { 0 };
+            let reserved = extension.encode_utf16().count() + 1 + suffix;
+            sanitizer.name_with_reserve(&output_name, reserved)
+        }
+        None => output_name,
+    }
+}
+
+/// The files a song is converted into, in its output directory
+fn song_outputs(
+    song: &SongInfo,
+    song_dir: &Path,
+    output_name: &str,
+    extension: &str,
+    options: &ConvertOptions,
+) -> Vec<StemOutput> {
+    if song.is_stem() {
+        stems::outputs(options.stem_mode, song_dir, output_name, extension)
+    } else {
+        vec![StemOutput {
+            stream: None,
+            stem: None,
+            path: song_dir.join(format!("{}.{}", output_name, extension)),
+        }]
+    }
+}
+
+/// The files a job will write in the output directory
+pub fn output_paths(
+    job: &ConversionJob,
+    output_dir: &Path,
+    options: &ConvertOptions,
+) -> Result<Vec<PathBuf>> {
+    let song = &job.song;
+    match (&job.action, &job.output_format) {
+        (Action::Convert, Some(format)) => {
+            let extension = targets::extension(format);
+            let output_name =
+                sanitized_output_name(song, options, output_name(song, options)?, &extension);
+            let song_dir = song_output_dir(song, output_dir, options);
+            Ok(
+                song_outputs(song, &song_dir, &output_name, &extension, options)
+                    .into_iter()
+                    .map(|output| output.path)
+                    .collect(),
+            )
+        }
+        (Action::SkipCompliant, _) if options.copy_compliant.is_some() => {
+            Ok(vec![copy_output_path(song, output_dir, options)?])
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// What FLAC compresses music to, roughly, as a share of its PCM size
+const FLAC_RATIO: f64 = 0.6;
+
+/// How many bytes a job will add to the output directory, estimated from the probed length,
+/// sample rate and bit depth or bit rate of the song, less what the outputs it overwrites take
+pub fn estimated_size(
+    job: &ConversionJob,
+    output_dir: &Path,
+    options: &ConvertOptions,
+) -> Result<u64> {
+    let song = &job.song;
+    let outputs = output_paths(job, output_dir, options)?;
+    let per_output = match (&job.action, &job.output_format) {
+        (Action::Convert, Some(format)) => {
+            let bytes_per_second = match song.get_format() {
+                AudioFormatType::Lossless(_) => {
+                    let sample_rate = cmp::min(*song.get_sample_rate(), options.max_sample_rate);
+                    let bytes_per_sample = match *song.get_bit_info() {
+                        bits if cmp::min(bits, options.max_bit_depth) > 16 => 3,
+                        _ => 2,
+                    };
+                    let pcm = (sample_rate * bytes_per_sample * analyzers::CHANNELS) as f64;
+                    if matches!(format, SupportedAudioFormat::FLAC) {
+                        pcm * FLAC_RATIO
+                    } else {
+                        pcm
+                    }
+                }
+                AudioFormatType::Lossy(SupportedAudioFormat::OPUS) => 320000.0 / 8.0,
+                _ => cmp::min(*song.get_bit_info(), 320000) as f64 / 8.0,
+            };
+            (song.get_duration().max(0.0) * bytes_per_second) as u64
+        }
+        (Action::SkipCompliant, _) if options.copy_compliant.is_some() => {
+            file_size(song.get_song_path())
+        }
+        _ => 0,
+    };
+    let overwritten: u64 = outputs.iter().map(|path| file_size(path)).sum();
+    Ok((per_output * outputs.len() as u64).saturating_sub(overwritten))
+}
+
+/// The directory a song's outputs go in, after mirroring the source structure and routing
+fn song_output_dir(song: &SongInfo, output_dir: &Path, options: &ConvertOptions) -> PathBuf {
+    let song_dir = if options.usb_layout {
+        usb_layout::song_dir(song, output_dir)
+    } else {
+        let song_dir = match &options.source_root {
+            Some(root) => routing::mirrored_dir(song, root, output_dir),
+            None => output_dir.to_path_buf(),
+        };
+        routing::output_dir_for(song, options.route_by, &options.route_map, &song_dir)
+    };
+    match &options.sanitizer {
+        Some(sanitizer) => sanitizer.dir(output_dir, &song_dir),
+        None => song_dir,
+    }
+}
+
+/// Copies or hard links a song that is already in a Rekordbox format into the output directory
+pub fn copy_song(
+    song: &SongInfo,
+    output_dir: &Path,
+    options: &ConvertOptions,
+    mode: CopyMode,
+) -> Result<()> {
+    let output_path = copy_output_path(song, output_dir, options)?;
+    read_only::check_writable(&output_path)?;
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if mode == CopyMode::Hardlink {
+        if output_path.exists() {
+            fs::remove_file(&output_path)?;
+        }
+        match fs::hard_link(song.get_song_path(), &output_path) {
+            Ok(()) => return Ok(()),
+            Err(e) => tracing::debug!(?output_path, ?e, "Could not hard link, copying instead"),
+        }
+    }
+    fs::copy(song.get_song_path(), &output_path)?;
+    Ok(())
+}
+
+/// Where a song that is already in a Rekordbox format is copied to
+fn copy_output_path(
+    song: &SongInfo,
+    output_dir: &Path,
+    options: &ConvertOptions,
+) -> Result<PathBuf> {
+    let output_dir = song_output_dir(song, output_dir, options);
+    let file_name = match (&options.name_template, song.get_song_path().extension()) {
+        (Some(_), Some(extension)) => format!(
+            "{}.{}",
+            output_name(song, options)?,
+            extension.to_string_lossy()
+        ),
+        _ => song.get_song_name()?,
+    };
+    Ok(match &options.sanitizer {
+        Some(sanitizer) => output_dir.join(sanitizer.file_name(&file_name)),
+        None => output_dir.join(file_name),
+    })
+}
+
+pub fn convert_songs_parallel(
+    jobs: Vec<ConversionJob>,
+    output_path: &Path,
+    options: Arc<ConvertOptions>,
+    journal: Option<Arc<Journal>>,
+) -> ConversionSummary {
+    let summary = Arc::new(Mutex::new(ConversionSummary::default()));
+    let n_jobs = jobs.len();
+    let progress = progress_bar(n_jobs, options.show_progress && !options.plain_progress);
+    let n_workers = options.convert_jobs;
+    let limit = if options.adaptive_jobs {
+        Some(Arc::new(AdaptiveLimit::new(n_workers)))
+    } else {
+        None
+    };
+    // Set when a song fails and the run is to stop at the first failure
+    let stopped = Arc::new(AtomicBool::new(false));
+    {
+        let summary_lock = summary.clone();
+        let output_path_copy = output_path.to_path_buf();
+        let options_copy = options.clone();
+        let progress = progress.clone();
+        workers::map_parallel(jobs, n_workers, move |job| {
+            let _slot = limit.as_ref().map(|l| l.acquire());
+            let song = &job.song;
+            {
+                let mut s = summary_lock.lock().unwrap();
+                s.n_iterated += 1;
+                tracing::debug!(
+                    n_songs = s.n_iterated,
+                    "Current number of songs iterated through"
+                );
+            }
+            let song_path = song.get_song_path();
+            let started = Instant::now();
+            let not_attempted = stopped.load(Ordering::SeqCst);
+            // The files the song will be written to
+            let outputs = output_paths(&job, &output_path_copy, &options_copy).unwrap_or_default();
+            if let (Some(journal), false) = (&journal, not_attempted) {
+                if !outputs.is_empty() {
+                    if let Err(e) = journal.start(song_path, &outputs) {
+                        tracing::warn!(?song_path, ?e, "Could not record started song");
+                    }
+                }
+            }
+            let mut converted = ConvertedSong::default();
+            let result = match job.action {
+                _ if not_attempted => Ok(None),
+                Action::Convert => {
+                    if let Some(name) = song_path.file_name() {
+                        progress.set_message(name.to_string_lossy().to_string());
+                    }
+                    convert_song(song, &output_path_copy, &options_copy).map(|song| {
+                        converted = song;
+                        Some(())
+                    })
+                }
+                Action::SkipCompliant => match options_copy.copy_compliant {
+                    Some(mode) => copy_song(song, &output_path_copy, &options_copy, mode).map(Some),
+                    None => {
+                        tracing::warn!(?song_path, "Already Rekordbox format!");
+                        Ok(None)
+                    }
+                },
+                Action::SkipUntagged => {
+                    tracing::debug!(?song_path, "Not tagged for conversion!");
+                    Ok(None)
+                }
+                Action::SkipFiltered => {
+                    tracing::debug!(?song_path, "Doesn't match the filter!");
+                    Ok(None)
+                }
+                Action::SkipUnchanged => {
+                    tracing::debug!(?song_path, "Unchanged since last conversion!");
+                    Ok(None)
+                }
+                Action::SkipExisting => {
+                    tracing::info!(?song_path, "Output already exists, keeping it");
+                    Ok(None)
+                }
+                Action::SkipInCollection => {
+                    tracing::debug!(?song_path, "Already in the Rekordbox collection!");
+                    Ok(None)
+                }
+                Action::SkipNoSpace => {
+                    tracing::info!(?song_path, "Doesn't fit on the device");
+                    Ok(None)
+                }
+                Action::Unsupported => Err(anyhow!(
+                    "{} has an unsupported file format!",
+                    song_path.to_string_lossy()
+                )),
+            };
+            let result = match result {
+                Ok(Some(())) if options_copy.verify && job.action == Action::Convert => {
+                    // Speed correction changes the length, so only decoding can be checked
+                    let expected_duration = Some(song.get_duration())
+                        .filter(|d| *d > 0.0 && options_copy.speed_correction_for(song).is_none());
+                    outputs
+                        .iter()
+                        .try_for_each(|output| verify::verify_output(output, expected_duration))
+                        .map(Some)
+                }
+                result => result,
+            };
+            // Before the song is recorded, so the state has the source as retagged
+            if let Ok(Some(())) = &result {
+                if options_copy.untag_source
+                    && job.action == Action::Convert
+                    && !options_copy.conversion_tag.is_empty()
+                {
+                    let tag = options_copy.conversion_tag.as_str();
+                    if let Err(e) = tagging::replace_tag(song_path, tag, "0") {
+                        tracing::warn!(?song_path, ?e, "Could not clear the source's tag");
+                    }
+                }
+            }
+            if let (Ok(Some(())), Some(journal)) = (&result, &journal) {
+                let settings = options_copy.output_settings(song);
+                let throttle = options_copy.read_throttle.as_deref();
+                if let Err(e) = journal.append(song_path, &outputs, &settings, throttle) {
+                    tracing::warn!(?song_path, ?e, "Could not record converted song");
+                }
+            }
+            let mut file_report = FileReport {
+                source: song_path.clone(),
+                decision: Decision::Skipped,
+                reason: None,
+                source_format: report::format_name(song),
+                target_format: None,
+                seconds: started.elapsed().as_secs_f64(),
+                source_bytes: file_size(song_path),
+                output_bytes: None,
+                error: None,
+                key: converted.key,
+                loudness: converted.loudness,
+            };
+            if let Ok(Some(())) = &result {
+                file_report.output_bytes = Some(outputs.iter().map(|p| file_size(p)).sum());
+            }
+            let mut s = summary_lock.lock().unwrap();
+            match &result {
+                Err(e) => {
+                    tracing::error!(?e);
+                    if options_copy.fail_fast {
+                        stopped.store(true, Ordering::SeqCst);
+                    }
+                    s.n_failed += 1;
+                    file_report.decision = Decision::Failed;
+                    file_report.error = Some(format!("{:#}", e));
+                }
+                Ok(None) if not_attempted => {
+                    s.n_skipped += 1;
+                    file_report.reason = Some(i18n::message("not-attempted", &[]));
+                }
+                Ok(None) => {
+                    s.n_skipped += 1;
+                    file_report.reason = Some(job.action.to_string());
+                }
+                Ok(Some(())) if job.action == Action::SkipCompliant => {
+                    file_report.decision = Decision::Copied;
+                    s.n_copied += 1;
+                    s.converted.push(song_path.clone());
+                }
+                Ok(Some(())) => {
+                    file_report.decision = Decision::Converted;
+                    file_report.target_format = job.output_format.as_ref().map(|f| f.to_string());
+                    s.n_converted += 1;
+                    s.converted.push(song_path.clone());
+                    tracing::debug!(
+                        n_converted = s.n_converted,
+                        "Current number of converted songs"
+                    );
+                }
+            }
+            let prefix = progress_prefix(&s, &options_copy);
+            if options_copy.show_progress && options_copy.plain_progress {
+                eprintln!(
+                    "[{}/{}] {} {} ({})",
+                    s.files.len() + 1,
+                    n_jobs,
+                    file_report.decision,
+                    song_path.display(),
+                    prefix
+                );
+            }
+            s.files.push(file_report);
+            progress.set_prefix(prefix);
+            progress.inc(1);
+        });
+    }
+    progress.finish_and_clear();
+    let summary = Arc::try_unwrap(summary)
+        .expect("Should not have more than one reference to the summary")
+        .into_inner()
+        .unwrap();
+    tracing::info!(
+        n_converted = summary.n_converted,
+        n_copied = summary.n_copied,
+        n_skipped = summary.n_skipped,
+        n_failed = summary.n_failed,
+        n_iterated = summary.n_iterated,
+        "Results of conversion"
+    );
+    summary
+}
+
+/// The last lines ffmpeg, or another tool, wrote to stderr, which say what went wrong
+pub(crate) fn stderr_excerpt(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(5)..].join("\n")
+}
+
+/// Size of a file in bytes, or 0 if it can't be read
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Failures so far, and the read throughput if reads are throttled
+fn progress_prefix(summary: &ConversionSummary, options: &ConvertOptions) -> String {
+    match &options.read_throttle {
+        Some(throttle) => i18n::message(
+            "progress-throughput",
+            &[
+                ("failed", summary.n_failed.into()),
+                ("mbps", format!("{:.1}", throttle.throughput_mbps()).into()),
+            ],
+        ),
+        None => i18n::message("progress-failed", &[("failed", summary.n_failed.into())]),
+    }
+}
+
+/// Progress bar showing songs done out of the total, failures, the song being converted and the
+/// estimated time remaining
+fn progress_bar(n_jobs: usize, show: bool) -> ProgressBar {
+    if !show {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(n_jobs as u64);
+    progress.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40} {pos}/{len} ({eta} left) {prefix} {wide_msg}",
+        )
+        .expect("Progress bar template is valid")
+        .progress_chars("=> "),
+    );
+    progress.set_prefix(i18n::message("progress-failed", &[("failed", 0.into())]));
+    progress
+}
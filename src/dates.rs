@@ -0,0 +1,36 @@
+use crate::song_info::SongInfo;
+use clap::ValueEnum;
+
+/// Which date to show as a song's year
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum YearSource {
+    /// The original release date, so a 2021 remaster of a 1992 track shows 1992
+    Original,
+    /// The date of the release the file came from
+    Release,
+}
+
+/// Names the original release date goes by in Vorbis comments, ID3v2.4 and ID3v2.3
+const ORIGINAL_DATE_TAGS: [&str; 4] = ["originaldate", "originalyear", "TDOR", "TORY"];
+/// Names the release date goes by
+const RELEASE_DATE_TAGS: [&str; 4] = ["date", "year", "TDRC", "TYER"];
+
+/// Metadata to write so the year shown is taken from the preferred date. The date it replaces is
+/// kept in RELEASEDATE so it isn't lost.
+pub fn date_metadata(song: &SongInfo, source: YearSource) -> Vec<(String, String)> {
+    if source == YearSource::Release {
+        return vec![];
+    }
+    let original = ORIGINAL_DATE_TAGS.iter().find_map(|t| song.get_tag(t));
+    let release = RELEASE_DATE_TAGS.iter().find_map(|t| song.get_tag(t));
+    match (original, release) {
+        (Some(original), release) if Some(original) != release => {
+            let mut metadata = vec![("date".to_string(), original.to_string())];
+            if let Some(release) = release {
+                metadata.push(("RELEASEDATE".to_string(), release.to_string()));
+            }
+            metadata
+        }
+        _ => vec![],
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Space kept free on a device for the state file, manifest and other files written after the
+/// songs, and for file system overhead
+pub const RESERVED_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Largest file FAT32 can hold
+const FAT32_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// File systems, as mount names them on Linux and macOS, that CDJs and XDJs read
+const PLAYER_FILESYSTEMS: [&str; 5] = ["vfat", "msdos", "fat32", "hfs", "hfsplus"];
+/// File systems only recent players read
+const RECENT_PLAYER_FILESYSTEMS: [&str; 1] = ["exfat"];
+/// What Linux calls file systems mounted through FUSE, like exFAT or NTFS, which can't be told
+/// apart from it
+const FUSE_FILESYSTEM: &str = "fuseblk";
+
+/// What to do when the songs won't fit on the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WhenFull {
+    /// Convert nothing
+    Refuse,
+    /// Convert the songs that fit, in order, and skip the rest
+    Trim,
+}
+
+/// The file system a device is formatted with, and its free space
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filesystem {
+    pub mount_point: PathBuf,
+    /// As mount names it, like vfat or msdos
+    pub kind: String,
+    pub free_bytes: u64,
+}
+
+impl Filesystem {
+    /// The file system a path is on
+    pub fn of(path: &Path) -> Result<Self> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("{} isn't mounted", path.display()))?;
+        let output = Command::new("mount").output()?;
+        if !output.status.success() {
+            return Err(anyhow!("Could not list mounted file systems"));
+        }
+        let mounts = String::from_utf8_lossy(&output.stdout);
+        let (mount_point, kind) = mounts
+            .lines()
+            .filter_map(parse_mount_line)
+            .filter(|(mount_point, _)| path.starts_with(mount_point))
+            .max_by_key(|(mount_point, _)| mount_point.components().count())
+            .ok_or_else(|| anyhow!("{} isn't on a mounted file system", path.display()))?;
+        Ok(Filesystem {
+            free_bytes: free_bytes(&path)?,
+            mount_point,
+            kind,
+        })
+    }
+
+    pub fn is_fat32(&self) -> bool {
+        ["vfat", "msdos", "fat32"].contains(&self.kind.as_str())
+    }
+
+    /// Fails for file systems players can't read, and warns for those only some read
+    pub fn check_readable_by_players(&self) -> Result<()> {
+        let kind = self.kind.as_str();
+        if PLAYER_FILESYSTEMS.contains(&kind) {
+            return Ok(());
+        }
+        if RECENT_PLAYER_FILESYSTEMS.contains(&kind) {
+            tracing::warn!(
+                mount_point = ?self.mount_point,
+                "The device is formatted as {}, which only recent players read, FAT32 works \
+                 with all of them",
+                kind
+            );
+            return Ok(());
+        }
+        if kind == FUSE_FILESYSTEM {
+            tracing::warn!(
+                mount_point = ?self.mount_point,
+                "Can't tell what the device is formatted as, players only read FAT32, HFS+ and \
+                 on recent models exFAT"
+            );
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{} is formatted as {}, which players can't read, format it as FAT32",
+            self.mount_point.display(),
+            kind
+        ))
+    }
+}
+
+/// The mount point and file system type of a line of mount's output, which is like
+/// "/dev/sdb1 on /media/DJUSB type vfat (rw,...)" on Linux and
+/// "/dev/disk2s1 on /Volumes/DJUSB (msdos, local, ...)" on macOS
+fn parse_mount_line(line: &str) -> Option<(PathBuf, String)> {
+    let (_, rest) = line.split_once(" on ")?;
+    if let Some((mount_point, rest)) = rest.rsplit_once(" type ") {
+        let kind = rest.split_whitespace().next()?;
+        return Some((PathBuf::from(mount_point), kind.to_lowercase()));
+    }
+    let (mount_point, options) = rest.rsplit_once(" (")?;
+    let kind = options.split([',', ')']).next()?.trim();
+    Some((PathBuf::from(mount_point), kind.to_lowercase()))
+}
+
+/// Free space on the file system a path is on, from POSIX df
+fn free_bytes(path: &Path) -> Result<u64> {
+    let output = Command::new("df").arg("-P").arg("-k").arg(path).output()?;
+    if !output.status.success() {
+        return Err(anyhow!("Could not find out the free space of {:?}", path));
+    }
+    // The line after the header, with the available 1024 byte blocks in its fourth column
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|blocks| blocks.parse::<u64>().ok())
+        .map(|blocks| blocks * 1024)
+        .ok_or_else(|| anyhow!("Could not read the free space of {:?} from df", path))
+}
+
+/// Which of a batch of songs, by the bytes each is estimated to take, go on the device: all of
+/// them if they fit, else, when trimming, those that fit in order. Songs too big for FAT32 are
+/// left out when trimming too.
+pub fn fit(estimates: &[u64], filesystem: &Filesystem, when_full: WhenFull) -> Result<Vec<bool>> {
+    let available = filesystem.free_bytes.saturating_sub(RESERVED_BYTES);
+    let too_big = |size: u64| filesystem.is_fat32() && size > FAT32_MAX_FILE_SIZE;
+    let total: u64 = estimates.iter().sum();
+    tracing::info!(
+        needed = total,
+        available,
+        "Checked the songs fit on {}",
+        filesystem.mount_point.display()
+    );
+    if when_full == WhenFull::Refuse {
+        if let Some(size) = estimates.iter().find(|size| too_big(**size)) {
+            return Err(anyhow!(
+                "A song would take {} MB, more than FAT32 holds in a file",
+                size / 1_000_000
+            ));
+        }
+        if total > available {
+            return Err(anyhow!(
+                "The songs would take about {} MB but {} only has {} MB free, pass --when-full \
+                 trim to convert those that fit",
+                total / 1_000_000,
+                filesystem.mount_point.display(),
+                available / 1_000_000
+            ));
+        }
+        return Ok(vec![true; estimates.len()]);
+    }
+    let mut left = available;
+    Ok(estimates
+        .iter()
+        .map(|size| {
+            let fits = *size <= left && !too_big(*size);
+            if fits {
+                left -= size;
+            }
+            fits
+        })
+        .collect())
+}
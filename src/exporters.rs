@@ -0,0 +1,449 @@
+use crate::analysis_cache::AnalysisCache;
+use crate::analyzers::{self, Analyzer, TrackAnalysis};
+use crate::backup;
+use crate::bpm_octave::{self, BpmRule};
+use crate::imported_analysis::{self, MikExport};
+use crate::read_only;
+use crate::rekordbox_xml::RekordboxXml;
+use crate::report::csv_field;
+use crate::serato::SeratoCrates;
+use crate::song_info::{self, ProbeBackend, SongInfo};
+use crate::state::{self, ConversionState};
+use crate::tempo_range::{self, BpmRange, TempoWarning};
+use crate::variants;
+use crate::workers;
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A converted song, as library formats see it
+#[derive(Clone, Debug)]
+pub struct TrackRecord {
+    /// TrackID, kept across exports
+    pub id: u32,
+    /// The converted file
+    pub path: PathBuf,
+    /// The file it was converted from
+    pub source: PathBuf,
+    pub song: SongInfo,
+    pub size: u64,
+    /// Day the file was converted, like 2024-01-31
+    pub date_added: String,
+    /// When the source was first converted, or the file was written if that isn't recorded
+    pub converted: SystemTime,
+    /// What the analyzers picked for the export found, empty if none were
+    pub analysis: TrackAnalysis,
+    /// The group of acapella, instrumental and dub versions of a track this is in, if any
+    pub variant_group: Option<String>,
+}
+
+impl TrackRecord {
+    fn read(
+        id: u32,
+        source: PathBuf,
+        path: PathBuf,
+        converted: Option<u64>,
+        probe_backend: ProbeBackend,
+    ) -> Result<Self> {
+        let metadata = fs::metadata(&path)?;
+        let song = song_info::from_file_cached(&path, None, probe_backend)?;
+        let converted = match converted {
+            Some(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            None => metadata.modified()?,
+        };
+        Ok(TrackRecord {
+            id,
+            size: metadata.len(),
+            date_added: backup::date(metadata.modified()?),
+            converted,
+            path,
+            source,
+            song,
+            analysis: TrackAnalysis::default(),
+            variant_group: None,
+        })
+    }
+
+    /// The title tag, or the file name without its extension if there is none
+    pub fn title(&self) -> String {
+        self.song
+            .get_tag("title")
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                self.path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            })
+    }
+
+    pub fn artist(&self) -> &str {
+        self.song.get_tag("artist").unwrap_or_default()
+    }
+
+    /// Year of the date tag, or an empty string if there is none
+    pub fn year(&self) -> String {
+        self.song
+            .get_tag("date")
+            .or_else(|| self.song.get_tag("year"))
+            .unwrap_or_default()
+            .chars()
+            .take(4)
+            .collect()
+    }
+}
+
+/// A named list of tracks, by TrackID
+#[derive(Clone, Debug)]
+pub struct Playlist {
+    pub name: String,
+    pub track_ids: Vec<u32>,
+}
+
+/// A file an exporter produced, by its path relative to the directory it is exported into
+#[derive(Clone, Debug)]
+pub struct ExportedFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+/// A DJ software library or playlist format the converted tracks can be exported to. Exporters
+/// only build the files; writing them is left to export, so a new format only needs an
+/// implementation and an entry in EXPORTERS.
+pub trait Exporter: Sync {
+    /// Name the format is chosen by on the command line
+    fn name(&self) -> &'static str;
+    /// What the format is for, for --help and error messages
+    fn description(&self) -> &'static str;
+    fn export(&self, tracks: &[TrackRecord], playlists: &[Playlist]) -> Result<Vec<ExportedFile>>;
+}
+
+/// Every format tracks can be exported to
+pub static EXPORTERS: [&dyn Exporter; 4] = [&RekordboxXml, &M3u8, &Csv, &SeratoCrates];
+
+/// The exporter of a format, by its name
+pub fn find(name: &str) -> Result<&'static dyn Exporter> {
+    EXPORTERS
+        .iter()
+        .copied()
+        .find(|e| e.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = EXPORTERS.iter().map(|e| e.name()).collect();
+            anyhow!(
+                "No export format called {}, choose one of: {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// Reads the tracks converted into an output directory, giving new ones a TrackID. Each track
+/// keeps its TrackID in the state file, so exporting again after converting more songs, or
+/// after re-converting changed ones, gives the same IDs and re-importing updates the existing
+/// entries instead of adding duplicates. Songs split into several stem files are exported as
+/// their first file.
+pub fn tracks(output_dir: &Path, probe_backend: ProbeBackend) -> Result<Vec<TrackRecord>> {
+    // Saving the state folds the journal into it, which would hide a cut short run from --resume
+    if state::interrupted_run(output_dir)?.is_some() {
+        return Err(anyhow!(
+            "A conversion into {} is still running or was cut short, finish it before exporting",
+            output_dir.display()
+        ));
+    }
+    let mut conversion_state = ConversionState::load(output_dir)?;
+    if conversion_state.assign_track_ids() {
+        conversion_state.save(output_dir)?;
+    }
+    let exported: Vec<(u32, PathBuf, PathBuf, Option<u64>)> = conversion_state
+        .sources()
+        .filter_map(|(source, record)| {
+            let output = record.outputs.iter().find(|o| o.is_file())?;
+            Some((
+                record.track_id?,
+                source.clone(),
+                output.clone(),
+                record.converted,
+            ))
+        })
+        .collect();
+    let tracks = workers::map_parallel(
+        exported,
+        workers::default_jobs(),
+        move |(id, source, path, converted)| match TrackRecord::read(
+            id,
+            source,
+            path.clone(),
+            converted,
+            probe_backend,
+        ) {
+            Ok(track) => Some(track),
+            Err(e) => {
+                tracing::warn!(?path, ?e, "Leaving song out of the export");
+                None
+            }
+        },
+    );
+    let mut tracks: Vec<TrackRecord> = tracks.into_iter().flatten().collect();
+    let songs: Vec<&SongInfo> = tracks.iter().map(|t| &t.song).collect();
+    let groups = variants::arrangement_groups(&songs);
+    let mut linked = false;
+    for (track, group) in tracks.iter_mut().zip(groups) {
+        linked |= conversion_state.set_variant_group(&track.source, group.clone());
+        track.variant_group = group;
+    }
+    if linked {
+        conversion_state.save(output_dir)?;
+    }
+    Ok(tracks)
+}
+
+/// A playlist for each track converted together with its acapella, instrumental or dub
+/// versions, holding them all, named after the track
+pub fn variant_playlists(tracks: &[TrackRecord]) -> Vec<Playlist> {
+    let mut groups: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+    for track in tracks {
+        if let Some(group) = &track.variant_group {
+            groups.entry(group).or_default().push(track.id);
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(name, track_ids)| Playlist {
+            name: name.to_string(),
+            track_ids,
+        })
+        .collect()
+}
+
+/// The playlist the tracks converted into an output directory are exported in, named after the
+/// directory
+pub fn playlist(output_dir: &Path, tracks: &[TrackRecord]) -> Result<Playlist> {
+    let name = output_dir
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Converted".to_string());
+    Ok(Playlist {
+        name,
+        track_ids: tracks.iter().map(|t| t.id).collect(),
+    })
+}
+
+/// Name of the playlist of recently converted tracks
+pub const NEW_PLAYLIST_NAME: &str = "New This Month";
+
+/// A playlist of the tracks first converted less than `days` days before `now`, newest first.
+/// Tracks age out of it as later exports are made.
+pub fn new_playlist(tracks: &[TrackRecord], days: u64, now: SystemTime) -> Playlist {
+    let since = now
+        .checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+        .unwrap_or(UNIX_EPOCH);
+    let mut recent: Vec<&TrackRecord> = tracks.iter().filter(|t| t.converted >= since).collect();
+    recent.sort_by_key(|t| std::cmp::Reverse(t.converted));
+    Playlist {
+        name: NEW_PLAYLIST_NAME.to_string(),
+        track_ids: recent.iter().map(|t| t.id).collect(),
+    }
+}
+
+/// The playlists tracks converted into an output directory are exported in: one named after the
+/// directory, and one of the tracks converted in the last `new_playlist_days` days if given
+pub fn playlists(
+    output_dir: &Path,
+    tracks: &[TrackRecord],
+    new_playlist_days: Option<u64>,
+) -> Result<Vec<Playlist>> {
+    let mut playlists = vec![playlist(output_dir, tracks)?];
+    if let Some(days) = new_playlist_days {
+        playlists.push(new_playlist(tracks, days, SystemTime::now()));
+    }
+    Ok(playlists)
+}
+
+/// What an export wrote, and what it found wrong with the playlists
+#[derive(Clone, Debug, Default)]
+pub struct ExportSummary {
+    pub written: Vec<PathBuf>,
+    /// Tracks far outside the BPM range declared for their playlist
+    pub tempo_warnings: Vec<TempoWarning>,
+}
+
+/// What an export does besides writing the library files
+#[derive(Clone, Default)]
+pub struct ExportOptions {
+    /// Analyzers the tracks are run through
+    pub analyzers: Vec<&'static dyn Analyzer>,
+    /// Where what the analyzers found is kept between runs
+    pub analysis_cache: Option<Arc<AnalysisCache>>,
+    /// The tempos declared for playlists, by playlist name
+    pub bpm_ranges: HashMap<String, BpmRange>,
+    /// What detected tempos are resolved between half and double time by
+    pub bpm_rules: Vec<BpmRule>,
+    /// Keys and tempos Mixed In Key analyzed, kept over what the analyzers found
+    pub mik_export: Option<MikExport>,
+    /// Also export a playlist of the tracks converted in this many days
+    pub new_playlist_days: Option<u64>,
+}
+
+/// Exports the tracks converted into an output directory, in a playlist named after it and
+/// optionally one of recently converted tracks, and writes the files into `to_dir`. The tracks are first run through the analyzers, if any are
+/// given, with detected tempos resolved between half and double time by the BPM rules, and keys
+/// and tempos imported from Mixed In Key or the tags it writes kept over detected ones. The
+/// playlists are then checked against the BPM ranges declared for them.
+pub fn export(
+    exporter: &dyn Exporter,
+    output_dir: &Path,
+    to_dir: &Path,
+    probe_backend: ProbeBackend,
+    options: &ExportOptions,
+) -> Result<ExportSummary> {
+    let mut tracks = tracks(output_dir, probe_backend)?;
+    analyzers::analyze_tracks(
+        &mut tracks,
+        &options.analyzers,
+        options.analysis_cache.clone(),
+    );
+    if let Some(cache) = &options.analysis_cache {
+        cache.save()?;
+    }
+    bpm_octave::disambiguate(&mut tracks, output_dir, &options.bpm_rules)?;
+    imported_analysis::apply(&mut tracks, options.mik_export.as_ref());
+    let playlists = playlists(output_dir, &tracks, options.new_playlist_days)?;
+    let tempo_warnings = tempo_range::check(&options.bpm_ranges, &tracks, &playlists);
+    let mut written = vec![];
+    for file in exporter.export(&tracks, &playlists)? {
+        let path = to_dir.join(&file.path);
+        read_only::check_writable(&path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        state::write_atomic(&path, &file.contents)?;
+        written.push(path);
+    }
+    Ok(ExportSummary {
+        written,
+        tempo_warnings,
+    })
+}
+
+/// A file name made of a playlist name, with characters that aren't allowed in file names
+/// replaced
+pub(crate) fn file_name(name: &str, extension: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    format!("{}.{}", name, extension)
+}
+
+/// Extended M3U playlists, in UTF-8, which most players and DJ software read
+pub struct M3u8;
+
+impl Exporter for M3u8 {
+    fn name(&self) -> &'static str {
+        "m3u8"
+    }
+
+    fn description(&self) -> &'static str {
+        "a UTF-8 M3U playlist per playlist"
+    }
+
+    fn export(&self, tracks: &[TrackRecord], playlists: &[Playlist]) -> Result<Vec<ExportedFile>> {
+        Ok(playlists
+            .iter()
+            .map(|playlist| {
+                let mut m3u = String::from("#EXTM3U\n");
+                for track in playlist_tracks(playlist, tracks) {
+                    let _ = writeln!(
+                        m3u,
+                        "#EXTINF:{},{} - {}",
+                        track.song.get_duration().round() as u64,
+                        track.artist(),
+                        track.title()
+                    );
+                    let _ = writeln!(m3u, "{}", track.path.display());
+                }
+                ExportedFile {
+                    path: PathBuf::from(file_name(&playlist.name, "m3u8")),
+                    contents: m3u.into_bytes(),
+                }
+            })
+            .collect())
+    }
+}
+
+/// A spreadsheet of the tracks and their tags
+pub struct Csv;
+
+impl Exporter for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn description(&self) -> &'static str {
+        "a CSV table of every track"
+    }
+
+    fn export(&self, tracks: &[TrackRecord], _playlists: &[Playlist]) -> Result<Vec<ExportedFile>> {
+        let mut csv = String::from(
+            "track_id,title,artist,album,genre,year,seconds,sample_rate,bytes,loudness_lufs,\
+             peak_dbfs,cutoff_hz,bpm,key,key_confidence,path\n",
+        );
+        // Analyses that weren't run are left empty
+        let optional = |value: Option<f64>| value.map(|v| format!("{:.1}", v)).unwrap_or_default();
+        for track in tracks {
+            let song = &track.song;
+            let fields = [
+                track.id.to_string(),
+                track.title(),
+                track.artist().to_string(),
+                song.get_tag("album").unwrap_or_default().to_string(),
+                song.get_tag("genre").unwrap_or_default().to_string(),
+                track.year(),
+                format!("{:.1}", song.get_duration()),
+                song.get_sample_rate().to_string(),
+                track.size.to_string(),
+                optional(track.analysis.loudness),
+                optional(track.analysis.peak),
+                optional(track.analysis.cutoff_hz),
+                track
+                    .analysis
+                    .bpm
+                    .map(|bpm| bpm.to_string())
+                    .unwrap_or_default(),
+                track.analysis.key.clone().unwrap_or_default(),
+                track
+                    .analysis
+                    .key_confidence
+                    .map(|confidence| confidence.to_string())
+                    .unwrap_or_default(),
+                track.path.display().to_string(),
+            ];
+            let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            let _ = writeln!(csv, "{}", fields.join(","));
+        }
+        Ok(vec![ExportedFile {
+            path: PathBuf::from("tracks.csv"),
+            contents: csv.into_bytes(),
+        }])
+    }
+}
+
+/// The tracks of a playlist, in its order
+pub(crate) fn playlist_tracks<'a>(
+    playlist: &'a Playlist,
+    tracks: &'a [TrackRecord],
+) -> impl Iterator<Item = &'a TrackRecord> {
+    playlist
+        .track_ids
+        .iter()
+        .filter_map(move |id| tracks.iter().find(|t| t.id == *id))
+}
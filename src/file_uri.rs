@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// The file URL of an absolute path, as Rekordbox expects in Locations and playlist players
+/// accept: file://localhost/Users/me/a%20song.aiff, or file://localhost/C:/Users/me/... on
+/// Windows. Everything but unreserved characters and separators is percent-encoded as UTF-8.
+pub fn to_uri(path: &Path) -> String {
+    uri_from_bytes(&path_bytes(path), cfg!(windows))
+}
+
+/// The path a file URL made by to_uri, or by Rekordbox, points to
+pub fn from_uri(uri: &str) -> Result<PathBuf> {
+    path_from_bytes(uri_to_bytes(uri, cfg!(windows))?)
+}
+
+fn uri_from_bytes(path: &[u8], windows: bool) -> String {
+    if !windows {
+        return format!("file://localhost{}", encode(path, false));
+    }
+    let mut path: Vec<u8> = path
+        .iter()
+        .map(|b| if *b == b'\\' { b'/' } else { *b })
+        .collect();
+    // Paths from canonicalize start with \\?\, or \\?\UNC\ for network shares
+    if path.starts_with(b"//?/UNC/") {
+        path.drain(2..8);
+    } else if path.starts_with(b"//?/") {
+        path.drain(..4);
+    }
+    // A network share, //server/share/..., whose server becomes the host
+    if let Some(share) = path.strip_prefix(b"//") {
+        let (host, rest) = match share.iter().position(|b| *b == b'/') {
+            Some(i) => share.split_at(i),
+            None => (share, &b""[..]),
+        };
+        return format!("file://{}{}", encode(host, false), encode(rest, false));
+    }
+    path.insert(0, b'/');
+    format!("file://localhost{}", encode(&path, true))
+}
+
+fn uri_to_bytes(uri: &str, windows: bool) -> Result<Vec<u8>> {
+    let rest = uri
+        .strip_prefix("file://")
+        .ok_or_else(|| anyhow!("Not a file URL: {}", uri))?;
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = decode(path).ok_or_else(|| anyhow!("Broken percent-encoding in {}", uri))?;
+    let local = host.is_empty() || host.eq_ignore_ascii_case("localhost");
+    match (windows, local) {
+        (false, true) => Ok(path),
+        (false, false) => Err(anyhow!("File URL on another host: {}", uri)),
+        (true, _) => {
+            let mut bytes = if local {
+                // /C:/... loses its leading slash
+                match path.as_slice() {
+                    [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_vec(),
+                    _ => path,
+                }
+            } else {
+                let host = decode(host).ok_or_else(|| anyhow!("Broken host in {}", uri))?;
+                [&b"//"[..], &host, &path].concat()
+            };
+            for b in bytes.iter_mut() {
+                if *b == b'/' {
+                    *b = b'\\';
+                }
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and slashes, and the colon of a
+/// Windows drive letter
+fn encode(path: &[u8], drive_letter: bool) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for (i, byte) in path.iter().enumerate() {
+        let is_drive_colon =
+            drive_letter && i == 2 && *byte == b':' && path[1].is_ascii_alphabetic();
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(*byte as char)
+            }
+            _ if is_drive_colon => encoded.push(':'),
+            _ => {
+                let _ = write!(encoded, "%{:02X}", byte);
+            }
+        }
+    }
+    encoded
+}
+
+/// Undoes percent-encoding, or None if a % doesn't start an escape
+fn decode(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// The bytes of a path: exactly as stored on Unix, where names needn't be UTF-8, and as UTF-8
+/// elsewhere
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    Ok(PathBuf::from(String::from_utf8(bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(path: &str, windows: bool) -> String {
+        let uri = uri_from_bytes(path.as_bytes(), windows);
+        let back = uri_to_bytes(&uri, windows).unwrap();
+        assert_eq!(String::from_utf8(back).unwrap(), path, "through {}", uri);
+        uri
+    }
+
+    #[test]
+    fn plain_unix_path() {
+        assert_eq!(
+            round_trip("/Users/dj/Music/track.aiff", false),
+            "file://localhost/Users/dj/Music/track.aiff"
+        );
+    }
+
+    #[test]
+    fn spaces_and_reserved_characters() {
+        assert_eq!(
+            round_trip("/Music/A B #1 (50% mix) & more?.mp3", false),
+            "file://localhost/Music/A%20B%20%231%20%2850%25%20mix%29%20%26%20more%3F.mp3"
+        );
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(
+            round_trip("/Music/Beyoncé/日本.flac", false),
+            "file://localhost/Music/Beyonc%C3%A9/%E6%97%A5%E6%9C%AC.flac"
+        );
+    }
+
+    #[test]
+    fn windows_drive_letter() {
+        assert_eq!(
+            round_trip(r"C:\Users\dj\Music\a b.aiff", true),
+            "file://localhost/C:/Users/dj/Music/a%20b.aiff"
+        );
+    }
+
+    #[test]
+    fn windows_colon_elsewhere_is_encoded() {
+        assert_eq!(
+            round_trip(r"D:\Music\Intro: Outro.mp3", true),
+            "file://localhost/D:/Music/Intro%3A%20Outro.mp3"
+        );
+    }
+
+    #[test]
+    fn windows_verbatim_prefix() {
+        let uri = uri_from_bytes(br"\\?\C:\Music\x.mp3", true);
+        assert_eq!(uri, "file://localhost/C:/Music/x.mp3");
+        assert_eq!(
+            uri_to_bytes(&uri, true).unwrap(),
+            br"C:\Music\x.mp3".to_vec()
+        );
+    }
+
+    #[test]
+    fn windows_network_share() {
+        assert_eq!(
+            round_trip(r"\\nas\music\a b.mp3", true),
+            "file://nas/music/a%20b.mp3"
+        );
+        let uri = uri_from_bytes(br"\\?\UNC\nas\music\x.mp3", true);
+        assert_eq!(uri, "file://nas/music/x.mp3");
+    }
+
+    #[test]
+    fn rekordbox_style_uri_without_localhost() {
+        assert_eq!(
+            uri_to_bytes("file:///Music/a%20b.mp3", false).unwrap(),
+            b"/Music/a b.mp3".to_vec()
+        );
+    }
+
+    #[test]
+    fn lowercase_escapes_decode() {
+        assert_eq!(
+            uri_to_bytes("file://localhost/a%c3%a9", false).unwrap(),
+            "/aé".as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn broken_uris_are_rejected() {
+        assert!(uri_to_bytes("/Music/a.mp3", false).is_err());
+        assert!(uri_to_bytes("file://localhost/a%2", false).is_err());
+        assert!(uri_to_bytes("file://localhost/a%zz", false).is_err());
+        assert!(uri_to_bytes("file://nas/music/a.mp3", false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_unix_path() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/Music/\xff.mp3"));
+        let uri = to_uri(path);
+        assert_eq!(uri, "file://localhost/Music/%FF.mp3");
+        assert_eq!(from_uri(&uri).unwrap(), path);
+    }
+}
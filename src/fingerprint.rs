@@ -0,0 +1,121 @@
+use crate::analyzers::{Analysis, Analyzer, TrackAnalysis, CHANNELS, SAMPLE_RATE};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::io::Write;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Chromaprint's command line tool, which ships with the library
+const FPCALC: &str = "fpcalc";
+
+/// An AcoustID fingerprint, which identifies a recording whatever its encoding, from the
+/// decoded samples piped into fpcalc
+pub struct Fingerprint;
+
+impl Analyzer for Fingerprint {
+    fn name(&self) -> &'static str {
+        "fingerprint"
+    }
+
+    fn description(&self) -> &'static str {
+        "a Chromaprint fingerprint, with fpcalc"
+    }
+
+    fn check(&self) -> Result<()> {
+        let found = Command::new(FPCALC)
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if found {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} isn't installed, install Chromaprint to get fingerprints",
+                FPCALC
+            ))
+        }
+    }
+
+    fn start(&self) -> Box<dyn Analysis> {
+        let fpcalc = Command::new(FPCALC)
+            .arg("-json")
+            .arg("-format")
+            .arg("s16le")
+            .arg("-rate")
+            .arg(SAMPLE_RATE.to_string())
+            .arg("-channels")
+            .arg(CHANNELS.to_string())
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        match fpcalc {
+            Ok(mut fpcalc) => Box::new(FingerprintAnalysis {
+                stdin: fpcalc.stdin.take(),
+                fpcalc: Some(fpcalc),
+                bytes: vec![],
+            }),
+            Err(e) => {
+                tracing::warn!(?e, "Could not start fpcalc");
+                Box::new(FingerprintAnalysis {
+                    stdin: None,
+                    fpcalc: None,
+                    bytes: vec![],
+                })
+            }
+        }
+    }
+}
+
+struct FingerprintAnalysis {
+    fpcalc: Option<Child>,
+    /// Closed once fpcalc has heard enough; it only fingerprints the start of a song
+    stdin: Option<ChildStdin>,
+    bytes: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct FpcalcOutput {
+    fingerprint: String,
+}
+
+impl Analysis for FingerprintAnalysis {
+    fn feed(&mut self, samples: &[f32]) {
+        let stdin = match &mut self.stdin {
+            Some(stdin) => stdin,
+            None => return,
+        };
+        self.bytes.clear();
+        self.bytes.extend(
+            samples.iter().flat_map(|sample| {
+                ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes()
+            }),
+        );
+        if stdin.write_all(&self.bytes).is_err() {
+            self.stdin = None;
+        }
+    }
+
+    fn finish(mut self: Box<Self>, analysis: &mut TrackAnalysis) {
+        // fpcalc prints the fingerprint once its input ends
+        self.stdin = None;
+        let output = match self.fpcalc.take().map(Child::wait_with_output) {
+            Some(Ok(output)) if output.status.success() => output,
+            Some(Ok(output)) => {
+                tracing::warn!(status = ?output.status, "fpcalc failed");
+                return;
+            }
+            Some(Err(e)) => {
+                tracing::warn!(?e, "fpcalc failed");
+                return;
+            }
+            None => return,
+        };
+        match serde_json::from_slice::<FpcalcOutput>(&output.stdout) {
+            Ok(parsed) => analysis.fingerprint = Some(parsed.fingerprint),
+            Err(e) => tracing::warn!(?e, "Could not read what fpcalc printed"),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+/// Languages user-facing messages are translated into, with their Fluent messages
+const LANGUAGES: [(&str, &str); 4] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+    ("ja", include_str!("../locales/ja.ftl")),
+];
+
+/// Language used when none is chosen, and for messages missing from a translation
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Messages in the chosen language, and in the default language to fall back on
+struct Localizer {
+    bundle: FluentBundle<FluentResource>,
+    fallback: FluentBundle<FluentResource>,
+}
+
+static LOCALIZER: OnceLock<Localizer> = OnceLock::new();
+
+/// Chooses the language of messages: the given one if it is supported, otherwise the one the
+/// environment asks for with LC_ALL, LC_MESSAGES or LANG, otherwise English. Only the first
+/// call has any effect; messages are in English if it is never called.
+pub fn init(language: Option<&str>) {
+    if let Some(language) = language.filter(|l| supported(l).is_none()) {
+        tracing::warn!(?language, "Messages aren't translated into this language");
+    }
+    let language = language
+        .and_then(supported)
+        .or_else(|| language_from_env().and_then(|l| supported(&l)))
+        .unwrap_or(DEFAULT_LANGUAGE);
+    let _ = LOCALIZER.set(Localizer {
+        bundle: bundle(language),
+        fallback: bundle(DEFAULT_LANGUAGE),
+    });
+}
+
+/// A message in the chosen language, with its arguments filled in. Falls back to the English
+/// message, and to the message id if there is none.
+pub fn message(id: &str, args: &[(&str, FluentValue)]) -> String {
+    let localizer = LOCALIZER.get_or_init(|| Localizer {
+        bundle: bundle(DEFAULT_LANGUAGE),
+        fallback: bundle(DEFAULT_LANGUAGE),
+    });
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, value.clone());
+    }
+    for bundle in [&localizer.bundle, &localizer.fallback] {
+        if let Some(pattern) = bundle.get_message(id).and_then(|m| m.value()) {
+            let mut errors = vec![];
+            let text = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+            if !errors.is_empty() {
+                tracing::debug!(?id, ?errors, "Could not format message");
+            }
+            return text.to_string();
+        }
+    }
+    id.to_string()
+}
+
+/// The supported language a language tag like "de-AT" or "de_DE.UTF-8" is in
+fn supported(tag: &str) -> Option<&'static str> {
+    let code = tag.split(['-', '_', '.']).next()?;
+    LANGUAGES
+        .iter()
+        .map(|(c, _)| *c)
+        .find(|c| c.eq_ignore_ascii_case(code))
+}
+
+fn language_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+fn bundle(language: &str) -> FluentBundle<FluentResource> {
+    let source = LANGUAGES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map(|(_, source)| *source)
+        .unwrap_or("");
+    let language_id: LanguageIdentifier = language.parse().expect("Language codes are valid");
+    let mut bundle = FluentBundle::new_concurrent(vec![language_id]);
+    // Isolation marks around arguments show up as garbage in terminals and CSV files
+    bundle.set_use_isolating(false);
+    // The messages are built in, so a syntax error is a bug; whatever did parse is still used
+    let resource =
+        FluentResource::try_new(source.to_string()).unwrap_or_else(|(resource, _)| resource);
+    if let Err(errors) = bundle.add_resource(resource) {
+        tracing::debug!(?language, ?errors, "Could not add messages");
+    }
+    bundle
+}
@@ -0,0 +1,175 @@
+use crate::anlz;
+use crate::exporters::TrackRecord;
+use crate::key;
+use crate::song_info::SongInfo;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Headers of the columns a Mixed In Key CSV export keeps the file name, key and tempo in, in
+/// lower case. Older versions call them differently.
+const FILE_COLUMNS: [&str; 3] = ["file name", "filename", "file"];
+const KEY_COLUMNS: [&str; 3] = ["key result", "key", "initial key"];
+const BPM_COLUMNS: [&str; 2] = ["bpm", "tempo"];
+
+/// A song's key and tempo as another program, like Mixed In Key or KeyFinder, analyzed them,
+/// with the key in the notation keys are detected in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportedAnalysis {
+    pub key: Option<String>,
+    pub bpm: Option<f64>,
+}
+
+impl ImportedAnalysis {
+    fn or(self, other: ImportedAnalysis) -> ImportedAnalysis {
+        ImportedAnalysis {
+            key: self.key.or(other.key),
+            bpm: self.bpm.or(other.bpm),
+        }
+    }
+}
+
+/// The songs of a Mixed In Key CSV export, by file name, as that is all older versions export
+#[derive(Clone, Debug, Default)]
+pub struct MikExport {
+    songs: HashMap<String, ImportedAnalysis>,
+}
+
+impl MikExport {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let contents = String::from_utf8_lossy(&contents);
+        let contents = contents.trim_start_matches('\u{feff}');
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow!("{} is empty", path.display()))?;
+        // Exports made with a European locale separate fields with semicolons
+        let separator = if header.contains(';') && !header.contains(',') {
+            ';'
+        } else {
+            ','
+        };
+        let header: Vec<String> = split_csv_line(header, separator)
+            .iter()
+            .map(|h| h.trim().to_lowercase())
+            .collect();
+        let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+        let file_column = column(&FILE_COLUMNS).ok_or_else(|| {
+            anyhow!(
+                "{} has no file name column, is it a Mixed In Key CSV export?",
+                path.display()
+            )
+        })?;
+        let key_column = column(&KEY_COLUMNS);
+        let bpm_column = column(&BPM_COLUMNS);
+        let mut songs = HashMap::new();
+        for line in lines {
+            let fields = split_csv_line(line, separator);
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|c| fields.get(c))
+                    .map(|f| f.trim())
+                    .filter(|f| !f.is_empty())
+            };
+            let file = match field(Some(file_column)) {
+                Some(file) => file_name_key(file),
+                None => continue,
+            };
+            let analysis = ImportedAnalysis {
+                key: field(key_column).and_then(key::normalize),
+                bpm: field(bpm_column)
+                    .and_then(|bpm| bpm.replace(',', ".").parse::<f64>().ok())
+                    .filter(|bpm| *bpm > 0.0),
+            };
+            songs.insert(file, analysis);
+        }
+        tracing::info!(n_songs = songs.len(), ?path, "Read Mixed In Key export");
+        Ok(MikExport { songs })
+    }
+
+    /// What the export has for a song, by its file name
+    pub fn get(&self, path: &Path) -> Option<&ImportedAnalysis> {
+        let name = path.file_name()?.to_string_lossy();
+        self.songs.get(&file_name_key(&name))
+    }
+}
+
+/// The file name a file name or path in an export is looked up by, in lower case
+fn file_name_key(file: &str) -> String {
+    let name = file.rsplit(['/', '\\']).next().unwrap_or(file);
+    name.to_lowercase()
+}
+
+/// The fields of a CSV line, with quoted fields unquoted
+fn split_csv_line(line: &str, separator: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The key and tempo a song is tagged with, in the places Mixed In Key and KeyFinder write
+/// them: the key tag, in any notation, or the start of the comment, like "8A - Energy 6"
+pub fn from_tags(song: &SongInfo) -> ImportedAnalysis {
+    // A comment is only taken to start with a key in Camelot or Open Key notation, as one
+    // starting with a word like "A" or "Em" isn't necessarily a key, unless that is all it is
+    let comment_key = || {
+        let comment = song.get_tag("comment")?.trim();
+        let first = comment.split([' ', '-', '/', ';']).next()?;
+        if first.starts_with(|c: char| c.is_ascii_digit()) || first == comment {
+            key::normalize(first)
+        } else {
+            None
+        }
+    };
+    ImportedAnalysis {
+        key: key::tagged_key(song)
+            .and_then(key::normalize)
+            .or_else(comment_key),
+        bpm: anlz::tagged_bpm(song),
+    }
+}
+
+/// A song's key and tempo from the Mixed In Key export, if it has them, or else from its tags
+pub fn for_song(song: &SongInfo, export: Option<&MikExport>) -> ImportedAnalysis {
+    let exported = export
+        .and_then(|export| export.get(song.get_song_path()))
+        .cloned()
+        .unwrap_or_default();
+    exported.or(from_tags(song))
+}
+
+/// Replaces the key and tempo analyzers found in exported tracks with those imported for their
+/// source files, as what Mixed In Key or KeyFinder found is kept over built-in detection
+pub fn apply(tracks: &mut [TrackRecord], export: Option<&MikExport>) {
+    for track in tracks.iter_mut() {
+        let exported = export
+            .and_then(|export| export.get(&track.source))
+            .cloned()
+            .unwrap_or_default();
+        let imported = exported.or(from_tags(&track.song));
+        if imported.key.is_some() {
+            track.analysis.key = imported.key;
+            track.analysis.key_confidence = None;
+        }
+        if imported.bpm.is_some() {
+            track.analysis.bpm = imported.bpm;
+        }
+    }
+}
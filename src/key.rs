@@ -0,0 +1,294 @@
+use crate::analyzers::{Analysis, Analyzer, TrackAnalysis, CHANNELS, SAMPLE_RATE};
+use crate::song_info::SongInfo;
+use crate::spectrum;
+use crate::state;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File in the output directory listing the songs whose key was detected with too little
+/// confidence to tag, to check by ear
+pub const REVIEW_FILE_NAME: &str = "key-review.json";
+
+/// Confidence below which a detected key isn't tagged
+pub const DEFAULT_MIN_CONFIDENCE: f64 = 0.1;
+
+/// Samples in each window the chroma is taken from. Long windows tell apart the semitones of
+/// the bass; windows don't overlap, as the key doesn't change from one to the next.
+const WINDOW: usize = 8192;
+/// Frequencies the chroma is taken from, in Hz: from C2, below which bins are more than a
+/// semitone apart, up to where harmonics outweigh the notes
+const MIN_FREQUENCY: f64 = 65.0;
+const MAX_FREQUENCY: f64 = 2100.0;
+
+/// How strongly each pitch class above the tonic is felt in major and minor keys, from
+/// Krumhansl and Kessler's listening tests
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// How much a chroma varies across pitch classes, as its standard deviation over its mean, for
+/// it to be clearly tonal. Flatter chromas, of noise or drums, correlate with key profiles by
+/// chance, so their confidence is scaled down.
+const TONAL_VARIATION: f64 = 0.3;
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+
+/// Tags a song's key may already be in
+const KEY_TAGS: [&str; 3] = ["initialkey", "TKEY", "key"];
+
+/// Musical key, from which pitch classes the song dwells on: its chroma, the energy of every
+/// pitch class, is matched against the profile of every major and minor key
+pub struct Key;
+
+impl Analyzer for Key {
+    fn name(&self) -> &'static str {
+        "key"
+    }
+
+    fn description(&self) -> &'static str {
+        "the musical key, with how confidently it was detected"
+    }
+
+    fn start(&self) -> Box<dyn Analysis> {
+        Box::new(KeyAnalysis {
+            samples: Vec::with_capacity(WINDOW),
+            chroma: [0.0; 12],
+        })
+    }
+}
+
+struct KeyAnalysis {
+    /// The mono samples of the window being filled
+    samples: Vec<f64>,
+    chroma: [f64; 12],
+}
+
+impl KeyAnalysis {
+    fn analyze_window(&mut self) {
+        let mut re: Vec<f64> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| sample * spectrum::hann(i, WINDOW))
+            .collect();
+        let mut im = vec![0.0; WINDOW];
+        spectrum::fft(&mut re, &mut im);
+        let bin_width = SAMPLE_RATE as f64 / WINDOW as f64;
+        let first = (MIN_FREQUENCY / bin_width).ceil() as usize;
+        let last = (MAX_FREQUENCY / bin_width).floor() as usize;
+        for bin in first..=last {
+            let midi = 69.0 + 12.0 * (bin as f64 * bin_width / 440.0).log2();
+            let pitch_class = (midi.round() as usize) % 12;
+            self.chroma[pitch_class] += (re[bin] * re[bin] + im[bin] * im[bin]).sqrt();
+        }
+    }
+}
+
+impl Analysis for KeyAnalysis {
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            let mono = frame.iter().map(|s| *s as f64).sum::<f64>() / CHANNELS as f64;
+            self.samples.push(mono);
+            if self.samples.len() == WINDOW {
+                self.analyze_window();
+                self.samples.clear();
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>, analysis: &mut TrackAnalysis) {
+        if let Some((key, confidence)) = estimate(&self.chroma) {
+            analysis.key = Some(key);
+            analysis.key_confidence = Some(confidence);
+        }
+    }
+}
+
+/// The key a chroma fits best, like "Am" or "F#", and how confident that is: how much better
+/// it correlates with the chroma than the next best key, leaving out its relative major or
+/// minor, which shares its notes and mixes with it anyway, scaled down for chromas too flat to
+/// have a key. None if the chroma is silent.
+pub fn estimate(chroma: &[f64; 12]) -> Option<(String, f64)> {
+    if chroma.iter().all(|c| *c <= 0.0) {
+        return None;
+    }
+    // Keys by tonic, majors first then minors
+    let correlations: Vec<f64> = (0..24)
+        .map(|key| {
+            let profile = if key < 12 {
+                &MAJOR_PROFILE
+            } else {
+                &MINOR_PROFILE
+            };
+            let tonic = key % 12;
+            let rotated: Vec<f64> = (0..12).map(|pc| profile[(pc + 12 - tonic) % 12]).collect();
+            correlation(chroma, &rotated)
+        })
+        .collect();
+    let best = (0..24).max_by(|a, b| correlations[*a].total_cmp(&correlations[*b]))?;
+    let relative = if best < 12 {
+        12 + (best + 9) % 12
+    } else {
+        (best + 3) % 12
+    };
+    let runner_up = (0..24)
+        .filter(|key| *key != best && *key != relative)
+        .map(|key| correlations[key])
+        .fold(f64::MIN, f64::max);
+    let name = format!(
+        "{}{}",
+        PITCH_CLASSES[best % 12],
+        if best < 12 { "" } else { "m" }
+    );
+    let mean = chroma.iter().sum::<f64>() / 12.0;
+    let deviation = (chroma.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / 12.0).sqrt();
+    let tonality = (deviation / mean / TONAL_VARIATION).min(1.0);
+    let confidence = ((correlations[best] - runner_up) * tonality).clamp(0.0, 1.0);
+    Some((name, (confidence * 1000.0).round() / 1000.0))
+}
+
+/// Pearson correlation of two series
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+    if variance_a <= 0.0 || variance_b <= 0.0 {
+        return 0.0;
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// The key a song is tagged with, if any
+pub fn tagged_key(song: &SongInfo) -> Option<&str> {
+    KEY_TAGS
+        .iter()
+        .find_map(|tag| song.get_tag(tag))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// A key written in Camelot notation like "8A", Open Key notation like "1m", or by name like
+/// "Am", "A minor" or "A#", in the notation keys are detected in, like "Am" or "Bb"
+pub fn normalize(key: &str) -> Option<String> {
+    let key = key.trim();
+    let (tonic, minor) = wheel_key(key).or_else(|| named_key(key))?;
+    Some(format!(
+        "{}{}",
+        PITCH_CLASSES[tonic],
+        if minor { "m" } else { "" }
+    ))
+}
+
+/// The tonic and mode of a key on the Camelot or Open Key wheel. Each step around the wheel is
+/// a fifth up; 8A and 1m are A minor, 8B and 1d C major.
+fn wheel_key(key: &str) -> Option<(usize, bool)> {
+    let split = key.find(|c: char| !c.is_ascii_digit())?;
+    let (number, mode) = key.split_at(split);
+    let number: usize = number.parse().ok().filter(|n| (1..=12).contains(n))?;
+    let (steps, minor) = match mode {
+        "A" | "a" => ((number + 4) % 12, true),
+        "B" | "b" => ((number + 4) % 12, false),
+        "m" => ((number + 11) % 12, true),
+        "d" => ((number + 11) % 12, false),
+        _ => return None,
+    };
+    let tonic = if minor { 9 } else { 0 };
+    Some(((tonic + 7 * steps) % 12, minor))
+}
+
+/// The tonic and mode of a key by name, like "Am", "F# major" or "Ebmin"
+fn named_key(key: &str) -> Option<(usize, bool)> {
+    let mut chars = key.chars();
+    let natural = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (tonic, mode) = if let Some(mode) = rest.strip_prefix(['#', '♯']) {
+        ((natural + 1) % 12, mode)
+    } else if let Some(mode) = rest.strip_prefix(['b', '♭']) {
+        ((natural + 11) % 12, mode)
+    } else {
+        (natural, rest)
+    };
+    let minor = match mode.trim().to_lowercase().as_str() {
+        "" | "maj" | "major" => false,
+        "m" | "min" | "minor" => true,
+        _ => return None,
+    };
+    Some((tonic, minor))
+}
+
+/// A key detected while converting a song, and whether it was confident enough to tag
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DetectedKey {
+    pub key: String,
+    pub confidence: f64,
+    pub tagged: bool,
+}
+
+/// The songs of an output directory whose detected key wasn't tagged, by source path, to check
+/// by ear and tag by hand
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Review {
+    pub songs: BTreeMap<PathBuf, DetectedKey>,
+}
+
+impl Review {
+    /// The review file of an output directory, empty if there is none yet
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(REVIEW_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Review::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("{} isn't a key review file", path.display()))
+    }
+
+    /// Adds the songs of a run whose key wasn't tagged, and takes off those that were tagged
+    /// this time, saving the file if that changed it. Returns how many songs are left to review.
+    pub fn update<'a, I>(output_dir: &Path, detected: I) -> Result<usize>
+    where
+        I: IntoIterator<Item = (&'a PathBuf, &'a DetectedKey)>,
+    {
+        let mut review = Review::load(output_dir)?;
+        let before = review.clone();
+        for (source, key) in detected {
+            if key.tagged {
+                review.songs.remove(source);
+            } else {
+                review.songs.insert(source.clone(), key.clone());
+            }
+        }
+        if review != before {
+            state::write_atomic(
+                &output_dir.join(REVIEW_FILE_NAME),
+                &serde_json::to_vec_pretty(&review)?,
+            )?;
+        }
+        Ok(review.songs.len())
+    }
+}
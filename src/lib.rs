@@ -0,0 +1,70 @@
+pub mod analysis_cache;
+pub mod analyzers;
+pub mod anlz;
+pub mod archive;
+pub mod arrow_ipc;
+pub mod artists;
+pub mod artwork;
+pub mod artwork_fetch;
+pub mod audition;
+pub mod backup;
+pub mod bpm_octave;
+pub mod compare;
+pub mod confirm;
+pub mod converter;
+pub mod dates;
+pub mod device;
+pub mod exporters;
+pub mod file_uri;
+pub mod fingerprint;
+pub mod i18n;
+pub mod imported_analysis;
+pub mod key;
+pub mod links;
+pub mod loudness;
+pub mod manifest;
+pub mod naming;
+pub mod native_probe;
+pub mod pdb;
+pub mod play_history;
+pub mod players;
+pub mod probe_cache;
+pub mod processors;
+pub mod query;
+pub mod read_only;
+pub mod record_pool;
+pub mod rekordbox_playlist;
+pub mod rekordbox_xml;
+pub mod report;
+pub mod routing;
+pub mod sanitize;
+pub mod scan_events;
+pub mod separation;
+pub mod serato;
+pub mod serato_markers;
+pub mod setlist;
+pub mod snapshot;
+pub mod song_info;
+pub mod spectrum;
+pub mod speed;
+pub mod state;
+pub mod stems;
+pub mod tag_filter;
+pub mod tagging;
+pub mod targets;
+pub mod tempo;
+pub mod tempo_range;
+pub mod throttle;
+pub mod usb_layout;
+pub mod variants;
+pub mod verify;
+pub mod watch;
+pub mod waveform;
+pub mod workers;
+pub mod xml_merge;
+pub mod xml_validate;
+
+pub use converter::{
+    Action, ConversionJob, ConversionSummary, ConvertOptions, Converter, CopyMode,
+};
+pub use scan_events::{ScanEvent, ScanEvents};
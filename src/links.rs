@@ -0,0 +1,119 @@
+use crate::state::{self, ConversionState, SourceRecord};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// A file a track was converted or copied into
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputLink {
+    pub path: PathBuf,
+    /// FNV-1a hash of the file as it is now, or None if it is missing
+    pub hash: Option<String>,
+}
+
+/// Ties together the representations of a track: its source file, the files in the output
+/// directory it was converted into, and its entry in the exported rekordbox.xml
+#[derive(Clone, Debug, Serialize)]
+pub struct TrackLink {
+    pub source: PathBuf,
+    /// FNV-1a hash of the source when it was converted
+    pub source_hash: String,
+    pub outputs: Vec<OutputLink>,
+    /// TrackID of the track's entry in rekordbox.xml, if it has been exported
+    pub track_id: Option<u32>,
+}
+
+impl TrackLink {
+    fn new(source: &Path, record: &SourceRecord) -> Self {
+        TrackLink {
+            source: source.to_path_buf(),
+            source_hash: record.hash.clone(),
+            outputs: record
+                .outputs
+                .iter()
+                .map(|path| OutputLink {
+                    path: path.clone(),
+                    hash: state::hash_file(path).ok(),
+                })
+                .collect(),
+            track_id: record.track_id,
+        }
+    }
+}
+
+/// Links of every track converted into an output directory
+pub fn all(output_dir: &Path) -> Result<Vec<TrackLink>> {
+    let state = ConversionState::load(output_dir)?;
+    let links = state
+        .sources()
+        .map(|(source, record)| TrackLink::new(source, record))
+        .collect();
+    Ok(links)
+}
+
+/// The link of the track a path belongs to, whether the path is its source or one of its
+/// outputs, or None if no track converted into the output directory has that path
+pub fn find(output_dir: &Path, path: &Path) -> Result<Option<TrackLink>> {
+    let state = ConversionState::load(output_dir)?;
+    // Paths may be relative or go through symlinks, so they are compared resolved as well
+    let resolved = path.canonicalize().ok();
+    let is_path = |candidate: &Path| {
+        candidate == path || (resolved.is_some() && candidate.canonicalize().ok() == resolved)
+    };
+    let link = state
+        .sources()
+        .find(|(source, record)| is_path(source) || record.outputs.iter().any(|o| is_path(o)))
+        .map(|(source, record)| TrackLink::new(source, record));
+    Ok(link)
+}
+
+/// Finds the sources of files named from elsewhere, like the Locations in a Rekordbox library
+/// or a history playlist, among the tracks converted into an output directory
+pub struct SourceIndex {
+    /// Sources by their own paths and those of their outputs, as recorded and resolved
+    by_path: HashMap<PathBuf, PathBuf>,
+    /// Sources by the file names of their outputs
+    by_name: HashMap<OsString, Vec<PathBuf>>,
+}
+
+impl SourceIndex {
+    pub fn new(state: &ConversionState) -> Self {
+        let mut by_path = HashMap::new();
+        let mut by_name: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+        for (source, record) in state.sources() {
+            for path in std::iter::once(source).chain(record.outputs.iter()) {
+                by_path.insert(path.clone(), source.clone());
+                if let Ok(resolved) = path.canonicalize() {
+                    by_path.insert(resolved, source.clone());
+                }
+            }
+            for output in record.outputs.iter() {
+                if let Some(name) = output.file_name() {
+                    by_name
+                        .entry(name.to_os_string())
+                        .or_default()
+                        .push(source.clone());
+                }
+            }
+        }
+        SourceIndex { by_path, by_name }
+    }
+
+    /// The source a path is, or was converted into. When the output directory was copied to a
+    /// USB stick or another computer since, the path is found by its file name, as long as only
+    /// one output has it.
+    pub fn source(&self, path: &Path) -> Option<&Path> {
+        if let Some(source) = self.by_path.get(path) {
+            return Some(source);
+        }
+        if let Some(source) = path.canonicalize().ok().and_then(|p| self.by_path.get(&p)) {
+            return Some(source);
+        }
+        match self.by_name.get(path.file_name()?)?.as_slice() {
+            [source] => Some(source),
+            _ => None,
+        }
+    }
+}
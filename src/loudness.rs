@@ -0,0 +1,231 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Command;
+
+/// Named loudness targets for common use cases
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LoudnessPreset {
+    /// -8 LUFS, loud enough to sit next to mastered club tracks
+    Club,
+    /// -14 LUFS, the level streaming services normalize to
+    Streaming,
+    /// Leave the loudness untouched
+    Archive,
+}
+
+impl LoudnessPreset {
+    /// Integrated loudness target in LUFS, or None if the preset doesn't normalize
+    pub fn target_lufs(&self) -> Option<f64> {
+        match self {
+            LoudnessPreset::Club => Some(-8.0),
+            LoudnessPreset::Streaming => Some(-14.0),
+            LoudnessPreset::Archive => None,
+        }
+    }
+}
+
+/// True peak ceiling passed to loudnorm, in dBTP
+const TRUE_PEAK: f64 = -1.0;
+/// Loudness range target passed to loudnorm, in LU
+const LOUDNESS_RANGE: f64 = 11.0;
+
+/// Loudness statistics printed by the first loudnorm pass. ffprobe style, every value is a string.
+#[derive(Clone, Debug, Deserialize)]
+struct LoudnormOutput {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Statistics printed by the second loudnorm pass, once it has normalized a song
+#[derive(Clone, Debug, Deserialize)]
+struct LoudnormResult {
+    output_i: String,
+    normalization_type: String,
+}
+
+/// Measured loudness of a song
+#[derive(Clone, Debug)]
+pub struct LoudnessMeasurement {
+    pub integrated: f64,
+    pub true_peak: f64,
+    pub range: f64,
+    pub threshold: f64,
+    pub offset: f64,
+}
+
+impl LoudnessMeasurement {
+    /// Gain in dB that normalization to the target will apply
+    pub fn gain_to(&self, target_lufs: f64) -> f64 {
+        target_lufs - self.integrated
+    }
+}
+
+/// How a song's loudness was normalized while converting it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Normalization {
+    /// Integrated loudness of the source, in LUFS
+    pub measured_lufs: f64,
+    pub target_lufs: f64,
+    /// Gain applied to reach the target, in dB: the difference between the loudness loudnorm
+    /// measured on its output and the measured loudness, or the planned gain if it printed none
+    pub gain_db: f64,
+    /// Integrated loudness of the output as loudnorm measured it, in LUFS
+    pub output_lufs: Option<f64>,
+    /// Whether loudnorm fell back from linear to dynamic normalization, because the gain would
+    /// have pushed peaks over its ceiling, which changes the song's dynamics
+    pub dynamic: bool,
+    /// How many dB the limiter is expected to pull the loudest peak down by, if the song was
+    /// boosted and went through it
+    pub gain_reduction_db: Option<f64>,
+}
+
+impl Normalization {
+    /// Records what the second loudnorm pass did, from the statistics it printed to ffmpeg's
+    /// stderr
+    pub fn update_from_loudnorm(&mut self, stderr: &[u8]) {
+        let stderr = String::from_utf8_lossy(stderr);
+        let result: Option<LoudnormResult> =
+            last_json_object(&stderr).and_then(|json| serde_json::from_str(json).ok());
+        match result {
+            Some(result) => {
+                if let Ok(output_lufs) = result.output_i.trim().parse::<f64>() {
+                    self.output_lufs = Some(output_lufs);
+                    self.gain_db = output_lufs - self.measured_lufs;
+                }
+                self.dynamic = result.normalization_type.trim() == "dynamic";
+            }
+            None => tracing::debug!("loudnorm printed no statistics"),
+        }
+    }
+}
+
+/// The last JSON object in ffmpeg's output, which is where loudnorm prints its statistics. They
+/// are a flat object, so it ends at the first closing brace.
+fn last_json_object(stderr: &str) -> Option<&str> {
+    let start = stderr.rfind('{')?;
+    let end = start + stderr[start..].find('}')?;
+    Some(&stderr[start..=end])
+}
+
+/// Runs the measuring pass of ffmpeg's loudnorm filter on a song, after the filters that come
+/// before loudnorm when converting it, so what is measured is what loudnorm will get
+#[tracing::instrument(level = "debug")]
+pub fn measure(path: &Path, filters: &[String], target_lufs: f64) -> Result<LoudnessMeasurement> {
+    let mut chain = filters.to_vec();
+    chain.push(format!(
+        "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+        target_lufs, TRUE_PEAK, LOUDNESS_RANGE
+    ));
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(path)
+        .arg("-af")
+        .arg(chain.join(","))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let json = last_json_object(&stderr)
+        .ok_or_else(|| anyhow!("loudnorm printed no measurements for {:?}", path))?;
+    let parsed: LoudnormOutput = serde_json::from_str(json)?;
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Invalid loudnorm measurement {:?} for {:?}", s, path))
+    };
+    Ok(LoudnessMeasurement {
+        integrated: parse(&parsed.input_i)?,
+        true_peak: parse(&parsed.input_tp)?,
+        range: parse(&parsed.input_lra)?,
+        threshold: parse(&parsed.input_thresh)?,
+        offset: parse(&parsed.target_offset)?,
+    })
+}
+
+/// Builds the second, linear loudnorm pass from a measurement. It prints what it did, which
+/// Normalization::update_from_loudnorm reads.
+pub fn loudnorm_filter(target_lufs: f64, measured: &LoudnessMeasurement) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=json",
+        target_lufs,
+        TRUE_PEAK,
+        LOUDNESS_RANGE,
+        measured.integrated,
+        measured.true_peak,
+        measured.range,
+        measured.threshold,
+        measured.offset
+    )
+}
+
+/// Builds an alimiter stage that keeps peaks under the ceiling, given in dBTP. Auto leveling is
+/// turned off, since loudnorm already set the level.
+pub fn limiter_filter(ceiling_db: f64) -> String {
+    format!(
+        "alimiter=limit={:.6}:level=false",
+        10f64.powf(ceiling_db / 20.0)
+    )
+}
+
+/// Range of limiter ceilings in dBTP. alimiter's limit goes down to 0.0625, about -24 dBTP, and
+/// a ceiling above full scale can't keep anything from clipping.
+pub const LIMITER_CEILING_RANGE: std::ops::RangeInclusive<f64> = -24.0..=0.0;
+
+/// Whether the limiter does anything with this ceiling, which it can't when loudnorm already
+/// keeps peaks under a lower one
+pub fn limits(ceiling_db: f64) -> bool {
+    ceiling_db < TRUE_PEAK
+}
+
+/// How many dB the limiter will have to pull the loudest peak down by after normalization.
+/// loudnorm has already kept peaks under its own ceiling by then.
+pub fn expected_gain_reduction(
+    measured: &LoudnessMeasurement,
+    target_lufs: f64,
+    ceiling_db: f64,
+) -> f64 {
+    ((measured.true_peak + measured.gain_to(target_lufs)).min(TRUE_PEAK) - ceiling_db).max(0.0)
+}
+
+/// Integrated loudness and true peak a song ends up with after normalizing it to the target,
+/// including the limiter stage if it applies. loudnorm keeps peaks under its own ceiling too.
+pub fn normalized_loudness(
+    measured: &LoudnessMeasurement,
+    target_lufs: f64,
+    limiter_ceiling: Option<f64>,
+) -> (f64, f64) {
+    let mut true_peak = (measured.true_peak + measured.gain_to(target_lufs)).min(TRUE_PEAK);
+    if let Some(ceiling) = limiter_ceiling.filter(|_| measured.gain_to(target_lufs) > 0.0) {
+        true_peak = true_peak.min(ceiling);
+    }
+    (target_lufs, true_peak)
+}
+
+/// Loudness ReplayGain 2.0 gains are relative to, in LUFS
+pub const REPLAYGAIN_REFERENCE: f64 = -18.0;
+
+/// ReplayGain track tags for a song with the given integrated loudness and true peak
+pub fn replaygain_tags(integrated: f64, true_peak: f64) -> Vec<(String, String)> {
+    vec![
+        (
+            "REPLAYGAIN_TRACK_GAIN".to_string(),
+            format!("{:.2} dB", REPLAYGAIN_REFERENCE - integrated),
+        ),
+        (
+            "REPLAYGAIN_TRACK_PEAK".to_string(),
+            format!("{:.6}", 10f64.powf(true_peak / 20.0)),
+        ),
+        (
+            "REPLAYGAIN_REFERENCE_LOUDNESS".to_string(),
+            format!("{:.2} LUFS", REPLAYGAIN_REFERENCE),
+        ),
+    ]
+}
@@ -1,283 +1,1446 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use song_info::{AudioFormatType, SupportedAudioFormat};
-use std::process::Command;
-use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
-use std::{
-    cmp, fs,
-    path::{Path, PathBuf},
-};
-mod song_info;
-use song_info::SongInfo;
+use clap::{Args, Parser, Subcommand};
+use rekordbox_file_conversion::analysis_cache::AnalysisCache;
+use rekordbox_file_conversion::analyzers;
+use rekordbox_file_conversion::archive::Archive;
+use rekordbox_file_conversion::artwork::{ArtworkCache, ArtworkOptions};
+use rekordbox_file_conversion::artwork_fetch::ArtworkFetcher;
+use rekordbox_file_conversion::audition;
+use rekordbox_file_conversion::backup;
+use rekordbox_file_conversion::bpm_octave;
+use rekordbox_file_conversion::compare::{self, EncoderProfile};
+use rekordbox_file_conversion::confirm;
+use rekordbox_file_conversion::dates::YearSource;
+use rekordbox_file_conversion::device::WhenFull;
+use rekordbox_file_conversion::exporters::{self, ExportOptions};
+use rekordbox_file_conversion::i18n;
+use rekordbox_file_conversion::imported_analysis::MikExport;
+use rekordbox_file_conversion::key;
+use rekordbox_file_conversion::links;
+use rekordbox_file_conversion::loudness::{self, LoudnessPreset};
+use rekordbox_file_conversion::manifest::{Manifest, MANIFEST_FILE_NAME};
+use rekordbox_file_conversion::pdb::Collection;
+use rekordbox_file_conversion::play_history;
+use rekordbox_file_conversion::players::{self, PlayerProfile};
+use rekordbox_file_conversion::probe_cache::ProbeCache;
+use rekordbox_file_conversion::processors;
+use rekordbox_file_conversion::query::{self, QueryFormat};
+use rekordbox_file_conversion::read_only;
+use rekordbox_file_conversion::record_pool;
+use rekordbox_file_conversion::rekordbox_playlist::PlaylistSelection;
+use rekordbox_file_conversion::rekordbox_xml;
+use rekordbox_file_conversion::report::{self, DirectoryReport, ReportFormat, RunReport};
+use rekordbox_file_conversion::routing::{RouteBy, RouteMap};
+use rekordbox_file_conversion::sanitize::NameSanitizer;
+use rekordbox_file_conversion::separation::SeparationTool;
+use rekordbox_file_conversion::setlist::{self, SetlistFormat};
+use rekordbox_file_conversion::snapshot;
+use rekordbox_file_conversion::song_info::{self, ProbeBackend};
+use rekordbox_file_conversion::speed::{self, SpeedCorrection};
+use rekordbox_file_conversion::state::ConversionState;
+use rekordbox_file_conversion::stems::StemMode;
+use rekordbox_file_conversion::tag_filter::TagFilter;
+use rekordbox_file_conversion::tagging;
+use rekordbox_file_conversion::targets::{ArchiveTarget, LosslessTarget, LossyTarget};
+use rekordbox_file_conversion::tempo_range::{self, Suspect};
+use rekordbox_file_conversion::throttle::ReadThrottle;
+use rekordbox_file_conversion::variants::{EditPolicy, ExplicitPolicy};
+use rekordbox_file_conversion::verify;
+use rekordbox_file_conversion::watch;
+use rekordbox_file_conversion::workers;
+use rekordbox_file_conversion::xml_merge;
+use rekordbox_file_conversion::xml_validate::{self, Severity};
+use rekordbox_file_conversion::{ConvertOptions, Converter, CopyMode};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// This app converts all tagged songs in a directory into a Rekordbox friendly format
 #[derive(Parser)]
 #[command(about, long_about = None)]
 struct App {
-    /// The folder with the songs you want to convert
-    #[arg(short, long)]
-    input_dir: String,
-    /// Output directory to store converted songs
-    #[arg(short, long)]
-    output_dir: String,
+    #[command(subcommand)]
+    command: Command,
+    /// Refuse to write anything, so verify, scan and report are safe to point at a USB stick
+    /// that is about to be played from
+    #[arg(long, global = true)]
+    read_only: bool,
+    /// Language of messages and reports: en, es, de or ja. Taken from LANG by default
+    #[arg(long, global = true)]
+    lang: Option<String>,
+    /// Print progress as a line per song instead of a progress bar, and without colors, for
+    /// screen readers and log collectors. On by default when TERM is dumb
+    #[arg(long, global = true)]
+    plain: bool,
+}
+
+// Parsed once, so the size of the convert arguments doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Command {
+    /// Convert the tagged songs in a directory
+    Convert(ConvertArgs),
+    /// Convert the tagged songs in a directory, then keep converting songs as they are added to
+    /// it or changed
+    Watch(WatchArgs),
+    /// Print what converting a directory would do with each song, without converting anything
+    Scan(SelectArgs),
+    /// Print what a file is to a converted track, its source or one of its outputs, with the
+    /// track's other files as JSON
+    Show(ShowArgs),
+    /// Export the songs converted into a directory to a DJ library or playlist format
+    Export(ExportArgs),
+    /// Write the songs converted into a directory as a Rekordbox XML library, keeping each
+    /// track's TrackID from earlier exports
+    ExportXml(ExportXmlArgs),
+    /// Check a Rekordbox XML library for what Rekordbox is known to trip over when importing it
+    ValidateXml(ValidateXmlArgs),
+    /// Read how often the converted tracks were played and how they were rated from a Rekordbox
+    /// XML library into the state of the output directory
+    ImportHistory(ImportHistoryArgs),
+    /// Print the set list of a Rekordbox history playlist, with the artist, title, label and
+    /// ISRC of each track read from the source it was converted from
+    Setlist(SetlistArgs),
+    /// Check that every song in a directory decodes without errors
+    Verify(VerifyArgs),
+    /// Measure the songs in a directory with the analyzers, printing a JSON line per song
+    Analyze(AnalyzeArgs),
+    /// Write everything probing and the analyzers found about the songs in a directory to an
+    /// Arrow IPC file, a row per song, to load into Polars or pandas
+    Snapshot(SnapshotArgs),
+    /// Check the files in an output directory, or a copy of it, against the manifest written
+    /// when converting into it
+    VerifyManifest(VerifyManifestArgs),
+    /// List the outputs that changed, are new or disappeared between two runs, by comparing the
+    /// hashes in their manifests. Runs converted with --deterministic only differ where the
+    /// audio or tags written did
+    DiffRuns(DiffRunsArgs),
+    /// Show the tags of a song, or change them with --set
+    Tag(TagArgs),
+    /// Summarize the formats and sizes of the songs in a directory
+    Report(ReportArgs),
+    /// Run a read-only SQL query against the state, manifest and tags of an output directory
+    Query(QueryArgs),
+    /// Play random excerpts of a song, or of the most recently converted songs in a directory,
+    /// to listen for encoding artifacts
+    Audition(AuditionArgs),
+    /// Encode a sample with two encoder profiles and write level matched copies of both, plus
+    /// their difference, to compare them by ear and by RMS
+    Compare(CompareArgs),
+    /// Archive the state file and other artifacts of an output directory into a timestamped
+    /// tarball
+    Backup(BackupArgs),
+    /// Bring back the artifacts of an output directory from a backup
+    Restore(RestoreArgs),
+}
+
+/// Arguments choosing which songs in a directory to convert
+#[derive(Args)]
+struct SelectArgs {
+    /// The folder with the songs you want to convert. Can be left out with --playlist, whose
+    /// tracks are then converted where they are
+    #[arg(short, long, required_unless_present = "playlist")]
+    input_dir: Option<String>,
     /// Tag to search for when looking for songs in the directory to convert. If not given then
     /// convert all songs in the input directory
     #[arg(short, long)]
     rekordbox_tag: Option<String>,
+    /// Only convert songs whose tags match this expression, like
+    /// 'REKORDBOX=1 && ENERGY>=7 && genre~"techno"'
+    #[arg(long)]
+    filter: Option<String>,
+    /// Rekordbox library exported as XML to take a playlist from, so only its tracks are
+    /// converted. Tracks are looked for in the input directory, by name if they have moved
+    #[arg(long, requires = "playlist")]
+    from_rekordbox_xml: Option<PathBuf>,
+    /// iTunes or Music library exported as XML to take playlists from, so only their tracks are
+    /// converted
+    #[arg(long, requires = "playlist", conflicts_with = "from_rekordbox_xml")]
+    from_itunes_xml: Option<PathBuf>,
+    /// Name of the playlist in --from-rekordbox-xml or --from-itunes-xml, or its path through
+    /// folders like "Gigs/Prep" when several playlists share the name. Without either, an M3U
+    /// or M3U8 file whose tracks are converted, relative paths being relative to the file. Give
+    /// it several times to convert the tracks of several playlists
+    #[arg(long)]
+    playlist: Vec<String>,
+    /// Serato crate file to take the songs to convert from, e.g. _Serato_/Subcrates/Prep.crate.
+    /// Tracks are looked for in the input directory, by name if they have moved
+    #[arg(long, conflicts_with = "playlist")]
+    from_serato_crate: Option<PathBuf>,
+    /// Skip songs already in a Rekordbox device library: the root of a USB stick Rekordbox
+    /// exported to, or its PIONEER/rekordbox/export.pdb. Songs match by title, artist and length
+    #[arg(long)]
+    skip_in_pdb: Option<PathBuf>,
+    /// What to do when both a clean and an explicit version of a track are selected for
+    /// conversion. Either way the versions are linked in the state file
+    #[arg(long, value_enum, default_value_t = ExplicitPolicy::Both)]
+    explicit_policy: ExplicitPolicy,
+    /// Which edit to convert when a track was delivered as e.g. an original and an intro edit,
+    /// among the edits selected for conversion
+    #[arg(long, value_enum, default_value_t = EditPolicy::All)]
+    edit_policy: EditPolicy,
+    /// Number of songs to probe at once, one per CPU by default. Probing waits on the disk, so
+    /// sources on a NAS benefit from more
+    #[arg(long)]
+    probe_jobs: Option<usize>,
+    /// How to read files to find out their format and tags. native doesn't start ffprobe for
+    /// every file, and falls back to it for formats it can't read
+    #[arg(long, value_enum, default_value_t = ProbeBackend::Ffprobe)]
+    probe_backend: ProbeBackend,
+    /// File to cache what probing finds out about each file in, so unchanged files aren't probed
+    /// again. Defaults to probe-cache.json in ~/.cache/rekordbox-file-conversion
+    #[arg(long)]
+    probe_cache: Option<PathBuf>,
+    /// Probe every file, without reading or writing the probe cache
+    #[arg(long, conflicts_with = "probe_cache")]
+    no_probe_cache: bool,
 }
 
-/// Function iterates through the directory and grabs file paths
-pub fn build_list_of_files(dir: &Path, files: &mut Vec<PathBuf>) {
-    if dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(dir) {
-            // Iterate through entries in the directory
-            for entry in entries {
-                if let Ok(e) = entry {
-                    let path = e.path();
-                    // If entry is a directory, recursively search through it
-                    if path.is_dir() {
-                        build_list_of_files(path.as_path(), files);
-                    } else {
-                        files.push(path);
-                    }
-                } else {
-                    tracing::error!("I/O error while reading directory entry: {:?}", entry)
-                }
-            }
+impl SelectArgs {
+    fn options(&self) -> anyhow::Result<ConvertOptions> {
+        let playlist = if let Some(xml) = &self.from_rekordbox_xml {
+            let selections = self
+                .playlist
+                .iter()
+                .map(|name| PlaylistSelection::load(xml, name))
+                .collect::<anyhow::Result<_>>()?;
+            Some(PlaylistSelection::combine(selections))
+        } else if let Some(library) = &self.from_itunes_xml {
+            Some(PlaylistSelection::from_itunes_xml(library, &self.playlist)?)
+        } else if !self.playlist.is_empty() {
+            let selections = self
+                .playlist
+                .iter()
+                .map(|m3u| PlaylistSelection::from_m3u(Path::new(m3u)))
+                .collect::<anyhow::Result<_>>()?;
+            Some(PlaylistSelection::combine(selections))
         } else {
-            tracing::error!("Error reading directory: {}", dir.display());
+            match &self.from_serato_crate {
+                Some(crate_path) => Some(PlaylistSelection::from_serato_crate(crate_path)?),
+                None => None,
+            }
+        };
+        let collection = match &self.skip_in_pdb {
+            Some(path) => Some(Collection::load(path)?),
+            None => None,
+        };
+        Ok(ConvertOptions {
+            conversion_tag: self.rekordbox_tag.clone().unwrap_or_default(),
+            filter: match &self.filter {
+                Some(expression) => Some(TagFilter::parse(expression)?),
+                None => None,
+            },
+            playlist,
+            collection,
+            explicit_policy: self.explicit_policy,
+            edit_policy: self.edit_policy,
+            probe_jobs: self.probe_jobs.unwrap_or_else(workers::default_jobs),
+            probe_backend: self.probe_backend,
+            probe_cache: if self.no_probe_cache {
+                None
+            } else {
+                self.probe_cache
+                    .clone()
+                    .or_else(ProbeCache::default_path)
+                    .map(|path| ProbeCache::open(&path))
+            },
+            ..Default::default()
+        })
+    }
+
+    /// The folder songs are mirrored from with --preserve-structure: the input directory, or
+    /// the folder of the first M3U playlist converted without one
+    fn source_root(&self) -> Option<PathBuf> {
+        if let Some(dir) = &self.input_dir {
+            return Some(PathBuf::from(dir));
         }
-    } else {
-        tracing::error!("{} is not a directory!", dir.display());
+        if self.from_rekordbox_xml.is_some() || self.from_itunes_xml.is_some() {
+            return None;
+        }
+        let m3u = self.playlist.first()?;
+        Path::new(m3u).parent().map(Path::to_path_buf)
+    }
+}
+
+#[derive(Args)]
+struct AnalysisCacheArgs {
+    /// File to cache what analyzers find in each song's audio in, so songs whose audio hasn't
+    /// changed, even if they were retagged, renamed or converted again, aren't analyzed again.
+    /// Defaults to analysis-cache.json in ~/.cache/rekordbox-file-conversion
+    #[arg(long)]
+    analysis_cache: Option<PathBuf>,
+    /// Analyze every song, without reading or writing the analysis cache
+    #[arg(long, conflicts_with = "analysis_cache")]
+    no_analysis_cache: bool,
+}
+
+impl AnalysisCacheArgs {
+    fn open(&self) -> Option<AnalysisCache> {
+        if self.no_analysis_cache {
+            return None;
+        }
+        self.analysis_cache
+            .clone()
+            .or_else(AnalysisCache::default_path)
+            .map(|path| AnalysisCache::open(&path))
+    }
+}
+
+/// The songs a converter picks: those in the input directory, or the tracks of its playlist
+/// when there is none
+fn select_files(converter: &Converter, input_dir: Option<&str>) -> anyhow::Result<Vec<PathBuf>> {
+    match input_dir {
+        Some(dir) => converter.scan(Path::new(dir)),
+        None => converter.playlist_files(),
+    }
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    #[command(flatten)]
+    select: SelectArgs,
+    /// Output directory to store converted songs
+    #[arg(short, long, required_unless_present = "device")]
+    output_dir: Option<String>,
+    /// Mounted USB stick to convert straight onto, like /Volumes/DJUSB, laid out as with
+    /// --usb-layout. Checks first that players can read its file system and that the songs fit
+    #[arg(long, conflicts_with_all = ["output_dir", "preserve_structure", "route_by"])]
+    device: Option<String>,
+    /// What to do when the songs won't fit on the --device: refuse to convert any, or trim the
+    /// batch to the songs that fit
+    #[arg(long, value_enum, default_value_t = WhenFull::Refuse, requires = "device")]
+    when_full: WhenFull,
+    /// Record pool naming convention to extract artist, title, genre, clean/dirty and intro/outro
+    /// info from. One of: generic, bpm-supreme
+    #[arg(long)]
+    pool_layout: Option<String>,
+    /// Normalize loudness to a preset target: club (-8 LUFS), streaming (-14 LUFS) or archive
+    /// (untouched)
+    #[arg(long, value_enum, default_value_t = LoudnessPreset::Archive)]
+    loudness_preset: LoudnessPreset,
+    /// Normalize loudness with EBU R128 to this integrated loudness in LUFS, -14 if no value is
+    /// given. Overrides --loudness-preset
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        num_args = 0..=1,
+        default_missing_value = "-14"
+    )]
+    normalize: Option<f64>,
+    /// When normalization raises the gain of a song, limit its peaks to this true peak ceiling
+    /// in dBTP, between -24 and 0 (e.g. -2.0), so boosted songs can't clip. Normalization
+    /// already keeps peaks under -1 dBTP, so only lower ceilings limit anything
+    #[arg(long, allow_hyphen_values = true)]
+    limiter_ceiling: Option<f64>,
+    /// Measure each song's loudness and write ReplayGain track gain and peak tags, so players
+    /// that level tracks by gain agree with each other
+    #[arg(long)]
+    replaygain: bool,
+    /// Remove DC offset and subsonic rumble (20 Hz high-pass), e.g. for vinyl rips
+    #[arg(long)]
+    cleanup: bool,
+    /// Only apply the cleanup filters to songs under this directory. Can be given multiple times
+    #[arg(long)]
+    cleanup_dir: Vec<PathBuf>,
+    /// Correct rips recorded at the wrong turntable speed, either as a speed factor (0.9972) or
+    /// in cents (-4.85c). Changes pitch along with speed. Single songs can be given their own
+    /// correction in speed-review.json in the output directory
+    #[arg(long, allow_hyphen_values = true)]
+    speed_correct: Option<SpeedCorrection>,
+    /// Whether to convert only the stereo master of NI stem files, or each of the four stems
+    #[arg(long, value_enum, default_value_t = StemMode::Master)]
+    stems: StemMode,
+    /// Split artist fields listing several artists ("A feat. B; C x D") and join them with this
+    /// separator instead, e.g. ", ". The individual artists are also written to an ARTISTS tag
+    #[arg(long)]
+    artist_separator: Option<String>,
+    /// Also split artist fields on "&", "," and "/". Single acts like "AC/DC" or "Earth, Wind &
+    /// Fire" are split too, so this is only for libraries that don't have them
+    #[arg(long, requires = "artist_separator")]
+    split_artists_loosely: bool,
+    /// Which date to show as the year: the original release date (so remasters don't show the
+    /// remaster year) or the date of the release the file came from
+    #[arg(long, value_enum, default_value_t = YearSource::Original)]
+    year_source: YearSource,
+    /// Name converted songs after their tags with a template such as "{artist} - {title}".
+    /// Missing tags can have a fallback, as in "{artist|Unknown Artist}"; otherwise the source
+    /// file name is used. Nothing is converted if two songs would get the same name
+    #[arg(long)]
+    name_template: Option<String>,
+    /// Make output file and folder names safe to copy to FAT32 and exFAT USB sticks, replacing
+    /// characters they don't allow and shortening long names
+    #[arg(long)]
+    sanitize_names: bool,
+    /// Character that replaces characters FAT32 doesn't allow, with --sanitize-names
+    #[arg(long, default_value_t = '_')]
+    replacement_char: char,
+    /// Longest file or folder name, with --sanitize-names. Long names are shortened keeping
+    /// their extension
+    #[arg(long, default_value_t = 255)]
+    max_name_length: usize,
+    /// Recreate each song's folder relative to the input directory under the output directory,
+    /// so songs are sorted the same way and don't collide by name
+    #[arg(long)]
+    preserve_structure: bool,
+    /// Sort converted songs into subfolders of the output directory by this metadata
+    #[arg(long, value_enum)]
+    route_by: Option<RouteBy>,
+    /// JSON file mapping tag values, or parts of them, to the folders --route-by sorts songs
+    /// into, like {"house": "House", "techno": "Techno"}
+    #[arg(long, requires = "route_by")]
+    route_map: Option<PathBuf>,
+    /// Lay the output directory out like a Rekordbox USB stick: songs in
+    /// Contents/<Artist>/<Album>, and a PIONEER folder for Rekordbox to export the device
+    /// library into, so the directory can be copied to the root of a stick as is
+    #[arg(long, conflicts_with_all = ["preserve_structure", "route_by"])]
+    usb_layout: bool,
+    /// Resize embedded artwork to at most this many pixels wide and high, as a JPEG
+    #[arg(long)]
+    max_artwork_size: Option<u32>,
+    /// JPEG quality of resized artwork, from 1 to 100
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    artwork_quality: u8,
+    /// Directory to keep the original artwork in when it is resized
+    #[arg(long)]
+    artwork_archive: Option<PathBuf>,
+    /// With --max-artwork-size, resize each distinct artwork once and reuse it for every song of
+    /// the same release (album and album artist) with the same cover, matched by perceptual
+    /// hash. Hashes are kept in the state file for later runs
+    #[arg(long)]
+    dedupe_artwork: bool,
+    /// Embed a cover image found next to songs without artwork (cover.jpg, folder.png, ...),
+    /// resized like embedded artwork with --max-artwork-size
+    #[arg(long)]
+    folder_artwork: bool,
+    /// Download artwork for songs without any from the Cover Art Archive, by their MusicBrainz
+    /// release, or the iTunes Search API, from a result with their artist and album or title,
+    /// and embed it
+    #[arg(long)]
+    fetch_artwork: bool,
+    /// Minimum number of milliseconds between requests to artwork providers
+    #[arg(long, default_value_t = 1000)]
+    fetch_artwork_interval: u64,
+    /// Print what would be done with each song without converting anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Render a waveform PNG of each converted song into a waveforms folder in the output
+    /// directory
+    #[arg(long)]
+    export_waveforms: bool,
+    /// With --usb-layout or --device, write Pioneer ANLZ files with waveforms and, for songs
+    /// with a BPM tag, a beat grid into PIONEER/USBANLZ, and an export.pdb listing the tracks
+    /// into PIONEER/rekordbox, so players show the waveforms without a Rekordbox export
+    #[arg(long)]
+    anlz: bool,
+    /// Detect the tempo of songs that have no BPM tag, and write it into the converted files'
+    /// BPM tag
+    #[arg(long)]
+    detect_bpm: bool,
+    /// JSON file of tempo ranges by genre or artist, like [{"genre": "dubstep", "min": 135,
+    /// "max": 150}], picking between half and double the detected tempo
+    #[arg(long)]
+    bpm_rules: Option<PathBuf>,
+    /// Detect the key of songs that have no key tag, and write it into the converted files' key
+    /// tag when it is detected confidently enough. The others are listed in key-review.json in
+    /// the output directory, to check by ear
+    #[arg(long)]
+    detect_key: bool,
+    /// Confidence, from 0 to 1, a detected key needs to be tagged. 0.1 by default
+    #[arg(long)]
+    min_key_confidence: Option<f64>,
+    /// Mixed In Key CSV export whose keys and tempos are tagged into the converted files that
+    /// have none, in place of detecting them. Songs are matched by file name
+    #[arg(long)]
+    mik_csv: Option<PathBuf>,
+    #[command(flatten)]
+    analysis_cache: AnalysisCacheArgs,
+    /// Make converting the same song with the same settings always give a byte-identical file:
+    /// tags are written in sorted order, and nothing saying when or by which ffmpeg version the
+    /// file was written is kept
+    #[arg(long)]
+    deterministic: bool,
+    /// JSON file declaring external tools to run on every song, such as a declicker, each with
+    /// a command, the stage it runs at (before-encode or after-encode), a timeout in seconds and
+    /// whether the song fails or goes on without it when it fails (on_failure fail or skip)
+    #[arg(long)]
+    processors: Option<PathBuf>,
+    /// Also write an acapella and an instrumental version of every converted song, separated
+    /// with Demucs or Spleeter, for rehearsing edits and tone play
+    #[arg(long, value_enum)]
+    separate: Option<SeparationTool>,
+    /// Only write rehearsal versions of songs with this tag set to 1
+    #[arg(long, requires = "separate")]
+    separate_tag: Option<String>,
+    /// Folder to write rehearsal versions to, mirroring the output directory. Defaults to
+    /// Rehearsal in the output directory
+    #[arg(long, requires = "separate")]
+    separate_dir: Option<PathBuf>,
+    /// Number of songs to convert at once, one per CPU by default
+    #[arg(long)]
+    convert_jobs: Option<usize>,
+    /// Convert fewer songs at once while other programs keep the machine busy, and up to
+    /// --convert-jobs when it is idle. Follows the load average on Linux
+    #[arg(long)]
+    adaptive_jobs: bool,
+    /// Highest sample rate to output, e.g. 48000 to keep 48 kHz files intact on players that
+    /// support them. Songs above it are resampled
+    #[arg(long, default_value_t = 44100)]
+    max_sample_rate: usize,
+    /// Highest bit depth of lossless output, 16 or 24
+    #[arg(long, default_value_t = 16)]
+    max_bit_depth: usize,
+    /// Check --max-sample-rate and --max-bit-depth against what this player can play
+    #[arg(long, value_enum)]
+    player: Option<PlayerProfile>,
+    /// Format to convert lossless songs to
+    #[arg(long, value_enum, default_value_t = LosslessTarget::Aiff)]
+    lossless_target: LosslessTarget,
+    /// Format to convert lossy songs to
+    #[arg(long, value_enum, default_value_t = LossyTarget::Mp3)]
+    lossy_target: LossyTarget,
+    /// Read songs from the input directory at no more than this many megabytes per second, e.g.
+    /// so converting from a NAS doesn't saturate the network. Songs are copied to a temporary
+    /// directory at this rate before converting, and probing, hashing and archiving them reads
+    /// at this rate too
+    #[arg(long = "max-read-MBps")]
+    max_read_mbps: Option<f64>,
+    /// Read at most this many songs from the input directory at once, whether to convert,
+    /// probe, hash or archive them
+    #[arg(long)]
+    max_concurrent_reads: Option<usize>,
+    /// Copy songs that are already in a Rekordbox format into the output directory instead of
+    /// skipping them, or hard link them with `hardlink`
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "copy")]
+    copy_compliant: Option<CopyMode>,
+    /// Also store every song picked, untouched, in this folder, laid out like the input
+    /// directory, with the checksum of every file in its manifest.json
+    #[arg(long)]
+    archive_dir: Option<PathBuf>,
+    /// How to store songs in the archive: untouched, or with WAV files as FLAC checked to
+    /// decode to the same samples
+    #[arg(long, value_enum, default_value_t = ArchiveTarget::Original, requires = "archive_dir")]
+    archive_target: ArchiveTarget,
+    /// Write a report of what happened to every song after converting, in this format
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+    /// File to write the report to. JSON reports go to stdout by default, CSV and HTML reports
+    /// to conversion-report.csv or .html in the output directory
+    #[arg(long)]
+    report_file: Option<PathBuf>,
+    /// Convert every song again, even the ones a previous run into the output directory already
+    /// converted and that haven't changed since. Implies --yes
+    #[arg(long)]
+    force: bool,
+    /// Overwrite existing files in the output directory without asking, even when there are many
+    #[arg(short, long)]
+    yes: bool,
+    /// Pick up a run into the output directory that was cut short, even a forced one: skip the
+    /// songs it finished and convert the ones it left half written again
+    #[arg(long)]
+    resume: bool,
+    /// Stop at the first song that fails to convert, instead of converting the rest first
+    #[arg(long)]
+    fail_fast: bool,
+    /// Decode every converted file after writing it, and count songs whose output doesn't
+    /// decode cleanly or is shorter or longer than the source as failed
+    #[arg(long)]
+    verify: bool,
+    /// After converting a song, set its --rekordbox-tag to 0 in the source file too, editing
+    /// only that tag in place, so later runs don't pick it again
+    #[arg(long, requires = "rekordbox_tag")]
+    untag_source: bool,
+}
+
+impl ConvertArgs {
+    /// Where the songs are converted to: the output directory, or the root of the device
+    fn output_dir(&self) -> PathBuf {
+        PathBuf::from(self.device.as_ref().or(self.output_dir.as_ref()).unwrap())
+    }
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    #[command(flatten)]
+    convert: ConvertArgs,
+    /// Seconds a new or changed file has to stay unchanged before it is converted, so songs
+    /// still being copied in are left alone
+    #[arg(long, default_value_t = 5)]
+    settle_secs: u64,
+}
+
+#[derive(Args)]
+struct ShowArgs {
+    /// A source song, or a file in the output directory it was converted into
+    path: PathBuf,
+    /// The output directory of the conversion
+    #[arg(short, long)]
+    output_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct QueryArgs {
+    /// The output directory of the conversion
+    output_dir: PathBuf,
+    /// The query, like "SELECT label, count(*) FROM tracks GROUP BY label"
+    #[arg(required_unless_present = "schema")]
+    sql: Option<String>,
+    /// How to print the results
+    #[arg(long, value_enum, default_value_t = QueryFormat::Table)]
+    format: QueryFormat,
+    /// Print the tables and views queries run against, instead of running one
+    #[arg(long)]
+    schema: bool,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// The output directory of the conversion
+    output_dir: PathBuf,
+    /// Format to export to: rekordbox-xml, m3u8, csv or serato
+    #[arg(short, long)]
+    format: String,
+    /// Folder to write the exported files into. Defaults to the output directory
+    #[arg(long)]
+    to: Option<PathBuf>,
+    /// How to read the converted files to find out their tags
+    #[arg(long, value_enum, default_value_t = ProbeBackend::Ffprobe)]
+    probe_backend: ProbeBackend,
+    /// Analyzers to run over the tracks before exporting them, separated by commas: loudness,
+    /// spectrum, bpm, key, fingerprint
+    #[arg(long, value_delimiter = ',')]
+    analyze: Vec<String>,
+    /// JSON file declaring the BPM range of playlists, like {"Peak time": {"min": 124, "max":
+    /// 130}}. Tracks far outside the range of their playlist are listed after exporting
+    #[arg(long)]
+    bpm_ranges: Option<PathBuf>,
+    /// JSON file of tempo ranges by genre or artist, picking between half and double the tempo
+    /// --analyze bpm detects, as for convert
+    #[arg(long)]
+    bpm_rules: Option<PathBuf>,
+    /// Mixed In Key CSV export whose keys and tempos are exported over those the analyzers find
+    #[arg(long)]
+    mik_csv: Option<PathBuf>,
+    /// Also export a "New This Month" playlist of the tracks first converted in the last DAYS
+    /// days, 30 if not given. Tracks drop out of it on later exports as they get older
+    #[arg(long, value_name = "DAYS", num_args = 0..=1, default_missing_value = "30")]
+    new_playlist_days: Option<u64>,
+    #[command(flatten)]
+    analysis_cache: AnalysisCacheArgs,
+}
+
+#[derive(Args)]
+struct ExportXmlArgs {
+    /// The output directory of the conversion
+    output_dir: PathBuf,
+    /// Where to write the library. Defaults to rekordbox.xml in the output directory
+    #[arg(short = 'x', long)]
+    xml: Option<PathBuf>,
+    /// How to read the converted files to find out their tags
+    #[arg(long, value_enum, default_value_t = ProbeBackend::Ffprobe)]
+    probe_backend: ProbeBackend,
+    /// Update this existing library, keeping its other tracks, playlists and cue points, and
+    /// print what changed, instead of writing a new one
+    #[arg(long)]
+    merge: Option<PathBuf>,
+    /// Also export a "New This Month" playlist of the tracks first converted in the last DAYS
+    /// days, 30 if not given. Tracks drop out of it on later exports as they get older
+    #[arg(long, value_name = "DAYS", num_args = 0..=1, default_missing_value = "30")]
+    new_playlist_days: Option<u64>,
+}
+
+#[derive(Args)]
+struct ImportHistoryArgs {
+    /// The output directory of the conversion
+    output_dir: PathBuf,
+    /// Library exported from Rekordbox with File > Export Collection in xml format
+    xml: PathBuf,
+    /// Also write the play counts and ratings that changed into the source files, as PLAYCOUNT
+    /// and RATING tags, so they are carried into the outputs on the next conversion
+    #[arg(long)]
+    write_tags: bool,
+}
+
+#[derive(Args)]
+struct SetlistArgs {
+    /// The output directory of the conversion
+    output_dir: PathBuf,
+    /// Name of the history playlist in --from-rekordbox-xml, like "HISTORY 2024-01-31", or its
+    /// path through folders. Without it, an M3U8 file Rekordbox exported the history to
+    playlist: String,
+    /// Rekordbox library exported as XML to take the history playlist from
+    #[arg(long)]
+    from_rekordbox_xml: Option<PathBuf>,
+    /// How to print the set list
+    #[arg(long, value_enum, default_value_t = SetlistFormat::Text)]
+    format: SetlistFormat,
+    /// How to read the sources to find out their tags
+    #[arg(long, value_enum, default_value_t = ProbeBackend::Ffprobe)]
+    probe_backend: ProbeBackend,
+}
+
+#[derive(Args)]
+struct ValidateXmlArgs {
+    /// The library to check
+    xml: PathBuf,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// The folder with the songs to verify, usually the output directory of a conversion
+    dir: PathBuf,
+    /// Number of songs to decode at once, one per CPU by default
+    #[arg(long)]
+    verify_jobs: Option<usize>,
+}
+
+#[derive(Args)]
+struct AnalyzeArgs {
+    /// The folder with the songs to analyze
+    dir: PathBuf,
+    /// Analyzers to run, separated by commas: loudness, spectrum, bpm, key, fingerprint. All of
+    /// them by default
+    #[arg(short, long, value_delimiter = ',')]
+    analyzers: Vec<String>,
+    /// Number of songs to analyze at once, one per CPU by default
+    #[arg(long)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    analysis_cache: AnalysisCacheArgs,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    /// The folder with the songs to snapshot
+    dir: PathBuf,
+    /// The Arrow IPC file to write, like library.arrow
+    #[arg(long)]
+    to: PathBuf,
+    /// Analyzers to run, separated by commas: loudness, spectrum, bpm, key, fingerprint. None by
+    /// default, which leaves the analysis columns empty
+    #[arg(short, long, value_delimiter = ',')]
+    analyzers: Vec<String>,
+    /// Number of songs to analyze at once, one per CPU by default
+    #[arg(long)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    analysis_cache: AnalysisCacheArgs,
+}
+
+#[derive(Args)]
+struct VerifyManifestArgs {
+    /// The output directory of a conversion, or a copy of it such as a USB stick
+    dir: PathBuf,
+}
+
+#[derive(Args)]
+struct DiffRunsArgs {
+    /// The output directory of the first run, or a manifest.json kept from it
+    a: PathBuf,
+    /// The output directory of the second run, or a manifest.json kept from it
+    b: PathBuf,
+}
+
+#[derive(Args)]
+struct TagArgs {
+    /// The song to show or change the tags of
+    file: PathBuf,
+    /// Set a tag, as KEY=VALUE. Can be given multiple times
+    #[arg(long, value_parser = tagging::parse_tag)]
+    set: Vec<(String, String)>,
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// The folder with the songs to summarize
+    dir: PathBuf,
+}
+
+#[derive(Args)]
+struct AuditionArgs {
+    /// A converted song, or an output directory to take the most recently converted songs from
+    path: PathBuf,
+    /// How many of the most recent songs in a directory to play
+    #[arg(short = 'n', long, default_value_t = 5)]
+    count: usize,
+    /// Length of each excerpt in seconds
+    #[arg(long, default_value_t = 10.0)]
+    seconds: f64,
+}
+
+#[derive(Args)]
+struct CompareArgs {
+    /// The song to encode
+    sample: PathBuf,
+    /// First encoder profile
+    #[arg(value_enum)]
+    a: EncoderProfile,
+    /// Second encoder profile
+    #[arg(value_enum)]
+    b: EncoderProfile,
+    /// Directory to write the encodings and the difference to
+    #[arg(short, long, default_value = "comparison")]
+    output_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct BackupArgs {
+    /// The output directory to back up
+    dir: PathBuf,
+    /// Directory to write the backup to
+    #[arg(long, default_value = ".")]
+    to: PathBuf,
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// The backup to restore
+    archive: PathBuf,
+    /// The output directory to restore it into
+    dir: PathBuf,
+}
+
+fn main() {
+    let app = App::parse();
+    let plain = app.plain || std::env::var("TERM").is_ok_and(|term| term == "dumb");
+    //Initialize tracing
+    // Logs go to stderr, so stdout only holds output meant for scripts, like reports
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(!plain)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    i18n::init(app.lang.as_deref());
+    if app.read_only {
+        read_only::enable();
+    }
+    let result = match app.command {
+        Command::Convert(args) => convert(args, plain),
+        Command::Watch(args) => watch(args, plain),
+        Command::Scan(args) => args.options().and_then(|options| {
+            let converter = Converter::new(options);
+            let files = select_files(&converter, args.input_dir.as_deref())?;
+            converter.dry_run_files(&files).map(|_| ())
+        }),
+        Command::Verify(args) => verify_dir(
+            &args.dir,
+            args.verify_jobs.unwrap_or_else(workers::default_jobs),
+        ),
+        Command::Analyze(args) => analyze(&args),
+        Command::Snapshot(args) => snapshot(&args),
+        Command::VerifyManifest(args) => verify_manifest(&args.dir),
+        Command::DiffRuns(args) => diff_runs(&args.a, &args.b),
+        Command::Show(args) => show(&args.output_dir, &args.path),
+        Command::Export(args) => export(&args),
+        Command::ExportXml(args) => export_xml(&args),
+        Command::ValidateXml(args) => validate_xml(&args.xml),
+        Command::ImportHistory(args) => {
+            play_history::import(&args.output_dir, &args.xml, args.write_tags)
+                .map(|import| println!("{}", import))
+        }
+        Command::Setlist(args) => print_setlist(&args),
+        Command::Tag(args) => tag(&args.file, &args.set),
+        Command::Report(args) => report(&args.dir),
+        Command::Query(args) => run_query(&args),
+        Command::Audition(args) => audition(&args),
+        Command::Compare(args) => compare::compare(&args.sample, args.a, args.b, &args.output_dir)
+            .map(|comparison| println!("{}", comparison)),
+        Command::Backup(args) => {
+            backup::backup(&args.dir, &args.to).map(|archive| println!("{}", archive.display()))
+        }
+        Command::Restore(args) => backup::restore(&args.archive, &args.dir),
+    };
+    if let Err(e) = result {
+        tracing::error!(?e);
         std::process::exit(1);
     }
 }
 
-// TO-DO: Implement control flow so that volumedetect is used if volume normalization is desired
-// Because volumedetect is a time-consuming process, user might not want to do it.
-// Perhaps implement concurrency to speed up conversions
-pub fn convert_song(song: &SongInfo, output_dir: &Path, conversion_tag: &str) -> Result<()> {
-    match song.get_format() {
-        AudioFormatType::Unsupported => {
-            return Err(anyhow!(
-                "{} has an unsupported file format!",
-                song.get_song_path().to_string_lossy()
-            ))
+fn convert(args: ConvertArgs, plain: bool) -> anyhow::Result<()> {
+    let in_folder = args.select.input_dir.clone();
+    let out_path = args.output_dir();
+    let dry_run = args.dry_run;
+    let report_format = args.report;
+    let report_file = args.report_file.clone();
+    let converter = Converter::new(convert_options(args, plain)?);
+    let files = select_files(&converter, in_folder.as_deref())?;
+    if dry_run {
+        converter.dry_run_files(&files)?;
+    } else {
+        let summary = converter.run_files(&files, &out_path)?;
+        converter.write_playlist(&files, &out_path)?;
+        if let Some(format) = report_format {
+            report::write_report(
+                &RunReport::from_summary(&summary),
+                format,
+                report_file
+                    .or_else(|| format.default_file_name().map(|name| out_path.join(name)))
+                    .as_deref(),
+            )?;
         }
-        _ => {
-            let song_name = song.get_song_name()?;
-            // If a song satisfies Rekordbox audio format, we can skip
-            if *song.get_sample_rate() <= 44100 && song.is_rekordbox_format() {
-                match song.get_format() {
-                    AudioFormatType::Lossless(_) => {
-                        if *song.get_bit_info() <= 16 {
-                            tracing::warn!(?song_name, "Already Rekordbox format!");
-                            return Ok(());
-                        }
-                    }
-                    AudioFormatType::Lossy(_) => {
-                        if *song.get_bit_info() <= 320000 {
-                            tracing::warn!(?song_name, "Already Rekordbox format!");
-                            return Ok(());
-                        }
-                    }
-                    _ => (), //can't occur since this code only gets evaluated if the format type is not unsupported
-                }
-            }
-            // If we are given a conversion tag, if a song does not have the specified conversion tag set to 1
-            // move on to the next song
-            let conversion_tag_arg;
-            if conversion_tag.len() > 0 {
-                match song.get_tags() {
-                    Some(tags) => match tags.get(conversion_tag) {
-                        // If conversion tag is not 1, skip
-                        Some(tag) => {
-                            if tag != "1" {
-                                return Err(anyhow!("Not tagged for conversion! {:?}", song_name));
-                            }
-                        }
-                        // If song does not have conversion tag, skip
-                        None => return Err(anyhow!("Not tagged for conversion! {:?}", song_name)),
-                    },
-                    // if song has no tags, skip
-                    None => return Err(anyhow!("Not tagged for conversion! {:?}", song_name)),
-                }
-                conversion_tag_arg = format!("{}=0", conversion_tag);
-            } else {
-                conversion_tag_arg = conversion_tag.to_string();
-            }
+        if summary.n_failed > 0 {
+            anyhow::bail!("{} songs failed to convert", summary.n_failed);
+        }
+        if summary.n_archive_failed > 0 {
+            anyhow::bail!("{} songs couldn't be archived", summary.n_archive_failed);
+        }
+    }
+    Ok(())
+}
 
-            let output_format;
-            let output_bit_info;
-            let output_bit_type;
-            let output_sample_rate = cmp::min(*song.get_sample_rate(), 44100);
-            let output_codec;
-            match song.get_format() {
-                AudioFormatType::Lossless(_) => {
-                    output_format = SupportedAudioFormat::AIFF.to_string();
-                    output_bit_type = "-sample_fmt";
-                    output_bit_info = format!("s{}", cmp::min(*song.get_bit_info(), 16));
-                    output_codec = String::from("pcm_s16le");
-                }
-                AudioFormatType::Lossy(_) => {
-                    output_format = SupportedAudioFormat::MP3.to_string();
-                    output_bit_type = "-b:a";
-                    output_bit_info = format!("{}k", cmp::min(*song.get_bit_info(), 320000) / 100);
-                    output_codec = String::from("mp3");
+fn watch(mut args: WatchArgs, plain: bool) -> anyhow::Result<()> {
+    if args.convert.dry_run {
+        anyhow::bail!("watch can't do a dry run, use scan instead");
+    }
+    // Changed files are reported with absolute paths, so the songs of the first run need them
+    // too for the state file to recognize them
+    let in_folder = match &args.convert.select.input_dir {
+        Some(dir) => Path::new(dir).canonicalize()?,
+        None => anyhow::bail!("watch needs an --input-dir to watch"),
+    };
+    args.convert.select.input_dir = Some(in_folder.to_string_lossy().to_string());
+    let out_path = args.convert.output_dir();
+    let report_format = args.convert.report;
+    let report_file = args.convert.report_file.clone();
+    let converter = Converter::new(convert_options(args.convert, plain)?);
+    watch::watch(
+        &converter,
+        &in_folder,
+        &out_path,
+        Duration::from_secs(args.settle_secs),
+        |summary| {
+            if let Some(format) = report_format {
+                let path = report_file
+                    .clone()
+                    .or_else(|| format.default_file_name().map(|name| out_path.join(name)));
+                let report = RunReport::from_summary(summary);
+                if let Err(e) = report::write_report(&report, format, path.as_deref()) {
+                    tracing::error!(?e, "Could not write report");
                 }
-                _ => return Ok(()), //can't occur as this code block only gets evaluated if the audio format is supported
             }
-            let mut output_file_path = output_dir.to_path_buf();
-            output_file_path.push(format!("{}.{}", song_name, output_format));
-
-            let mut convert_command = Command::new("ffmpeg");
-            convert_command
-                .arg("-y")
-                .arg("-i")
-                .arg(song.get_song_path())
-                .arg("-acodec")
-                .arg(output_codec)
-                .arg("-ar")
-                .arg(format!("{}", output_sample_rate))
-                .arg("-write_id3v2")
-                .arg("1")
-                .arg("-metadata")
-                .arg("REKORDBOX=1");
-
-            if conversion_tag.len() > 0 {
-                convert_command.arg("-metadata").arg(conversion_tag_arg);
+        },
+    )
+}
+
+/// Checks the convert arguments and turns them into options for the converter
+fn convert_options(args: ConvertArgs, plain: bool) -> anyhow::Result<ConvertOptions> {
+    let source_root = args.select.source_root();
+    let archive_root = source_root.clone();
+    let output_dir = args.output_dir();
+    if let Some(target) = args.normalize {
+        // The range loudnorm accepts
+        if !(-70.0..=-5.0).contains(&target) {
+            tracing::error!(?target, "Loudness target must be between -70 and -5 LUFS!");
+            std::process::exit(1);
+        }
+    }
+    if let Some(ceiling) = args.limiter_ceiling {
+        if !loudness::LIMITER_CEILING_RANGE.contains(&ceiling) {
+            anyhow::bail!(
+                "--limiter-ceiling must be between {} and {} dBTP, got {}",
+                loudness::LIMITER_CEILING_RANGE.start(),
+                loudness::LIMITER_CEILING_RANGE.end(),
+                ceiling
+            );
+        }
+        if args.normalize.is_none() && args.loudness_preset.target_lufs().is_none() {
+            anyhow::bail!("--limiter-ceiling only applies with --normalize or a --loudness-preset");
+        }
+    }
+    if let Some(tool) = args.separate {
+        tool.check()?;
+    }
+    if let Some(confidence) = args.min_key_confidence {
+        if !(0.0..=1.0).contains(&confidence) {
+            anyhow::bail!(
+                "--min-key-confidence must be between 0 and 1, got {}",
+                confidence
+            );
+        }
+    }
+    if args.anlz && !args.usb_layout && args.device.is_none() {
+        anyhow::bail!("--anlz only applies with --usb-layout or --device");
+    }
+    if let Some(mbps) = args.max_read_mbps {
+        if mbps <= 0.0 {
+            anyhow::bail!("--max-read-MBps must be positive, got {}", mbps);
+        }
+    }
+    // Shared by the conversion and the archive, so their reads add up to the limits
+    let read_throttle = if args.max_read_mbps.is_some() || args.max_concurrent_reads.is_some() {
+        Some(Arc::new(ReadThrottle::new(
+            args.max_read_mbps,
+            args.max_concurrent_reads.unwrap_or(usize::MAX),
+        )))
+    } else {
+        None
+    };
+    players::validate(args.max_sample_rate, args.max_bit_depth, args.player)?;
+    let pool_parser = args.pool_layout.as_ref().map(|name| {
+        record_pool::parser_by_name(name).unwrap_or_else(|| {
+            tracing::error!(?name, "Unknown record pool layout!");
+            std::process::exit(1);
+        })
+    });
+    let artwork = match args.max_artwork_size {
+        Some(max_size) => Some(ArtworkOptions {
+            max_size,
+            quality: args.artwork_quality,
+            archive_dir: args.artwork_archive.clone(),
+        }),
+        None => None,
+    };
+    let assume_yes = args.yes || args.force;
+    Ok(ConvertOptions {
+        pool_parser,
+        loudness_preset: args.loudness_preset,
+        normalize: args.normalize,
+        limiter_ceiling: args.limiter_ceiling,
+        replaygain: args.replaygain,
+        cleanup: args.cleanup,
+        cleanup_dirs: args.cleanup_dir,
+        speed_correction: args.speed_correct,
+        speed_review: speed::Review::load(&output_dir)?,
+        stem_mode: args.stems,
+        artist_separator: args.artist_separator,
+        split_artists_loosely: args.split_artists_loosely,
+        year_source: args.year_source,
+        name_template: args.name_template,
+        sanitizer: if args.sanitize_names {
+            Some(NameSanitizer {
+                replacement: args.replacement_char,
+                max_length: args.max_name_length,
+            })
+        } else {
+            None
+        },
+        source_root: if args.preserve_structure {
+            source_root
+        } else {
+            None
+        },
+        route_by: args.route_by,
+        route_map: match &args.route_map {
+            Some(path) => RouteMap::load(path)?,
+            None => RouteMap::default(),
+        },
+        usb_layout: args.usb_layout || args.device.is_some(),
+        device_preflight: args.device.is_some().then_some(args.when_full),
+        artwork,
+        folder_artwork: args.folder_artwork,
+        artwork_fetcher: if args.fetch_artwork {
+            Some(ArtworkFetcher::new(Duration::from_millis(
+                args.fetch_artwork_interval,
+            )))
+        } else {
+            None
+        },
+        artwork_cache: if args.dedupe_artwork {
+            Some(ArtworkCache::new())
+        } else {
+            None
+        },
+        show_progress: true,
+        plain_progress: plain,
+        export_waveforms: args.export_waveforms,
+        anlz: args.anlz,
+        detect_bpm: args.detect_bpm,
+        bpm_rules: match &args.bpm_rules {
+            Some(path) => bpm_octave::load_rules(path)?,
+            None => vec![],
+        },
+        bpm_review: bpm_octave::Review::load(&output_dir)?,
+        detect_key: args.detect_key,
+        analysis_cache: if args.detect_bpm || args.detect_key {
+            args.analysis_cache.open()
+        } else {
+            None
+        },
+        min_key_confidence: args
+            .min_key_confidence
+            .unwrap_or(key::DEFAULT_MIN_CONFIDENCE),
+        mik_export: match &args.mik_csv {
+            Some(path) => Some(MikExport::load(path)?),
+            None => None,
+        },
+        deterministic: args.deterministic,
+        processors: match &args.processors {
+            Some(path) => processors::load(path)?,
+            None => vec![],
+        },
+        separation: args.separate,
+        separation_tag: args.separate_tag.clone().unwrap_or_default(),
+        separation_dir: args.separate_dir.clone(),
+        convert_jobs: args.convert_jobs.unwrap_or_else(workers::default_jobs),
+        adaptive_jobs: args.adaptive_jobs,
+        max_sample_rate: args.max_sample_rate,
+        max_bit_depth: args.max_bit_depth,
+        lossless_target: args.lossless_target,
+        lossy_target: args.lossy_target,
+        read_throttle: read_throttle.clone(),
+        copy_compliant: args.copy_compliant,
+        archive: match args.archive_dir {
+            Some(dir) => Some(Archive {
+                dir,
+                source_root: archive_root,
+                target: args.archive_target,
+                read_throttle,
+            }),
+            None => None,
+        },
+        incremental: !args.force,
+        resume: args.resume,
+        fail_fast: args.fail_fast,
+        verify: args.verify,
+        untag_source: args.untag_source,
+        confirm_overwrite: Some(Box::new(move |files: &[PathBuf]| {
+            if assume_yes || files.len() <= confirm::THRESHOLD {
+                return Ok(true);
             }
-            convert_command
-                .arg(output_bit_type)
-                .arg(output_bit_info)
-                .arg(output_file_path);
-            // If we ran into an error when converting the file, log it and then move on to the next file
-            convert_command.output()?;
+            let heading = i18n::message("confirm-overwrite", &[("count", files.len().into())]);
+            confirm::confirm(&confirm::summarize(&heading, files))
+        })),
+        ..args.select.options()?
+    })
+}
+
+fn verify_dir(dir: &Path, n_jobs: usize) -> anyhow::Result<()> {
+    let converter = Converter::new(ConvertOptions::default());
+    let files = converter.scan(dir)?;
+    let songs = converter.probe(&files);
+    let paths: Vec<PathBuf> = songs.iter().map(|s| s.get_song_path().clone()).collect();
+    let summary = verify::verify_files(&paths, n_jobs);
+    for (path, error) in summary.failures.iter() {
+        println!("{}: {}", path.display(), error);
+    }
+    println!(
+        "{}",
+        i18n::message(
+            "verify-summary",
+            &[
+                ("verified", summary.n_verified.into()),
+                ("failed", summary.failures.len().into()),
+            ]
+        )
+    );
+    if !summary.failures.is_empty() {
+        anyhow::bail!("{} songs failed to decode", summary.failures.len());
+    }
+    Ok(())
+}
+
+fn show(output_dir: &Path, path: &Path) -> anyhow::Result<()> {
+    match links::find(output_dir, path)? {
+        Some(link) => {
+            println!("{}", serde_json::to_string_pretty(&link)?);
             Ok(())
         }
+        None => anyhow::bail!(
+            "{} isn't a song converted into {}, or one of its outputs",
+            path.display(),
+            output_dir.display()
+        ),
     }
 }
 
-pub fn convert_songs_parallel(songs: &Vec<PathBuf>, output_path: &str, tag: &str) -> Result<()> {
-    let mut handles: Vec<JoinHandle<Result<()>>> = vec![];
-    let n_converted = Arc::new(Mutex::new(0));
-    let n_iterated = Arc::new(Mutex::new(0));
-    for song in songs
-        .iter()
-        .filter_map(|s| song_info::from_file(s.as_path()).ok())
-    {
-        let n_converted_lock = n_converted.clone();
-        let n_iterated_lock = n_iterated.clone();
-        let output_path_copy = output_path.to_string();
-        let tag_copy = tag.to_string();
-        let handle = thread::spawn(move || {
-            {
-                let mut i = n_iterated_lock.lock().unwrap();
-                *i += 1;
-                tracing::debug!(n_songs = *i, "Current number of songs iterated through");
+fn run_query(args: &QueryArgs) -> anyhow::Result<()> {
+    let sql = match (&args.sql, args.schema) {
+        (Some(sql), false) => sql,
+        _ => {
+            print!("{}", query::SCHEMA);
+            return Ok(());
+        }
+    };
+    // Tags come from the sources as they are now, which the probe cache makes quick
+    let sources: Vec<PathBuf> = ConversionState::load(&args.output_dir)?
+        .sources()
+        .map(|(path, _)| path.clone())
+        .filter(|path| path.exists())
+        .collect();
+    let converter = Converter::new(ConvertOptions {
+        probe_cache: ProbeCache::default_path().map(|path| ProbeCache::open(&path)),
+        ..Default::default()
+    });
+    let songs = converter.probe(&sources);
+    print!(
+        "{}",
+        query::run(&args.output_dir, &songs, sql, args.format)?
+    );
+    Ok(())
+}
+
+fn diff_runs(a: &Path, b: &Path) -> anyhow::Result<()> {
+    let diff = Manifest::of_run(a)?.diff(&Manifest::of_run(b)?);
+    let lists = [
+        ("diff-changed", &diff.changed),
+        ("diff-added", &diff.added),
+        ("diff-removed", &diff.removed),
+    ];
+    for (message, paths) in lists.iter() {
+        for path in paths.iter() {
+            println!("{}: {}", path.display(), i18n::message(message, &[]));
+        }
+    }
+    println!(
+        "{}",
+        i18n::message(
+            "diff-summary",
+            &[
+                ("unchanged", diff.n_unchanged.into()),
+                ("changed", diff.changed.len().into()),
+                ("added", diff.added.len().into()),
+                ("removed", diff.removed.len().into()),
+            ]
+        )
+    );
+    Ok(())
+}
+
+fn verify_manifest(dir: &Path) -> anyhow::Result<()> {
+    let manifest = Manifest::load(dir)?
+        .ok_or_else(|| anyhow::anyhow!("{} has no {}", dir.display(), MANIFEST_FILE_NAME))?;
+    let check = manifest.check(dir);
+    for path in check.missing.iter() {
+        println!(
+            "{}: {}",
+            path.display(),
+            i18n::message("manifest-missing", &[])
+        );
+    }
+    for (path, problem) in check.changed.iter() {
+        println!("{}: {}", path.display(), problem);
+    }
+    println!(
+        "{}",
+        i18n::message(
+            "manifest-summary",
+            &[
+                ("checked", check.n_checked.into()),
+                ("missing", check.missing.len().into()),
+                ("changed", check.changed.len().into()),
+            ]
+        )
+    );
+    if !check.is_ok() {
+        anyhow::bail!("{} doesn't match its manifest", dir.display());
+    }
+    Ok(())
+}
+
+fn analyze(args: &AnalyzeArgs) -> anyhow::Result<()> {
+    let analyzers = if args.analyzers.is_empty() {
+        analyzers::available(&analyzers::ANALYZERS)
+    } else {
+        find_analyzers(&args.analyzers)?
+    };
+    let converter = Converter::new(ConvertOptions::default());
+    let files = converter.scan(&args.dir)?;
+    let songs = converter.probe(&files);
+    let paths: Vec<PathBuf> = songs.iter().map(|s| s.get_song_path().clone()).collect();
+    let cache = args.analysis_cache.open().map(Arc::new);
+    let results = analyzers::analyze_all(
+        paths.clone(),
+        &analyzers,
+        args.jobs.unwrap_or_else(workers::default_jobs),
+        cache.clone(),
+    );
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(analysis) => {
+                let mut line = serde_json::to_value(&analysis)?;
+                line["path"] = path.display().to_string().into();
+                println!("{}", line);
             }
-            {
-                let out_path = Path::new(&output_path_copy);
-                if let Err(e) = convert_song(&song, out_path, &tag_copy) {
-                    tracing::error!(?e);
-                } else {
-                    let mut c = n_converted_lock.lock().unwrap();
-                    *c += 1;
-                    tracing::debug!(n_converted = *c, "Current number of converted songs");
+            Err(e) => tracing::error!(?path, ?e, "Could not analyze song"),
+        }
+    }
+    Ok(())
+}
+
+fn snapshot(args: &SnapshotArgs) -> anyhow::Result<()> {
+    let analyzers = find_analyzers(&args.analyzers)?;
+    let converter = Converter::new(ConvertOptions::default());
+    let files = converter.scan(&args.dir)?;
+    let songs = converter.probe(&files);
+    let analyses = if analyzers.is_empty() {
+        vec![None; songs.len()]
+    } else {
+        let cache = args.analysis_cache.open().map(Arc::new);
+        let results = analyzers::analyze_all(
+            songs.iter().map(|s| s.get_song_path().clone()).collect(),
+            &analyzers,
+            args.jobs.unwrap_or_else(workers::default_jobs),
+            cache.clone(),
+        );
+        if let Some(cache) = cache {
+            cache.save()?;
+        }
+        songs
+            .iter()
+            .zip(results)
+            .map(|(song, result)| match result {
+                Ok(analysis) => Some(analysis),
+                Err(e) => {
+                    tracing::error!(path = ?song.get_song_path(), ?e, "Could not analyze song");
+                    None
                 }
-            }
-            Ok(())
-        });
-        handles.push(handle);
+            })
+            .collect()
+    };
+    let rows: Vec<_> = songs.into_iter().zip(analyses).collect();
+    snapshot::write(&args.to, &rows)?;
+    println!("{}", args.to.display());
+    Ok(())
+}
+
+/// The analyzers named, without the ones that can't run here
+fn find_analyzers(names: &[String]) -> anyhow::Result<Vec<&'static dyn analyzers::Analyzer>> {
+    let analyzers = names
+        .iter()
+        .map(|name| analyzers::find(name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(analyzers::available(&analyzers))
+}
+
+fn export(args: &ExportArgs) -> anyhow::Result<()> {
+    let exporter = exporters::find(&args.format)?;
+    let to_dir = args.to.as_ref().unwrap_or(&args.output_dir);
+    let analyzers = find_analyzers(&args.analyze)?;
+    let options = ExportOptions {
+        analysis_cache: if analyzers.is_empty() {
+            None
+        } else {
+            args.analysis_cache.open().map(Arc::new)
+        },
+        analyzers,
+        bpm_ranges: match &args.bpm_ranges {
+            Some(path) => tempo_range::load(path)?,
+            None => Default::default(),
+        },
+        bpm_rules: match &args.bpm_rules {
+            Some(path) => bpm_octave::load_rules(path)?,
+            None => vec![],
+        },
+        mik_export: match &args.mik_csv {
+            Some(path) => Some(MikExport::load(path)?),
+            None => None,
+        },
+        new_playlist_days: args.new_playlist_days,
+    };
+    let summary = exporters::export(
+        exporter,
+        &args.output_dir,
+        to_dir,
+        args.probe_backend,
+        &options,
+    )?;
+    for path in summary.written {
+        tracing::info!(?path, "Exported {}", exporter.description());
     }
-    for handle in handles {
-        let _ = handle.join().unwrap();
+    for warning in summary.tempo_warnings.iter() {
+        let message = match warning.suspect {
+            Suspect::HalfTime => "tempo-half-time",
+            Suspect::DoubleTime => "tempo-double-time",
+            Suspect::Misfiled => "tempo-misfiled",
+        };
+        println!(
+            "{}: {}",
+            warning.path.display(),
+            i18n::message(
+                message,
+                &[
+                    ("playlist", warning.playlist.as_str().into()),
+                    ("bpm", ((warning.bpm * 10.0).round() / 10.0).into()),
+                    ("min", warning.range.min.into()),
+                    ("max", warning.range.max.into()),
+                ]
+            )
+        );
     }
-    let n_converted = Arc::try_unwrap(n_converted)
-        .expect("Should not have more than reference to n_converted")
-        .into_inner()
-        .unwrap();
-    let n_iterated = Arc::try_unwrap(n_iterated)
-        .expect("Should not have more than reference to n_converted")
-        .into_inner()
-        .unwrap();
-    tracing::info!(?n_converted, ?n_iterated, "Results of conversion");
     Ok(())
 }
 
-/**
-// Helper function to find the peak RMS of an audio file
-fn get_max_volume(path: &str) -> Option<f64> {
-    let output = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(path)
-        .arg("-filter:a")
-        .arg("volumedetect")
-        .arg("-f")
-        .arg("null")
-        .arg("dummy.mp3") //dummy output that ffmpeg requires
-        .output()
-        .expect("failed to get volume");
-    let mut max_volume = None;
-    let vol_output = String::from_utf8(output.stderr);
-    if let Ok(vol_output) = vol_output {
-        // Find the line with max volume
-        let line: String = vol_output
-            .lines()
-            .filter(|s| s.ends_with("dB"))
-            .filter(|s| s.contains("max_volume"))
-            .collect();
-        // Parse the max volume line to find the level
-        let mut parsed_num: Vec<f64> = line
-            .split(' ')
-            .filter_map(|s| s.parse::<f64>().ok())
-            .collect();
-        if parsed_num.len() != 1 {
-            log::error!("Volume for {} not parsed correctly!", path);
-        } else {
-            max_volume = parsed_num.pop();
+fn export_xml(args: &ExportXmlArgs) -> anyhow::Result<()> {
+    let xml_path = args
+        .xml
+        .clone()
+        .unwrap_or_else(|| args.output_dir.join(rekordbox_xml::XML_FILE_NAME));
+    match &args.merge {
+        Some(existing) => {
+            let diff = xml_merge::merge(
+                &args.output_dir,
+                existing,
+                &xml_path,
+                args.probe_backend,
+                args.new_playlist_days,
+            )?;
+            println!("{}", diff);
+        }
+        None => {
+            let n_tracks = rekordbox_xml::export(
+                &args.output_dir,
+                &xml_path,
+                args.probe_backend,
+                args.new_playlist_days,
+            )?;
+            tracing::info!(n_tracks, ?xml_path, "Exported Rekordbox library");
         }
-    } else {
-        log::error!("Could not parse output from volumedetect for {}", path);
     }
-    max_volume
+    Ok(())
 }
-*/
 
-fn main() {
-    //Initialize tracing
-    let subscriber = tracing_subscriber::FmtSubscriber::new();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+fn print_setlist(args: &SetlistArgs) -> anyhow::Result<()> {
+    let history = match &args.from_rekordbox_xml {
+        Some(xml) => PlaylistSelection::load(xml, &args.playlist)?,
+        None => PlaylistSelection::from_m3u(Path::new(&args.playlist))?,
+    };
+    let entries = setlist::setlist(&args.output_dir, &history, args.probe_backend)?;
+    print!("{}", setlist::format(&entries, args.format));
+    Ok(())
+}
 
-    let app = App::parse();
+fn validate_xml(path: &Path) -> anyhow::Result<()> {
+    let problems = xml_validate::validate(&std::fs::read_to_string(path)?);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.iter().any(|p| p.severity == Severity::Error) {
+        anyhow::bail!("Rekordbox wouldn't import {} right", path.display());
+    }
+    Ok(())
+}
 
-    let in_folder = Path::new(app.input_dir.as_str());
-    let out_path = Path::new(app.output_dir.as_str());
-    if !out_path.is_dir() {
-        tracing::error!("Provided output path is not a directory!");
-        std::process::exit(1);
+fn tag(file: &Path, tags: &[(String, String)]) -> anyhow::Result<()> {
+    if !tags.is_empty() {
+        tagging::write_tags(file, tags)?;
     }
-    let mut songs = Vec::new();
-    build_list_of_files(in_folder, &mut songs);
-    //okay to unwrap here because out was converted from a str originally
-    let _ = convert_songs_parallel(
-        &songs,
-        &app.output_dir,
-        app.rekordbox_tag.unwrap_or_default().as_str(),
-    )
-    .unwrap();
+    let song = song_info::from_file(file)?;
+    if let Some(serde_json::Value::Object(tags)) = song.get_tags() {
+        for (key, value) in tags {
+            match value.as_str() {
+                Some(value) => println!("{}={}", key, value),
+                None => println!("{}={}", key, value),
+            }
+        }
+    }
+    Ok(())
 }
 
-/*
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn report(dir: &Path) -> anyhow::Result<()> {
+    let converter = Converter::new(ConvertOptions::default());
+    let files = converter.scan(dir)?;
+    let songs = converter.probe(&files);
+    println!("{}", DirectoryReport::from_songs(&songs));
+    Ok(())
+}
 
-    #[test]
-    fn test_get_volume() {
-        assert_eq!(get_max_volume("/home/klu/Music/Hanna - Intercession, On Behalf.flac").unwrap(), -1.0);
-        assert!(get_max_volume("dummy.mp3").is_none());
-    }
+fn audition(args: &AuditionArgs) -> anyhow::Result<()> {
+    let paths = if args.path.is_dir() {
+        audition::recent_files(&args.path, args.count)?
+    } else {
+        vec![args.path.clone()]
+    };
+    audition::audition(&paths, args.seconds)
 }
-*/
@@ -0,0 +1,222 @@
+use crate::backup;
+use crate::converter::build_list_of_files;
+use crate::i18n;
+use crate::song_info;
+use crate::state::{self, hash_file, hash_file_throttled, modified_and_size};
+use crate::throttle::ReadThrottle;
+use crate::usb_layout;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest kept in the output directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// What an output file looked like when the manifest was written
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    /// FNV-1a hash of the file contents, as hex
+    pub hash: String,
+    /// Length in seconds, for audio files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// Modification time in seconds since the Unix epoch, so unchanged files aren't hashed
+    /// again when the manifest is updated. Not checked when verifying, as copying a file
+    /// usually changes it
+    pub modified: u64,
+}
+
+/// Hashes, sizes and lengths of every file in an output directory, by their path relative to
+/// it, so a copy of the directory, e.g. on a USB stick, can be checked against it later
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+/// How a copy of an output directory differs from its manifest
+#[derive(Clone, Debug, Default)]
+pub struct ManifestCheck {
+    pub n_checked: usize,
+    /// Files in the manifest that aren't in the directory
+    pub missing: Vec<PathBuf>,
+    /// Files whose size or contents differ, with how
+    pub changed: Vec<(PathBuf, String)>,
+}
+
+impl ManifestCheck {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// How the outputs of one run differ from another's
+#[derive(Clone, Debug, Default)]
+pub struct ManifestDiff {
+    pub n_unchanged: usize,
+    /// Files in both runs whose contents differ
+    pub changed: Vec<PathBuf>,
+    /// Files only the second run has
+    pub added: Vec<PathBuf>,
+    /// Files only the first run has
+    pub removed: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// Reads the manifest of a directory, if it has one
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Every file listed, by its path relative to the directory
+    pub fn files(&self) -> impl Iterator<Item = (&PathBuf, &ManifestEntry)> {
+        self.files.iter()
+    }
+
+    /// Writes the manifest into a directory atomically
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        state::write_atomic(&path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Builds the manifest of a directory, reusing the entries of an earlier manifest for files
+    /// whose size and modification time haven't changed. Other files are read through the read
+    /// throttle if there is one.
+    pub fn build(
+        dir: &Path,
+        previous: Option<&Manifest>,
+        throttle: Option<&ReadThrottle>,
+    ) -> Result<Self> {
+        let mut files = vec![];
+        build_list_of_files(dir, &mut files)?;
+        let mut manifest = Manifest::default();
+        for path in files {
+            let relative = match path.strip_prefix(dir) {
+                Ok(relative) if is_listed(relative) => relative.to_path_buf(),
+                _ => continue,
+            };
+            let (modified, size) = modified_and_size(&path)?;
+            let reused = previous
+                .and_then(|m| m.files.get(&relative))
+                .filter(|e| e.modified == modified && e.size == size);
+            let entry = match reused {
+                Some(entry) => entry.clone(),
+                None => ManifestEntry {
+                    size,
+                    hash: hash_file_throttled(&path, throttle)?,
+                    duration: {
+                        let _slot = throttle.map(ReadThrottle::slot);
+                        song_info::from_file(&path)
+                            .ok()
+                            .map(|song| song.get_duration())
+                            .filter(|d| *d > 0.0)
+                    },
+                    modified,
+                },
+            };
+            manifest.files.insert(relative, entry);
+        }
+        Ok(manifest)
+    }
+
+    /// Brings the manifest of a directory up to date with the files in it, reading them through
+    /// the read throttle if there is one
+    pub fn update(dir: &Path, throttle: Option<&ReadThrottle>) -> Result<()> {
+        let previous = Manifest::load(dir).unwrap_or_else(|e| {
+            tracing::warn!(
+                ?e,
+                "Could not read the old manifest, hashing every file again"
+            );
+            None
+        });
+        Manifest::build(dir, previous.as_ref(), throttle)?.save(dir)
+    }
+
+    /// The manifest of a run: the manifest.json of its output directory, one kept from an
+    /// earlier run, or, for a directory without one, what it would be
+    pub fn of_run(path: &Path) -> Result<Self> {
+        if path.is_file() {
+            return Ok(serde_json::from_slice(&fs::read(path)?)?);
+        }
+        match Manifest::load(path)? {
+            Some(manifest) => Ok(manifest),
+            None => {
+                tracing::info!(?path, "No manifest, hashing every file");
+                Manifest::build(path, None, None)
+            }
+        }
+    }
+
+    /// Compares the outputs of this run with another's by their hashes. Converting with
+    /// --deterministic makes a file's hash only change when the audio or tags written do.
+    pub fn diff(&self, other: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+        for (relative, entry) in &self.files {
+            match other.files.get(relative) {
+                Some(other_entry) if other_entry.hash == entry.hash => diff.n_unchanged += 1,
+                Some(_) => diff.changed.push(relative.clone()),
+                None => diff.removed.push(relative.clone()),
+            }
+        }
+        diff.added = other
+            .files
+            .keys()
+            .filter(|relative| !self.files.contains_key(*relative))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    /// Checks the files of a directory, which may be a copy of the one the manifest was
+    /// written for, against the manifest
+    pub fn check(&self, dir: &Path) -> ManifestCheck {
+        let mut check = ManifestCheck::default();
+        for (relative, entry) in &self.files {
+            check.n_checked += 1;
+            let path = dir.join(relative);
+            if !path.is_file() {
+                check.missing.push(relative.clone());
+                continue;
+            }
+            let problem = match (modified_and_size(&path), hash_file(&path)) {
+                (Ok((_, size)), _) if size != entry.size => Some(i18n::message(
+                    "manifest-size",
+                    &[("size", size.into()), ("expected", entry.size.into())],
+                )),
+                (_, Ok(hash)) if hash != entry.hash => Some(i18n::message(
+                    "manifest-hash",
+                    &[
+                        ("hash", hash.into()),
+                        ("expected", entry.hash.clone().into()),
+                    ],
+                )),
+                (Err(e), _) | (_, Err(e)) => Some(i18n::message(
+                    "manifest-unreadable",
+                    &[("error", e.to_string().into())],
+                )),
+                _ => None,
+            };
+            if let Some(problem) = problem {
+                tracing::error!(?path, %problem, "File doesn't match the manifest!");
+                check.changed.push((relative.clone(), problem));
+            }
+        }
+        check
+    }
+}
+
+/// Whether a file in the output directory belongs in its manifest, which leaves out the
+/// manifest itself and the other bookkeeping files, files still being written, and the PIONEER
+/// folder of a USB layout, which Rekordbox rewrites on every export to the stick
+fn is_listed(relative: &Path) -> bool {
+    let name = relative.to_string_lossy();
+    !backup::ARTIFACTS.contains(&name.as_ref())
+        && !name.ends_with(".tmp")
+        && !usb_layout::is_in_pioneer_dir(relative)
+}
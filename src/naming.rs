@@ -0,0 +1,65 @@
+use crate::song_info::SongInfo;
+
+/// Looks up the value of a template placeholder for a song
+fn placeholder_value(song: &SongInfo, key: &str) -> Option<String> {
+    let value = match key {
+        "filename" => song
+            .get_song_path()
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string()),
+        // Dates come as 2019-05-03 as often as 2019
+        "year" => song
+            .get_tag("date")
+            .or_else(|| song.get_tag("year"))
+            .map(|d| d.chars().take(4).collect()),
+        // Track numbers come as 3/12 in some containers
+        "track" => song
+            .get_tag("track")
+            .and_then(|t| t.split('/').next())
+            .map(|t| t.trim().to_string()),
+        key => song.get_tag(key).map(|v| v.to_string()),
+    };
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Names an output file after a template such as "{artist} - {title}". Placeholders are tag
+/// names, plus {filename} for the source file name without extension, and can give a fallback
+/// for songs without the tag as in {artist|Unknown Artist}. If a placeholder without fallback
+/// is missing, the source file name is used instead. Path separators are replaced, so a tag
+/// can't put the file in another folder.
+pub fn render(template: &str, song: &SongInfo) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                name.push_str(&rest[start..]);
+                rest = "";
+                break;
+            }
+        };
+        let placeholder = &rest[start + 1..end];
+        let (key, fallback) = match placeholder.split_once('|') {
+            Some((key, fallback)) => (key.trim(), Some(fallback)),
+            None => (placeholder.trim(), None),
+        };
+        match placeholder_value(song, key).or_else(|| fallback.map(|f| f.to_string())) {
+            Some(value) => name.push_str(&value),
+            None => {
+                tracing::debug!(song = ?song.get_song_path(), %key, "Missing tag for file name");
+                return placeholder_value(song, "filename").unwrap_or_default();
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+    let name = name.replace(['/', '\\'], "-").trim().to_string();
+    if name.is_empty() {
+        return placeholder_value(song, "filename").unwrap_or_default();
+    }
+    name
+}
@@ -0,0 +1,107 @@
+use lofty::file::FileType;
+use lofty::picture::MimeType;
+use lofty::prelude::*;
+use lofty::tag::Tag;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// Reads a file's tags and stream parameters without running ffprobe, laid out like ffprobe's
+/// JSON output so it is parsed, and cached, the same way. None if the file is in a format
+/// lofty can't read well enough, which is left to ffprobe.
+pub fn probe(path: &Path) -> Option<Value> {
+    let file = match lofty::read_from_path(path) {
+        Ok(file) => file,
+        Err(e) => {
+            tracing::debug!(?path, %e, "Could not read file natively");
+            return None;
+        }
+    };
+    let properties = file.properties();
+    let bit_depth = properties.bit_depth().map(usize::from);
+    let (format_name, codec_name) = match file.file_type() {
+        FileType::Aiff => ("aiff", format!("pcm_s{}be", bit_depth?)),
+        FileType::Wav => ("wav", format!("pcm_s{}le", bit_depth?)),
+        FileType::Flac => ("flac", "flac".to_string()),
+        FileType::Mpeg => ("mp3", "mp3".to_string()),
+        FileType::Aac => ("aac", "aac".to_string()),
+        // Of the codecs an mp4 container can hold, lofty only reports a bit depth for ALAC
+        FileType::Mp4 => (
+            "mov,mp4,m4a,3gp,3g2,mj2",
+            if bit_depth.is_some() { "alac" } else { "aac" }.to_string(),
+        ),
+        FileType::Vorbis => ("ogg", "vorbis".to_string()),
+        FileType::Opus => ("ogg", "opus".to_string()),
+        FileType::WavPack => ("wv", "wavpack".to_string()),
+        FileType::Ape => ("ape", "ape".to_string()),
+        _ => return None,
+    };
+    let mut stream = json!({ "codec_name": codec_name, "codec_type": "audio" });
+    // ffprobe leaves out what it doesn't know, and gives bit rates in bit/s where lofty uses
+    // kbit/s
+    let numbers = [
+        ("bits_per_raw_sample", bit_depth),
+        ("sample_rate", properties.sample_rate().map(|r| r as usize)),
+        (
+            "bit_rate",
+            properties.audio_bitrate().map(|r| r as usize * 1000),
+        ),
+    ];
+    for (name, value) in numbers {
+        if let Some(value) = value {
+            stream[name] = Value::String(value.to_string());
+        }
+    }
+    let mut streams = vec![stream];
+    // Embedded artwork, which ffprobe shows as a video stream
+    let artwork =
+        file.tags()
+            .iter()
+            .flat_map(|tag| tag.pictures())
+            .find_map(|picture| match picture.mime_type() {
+                Some(MimeType::Jpeg) => Some("mjpeg"),
+                Some(MimeType::Png) => Some("png"),
+                _ => None,
+            });
+    if let Some(codec_name) = artwork {
+        streams.push(json!({ "codec_name": codec_name, "codec_type": "video" }));
+    }
+    let mut tags = Map::new();
+    for tag in file.tags() {
+        add_tags(tag, &mut tags);
+    }
+    Some(json!({
+        "streams": streams,
+        "format": {
+            "format_name": format_name,
+            "tags": tags,
+            "duration": properties.duration().as_secs_f64().to_string(),
+        },
+    }))
+}
+
+/// Adds the text items of a tag under the names ffprobe gives them, keeping values already
+/// added from an earlier tag
+fn add_tags(tag: &Tag, tags: &mut Map<String, Value>) {
+    for item in tag.items() {
+        let value = match item.value().text() {
+            Some(value) => value,
+            None => continue,
+        };
+        let key = match item.key() {
+            ItemKey::TrackTitle => "title",
+            ItemKey::TrackArtist => "artist",
+            ItemKey::AlbumTitle => "album",
+            ItemKey::AlbumArtist => "album_artist",
+            ItemKey::Genre => "genre",
+            ItemKey::RecordingDate | ItemKey::Year => "date",
+            ItemKey::TrackNumber => "track",
+            ItemKey::Unknown(key) => key.as_str(),
+            key => match key.map_key(tag.tag_type(), true) {
+                Some(key) => key,
+                None => continue,
+            },
+        };
+        tags.entry(key.to_string())
+            .or_insert_with(|| Value::String(value.to_string()));
+    }
+}
@@ -0,0 +1,657 @@
+use crate::song_info::SongInfo;
+use crate::state;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where Rekordbox writes the database on a device it exported to
+pub const DEVICE_PDB_PATH: &str = "PIONEER/rekordbox/export.pdb";
+
+/// How many seconds the length of a song may be off from the track's and still match. Rekordbox
+/// keeps whole seconds, and the song may be another encode of the same master.
+const DURATION_TOLERANCE: u32 = 2;
+
+/// Table types, as numbered in the file header
+const TRACKS_TABLE: u32 = 0;
+const ARTISTS_TABLE: u32 = 2;
+/// Tables Rekordbox writes into every database, numbered 0 up, empty ones included
+const TABLE_COUNT: u32 = 20;
+
+/// Size of the pages written, as Rekordbox writes them
+const PAGE_SIZE: usize = 4096;
+
+/// Size of a page header; rows are stored in the heap after it
+const PAGE_HEADER_SIZE: usize = 0x28;
+/// Row offsets are grouped 16 at a time at the end of a page, with a bit per row saying whether
+/// it is in use
+const ROW_GROUP_SIZE: usize = 0x24;
+const ROWS_PER_GROUP: usize = 16;
+/// Pages with this flag hold an index instead of rows
+const INDEX_PAGE_FLAG: u8 = 0x40;
+/// Flags of the data pages written
+const DATA_PAGE_FLAGS: u8 = 0x24;
+
+/// Where a track row keeps its fields, and which of its strings are which
+const TRACK_SUBTYPE: u16 = 0x24;
+const TRACK_SAMPLE_RATE: usize = 0x08;
+const TRACK_FILE_SIZE: usize = 0x10;
+const TRACK_TEMPO: usize = 0x38;
+const TRACK_ARTIST_ID: usize = 0x44;
+const TRACK_ID: usize = 0x48;
+const TRACK_DURATION: usize = 0x54;
+const TRACK_FILE_TYPE: usize = 0x5a;
+const TRACK_STRINGS: usize = 0x5e;
+const TRACK_STRING_COUNT: usize = 21;
+const TRACK_ANALYZE_PATH_STRING: usize = 14;
+const TRACK_TITLE_STRING: usize = 17;
+const TRACK_FILE_NAME_STRING: usize = 19;
+const TRACK_PATH_STRING: usize = 20;
+
+/// Artist rows with an 8 bit offset to their name, which is right after the row header
+const ARTIST_SUBTYPE: u16 = 0x60;
+const ARTIST_NAME: usize = 0x0a;
+
+/// A track in a Rekordbox device library
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdbTrack {
+    pub id: u32,
+    pub title: String,
+    pub artist: String,
+    /// Length in seconds
+    pub duration: u32,
+    /// Path of the file on the device
+    pub path: String,
+    /// Path on the device of the track's ANLZ .DAT file, empty if it has none
+    pub analyze_path: String,
+    /// Tempo in hundredths of a BPM, 0 if unknown
+    pub tempo: u32,
+    pub sample_rate: u32,
+    pub file_size: u32,
+    /// Format of the file, as numbered by Rekordbox
+    pub file_type: u16,
+}
+
+/// The number Rekordbox gives a format in the file type of a track, 0 for formats it doesn't
+/// play
+pub fn file_type(path: &Path) -> u16 {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "mp3" => 0x01,
+        "m4a" | "mp4" => 0x04,
+        "flac" => 0x05,
+        "wav" => 0x0b,
+        "aif" | "aiff" => 0x0c,
+        _ => 0,
+    }
+}
+
+/// Reads the tracks of a Rekordbox device library: the export.pdb database Rekordbox writes to
+/// USB sticks and SD cards for CDJs, in the DeviceSQL format documented by the crate-digger and
+/// rekordcrate projects. Only the tracks and artists tables are read.
+pub fn read(pdb_path: &Path) -> Result<Vec<PdbTrack>> {
+    let data =
+        fs::read(pdb_path).with_context(|| format!("Could not read {}", pdb_path.display()))?;
+    parse(&data).with_context(|| format!("{} isn't a Rekordbox database", pdb_path.display()))
+}
+
+fn parse(data: &[u8]) -> Result<Vec<PdbTrack>> {
+    let page_size = u32_at(data, 4)? as usize;
+    let n_tables = u32_at(data, 8)? as usize;
+    if page_size < PAGE_HEADER_SIZE + ROW_GROUP_SIZE {
+        return Err(anyhow!("Page size {} is too small", page_size));
+    }
+    let mut artists = HashMap::new();
+    let mut track_rows = vec![];
+    for table in 0..n_tables {
+        // Table pointers follow the 28 byte file header: type, empty candidate, first and last page
+        let pointer = 0x1c + table * 16;
+        let table_type = u32_at(data, pointer)?;
+        if table_type != TRACKS_TABLE && table_type != ARTISTS_TABLE {
+            continue;
+        }
+        let mut page_index = u32_at(data, pointer + 8)? as usize;
+        let last_page = u32_at(data, pointer + 12)? as usize;
+        // Pages are chained; the chain of a table ends at its last page, and the count guards
+        // against a broken file looping
+        for _ in 0..data.len() / page_size {
+            let page = data
+                .get(page_index * page_size..(page_index + 1) * page_size)
+                .ok_or_else(|| anyhow!("Page {} is past the end of the file", page_index))?;
+            for row in rows(page)? {
+                match table_type {
+                    TRACKS_TABLE => track_rows.push(row),
+                    _ => {
+                        if let Ok((id, name)) = artist(row) {
+                            artists.insert(id, name);
+                        }
+                    }
+                }
+            }
+            if page_index == last_page {
+                break;
+            }
+            page_index = u32_at(page, 12)? as usize;
+        }
+    }
+    let tracks = track_rows
+        .into_iter()
+        .filter_map(|row| match track(row, &artists) {
+            Ok(track) => Some(track),
+            Err(e) => {
+                tracing::debug!(?e, "Skipping unreadable track row");
+                None
+            }
+        })
+        .collect();
+    Ok(tracks)
+}
+
+/// The rows in use on a data page
+fn rows(page: &[u8]) -> Result<Vec<&[u8]>> {
+    if page[0x1b] & INDEX_PAGE_FLAG != 0 {
+        return Ok(vec![]);
+    }
+    let n_rows_small = page[0x18] as usize;
+    let n_rows_large = u16_at(page, 0x22)? as usize;
+    // The large count is only used once a page outgrows the small one, and 0x1fff means unset
+    let n_rows = if n_rows_large > n_rows_small && n_rows_large != 0x1fff {
+        n_rows_large
+    } else {
+        n_rows_small
+    };
+    let heap = &page[PAGE_HEADER_SIZE..];
+    let mut rows = vec![];
+    for group in 0..n_rows.div_ceil(ROWS_PER_GROUP) {
+        let group_end = page.len() - group * ROW_GROUP_SIZE;
+        // A broken count can't run the groups into the heap
+        if group_end < PAGE_HEADER_SIZE + ROW_GROUP_SIZE {
+            break;
+        }
+        let present = u16_at(page, group_end - 4)?;
+        let n_in_group = (n_rows - group * ROWS_PER_GROUP).min(ROWS_PER_GROUP);
+        for row in 0..n_in_group {
+            if present & (1 << row) == 0 {
+                continue;
+            }
+            let offset = u16_at(page, group_end - 6 - 2 * row)? as usize;
+            if let Some(row) = heap.get(offset..) {
+                rows.push(row);
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn artist(row: &[u8]) -> Result<(u32, String)> {
+    let subtype = u16_at(row, 0)?;
+    let id = u32_at(row, 4)?;
+    // Rows with longer names point to them with a 16 bit offset instead of an 8 bit one
+    let name_offset = if subtype == 0x64 {
+        u16_at(row, 0x0a)? as usize
+    } else {
+        *row.get(0x09)
+            .ok_or_else(|| anyhow!("Artist row is cut off"))? as usize
+    };
+    Ok((id, string_at(row, name_offset)?))
+}
+
+fn track(row: &[u8], artists: &HashMap<u32, String>) -> Result<PdbTrack> {
+    let string = |index: usize| -> Result<String> {
+        let offset = u16_at(row, TRACK_STRINGS + 2 * index)? as usize;
+        string_at(row, offset)
+    };
+    Ok(PdbTrack {
+        id: u32_at(row, TRACK_ID)?,
+        title: string(TRACK_TITLE_STRING)?,
+        artist: artists
+            .get(&u32_at(row, TRACK_ARTIST_ID)?)
+            .cloned()
+            .unwrap_or_default(),
+        duration: u16_at(row, TRACK_DURATION)? as u32,
+        path: string(TRACK_PATH_STRING)?,
+        analyze_path: string(TRACK_ANALYZE_PATH_STRING)?,
+        tempo: u32_at(row, TRACK_TEMPO)?,
+        sample_rate: u32_at(row, TRACK_SAMPLE_RATE)?,
+        file_size: u32_at(row, TRACK_FILE_SIZE)?,
+        file_type: u16_at(row, TRACK_FILE_TYPE)?,
+    })
+}
+
+/// Reads a DeviceSQL string. Short ASCII strings have their length, header included, in the
+/// upper 7 bits of an odd first byte; longer ones have a flag byte saying whether they are ASCII
+/// or UTF-16, then a 16 bit length, header included, and a padding byte.
+fn string_at(row: &[u8], offset: usize) -> Result<String> {
+    let cut_off = || anyhow!("String at {} is cut off", offset);
+    let header = *row.get(offset).ok_or_else(cut_off)?;
+    if header & 1 == 1 {
+        let length = (header >> 1) as usize;
+        let text = row.get(offset + 1..offset + length).ok_or_else(cut_off)?;
+        return Ok(String::from_utf8_lossy(text).into_owned());
+    }
+    let length = u16_at(row, offset + 1)? as usize;
+    let text = row
+        .get(offset + 4..offset + length.max(4))
+        .ok_or_else(cut_off)?;
+    match header {
+        0x40 => Ok(String::from_utf8_lossy(text).into_owned()),
+        0x90 => {
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            Ok(String::from_utf16_lossy(&units))
+        }
+        _ => Err(anyhow!("Unknown string kind {:#x}", header)),
+    }
+}
+
+/// Writes a Rekordbox device library holding the given tracks, in the format `read` reads: a
+/// file header pointing to every table Rekordbox writes, and pages of track and artist rows.
+/// The other tables are left empty, as on a stick with no playlists, so players list the
+/// tracks by title and artist and load the ANLZ files their rows point to.
+pub fn write(pdb_path: &Path, tracks: &[PdbTrack]) -> Result<()> {
+    let data = encode(tracks)?;
+    if let Some(parent) = pdb_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    state::write_atomic(pdb_path, &data)
+        .with_context(|| format!("Could not write {}", pdb_path.display()))
+}
+
+fn encode(tracks: &[PdbTrack]) -> Result<Vec<u8>> {
+    let mut artist_ids: HashMap<&str, u32> = HashMap::new();
+    let mut artist_rows = vec![];
+    let mut track_rows = vec![];
+    for track in tracks {
+        let artist_id = match track.artist.as_str() {
+            "" => 0,
+            name => {
+                let next_id = artist_ids.len() as u32 + 1;
+                *artist_ids.entry(name).or_insert_with(|| {
+                    artist_rows.push(artist_row(next_id, name));
+                    next_id
+                })
+            }
+        };
+        track_rows.push(track_row(track, artist_id)?);
+    }
+    // Page 0 is the file header
+    let mut pages = vec![vec![0; PAGE_SIZE]];
+    let mut pointers = vec![];
+    for table_type in 0..TABLE_COUNT {
+        let rows = match table_type {
+            TRACKS_TABLE => std::mem::take(&mut track_rows),
+            ARTISTS_TABLE => std::mem::take(&mut artist_rows),
+            _ => vec![],
+        };
+        let first_page = pages.len() as u32;
+        pages.extend(data_pages(table_type, first_page, rows)?);
+        pointers.push((table_type, first_page, pages.len() as u32 - 1));
+    }
+    // Each table's last page points on to a page past the end of the file, where Rekordbox
+    // would add the table's next page
+    let n_pages = pages.len() as u32;
+    for (i, (_, _, last_page)) in pointers.iter().enumerate() {
+        put_u32(&mut pages[*last_page as usize], 0x0c, n_pages + i as u32);
+    }
+    let header = &mut pages[0];
+    put_u32(header, 0x04, PAGE_SIZE as u32);
+    put_u32(header, 0x08, TABLE_COUNT);
+    put_u32(header, 0x0c, n_pages + TABLE_COUNT);
+    put_u32(header, 0x10, 5);
+    put_u32(header, 0x14, 1);
+    for (i, (table_type, first_page, last_page)) in pointers.into_iter().enumerate() {
+        let pointer = 0x1c + i * 16;
+        put_u32(header, pointer, table_type);
+        put_u32(header, pointer + 4, n_pages + i as u32);
+        put_u32(header, pointer + 8, first_page);
+        put_u32(header, pointer + 12, last_page);
+    }
+    Ok(pages.concat())
+}
+
+/// The pages of a table, as many as its rows need and at least one. Rows are kept 4 byte
+/// aligned in the heap, and a page is full when its heap would run into its row groups.
+fn data_pages(table_type: u32, first_page: u32, rows: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+    let fits = |heap: &[u8], n_rows: usize, row: &[u8]| {
+        PAGE_HEADER_SIZE + heap.len() + row.len() + n_rows.div_ceil(ROWS_PER_GROUP) * ROW_GROUP_SIZE
+            <= PAGE_SIZE
+    };
+    let mut pages: Vec<(Vec<u8>, Vec<usize>)> = vec![(vec![], vec![])];
+    for mut row in rows {
+        row.resize(row.len().next_multiple_of(4), 0);
+        let (heap, offsets) = pages.last_mut().expect("there is always a page");
+        if !fits(heap, offsets.len() + 1, &row) {
+            if offsets.is_empty() {
+                return Err(anyhow!(
+                    "A row of {} bytes doesn't fit on a page",
+                    row.len()
+                ));
+            }
+            pages.push((vec![], vec![]));
+        }
+        let (heap, offsets) = pages.last_mut().expect("there is always a page");
+        offsets.push(heap.len());
+        heap.extend(row);
+    }
+    let n_pages = pages.len();
+    Ok(pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, (heap, offsets))| {
+            let mut page = vec![0; PAGE_SIZE];
+            let page_index = first_page + i as u32;
+            put_u32(&mut page, 0x04, page_index);
+            put_u32(&mut page, 0x08, table_type);
+            if i + 1 < n_pages {
+                put_u32(&mut page, 0x0c, page_index + 1);
+            }
+            page[0x18] = offsets.len().min(0xff) as u8;
+            page[0x1b] = DATA_PAGE_FLAGS;
+            let index_size = offsets.len().div_ceil(ROWS_PER_GROUP) * ROW_GROUP_SIZE;
+            let free = PAGE_SIZE - PAGE_HEADER_SIZE - heap.len() - index_size;
+            put_u16(&mut page, 0x1c, free as u16);
+            put_u16(&mut page, 0x1e, heap.len() as u16);
+            put_u16(&mut page, 0x22, offsets.len() as u16);
+            page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + heap.len()].copy_from_slice(&heap);
+            for (group, offsets) in offsets.chunks(ROWS_PER_GROUP).enumerate() {
+                let group_end = PAGE_SIZE - group * ROW_GROUP_SIZE;
+                let present = (1u32 << offsets.len()) - 1;
+                put_u16(&mut page, group_end - 4, present as u16);
+                for (row, offset) in offsets.iter().enumerate() {
+                    put_u16(&mut page, group_end - 6 - 2 * row, *offset as u16);
+                }
+            }
+            page
+        })
+        .collect())
+}
+
+fn artist_row(id: u32, name: &str) -> Vec<u8> {
+    let mut row = vec![0; ARTIST_NAME];
+    put_u16(&mut row, 0, ARTIST_SUBTYPE);
+    put_u32(&mut row, 4, id);
+    row[0x08] = 0x03;
+    row[0x09] = ARTIST_NAME as u8;
+    row.extend(device_string(name));
+    row
+}
+
+fn track_row(track: &PdbTrack, artist_id: u32) -> Result<Vec<u8>> {
+    let mut row = vec![0; TRACK_STRINGS + 2 * TRACK_STRING_COUNT];
+    put_u16(&mut row, 0, TRACK_SUBTYPE);
+    put_u32(&mut row, TRACK_SAMPLE_RATE, track.sample_rate);
+    put_u32(&mut row, TRACK_FILE_SIZE, track.file_size);
+    put_u32(&mut row, TRACK_TEMPO, track.tempo);
+    put_u32(&mut row, TRACK_ARTIST_ID, artist_id);
+    put_u32(&mut row, TRACK_ID, track.id);
+    put_u16(&mut row, TRACK_DURATION, track.duration.min(0xffff) as u16);
+    // Values Rekordbox writes into every track row
+    put_u16(&mut row, 0x56, 0x29);
+    put_u16(&mut row, 0x5c, 0x03);
+    put_u16(&mut row, TRACK_FILE_TYPE, track.file_type);
+    let file_name = track.path.rsplit('/').next().unwrap_or_default();
+    for index in 0..TRACK_STRING_COUNT {
+        let text = match index {
+            TRACK_ANALYZE_PATH_STRING => &track.analyze_path,
+            TRACK_TITLE_STRING => &track.title,
+            TRACK_FILE_NAME_STRING => file_name,
+            TRACK_PATH_STRING => &track.path,
+            _ => "",
+        };
+        let offset = u16::try_from(row.len())
+            .map_err(|_| anyhow!("The strings of track {} don't fit in a row", track.id))?;
+        put_u16(&mut row, TRACK_STRINGS + 2 * index, offset);
+        row.extend(device_string(text));
+    }
+    Ok(row)
+}
+
+/// Encodes a DeviceSQL string, the way `string_at` reads it: short ASCII strings with their
+/// length in the first byte, longer ASCII strings with a 16 bit length, and anything else as
+/// UTF-16
+fn device_string(text: &str) -> Vec<u8> {
+    let long = |kind: u8, bytes: Vec<u8>| {
+        let length = (bytes.len() + 4).min(0xffff) as u16;
+        let mut encoded = vec![kind];
+        encoded.extend(length.to_le_bytes());
+        encoded.push(0);
+        encoded.extend(bytes);
+        encoded
+    };
+    if !text.is_ascii() {
+        return long(
+            0x90,
+            text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        );
+    }
+    if text.len() + 1 > 0x7f {
+        return long(0x40, text.as_bytes().to_vec());
+    }
+    let mut encoded = vec![(((text.len() + 1) << 1) | 1) as u8];
+    encoded.extend(text.as_bytes());
+    encoded
+}
+
+fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Offset {} is past the end", offset))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Offset {} is past the end", offset))
+}
+
+/// The tracks already in a Rekordbox collection, to leave songs that are in it out of a run.
+/// Songs match tracks by title and artist, ignoring case and spacing, and by length.
+#[derive(Clone, Debug, Default)]
+pub struct Collection {
+    /// Lengths of the tracks with each title and artist
+    durations: HashMap<(String, String), Vec<u32>>,
+}
+
+impl Collection {
+    /// Reads the collection of a device library, given its export.pdb or the root of the device
+    pub fn load(path: &Path) -> Result<Self> {
+        let pdb_path = if path.is_dir() {
+            path.join(DEVICE_PDB_PATH)
+        } else {
+            PathBuf::from(path)
+        };
+        let tracks = read(&pdb_path)?;
+        tracing::info!(
+            n_tracks = tracks.len(),
+            ?pdb_path,
+            "Read Rekordbox collection"
+        );
+        Ok(Collection::from_tracks(&tracks))
+    }
+
+    pub fn from_tracks(tracks: &[PdbTrack]) -> Self {
+        let mut durations: HashMap<(String, String), Vec<u32>> = HashMap::new();
+        for track in tracks {
+            durations
+                .entry((normalize(&track.title), normalize(&track.artist)))
+                .or_default()
+                .push(track.duration);
+        }
+        Collection { durations }
+    }
+
+    /// Whether a song is already in the collection. Songs without a title tag never are.
+    pub fn contains(&self, song: &SongInfo) -> bool {
+        let title = match song.get_tag("title") {
+            Some(title) => normalize(title),
+            None => return false,
+        };
+        let artist = normalize(song.get_tag("artist").unwrap_or_default());
+        let duration = song.get_duration().round() as u32;
+        self.durations
+            .get(&(title, artist))
+            .is_some_and(|durations| {
+                durations
+                    .iter()
+                    .any(|d| d.abs_diff(duration) <= DURATION_TOLERANCE)
+            })
+    }
+}
+
+/// Lowercase, with runs of whitespace made a single space
+fn normalize(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: u32, title: &str, artist: &str) -> PdbTrack {
+        PdbTrack {
+            id,
+            title: title.to_string(),
+            artist: artist.to_string(),
+            duration: 300 + id,
+            path: format!("/Contents/{}/{}.mp3", artist, title),
+            analyze_path: format!("/PIONEER/USBANLZ/P001/{:08X}/ANLZ0000.DAT", id),
+            tempo: 12800,
+            sample_rate: 44100,
+            file_size: 1_000_000 + id,
+            file_type: file_type(Path::new("song.mp3")),
+        }
+    }
+
+    /// A small page of a table, with rows at the given heap offsets and the bits of the ones in
+    /// use
+    fn page(
+        index: u32,
+        table_type: u32,
+        next: u32,
+        heap: &[u8],
+        offsets: &[u16],
+        present: u16,
+    ) -> Vec<u8> {
+        let mut page = vec![0; 0x200];
+        put_u32(&mut page, 0x04, index);
+        put_u32(&mut page, 0x08, table_type);
+        put_u32(&mut page, 0x0c, next);
+        page[0x18] = offsets.len() as u8;
+        page[0x1b] = 0x34;
+        page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + heap.len()].copy_from_slice(heap);
+        put_u16(&mut page, 0x200 - 4, present);
+        for (row, offset) in offsets.iter().enumerate() {
+            put_u16(&mut page, 0x200 - 6 - 2 * row, *offset);
+        }
+        page
+    }
+
+    #[test]
+    fn handcrafted_pages() {
+        // Page 0: 512 byte pages, two tables
+        let mut header = vec![0; 0x200];
+        put_u32(&mut header, 0x04, 0x200);
+        put_u32(&mut header, 0x08, 2);
+        for (i, &(table_type, first, last)) in [(ARTISTS_TABLE, 1, 1), (TRACKS_TABLE, 2, 3)]
+            .iter()
+            .enumerate()
+        {
+            put_u32(&mut header, 0x1c + i * 16, table_type);
+            put_u32(&mut header, 0x1c + i * 16 + 8, first);
+            put_u32(&mut header, 0x1c + i * 16 + 12, last);
+        }
+
+        // Page 1: an artist with a short ASCII name after an 8 bit offset, and one with a
+        // UTF-16 name after a 16 bit offset
+        let mut artists = vec![
+            0x60, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0x0a, // row header, id 7
+            0x0b, b'D', b'a', b'f', b't', 0x00, // "Daft", padded
+        ];
+        artists.extend([
+            0x64, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0x00, 0x0c, 0x00, // id 8
+            0x90, 0x0a, 0x00, 0x00, b'R', 0x00, 0xf3, 0x00, b's', 0x00, // "Rós" in UTF-16
+        ]);
+        let artists = page(1, ARTISTS_TABLE, 2, &artists, &[0x00, 0x10], 0b11);
+
+        // Page 2: the index page tracks start with, which holds no rows
+        let mut index = page(2, TRACKS_TABLE, 3, &[], &[], 0);
+        index[0x1b] = 0x64;
+
+        // Page 3: a track by artist 8, and a deleted one after it
+        let mut track = vec![0; TRACK_STRINGS + 2 * TRACK_STRING_COUNT];
+        track[0] = 0x24;
+        put_u32(&mut track, TRACK_ARTIST_ID, 8);
+        put_u32(&mut track, TRACK_ID, 42);
+        put_u16(&mut track, TRACK_DURATION, 215);
+        let empty = track.len() as u16;
+        track.push(0x03);
+        for string in 0..TRACK_STRING_COUNT {
+            put_u16(&mut track, TRACK_STRINGS + 2 * string, empty);
+        }
+        let offset = track.len() as u16;
+        put_u16(&mut track, TRACK_STRINGS + 2 * TRACK_TITLE_STRING, offset);
+        track.extend(b"\x0bHope");
+        let offset = track.len() as u16;
+        put_u16(&mut track, TRACK_STRINGS + 2 * TRACK_PATH_STRING, offset);
+        track.extend([0x40, 0x0f, 0x00, 0x00]);
+        track.extend(b"/a/Hope.mp3");
+        let rows = [track.clone(), track.clone()].concat();
+        let tracks = page(3, TRACKS_TABLE, 4, &rows, &[0, track.len() as u16], 0b01);
+
+        let data = [header, artists, index, tracks].concat();
+        assert_eq!(
+            parse(&data).unwrap(),
+            vec![PdbTrack {
+                id: 42,
+                title: "Hope".to_string(),
+                artist: "Rós".to_string(),
+                duration: 215,
+                path: "/a/Hope.mp3".to_string(),
+                ..PdbTrack::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn short_strings_hold_their_length_in_the_first_byte() {
+        assert_eq!(string_at(&b"\x0bDaft"[..], 0).unwrap(), "Daft");
+        assert_eq!(string_at(&b"\x03"[..], 0).unwrap(), "");
+        assert!(string_at(&b"\x0bDa"[..], 0).is_err());
+        assert!(string_at(&b"\x20\x08\x00\x00abcd"[..], 0).is_err());
+    }
+
+    #[test]
+    fn written_libraries_read_back() {
+        // Enough tracks for several pages and row groups, with long and non-ASCII strings
+        let mut tracks: Vec<PdbTrack> = (1..=60)
+            .map(|id| track(id, &format!("Track {}", id), &format!("Artist {}", id % 7)))
+            .collect();
+        tracks.push(track(61, &"Long title ".repeat(20), "Sigur Rós"));
+        tracks.push(track(62, "No Artist", ""));
+        let data = encode(&tracks).unwrap();
+        assert_eq!(data.len() % PAGE_SIZE, 0);
+        assert_eq!(parse(&data).unwrap(), tracks);
+    }
+
+    #[test]
+    fn empty_libraries_read_back() {
+        assert_eq!(parse(&encode(&[]).unwrap()).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::file_uri;
+use crate::i18n;
+use crate::links::SourceIndex;
+use crate::state::{self, ConversionState};
+use crate::tagging;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Tag the play count is written to in source files
+pub const PLAY_COUNT_TAG: &str = "PLAYCOUNT";
+/// Tag the rating is written to in source files, in stars from 0 to 5
+pub const RATING_TAG: &str = "RATING";
+
+/// What importing the play history of a Rekordbox library changed
+#[derive(Clone, Debug, Default)]
+pub struct HistoryImport {
+    /// Sources whose play count or rating changed, with the new ones
+    pub updated: Vec<(PathBuf, u32, u8)>,
+    /// Number of converted tracks in the library whose history was already known
+    pub unchanged: usize,
+    /// Number of tracks in the library that weren't converted into the output directory
+    pub unmatched: usize,
+}
+
+impl std::fmt::Display for HistoryImport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (source, play_count, rating) in &self.updated {
+            let args = [
+                ("name", source.display().to_string().into()),
+                ("plays", (*play_count).into()),
+                ("rating", u32::from(*rating).into()),
+            ];
+            writeln!(f, "{}", i18n::message("history-updated", &args))?;
+        }
+        let args = [
+            ("updated", self.updated.len().into()),
+            ("unchanged", self.unchanged.into()),
+            ("unmatched", self.unmatched.into()),
+        ];
+        write!(f, "{}", i18n::message("history-summary", &args))
+    }
+}
+
+/// Stars from 0 to 5 of a Rekordbox Rating attribute, which goes 0, 51, 102, 153, 204, 255
+fn stars(rating: u32) -> u8 {
+    ((rating.min(255) + 25) / 51) as u8
+}
+
+/// Reads how often the tracks of a Rekordbox library, exported with File > Export Collection in
+/// xml format, were played and how they were rated, and keeps that in the state of the output
+/// directory they were converted into. Tracks are matched to their sources by Location, or by
+/// file name when the library was made from a copy of the output directory. With `write_tags`
+/// the play counts and ratings that changed are also written into the source files, which are
+/// then converted again on the next run, carrying the tags into the outputs.
+pub fn import(output_dir: &Path, xml_path: &Path, write_tags: bool) -> Result<HistoryImport> {
+    let xml = fs::read_to_string(xml_path)
+        .with_context(|| format!("Could not read {}", xml_path.display()))?;
+    let document = roxmltree::Document::parse(&xml)
+        .with_context(|| format!("{} isn't an XML file", xml_path.display()))?;
+    let library = document.root_element();
+    if !library.has_tag_name("DJ_PLAYLISTS") {
+        return Err(anyhow!("{} isn't a Rekordbox library", xml_path.display()));
+    }
+    // Saving the state folds the journal into it, which would hide a cut short run from --resume
+    if state::interrupted_run(output_dir)?.is_some() {
+        return Err(anyhow!(
+            "A conversion into {} is still running or was cut short, finish it first",
+            output_dir.display()
+        ));
+    }
+    let mut state = ConversionState::load(output_dir)?;
+    let index = SourceIndex::new(&state);
+    let mut import = HistoryImport::default();
+    let mut updated = vec![];
+    for track in library
+        .children()
+        .filter(|c| c.has_tag_name("COLLECTION"))
+        .flat_map(|c| c.children())
+        .filter(|c| c.has_tag_name("TRACK"))
+    {
+        let source = track
+            .attribute("Location")
+            .and_then(|l| file_uri::from_uri(l).ok())
+            .and_then(|path| index.source(&path).map(Path::to_path_buf));
+        let source = match source {
+            Some(source) => source,
+            None => {
+                import.unmatched += 1;
+                continue;
+            }
+        };
+        let number = |name: &str| track.attribute(name).and_then(|v| v.parse().ok());
+        let play_count: u32 = number("PlayCount").unwrap_or(0);
+        let rating = stars(number("Rating").unwrap_or(0));
+        if state.set_play_history(&source, play_count, rating) {
+            updated.push((source, play_count, rating));
+        } else {
+            import.unchanged += 1;
+        }
+    }
+    if !updated.is_empty() {
+        state.save(output_dir)?;
+    }
+    if write_tags {
+        for (source, play_count, rating) in &updated {
+            let tags = [
+                (PLAY_COUNT_TAG.to_string(), play_count.to_string()),
+                (RATING_TAG.to_string(), rating.to_string()),
+            ];
+            if let Err(e) = tagging::write_tags(source, &tags) {
+                tracing::warn!(
+                    ?source,
+                    ?e,
+                    "Could not tag the source with its play history"
+                );
+            }
+        }
+    }
+    import.updated = updated;
+    Ok(import)
+}
@@ -0,0 +1,67 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+/// Sample rates CDJs and Rekordbox play
+pub const SAMPLE_RATES: [usize; 4] = [44100, 48000, 88200, 96000];
+/// Bit depths CDJs and Rekordbox play
+pub const BIT_DEPTHS: [usize; 2] = [16, 24];
+
+/// Players whose limits the output is checked against
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PlayerProfile {
+    /// CDJ-2000 and CDJ-2000NXS, up to 48 kHz / 24 bit
+    Cdj2000,
+    /// CDJ-2000NXS2, up to 96 kHz / 24 bit
+    Cdj2000nxs2,
+    /// CDJ-3000, up to 96 kHz / 24 bit
+    Cdj3000,
+}
+
+impl PlayerProfile {
+    pub fn max_sample_rate(&self) -> usize {
+        match self {
+            PlayerProfile::Cdj2000 => 48000,
+            PlayerProfile::Cdj2000nxs2 | PlayerProfile::Cdj3000 => 96000,
+        }
+    }
+
+    pub fn max_bit_depth(&self) -> usize {
+        24
+    }
+}
+
+/// Checks that a sample rate and bit depth ceiling is something players can play, and within
+/// what the given player supports
+pub fn validate(
+    max_sample_rate: usize,
+    max_bit_depth: usize,
+    player: Option<PlayerProfile>,
+) -> Result<()> {
+    if !SAMPLE_RATES.contains(&max_sample_rate) {
+        return Err(anyhow!(
+            "Unsupported sample rate {}, expected one of {:?}",
+            max_sample_rate,
+            SAMPLE_RATES
+        ));
+    }
+    if !BIT_DEPTHS.contains(&max_bit_depth) {
+        return Err(anyhow!(
+            "Unsupported bit depth {}, expected one of {:?}",
+            max_bit_depth,
+            BIT_DEPTHS
+        ));
+    }
+    if let Some(player) = player {
+        if max_sample_rate > player.max_sample_rate() || max_bit_depth > player.max_bit_depth() {
+            return Err(anyhow!(
+                "{:?} plays at most {} Hz / {} bit, but {} Hz / {} bit was asked for",
+                player,
+                player.max_sample_rate(),
+                player.max_bit_depth(),
+                max_sample_rate,
+                max_bit_depth
+            ));
+        }
+    }
+    Ok(())
+}
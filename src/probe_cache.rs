@@ -0,0 +1,91 @@
+use crate::read_only;
+use crate::state::{self, write_atomic};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What ffprobe said about a file, and what the file looked like then
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CacheEntry {
+    /// Modification time in seconds since the Unix epoch
+    modified: u64,
+    size: u64,
+    probe: serde_json::Value,
+}
+
+/// ffprobe's output for files probed by earlier runs, kept in a JSON file so songs that haven't
+/// changed aren't probed again. Files that aren't audio are remembered too, so they are skipped
+/// without running ffprobe as well.
+pub struct ProbeCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<PathBuf, CacheEntry>>,
+}
+
+impl ProbeCache {
+    /// Reads the cache file, or starts an empty cache if there is none or it can't be read
+    pub fn open(path: &Path) -> Self {
+        let entries = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                tracing::warn!(?path, ?e, "Could not read probe cache, starting over");
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        ProbeCache {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Where the cache is kept unless told otherwise: in the user's cache directory
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(
+            cache_dir
+                .join("rekordbox-file-conversion")
+                .join("probe-cache.json"),
+        )
+    }
+
+    /// ffprobe's output for a file, if it was cached and the file hasn't changed since
+    pub fn get(&self, path: &Path) -> Option<serde_json::Value> {
+        let (modified, size) = state::modified_and_size(path).ok()?;
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(path)
+            .filter(|entry| entry.modified == modified && entry.size == size)
+            .map(|entry| entry.probe.clone())
+    }
+
+    pub fn insert(&self, path: &Path, probe: &serde_json::Value) {
+        if let Ok((modified, size)) = state::modified_and_size(path) {
+            self.entries.lock().unwrap().insert(
+                path.to_path_buf(),
+                CacheEntry {
+                    modified,
+                    size,
+                    probe: probe.clone(),
+                },
+            );
+        }
+    }
+
+    /// Writes the cache file, leaving out files that no longer exist
+    pub fn save(&self) -> Result<()> {
+        if read_only::is_enabled() {
+            return Ok(());
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|path, _| path.exists());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &serde_json::to_vec(&*entries)?)
+    }
+}
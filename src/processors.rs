@@ -0,0 +1,225 @@
+use crate::converter::stderr_excerpt;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a running processor is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Placeholders in a processor's command for the file it reads and the file it writes
+const INPUT_PLACEHOLDER: &str = "{input}";
+const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+/// Where in a song's conversion a processor runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// On the source, before anything measures or encodes it; everything after works on what
+    /// the processor wrote, while tags and artwork still come from the source
+    BeforeEncode,
+    /// On every converted file, which is replaced by what the processor wrote
+    AfterEncode,
+}
+
+/// What a processor failing or timing out does to the song
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnFailure {
+    /// The song fails, and is reported as failed with the processor's error
+    #[default]
+    Fail,
+    /// The processor is skipped for the song, which goes on with the audio it was given
+    Skip,
+}
+
+fn default_timeout() -> u64 {
+    600
+}
+
+/// An external tool run on every converted song, e.g. a declicker or stem separator, declared
+/// in a processors file
+#[derive(Clone, Debug, Deserialize)]
+pub struct Processor {
+    /// Name for logs and errors
+    pub name: String,
+    /// The program and its arguments. {input} is replaced by the file to read and {output} by
+    /// the file to write
+    pub command: Vec<String>,
+    pub stage: Stage,
+    /// Seconds the processor may take on a song before it is stopped
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    /// Extension of the file a before-encode processor writes, wav by default, so it knows what
+    /// format to write
+    #[serde(default)]
+    pub extension: Option<String>,
+}
+
+/// Reads the processors declared in a JSON file, a list of processors in the order they run
+pub fn load(path: &Path) -> Result<Vec<Processor>> {
+    let contents = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let processors: Vec<Processor> = serde_json::from_slice(&contents)
+        .with_context(|| format!("{} isn't a list of processors", path.display()))?;
+    for processor in processors.iter() {
+        if processor.command.is_empty() {
+            return Err(anyhow!("Processor {} has no command", processor.name));
+        }
+        for placeholder in [INPUT_PLACEHOLDER, OUTPUT_PLACEHOLDER].iter() {
+            if !processor
+                .command
+                .iter()
+                .any(|arg| arg.contains(placeholder))
+            {
+                return Err(anyhow!(
+                    "The command of processor {} has no {}",
+                    processor.name,
+                    placeholder
+                ));
+            }
+        }
+    }
+    tracing::info!(n_processors = processors.len(), ?path, "Loaded processors");
+    Ok(processors)
+}
+
+impl Processor {
+    /// Runs the processor on a file, stopping it if it takes longer than its timeout
+    pub fn run(&self, input: &Path, output: &Path) -> Result<()> {
+        let args: Vec<String> = self
+            .command
+            .iter()
+            .map(|arg| {
+                arg.replace(INPUT_PLACEHOLDER, &input.to_string_lossy())
+                    .replace(OUTPUT_PLACEHOLDER, &output.to_string_lossy())
+            })
+            .collect();
+        let started = Instant::now();
+        let mut child = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start processor {}", self.name))?;
+        // Read alongside, so a processor that prints a lot can't fill the pipe and stall
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let errors = thread::spawn(move || {
+            let mut errors = vec![];
+            let _ = stderr.read_to_end(&mut errors);
+            errors
+        });
+        let timeout = Duration::from_secs(self.timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!(
+                    "Processor {} timed out after {} s",
+                    self.name,
+                    self.timeout
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        let errors = errors.join().unwrap_or_default();
+        if !status.success() {
+            return Err(anyhow!(
+                "Processor {} failed: {}",
+                self.name,
+                stderr_excerpt(&errors)
+            ));
+        }
+        if !output.is_file() {
+            return Err(anyhow!(
+                "Processor {} didn't write {}",
+                self.name,
+                output.display()
+            ));
+        }
+        tracing::debug!(
+            processor = %self.name,
+            seconds = started.elapsed().as_secs_f64(),
+            "Ran processor"
+        );
+        Ok(())
+    }
+}
+
+static N_WORKSPACES: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory for what external tools write for one song, removed again when dropped
+#[derive(Debug)]
+pub struct Workspace {
+    dir: PathBuf,
+    n_files: usize,
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Workspace {
+    pub fn new() -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "rekordbox-processors-{}-{}",
+            std::process::id(),
+            N_WORKSPACES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(Workspace { dir, n_files: 0 })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A new path in the workspace for a processor to write to
+    fn file(&mut self, processor: &Processor, extension: &str) -> PathBuf {
+        self.n_files += 1;
+        self.dir
+            .join(format!("{}-{}.{}", self.n_files, processor.name, extension))
+    }
+
+    /// Runs the processors of a stage one after another, each on what the one before wrote,
+    /// starting from `input`. Returns the file the last one wrote, or None if none did. A
+    /// processor that fails with on_failure skip is left out of the chain.
+    pub fn run_stage(
+        &mut self,
+        processors: &[Processor],
+        stage: Stage,
+        input: &Path,
+        extension: &str,
+    ) -> Result<Option<PathBuf>> {
+        let mut current: Option<PathBuf> = None;
+        for processor in processors.iter().filter(|p| p.stage == stage) {
+            // What a file was encoded to stays what it is
+            let extension = match stage {
+                Stage::BeforeEncode => processor.extension.as_deref().unwrap_or(extension),
+                Stage::AfterEncode => extension,
+            };
+            let output = self.file(processor, extension);
+            let from = current.as_deref().unwrap_or(input);
+            match processor.run(from, &output) {
+                Ok(()) => current = Some(output),
+                Err(e) if processor.on_failure == OnFailure::Skip => {
+                    tracing::warn!(?input, ?e, "Skipping processor");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(current)
+    }
+}
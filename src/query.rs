@@ -0,0 +1,250 @@
+use crate::manifest::Manifest;
+use crate::report;
+use crate::song_info::SongInfo;
+use crate::state::ConversionState;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The tables and views queries run against. Columns are only ever added, so queries written
+/// against this keep working.
+pub const SCHEMA: &str = "\
+-- Every source file converted into the output directory
+CREATE TABLE sources (
+    path TEXT PRIMARY KEY,
+    -- Modification time of the source when it was converted, in seconds since the Unix epoch
+    modified INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    -- FNV-1a hash of the source when it was converted, as hex
+    hash TEXT NOT NULL,
+    -- TrackID in exported rekordbox.xml files, NULL until the track is exported
+    track_id INTEGER,
+    -- The track this is an acapella, instrumental or dub version of, or the track itself
+    variant_group TEXT,
+    -- Average hash of the source's artwork, as hex, if --dedupe-artwork hashed it
+    artwork_hash TEXT,
+    -- 1 if the source still exists
+    present INTEGER NOT NULL,
+    -- Format and length in seconds, as probed now, NULL if the source is gone
+    format TEXT,
+    duration REAL,
+    -- How often the track was played and its stars from 0 to 5 in Rekordbox, as last imported
+    -- with import-history, NULL if never imported
+    play_count INTEGER,
+    rating INTEGER
+);
+-- The files each source was converted or copied into
+CREATE TABLE outputs (
+    source TEXT NOT NULL REFERENCES sources (path),
+    path TEXT NOT NULL,
+    -- Relative to the output directory, as manifest.path is
+    relative_path TEXT NOT NULL,
+    -- 1 if the output still exists
+    present INTEGER NOT NULL
+);
+-- The clean or explicit versions of the same track as each source
+CREATE TABLE versions (
+    source TEXT NOT NULL REFERENCES sources (path),
+    other TEXT NOT NULL
+);
+-- Every file of the output directory as the manifest last listed it
+CREATE TABLE manifest (
+    path TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    duration REAL,
+    modified INTEGER NOT NULL
+);
+-- The tags of each source, as probed now, with names in lower case
+CREATE TABLE tags (
+    source TEXT NOT NULL REFERENCES sources (path),
+    name TEXT NOT NULL,
+    value TEXT
+);
+-- A row per source with its common tags
+CREATE VIEW tracks AS
+SELECT
+    s.path,
+    s.track_id,
+    s.present,
+    s.format,
+    s.duration,
+    (SELECT value FROM tags WHERE source = s.path AND name = 'artist') AS artist,
+    (SELECT value FROM tags WHERE source = s.path AND name = 'title') AS title,
+    (SELECT value FROM tags WHERE source = s.path AND name = 'album') AS album,
+    (SELECT value FROM tags WHERE source = s.path AND name = 'genre') AS genre,
+    (SELECT value FROM tags WHERE source = s.path
+        AND name IN ('label', 'publisher', 'organization')) AS label,
+    (SELECT value FROM tags WHERE source = s.path AND name IN ('bpm', 'tbpm')) AS bpm,
+    (SELECT value FROM tags WHERE source = s.path
+        AND name IN ('initialkey', 'key', 'tkey')) AS key,
+    (SELECT count(*) FROM outputs WHERE source = s.path) AS n_outputs
+FROM sources s;
+";
+
+/// How query results are printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// An aligned table
+    Table,
+    Csv,
+    /// An array of objects
+    Json,
+}
+
+impl QueryFormat {
+    fn mode(&self) -> &'static str {
+        match self {
+            QueryFormat::Table => ".mode table",
+            QueryFormat::Csv => ".mode csv",
+            QueryFormat::Json => ".mode json",
+        }
+    }
+}
+
+/// Runs a query against what is known about an output directory: its state file, its manifest,
+/// and the tags of its sources as probed, and returns what it printed. The database only lives
+/// in memory, and sqlite3 runs in safe mode with writes turned off, so a query can't change or
+/// reach any file.
+pub fn run(
+    output_dir: &Path,
+    songs: &[SongInfo],
+    sql: &str,
+    format: QueryFormat,
+) -> Result<String> {
+    let mut script = database(output_dir, songs)?;
+    // Queries end up in the same script, so they may not run dot commands of their own
+    if sql.lines().any(|line| line.trim_start().starts_with('.')) {
+        return Err(anyhow!("Queries can't hold sqlite3 dot commands"));
+    }
+    writeln!(script, "PRAGMA query_only = ON;")?;
+    writeln!(script, ".headers on")?;
+    writeln!(script, "{}", format.mode())?;
+    writeln!(script, "{};", sql.trim().trim_end_matches(';'))?;
+
+    let mut sqlite = Command::new("sqlite3")
+        .arg("-safe")
+        .arg("-bail")
+        .arg(":memory:")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Could not run sqlite3, is it installed?")?;
+    // Written from another thread, as sqlite3 may fill its output before reading all of it
+    let mut stdin = sqlite.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(script.as_bytes()));
+    let output = sqlite.wait_with_output()?;
+    writer.join().unwrap()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Query failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A script creating the schema and filling it in
+fn database(output_dir: &Path, songs: &[SongInfo]) -> Result<String> {
+    let state = ConversionState::load(output_dir)?;
+    let manifest = Manifest::load(output_dir)?.unwrap_or_default();
+    let songs: HashMap<&Path, &SongInfo> = songs
+        .iter()
+        .map(|song| (song.get_song_path().as_path(), song))
+        .collect();
+
+    let mut script = String::from(SCHEMA);
+    writeln!(script, "BEGIN;")?;
+    for (path, record) in state.sources() {
+        let song = songs.get(path.as_path());
+        let source = text(&path.to_string_lossy());
+        writeln!(
+            script,
+            "INSERT INTO sources VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {});",
+            source,
+            record.modified,
+            record.size,
+            text(&record.hash),
+            record
+                .track_id
+                .map_or("NULL".to_string(), |id| id.to_string()),
+            record
+                .variant_group
+                .as_deref()
+                .map_or("NULL".to_string(), text),
+            record
+                .artwork_hash
+                .as_deref()
+                .map_or("NULL".to_string(), text),
+            path.exists() as u8,
+            song.map_or("NULL".to_string(), |song| text(&report::format_name(song))),
+            song.map(|song| song.get_duration())
+                .filter(|duration| *duration > 0.0)
+                .map_or("NULL".to_string(), |duration| duration.to_string()),
+            record
+                .play_count
+                .map_or("NULL".to_string(), |n| n.to_string()),
+            record.rating.map_or("NULL".to_string(), |n| n.to_string()),
+        )?;
+        for output in record.outputs.iter() {
+            let relative = output.strip_prefix(output_dir).unwrap_or(output);
+            writeln!(
+                script,
+                "INSERT INTO outputs VALUES ({}, {}, {}, {});",
+                source,
+                text(&output.to_string_lossy()),
+                text(&relative.to_string_lossy()),
+                output.exists() as u8,
+            )?;
+        }
+        for other in record.other_versions.iter() {
+            writeln!(
+                script,
+                "INSERT INTO versions VALUES ({}, {});",
+                source,
+                text(&other.to_string_lossy()),
+            )?;
+        }
+        let tags = song
+            .and_then(|song| song.get_tags().as_ref())
+            .and_then(|tags| tags.as_object());
+        for (name, value) in tags.into_iter().flatten() {
+            let value = match value {
+                serde_json::Value::String(value) => text(value),
+                value => text(&value.to_string()),
+            };
+            writeln!(
+                script,
+                "INSERT INTO tags VALUES ({}, {}, {});",
+                source,
+                text(&name.to_lowercase()),
+                value
+            )?;
+        }
+    }
+    for (path, entry) in manifest.files() {
+        writeln!(
+            script,
+            "INSERT INTO manifest VALUES ({}, {}, {}, {}, {});",
+            text(&path.to_string_lossy()),
+            entry.size,
+            text(&entry.hash),
+            entry
+                .duration
+                .map_or("NULL".to_string(), |duration| duration.to_string()),
+            entry.modified,
+        )?;
+    }
+    writeln!(script, "COMMIT;")?;
+    Ok(script)
+}
+
+/// A string as an SQL literal
+fn text(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''").replace('\0', ""))
+}
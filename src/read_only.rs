@@ -0,0 +1,24 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Refuses every write this crate would make for the rest of the process, so a directory can be
+/// inspected without any chance of changing it, such as a USB stick about to be played from.
+/// Reading songs with ffprobe and decoding them with ffmpeg doesn't write anything.
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+/// Fails if writes are refused. Called before anything creates, changes or removes a file
+pub fn check_writable(path: &Path) -> Result<()> {
+    if is_enabled() {
+        bail!("Not writing {} in read-only mode", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+
+/// Whether a pool delivered the radio-safe or the explicit version of a track
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Explicitness {
+    Clean,
+    Dirty,
+}
+
+/// DJ-friendly edits that record pools deliver alongside the original
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PoolEdit {
+    Intro,
+    Outro,
+    QuickHit,
+}
+
+impl std::fmt::Display for Explicitness {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for Explicitness {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "clean" => Ok(Explicitness::Clean),
+            "dirty" | "explicit" => Ok(Explicitness::Dirty),
+            _ => Err(anyhow::anyhow!("Not a clean/dirty version: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for PoolEdit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PoolEdit::QuickHit => write!(f, "Quick Hit"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Information a record pool encodes in its file and folder names
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolInfo {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub genre: Option<String>,
+    pub explicitness: Option<Explicitness>,
+    pub edit: Option<PoolEdit>,
+}
+
+impl PoolInfo {
+    /// Metadata key/value pairs to pass on to ffmpeg
+    pub fn to_metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![];
+        if let Some(artist) = &self.artist {
+            metadata.push(("artist".to_string(), artist.clone()));
+        }
+        if let Some(title) = &self.title {
+            metadata.push(("title".to_string(), title.clone()));
+        }
+        if let Some(genre) = &self.genre {
+            metadata.push(("genre".to_string(), genre.clone()));
+        }
+        if let Some(explicitness) = &self.explicitness {
+            metadata.push(("VERSION".to_string(), explicitness.to_string()));
+        }
+        if let Some(edit) = &self.edit {
+            metadata.push(("EDIT".to_string(), edit.to_string()));
+        }
+        metadata
+    }
+}
+
+/// A parser for the file naming conventions of one record pool
+pub trait PoolParser: Send + Sync {
+    /// Name used to select the parser from the command line
+    fn name(&self) -> &'static str;
+    /// Extract whatever information the layout carries, or None if the path does not match it
+    fn parse(&self, path: &Path) -> Option<PoolInfo>;
+}
+
+/// "Artist - Title (Clean) [Intro].mp3" with no meaningful folder structure
+pub struct GenericParser;
+
+/// "BPM Supreme/Genre/Artist - Title (Clean) [Intro].mp3"
+pub struct BpmSupremeParser;
+
+impl PoolParser for GenericParser {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+
+    fn parse(&self, path: &Path) -> Option<PoolInfo> {
+        parse_file_stem(path.file_stem()?.to_str()?)
+    }
+}
+
+impl PoolParser for BpmSupremeParser {
+    fn name(&self) -> &'static str {
+        "bpm-supreme"
+    }
+
+    fn parse(&self, path: &Path) -> Option<PoolInfo> {
+        let mut info = parse_file_stem(path.file_stem()?.to_str()?)?;
+        // The folder a track was delivered in is its genre
+        info.genre = path
+            .parent()
+            .and_then(|p| p.file_name())
+            .and_then(|p| p.to_str())
+            .map(|p| p.to_string());
+        Some(info)
+    }
+}
+
+/// All parsers that can be selected with `--pool-layout`
+pub fn parsers() -> Vec<Box<dyn PoolParser>> {
+    vec![Box::new(GenericParser), Box::new(BpmSupremeParser)]
+}
+
+/// Look up a parser by the name it is registered under
+pub fn parser_by_name(name: &str) -> Option<Box<dyn PoolParser>> {
+    parsers().into_iter().find(|p| p.name() == name)
+}
+
+/// Splits "Artist - Title (Clean) [Intro]" into its parts.
+fn parse_file_stem(stem: &str) -> Option<PoolInfo> {
+    let (title, mut info) = strip_version_groups(stem);
+    match title.split_once(" - ") {
+        Some((artist, title)) => {
+            info.artist = Some(artist.trim().to_string());
+            info.title = Some(title.trim().to_string());
+        }
+        None if !title.is_empty() => info.title = Some(title),
+        None => (),
+    }
+    if info == PoolInfo::default() {
+        None
+    } else {
+        Some(info)
+    }
+}
+
+/// Parses a title tag like "Title (Clean) [Intro]", which pools often use instead of or as well as
+/// the file name.
+pub fn parse_title(title: &str) -> Option<PoolInfo> {
+    let (title, mut info) = strip_version_groups(title);
+    if title.is_empty() {
+        return None;
+    }
+    info.title = Some(title);
+    Some(info)
+}
+
+/// Removes bracketed groups that describe the version or edit, recording what they said. Anything
+/// else in brackets, like "(Extended Mix)", is kept.
+fn strip_version_groups(name: &str) -> (String, PoolInfo) {
+    let mut info = PoolInfo::default();
+    let mut title = String::new();
+    let mut rest = name;
+    while let Some(start) = rest.find(['(', '[']) {
+        let close = if rest[start..].starts_with('(') {
+            ')'
+        } else {
+            ']'
+        };
+        let end = match rest[start..].find(close) {
+            Some(end) => start + end,
+            None => break,
+        };
+        let group = &rest[start + 1..end];
+        let mut recognized = false;
+        let group = group.to_lowercase();
+        let words: Vec<&str> = group.split_whitespace().collect();
+        for (i, word) in words.iter().enumerate() {
+            match *word {
+                "clean" => info.explicitness = Some(Explicitness::Clean),
+                "dirty" | "explicit" => info.explicitness = Some(Explicitness::Dirty),
+                "intro" => info.edit = Some(PoolEdit::Intro),
+                "outro" => info.edit = Some(PoolEdit::Outro),
+                // Only as a phrase, as "quick" or "hit" alone also turn up in remix names
+                "quickhit" | "quick-hit" => info.edit = Some(PoolEdit::QuickHit),
+                "quick" if words.get(i + 1) == Some(&"hit") => info.edit = Some(PoolEdit::QuickHit),
+                "hit" if i > 0 && words[i - 1] == "quick" => (),
+                _ => continue,
+            }
+            recognized = true;
+        }
+        title.push_str(&rest[..start]);
+        if !recognized {
+            title.push_str(&rest[start..=end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    title.push_str(rest);
+    (title.split_whitespace().collect::<Vec<_>>().join(" "), info)
+}
@@ -0,0 +1,373 @@
+use crate::file_uri;
+use crate::serato;
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The tracks of a playlist in a Rekordbox XML library, of a Serato crate, of an M3U file or of
+/// playlists in an iTunes library, which selects the songs to convert instead of, or on top of, a tag
+#[derive(Clone, Debug)]
+pub struct PlaylistSelection {
+    /// The playlist, with the folders it is in, like "Gigs/Prep"
+    pub name: String,
+    /// Where Rekordbox has the tracks, in playlist order
+    pub locations: Vec<PathBuf>,
+}
+
+impl PlaylistSelection {
+    /// Reads a playlist from a library exported with File > Export Collection in xml format. The
+    /// playlist is found by its name, or by its path through folders like "Gigs/Prep" when
+    /// several playlists share a name.
+    pub fn load(xml_path: &Path, playlist: &str) -> Result<Self> {
+        let xml = fs::read_to_string(xml_path)
+            .with_context(|| format!("Could not read {}", xml_path.display()))?;
+        let document = roxmltree::Document::parse(&xml)
+            .with_context(|| format!("{} isn't an XML file", xml_path.display()))?;
+        let library = document.root_element();
+        let child = |name: &str| library.children().find(|c| c.has_tag_name(name));
+        let track_locations: HashMap<&str, &str> = child("COLLECTION")
+            .into_iter()
+            .flat_map(|c| c.children())
+            .filter(|c| c.has_tag_name("TRACK"))
+            .filter_map(|t| Some((t.attribute("TrackID")?, t.attribute("Location")?)))
+            .collect();
+        let mut playlists = vec![];
+        if let Some(root) = child("PLAYLISTS").and_then(|p| p.children().find(|c| c.is_element())) {
+            find_playlists(root, "", &mut playlists);
+        }
+        let (name, node) = find_one(&playlists, playlist, xml_path)?;
+        // Keys are TrackIDs, unless KeyType says they are Locations
+        let by_location = node.attribute("KeyType") == Some("1");
+        let mut locations = vec![];
+        for key in node
+            .children()
+            .filter(|c| c.has_tag_name("TRACK"))
+            .filter_map(|c| c.attribute("Key"))
+        {
+            let location = if by_location {
+                Some(key)
+            } else {
+                track_locations.get(key).copied()
+            };
+            match location.map(file_uri::from_uri) {
+                Some(Ok(path)) => locations.push(path),
+                Some(Err(e)) => {
+                    tracing::warn!(?key, ?e, "Leaving out a track with a broken Location")
+                }
+                None => tracing::warn!(?key, "Leaving out a track that isn't in the collection"),
+            }
+        }
+        Ok(PlaylistSelection {
+            name: name.clone(),
+            locations,
+        })
+    }
+
+    /// Reads playlists from an iTunes or Music library exported with File > Library > Export
+    /// Library, whose tracks are all selected, in order. Playlists are found by name, or by their
+    /// path through folders like "Gigs/Prep". Libraries can be large, so they are parsed once
+    /// for all the playlists.
+    pub fn from_itunes_xml(xml_path: &Path, playlists: &[String]) -> Result<Self> {
+        let xml = fs::read_to_string(xml_path)
+            .with_context(|| format!("Could not read {}", xml_path.display()))?;
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let document = roxmltree::Document::parse_with_options(&xml, options)
+            .with_context(|| format!("{} isn't an XML file", xml_path.display()))?;
+        let library = document
+            .root_element()
+            .children()
+            .find(|c| c.has_tag_name("dict"))
+            .map(plist_dict)
+            .ok_or_else(|| anyhow!("{} isn't an iTunes library", xml_path.display()))?;
+        let track_locations: HashMap<&str, &str> = library
+            .get("Tracks")
+            .into_iter()
+            .flat_map(|tracks| plist_dict(*tracks))
+            .filter_map(|(id, track)| Some((id, plist_dict(track).get("Location")?.text()?)))
+            .collect();
+        let entries: Vec<HashMap<&str, roxmltree::Node>> = library
+            .get("Playlists")
+            .into_iter()
+            .flat_map(|p| p.children().filter(|c| c.has_tag_name("dict")))
+            .map(plist_dict)
+            .collect();
+        let text = |entry: &HashMap<&str, roxmltree::Node<'_, '_>>, key: &str| {
+            entry.get(key).and_then(|n| n.text()).map(str::to_string)
+        };
+        let parents: HashMap<String, (String, Option<String>)> = entries
+            .iter()
+            .filter_map(|e| {
+                Some((
+                    text(e, "Playlist Persistent ID")?,
+                    (text(e, "Name")?, text(e, "Parent Persistent ID")),
+                ))
+            })
+            .collect();
+        let named: Vec<(String, &HashMap<&str, roxmltree::Node>)> = entries
+            .iter()
+            .filter_map(|e| {
+                let mut path = text(e, "Name")?;
+                let mut parent = text(e, "Parent Persistent ID");
+                // Folders are playlists too, whose tracks are those of the playlists in them
+                while let Some((name, grandparent)) = parent.and_then(|id| parents.get(&id)) {
+                    path = format!("{}/{}", name, path);
+                    parent = grandparent.clone();
+                }
+                Some((path, e))
+            })
+            .collect();
+        let mut selections = vec![];
+        for playlist in playlists {
+            let (name, entry) = find_one(&named, playlist, xml_path)?;
+            let mut locations = vec![];
+            for item in entry
+                .get("Playlist Items")
+                .into_iter()
+                .flat_map(|items| items.children().filter(|c| c.has_tag_name("dict")))
+            {
+                let id = plist_dict(item)
+                    .get("Track ID")
+                    .and_then(|n| n.text())
+                    .unwrap_or_default();
+                match track_locations.get(id).map(|l| file_uri::from_uri(l)) {
+                    Some(Ok(path)) => locations.push(path),
+                    Some(Err(e)) => {
+                        tracing::warn!(?id, ?e, "Leaving out a track with a broken Location")
+                    }
+                    // Apple Music tracks that were never downloaded have no file
+                    None => tracing::warn!(?id, "Leaving out a track that isn't a local file"),
+                }
+            }
+            selections.push(PlaylistSelection {
+                name: name.clone(),
+                locations,
+            });
+        }
+        let selection = PlaylistSelection::combine(selections);
+        tracing::info!(
+            name = selection.name.as_str(),
+            n_tracks = selection.locations.len(),
+            "Read iTunes playlists"
+        );
+        Ok(selection)
+    }
+
+    /// One selection of the tracks of several, in order, each track once, named after all of
+    /// them
+    pub fn combine(selections: Vec<Self>) -> Self {
+        if selections.len() == 1 {
+            return selections.into_iter().next().expect("one selection");
+        }
+        let names: Vec<&str> = selections.iter().map(|s| s.name.as_str()).collect();
+        let name = names.join(" + ");
+        let mut seen = BTreeSet::new();
+        let locations = selections
+            .iter()
+            .flat_map(|s| s.locations.iter())
+            .filter(|location| seen.insert(location.to_path_buf()))
+            .cloned()
+            .collect();
+        PlaylistSelection { name, locations }
+    }
+
+    /// Reads the tracks of a Serato crate file, from the _Serato_/Subcrates folder of a drive
+    pub fn from_serato_crate(crate_path: &Path) -> Result<Self> {
+        let read = serato::read_crate(crate_path)?;
+        tracing::info!(
+            name = read.name.as_str(),
+            n_tracks = read.tracks.len(),
+            "Read Serato crate"
+        );
+        Ok(PlaylistSelection {
+            name: read.name,
+            locations: read.tracks,
+        })
+    }
+
+    /// Reads an M3U or M3U8 playlist. Lines starting with # are comments or extended M3U
+    /// information and are skipped. Entries are paths, or file:// URIs, and relative paths are
+    /// relative to the playlist's folder.
+    pub fn from_m3u(m3u_path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(m3u_path).with_context(|| format!("Could not read {}", m3u_path.display()))?;
+        // Plain .m3u files are often in a legacy encoding, so don't give up on those
+        let contents = String::from_utf8_lossy(&contents);
+        let folder = m3u_path.parent().unwrap_or_else(|| Path::new(""));
+        let mut locations = vec![];
+        for line in contents.trim_start_matches('\u{feff}').lines() {
+            let entry = line.trim();
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            let path = if entry.starts_with("file://") {
+                match file_uri::from_uri(entry) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        tracing::warn!(?entry, ?e, "Leaving out a track with a broken URI");
+                        continue;
+                    }
+                }
+            } else {
+                PathBuf::from(entry)
+            };
+            locations.push(if path.is_relative() {
+                folder.join(path)
+            } else {
+                path
+            });
+        }
+        let name = m3u_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        tracing::info!(
+            name = name.as_str(),
+            n_tracks = locations.len(),
+            "Read M3U playlist"
+        );
+        Ok(PlaylistSelection { name, locations })
+    }
+
+    /// The tracks of the playlist that are files, for converting a playlist without an input
+    /// directory. Tracks that aren't found are logged.
+    pub fn existing(&self) -> Vec<PathBuf> {
+        let mut seen = BTreeSet::new();
+        let mut existing = vec![];
+        for location in &self.locations {
+            if !location.is_file() {
+                tracing::warn!(
+                    ?location,
+                    playlist = self.name.as_str(),
+                    "Track of the playlist doesn't exist"
+                );
+            } else if seen.insert(location.clone()) {
+                existing.push(location.clone());
+            }
+        }
+        existing
+    }
+
+    /// The files that are tracks of the playlist, in playlist order. A track is found at its
+    /// Location, or, when the library was exported on another computer or the files were moved
+    /// since, by its file name. Tracks found in neither way are logged.
+    pub fn select(&self, files: &[PathBuf]) -> Vec<PathBuf> {
+        let canonical: HashMap<PathBuf, &PathBuf> = files
+            .iter()
+            .filter_map(|f| Some((f.canonicalize().ok()?, f)))
+            .collect();
+        let mut by_name: HashMap<OsString, Vec<&PathBuf>> = HashMap::new();
+        for file in files {
+            if let Some(name) = file.file_name() {
+                by_name.entry(name.to_os_string()).or_default().push(file);
+            }
+        }
+        let mut selected = vec![];
+        let mut seen = BTreeSet::new();
+        for location in &self.locations {
+            let found = location
+                .canonicalize()
+                .ok()
+                .and_then(|l| canonical.get(&l).copied())
+                .or_else(|| match by_name.get(location.file_name()?)?.as_slice() {
+                    [file] => {
+                        tracing::info!(?location, ?file, "Found a moved track by its name");
+                        Some(*file)
+                    }
+                    files => {
+                        tracing::warn!(?location, ?files, "Several files have the track's name");
+                        None
+                    }
+                });
+            match found {
+                Some(file) => {
+                    if seen.insert(file.clone()) {
+                        selected.push(file.clone());
+                    }
+                }
+                None => tracing::warn!(
+                    ?location,
+                    playlist = self.name.as_str(),
+                    "Track of the playlist isn't in the input directory"
+                ),
+            }
+        }
+        selected
+    }
+
+    /// Whether a file is one of the playlist's tracks, by its Location or its file name
+    pub fn contains(&self, file: &Path) -> bool {
+        let canonical = file.canonicalize().ok();
+        self.locations.iter().any(|location| {
+            canonical.is_some() && location.canonicalize().ok() == canonical
+                || location.file_name().is_some() && location.file_name() == file.file_name()
+        })
+    }
+}
+
+/// The playlist found by its path through folders, or by its name if only one has it
+fn find_one<'a, T>(
+    playlists: &'a [(String, T)],
+    playlist: &str,
+    library: &Path,
+) -> Result<&'a (String, T)> {
+    let wanted = playlist.trim_matches('/');
+    let matching: Vec<&(String, T)> = playlists
+        .iter()
+        .filter(|(path, _)| path == wanted || path.rsplit('/').next() == Some(wanted))
+        .collect();
+    match matching.as_slice() {
+        [found] => Ok(found),
+        [] => Err(anyhow!(
+            "{} has no playlist called {:?}",
+            library.display(),
+            playlist
+        )),
+        several => {
+            let names: Vec<&str> = several.iter().map(|(path, _)| path.as_str()).collect();
+            Err(anyhow!(
+                "Several playlists are called {:?}, choose one of: {}",
+                playlist,
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+/// The entries of a property list dict, whose children are keys each followed by its value
+fn plist_dict<'a, 'input>(
+    dict: roxmltree::Node<'a, 'input>,
+) -> HashMap<&'a str, roxmltree::Node<'a, 'input>> {
+    let mut entries = HashMap::new();
+    let mut children = dict.children().filter(|c| c.is_element());
+    while let (Some(key), Some(value)) = (children.next(), children.next()) {
+        if key.has_tag_name("key") {
+            entries.insert(key.text().unwrap_or_default(), value);
+        }
+    }
+    entries
+}
+
+/// Collects the playlists under a folder NODE, with their paths through the folders
+fn find_playlists<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    parent: &str,
+    playlists: &mut Vec<(String, roxmltree::Node<'a, 'input>)>,
+) {
+    for child in node.children().filter(|c| c.has_tag_name("NODE")) {
+        let name = child.attribute("Name").unwrap_or_default();
+        let path = if parent.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", parent, name)
+        };
+        match child.attribute("Type") {
+            Some("0") => find_playlists(child, &path, playlists),
+            _ => playlists.push((path, child)),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::exporters::{self, ExportedFile, Exporter, Playlist, TrackRecord};
+use crate::file_uri;
+use crate::imported_analysis;
+use crate::serato_markers::{self, MarkerKind};
+use crate::song_info::ProbeBackend;
+use crate::state;
+use crate::xml_validate::{self, Severity};
+use anyhow::{anyhow, Context, Result};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the exported Rekordbox library in the output directory
+pub const XML_FILE_NAME: &str = "rekordbox.xml";
+
+/// Rekordbox's XML library format, which Rekordbox imports tracks and playlists from
+pub struct RekordboxXml;
+
+impl Exporter for RekordboxXml {
+    fn name(&self) -> &'static str {
+        "rekordbox-xml"
+    }
+
+    fn description(&self) -> &'static str {
+        "a Rekordbox XML library"
+    }
+
+    fn export(&self, tracks: &[TrackRecord], playlists: &[Playlist]) -> Result<Vec<ExportedFile>> {
+        let xml = to_xml(tracks, playlists);
+        validate(&xml)?;
+        Ok(vec![ExportedFile {
+            path: PathBuf::from(XML_FILE_NAME),
+            contents: xml.into_bytes(),
+        }])
+    }
+}
+
+/// Writes the tracks converted into an output directory as a Rekordbox XML library, with a
+/// playlist named after the directory holding all of them, and one of the tracks converted in
+/// the last `new_playlist_days` days if given. Returns the number of tracks.
+pub fn export(
+    output_dir: &Path,
+    xml_path: &Path,
+    probe_backend: ProbeBackend,
+    new_playlist_days: Option<u64>,
+) -> Result<usize> {
+    let tracks = exporters::tracks(output_dir, probe_backend)?;
+    let playlists = exporters::playlists(output_dir, &tracks, new_playlist_days)?;
+    write_validated(xml_path, &to_xml(&tracks, &playlists))?;
+    Ok(tracks.len())
+}
+
+/// Writes a library after checking it for what Rekordbox trips over, refusing to write it if
+/// Rekordbox wouldn't import it right
+pub(crate) fn write_validated(xml_path: &Path, xml: &str) -> Result<()> {
+    validate(xml).with_context(|| format!("Not writing {}", xml_path.display()))?;
+    state::write_atomic(xml_path, xml.as_bytes())
+}
+
+/// Checks a library for what Rekordbox trips over, logging the warnings and failing on errors
+fn validate(xml: &str) -> Result<()> {
+    let problems = xml_validate::validate(xml);
+    for problem in problems.iter().filter(|p| p.severity == Severity::Warning) {
+        tracing::warn!(%problem, "Rekordbox may not import this right");
+    }
+    let errors: Vec<String> = problems
+        .iter()
+        .filter(|p| p.severity == Severity::Error)
+        .map(|p| p.to_string())
+        .collect();
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "Rekordbox wouldn't import the library:\n{}",
+            errors.join("\n")
+        ));
+    }
+    Ok(())
+}
+
+/// Folder the playlists of versions of the same track are in
+const VARIANTS_FOLDER: &str = "Variants";
+
+/// A Rekordbox XML library of the tracks, with the playlists at the top level, and a playlist
+/// for each track with acapella, instrumental or dub versions in a Variants folder
+pub fn to_xml(tracks: &[TrackRecord], playlists: &[Playlist]) -> String {
+    let variants = exporters::variant_playlists(tracks);
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<DJ_PLAYLISTS Version=\"1.0.0\">\n");
+    let _ = writeln!(
+        xml,
+        "  <PRODUCT Name=\"{}\" Version=\"{}\" Company=\"\"/>",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    let _ = writeln!(xml, "  <COLLECTION Entries=\"{}\">", tracks.len());
+    for track in tracks {
+        let marks = position_marks(track);
+        if marks.is_empty() {
+            let _ = writeln!(xml, "    <TRACK{}/>", attributes(&track_attributes(track)));
+            continue;
+        }
+        let _ = writeln!(xml, "    <TRACK{}>", attributes(&track_attributes(track)));
+        for mark in marks {
+            let _ = writeln!(xml, "      <POSITION_MARK{}/>", attributes(&mark));
+        }
+        xml.push_str("    </TRACK>\n");
+    }
+    xml.push_str("  </COLLECTION>\n");
+    xml.push_str("  <PLAYLISTS>\n");
+    let _ = writeln!(
+        xml,
+        "    <NODE Type=\"0\" Name=\"ROOT\" Count=\"{}\">",
+        playlists.len() + !variants.is_empty() as usize
+    );
+    for playlist in playlists {
+        write_playlist(&mut xml, playlist, 3);
+    }
+    if !variants.is_empty() {
+        let _ = writeln!(
+            xml,
+            "      <NODE Type=\"0\" Name=\"{}\" Count=\"{}\">",
+            VARIANTS_FOLDER,
+            variants.len()
+        );
+        for playlist in &variants {
+            write_playlist(&mut xml, playlist, 4);
+        }
+        xml.push_str("      </NODE>\n");
+    }
+    xml.push_str("    </NODE>\n");
+    xml.push_str("  </PLAYLISTS>\n");
+    xml.push_str("</DJ_PLAYLISTS>\n");
+    xml
+}
+
+fn write_playlist(xml: &mut String, playlist: &Playlist, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(
+        xml,
+        "{}<NODE Name=\"{}\" Type=\"1\" KeyType=\"0\" Entries=\"{}\">",
+        indent,
+        xml_escape(&playlist.name),
+        playlist.track_ids.len()
+    );
+    for id in &playlist.track_ids {
+        let _ = writeln!(xml, "{}  <TRACK Key=\"{}\"/>", indent, id);
+    }
+    let _ = writeln!(xml, "{}</NODE>", indent);
+}
+
+/// The attributes of a track's TRACK element in the collection
+pub(crate) fn track_attributes(track: &TrackRecord) -> Vec<(&'static str, String)> {
+    let song = &track.song;
+    let tag = |key: &str| song.get_tag(key).unwrap_or_default().to_string();
+    // Tempo and key as analyzed for the export, or else as tagged, like by Mixed In Key
+    let tagged = imported_analysis::from_tags(song);
+    let bpm = track.analysis.bpm.or(tagged.bpm);
+    let key = track.analysis.key.clone().or(tagged.key);
+    let mut attributes = vec![
+        ("TrackID", track.id.to_string()),
+        ("Name", track.title()),
+        ("Artist", track.artist().to_string()),
+        ("Album", tag("album")),
+        ("Genre", tag("genre")),
+        ("Kind", kind(&track.path)),
+        ("Size", track.size.to_string()),
+        (
+            "TotalTime",
+            (song.get_duration().round() as u64).to_string(),
+        ),
+        ("Year", track.year()),
+    ];
+    if let Some(bpm) = bpm {
+        attributes.push(("AverageBpm", format!("{:.2}", bpm)));
+    }
+    attributes.extend([
+        ("DateAdded", track.date_added.clone()),
+        ("SampleRate", song.get_sample_rate().to_string()),
+        ("Location", location(&track.path)),
+    ]);
+    if let Some(key) = key {
+        attributes.push(("Tonality", key));
+    }
+    attributes
+}
+
+/// Hot cues Rekordbox has pads for
+const HOT_CUE_PADS: u8 = 8;
+
+/// The POSITION_MARK elements of a track, from the cue points and loops set in Serato. Cues
+/// keep their pads as hot cues; loops, whose pads Serato numbers apart from the cues', become
+/// memory loops.
+pub(crate) fn position_marks(track: &TrackRecord) -> Vec<Vec<(&'static str, String)>> {
+    serato_markers::of_track(track)
+        .iter()
+        .map(|marker| {
+            let (kind, num) = match marker.kind {
+                MarkerKind::Cue if marker.index < HOT_CUE_PADS => ("0", marker.index as i32),
+                MarkerKind::Cue => ("0", -1),
+                MarkerKind::Loop => ("4", -1),
+            };
+            let mut attributes = vec![
+                ("Name", marker.name.clone()),
+                ("Type", kind.to_string()),
+                ("Start", format!("{:.3}", marker.start)),
+            ];
+            if let Some(end) = marker.end {
+                attributes.push(("End", format!("{:.3}", end)));
+            }
+            attributes.push(("Num", num.to_string()));
+            let (red, green, blue) = marker.color;
+            attributes.push(("Red", red.to_string()));
+            attributes.push(("Green", green.to_string()));
+            attributes.push(("Blue", blue.to_string()));
+            attributes
+        })
+        .collect()
+}
+
+fn attributes(attributes: &[(&str, String)]) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, xml_escape(value)))
+        .collect()
+}
+
+/// What Rekordbox calls the kind of file, e.g. "AIFF File"
+fn kind(path: &Path) -> String {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "AIF" | "AIFF" => "AIFF File".to_string(),
+        "M4A" | "MP4" => "M4A File".to_string(),
+        extension => format!("{} File", extension),
+    }
+}
+
+/// The file URL Rekordbox expects in Location, of the file with symlinks resolved
+fn location(path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    file_uri::to_uri(&path)
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -0,0 +1,452 @@
+use crate::converter::ConversionSummary;
+use crate::i18n;
+use crate::key::DetectedKey;
+use crate::loudness::Normalization;
+use crate::read_only;
+use crate::song_info::{AudioFormatType, SongInfo};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Number and total size of songs sharing a format
+#[derive(Clone, Debug, Default)]
+pub struct FormatStats {
+    pub n_songs: usize,
+    pub total_bytes: u64,
+}
+
+/// Summary of the songs in a directory, grouped by format and sample rate
+#[derive(Clone, Debug, Default)]
+pub struct DirectoryReport {
+    pub formats: BTreeMap<String, FormatStats>,
+}
+
+impl DirectoryReport {
+    pub fn from_songs(songs: &[SongInfo]) -> Self {
+        let mut report = DirectoryReport::default();
+        for song in songs {
+            let key = format!("{:?} {} Hz", song.get_format(), song.get_sample_rate());
+            let stats = report.formats.entry(key).or_default();
+            stats.n_songs += 1;
+            stats.total_bytes += fs::metadata(song.get_song_path())
+                .map(|m| m.len())
+                .unwrap_or(0);
+        }
+        report
+    }
+}
+
+impl std::fmt::Display for DirectoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut n_songs = 0;
+        let mut total_bytes = 0;
+        let songs = i18n::message("directory-songs", &[]);
+        for (format, stats) in self.formats.iter() {
+            writeln!(
+                f,
+                "{:<40} {:>6} {} {:>10.1} MB",
+                format,
+                stats.n_songs,
+                songs,
+                stats.total_bytes as f64 / 1e6
+            )?;
+            n_songs += stats.n_songs;
+            total_bytes += stats.total_bytes;
+        }
+        write!(
+            f,
+            "{:<40} {:>6} {} {:>10.1} MB",
+            i18n::message("directory-total", &[]),
+            n_songs,
+            songs,
+            total_bytes as f64 / 1e6
+        )
+    }
+}
+
+/// What happened to a song in a run
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Converted,
+    Copied,
+    Skipped,
+    Failed,
+}
+
+/// What happened to one song in a run
+#[derive(Clone, Debug, Serialize)]
+pub struct FileReport {
+    pub source: PathBuf,
+    pub decision: Decision,
+    /// Why the song was skipped
+    pub reason: Option<String>,
+    pub source_format: String,
+    /// Format the song was converted to
+    pub target_format: Option<String>,
+    /// Seconds spent on the song
+    pub seconds: f64,
+    pub source_bytes: u64,
+    /// Size of the files written for the song, if it was converted or copied
+    pub output_bytes: Option<u64>,
+    pub error: Option<String>,
+    /// Key detected for the song, with its confidence and whether it was tagged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<DetectedKey>,
+    /// Loudness measured and gain applied to normalize the song, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<Normalization>,
+}
+
+/// Name of a song's format for reports, or its codec if the format isn't supported
+pub fn format_name(song: &SongInfo) -> String {
+    match song.get_format() {
+        AudioFormatType::Lossless(format) | AudioFormatType::Lossy(format) => format.to_string(),
+        AudioFormatType::Unsupported => song.get_codec().to_string(),
+    }
+}
+
+/// Report of a conversion run, for scripts to consume
+#[derive(Clone, Debug, Serialize)]
+pub struct RunReport {
+    pub n_converted: usize,
+    pub n_copied: usize,
+    pub n_skipped: usize,
+    pub n_failed: usize,
+    /// Size of the songs that were converted or copied, before and after
+    pub source_bytes: u64,
+    pub output_bytes: u64,
+    pub files: Vec<FileReport>,
+}
+
+impl RunReport {
+    pub fn from_summary(summary: &ConversionSummary) -> Self {
+        let mut files = summary.files.clone();
+        files.sort_by(|a, b| a.source.cmp(&b.source));
+        let written = files.iter().filter(|f| f.output_bytes.is_some());
+        let source_bytes = written.clone().map(|f| f.source_bytes).sum();
+        let output_bytes = written.filter_map(|f| f.output_bytes).sum();
+        RunReport {
+            n_converted: summary.n_converted,
+            n_copied: summary.n_copied,
+            n_skipped: summary.n_skipped,
+            n_failed: summary.n_failed,
+            source_bytes,
+            output_bytes,
+            files,
+        }
+    }
+
+    /// Bytes saved by converting, negative if the output is larger than the source
+    pub fn bytes_saved(&self) -> i64 {
+        self.source_bytes as i64 - self.output_bytes as i64
+    }
+
+    /// One row per song, with a header row
+    pub fn to_csv(&self) -> String {
+        let mut csv = [
+            "source",
+            "decision",
+            "reason",
+            "source_format",
+            "target_format",
+            "seconds",
+            "source_bytes",
+            "output_bytes",
+            "error",
+            "key",
+            "key_confidence",
+            "key_tagged",
+            "measured_lufs",
+            "target_lufs",
+            "output_lufs",
+            "gain_db",
+            "normalization",
+            "gain_reduction_db",
+        ]
+        .join(",");
+        csv.push('\n');
+        for file in self.files.iter() {
+            let row = [
+                file.source.to_string_lossy().to_string(),
+                decision_name(file.decision).to_string(),
+                file.reason.clone().unwrap_or_default(),
+                file.source_format.clone(),
+                file.target_format.clone().unwrap_or_default(),
+                format!("{:.2}", file.seconds),
+                file.source_bytes.to_string(),
+                file.output_bytes.map(|b| b.to_string()).unwrap_or_default(),
+                file.error.clone().unwrap_or_default(),
+                file.key.as_ref().map(|k| k.key.clone()).unwrap_or_default(),
+                file.key
+                    .as_ref()
+                    .map(|k| format!("{:.3}", k.confidence))
+                    .unwrap_or_default(),
+                file.key
+                    .as_ref()
+                    .map(|k| k.tagged.to_string())
+                    .unwrap_or_default(),
+                loudness(file, |l| l.measured_lufs),
+                loudness(file, |l| l.target_lufs),
+                output_loudness(file),
+                loudness(file, |l| l.gain_db),
+                file.loudness
+                    .as_ref()
+                    .map(|l| if l.dynamic { "dynamic" } else { "linear" })
+                    .unwrap_or_default()
+                    .to_string(),
+                limited(file),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// A standalone page with the totals, then a table each of converted, skipped and failed songs
+    pub fn to_html(&self) -> String {
+        let t = |id: &str| i18n::message(id, &[]);
+        let title = html_escape(&t("report-title"));
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n{1}\
+             </head>\n<body>\n<h1>{0}</h1>\n",
+            title, HTML_STYLE
+        );
+        let counts = i18n::message(
+            "report-counts",
+            &[
+                ("converted", self.n_converted.into()),
+                ("copied", self.n_copied.into()),
+                ("skipped", self.n_skipped.into()),
+                ("failed", self.n_failed.into()),
+            ],
+        );
+        let sizes = i18n::message(
+            "report-sizes",
+            &[
+                (
+                    "source",
+                    format!("{:.1}", self.source_bytes as f64 / 1e6).into(),
+                ),
+                (
+                    "output",
+                    format!("{:.1}", self.output_bytes as f64 / 1e6).into(),
+                ),
+                (
+                    "saved",
+                    format!("{:.1}", self.bytes_saved() as f64 / 1e6).into(),
+                ),
+            ],
+        );
+        html.push_str(&format!(
+            "<p>{}</p>\n<p>{}</p>\n",
+            html_escape(&counts),
+            html_escape(&sizes)
+        ));
+        html.push_str(&format!(
+            "<h2>{}</h2>\n<table>\n",
+            html_escape(&t("report-converted"))
+        ));
+        html.push_str(&html_row(
+            "th",
+            &[
+                &t("report-song"),
+                &t("report-from"),
+                &t("report-to"),
+                &t("report-source-mb"),
+                &t("report-output-mb"),
+                &t("report-seconds"),
+                &t("report-measured-lufs"),
+                &t("report-output-lufs"),
+                &t("report-gain-db"),
+                &t("report-limited-db"),
+            ],
+        ));
+        let copied = t("report-copied");
+        for file in self
+            .files
+            .iter()
+            .filter(|f| matches!(f.decision, Decision::Converted | Decision::Copied))
+        {
+            html.push_str(&html_row(
+                "td",
+                &[
+                    &file.source.to_string_lossy(),
+                    &file.source_format,
+                    file.target_format.as_deref().unwrap_or(&copied),
+                    &format!("{:.1}", file.source_bytes as f64 / 1e6),
+                    &format!("{:.1}", file.output_bytes.unwrap_or(0) as f64 / 1e6),
+                    &format!("{:.1}", file.seconds),
+                    &loudness(file, |l| l.measured_lufs),
+                    &output_loudness(file),
+                    &gain(file),
+                    &limited(file),
+                ],
+            ));
+        }
+        html.push_str(&format!(
+            "</table>\n<h2>{}</h2>\n<table>\n",
+            html_escape(&t("report-skipped"))
+        ));
+        html.push_str(&html_row("th", &[&t("report-song"), &t("report-reason")]));
+        for file in self
+            .files
+            .iter()
+            .filter(|f| f.decision == Decision::Skipped)
+        {
+            html.push_str(&html_row(
+                "td",
+                &[
+                    &file.source.to_string_lossy(),
+                    file.reason.as_deref().unwrap_or(""),
+                ],
+            ));
+        }
+        html.push_str(&format!(
+            "</table>\n<h2>{}</h2>\n<table>\n",
+            html_escape(&t("report-failed"))
+        ));
+        html.push_str(&html_row("th", &[&t("report-song"), &t("report-error")]));
+        for file in self.files.iter().filter(|f| f.decision == Decision::Failed) {
+            // The error holds ffmpeg's output, which is only readable with its line breaks
+            html.push_str(&format!(
+                "<tr><td>{}</td><td><pre>{}</pre></td></tr>\n",
+                html_escape(&file.source.to_string_lossy()),
+                html_escape(file.error.as_deref().unwrap_or(""))
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+const HTML_STYLE: &str = "<style>
+body { font-family: sans-serif; }
+table { border-collapse: collapse; }
+td, th { border: 1px solid #ccc; padding: 2px 6px; text-align: left; vertical-align: top; }
+pre { margin: 0; white-space: pre-wrap; }
+</style>
+";
+
+/// A value of how a song was normalized, to a tenth of a dB, or nothing if it wasn't
+fn loudness(file: &FileReport, value: impl Fn(&Normalization) -> f64) -> String {
+    file.loudness
+        .as_ref()
+        .map(|l| format!("{:.1}", value(l)))
+        .unwrap_or_default()
+}
+
+/// The loudness loudnorm measured on a song's output, or nothing if it wasn't normalized
+fn output_loudness(file: &FileReport) -> String {
+    file.loudness
+        .as_ref()
+        .and_then(|l| l.output_lufs)
+        .map(|lufs| format!("{:.1}", lufs))
+        .unwrap_or_default()
+}
+
+/// The gain applied to a song, marked if loudnorm had to normalize it dynamically
+fn gain(file: &FileReport) -> String {
+    match &file.loudness {
+        Some(l) if l.dynamic => i18n::message(
+            "report-gain-dynamic",
+            &[("gain", format!("{:.1}", l.gain_db).into())],
+        ),
+        _ => loudness(file, |l| l.gain_db),
+    }
+}
+
+/// How many dB the limiter took off a song's peaks, or nothing if it didn't go through it
+fn limited(file: &FileReport) -> String {
+    file.loudness
+        .as_ref()
+        .and_then(|l| l.gain_reduction_db)
+        .map(|db| format!("{:.1}", db))
+        .unwrap_or_default()
+}
+
+/// A table row of header or data cells
+fn html_row(cell: &str, values: &[&str]) -> String {
+    let cells: String = values
+        .iter()
+        .map(|value| format!("<{0}>{1}</{0}>", cell, html_escape(value)))
+        .collect();
+    format!("<tr>{}</tr>\n", cells)
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", decision_name(*self))
+    }
+}
+
+fn decision_name(decision: Decision) -> &'static str {
+    match decision {
+        Decision::Converted => "converted",
+        Decision::Copied => "copied",
+        Decision::Skipped => "skipped",
+        Decision::Failed => "failed",
+    }
+}
+
+/// Quotes a CSV field if it holds a separator, quote or line break
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats a run report can be written in
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl ReportFormat {
+    /// Name of the file the report is written to in the output directory when no file is given,
+    /// or None if it goes to stdout
+    pub fn default_file_name(&self) -> Option<&'static str> {
+        match self {
+            ReportFormat::Json => None,
+            ReportFormat::Csv => Some("conversion-report.csv"),
+            ReportFormat::Html => Some("conversion-report.html"),
+        }
+    }
+}
+
+/// Writes a run report to a file, or to stdout if no path is given
+pub fn write_report(report: &RunReport, format: ReportFormat, path: Option<&Path>) -> Result<()> {
+    let contents = match format {
+        ReportFormat::Json => serde_json::to_vec_pretty(report)?,
+        ReportFormat::Csv => report.to_csv().into_bytes(),
+        ReportFormat::Html => report.to_html().into_bytes(),
+    };
+    match path {
+        Some(path) => {
+            read_only::check_writable(path)?;
+            fs::write(path, contents)?
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&contents)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
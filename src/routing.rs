@@ -0,0 +1,128 @@
+use crate::song_info::SongInfo;
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Metadata to sort converted songs into subfolders of the output directory by
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RouteBy {
+    /// Record label
+    Label,
+    /// Genre, the first one for songs tagged with several
+    Genre,
+}
+
+impl RouteBy {
+    /// Tag names the value goes by in different containers, in order of preference
+    fn tags(&self) -> &'static [&'static str] {
+        match self {
+            RouteBy::Label => &["label", "publisher", "organization"],
+            RouteBy::Genre => &["genre"],
+        }
+    }
+
+    /// Folder for songs that don't have the tag
+    fn fallback(&self) -> &'static str {
+        match self {
+            RouteBy::Label => "Unknown Label",
+            RouteBy::Genre => "Unknown Genre",
+        }
+    }
+
+    /// The value songs are routed by, of a tag that may hold several separated by semicolons,
+    /// as ID3 tags with several genres read
+    fn value<'a>(&self, tag: &'a str) -> &'a str {
+        match self {
+            RouteBy::Genre => tag.split(';').next().unwrap_or(tag),
+            RouteBy::Label => tag,
+        }
+    }
+}
+
+/// Folders songs are routed to in place of the one named after their tag, by part of the tag
+/// value, like {"house": "House", "techno": "Techno"} to route "Deep House" and "Tech House"
+/// into House
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteMap {
+    /// Folder by part of the tag value, in lower case
+    folders: BTreeMap<String, String>,
+}
+
+impl RouteMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let folders: BTreeMap<String, String> =
+            serde_json::from_slice(&contents).with_context(|| {
+                format!(
+                    "{} isn't an object of tag values to folders",
+                    path.display()
+                )
+            })?;
+        if let Some((value, _)) = folders.iter().find(|(_, folder)| folder.trim().is_empty()) {
+            return Err(anyhow!("The folder for {:?} is empty", value));
+        }
+        Ok(RouteMap {
+            folders: folders
+                .into_iter()
+                .map(|(value, folder)| (value.trim().to_lowercase(), folder))
+                .collect(),
+        })
+    }
+
+    /// The folder for a tag value: that of the value itself, or else of the longest part of it
+    /// that is mapped, so "tech house" can go elsewhere than "house"
+    fn folder(&self, value: &str) -> Option<&str> {
+        let value = value.to_lowercase();
+        self.folders
+            .get(&value)
+            .or_else(|| {
+                self.folders
+                    .iter()
+                    .filter(|(part, _)| value.contains(part.as_str()))
+                    .max_by_key(|(part, _)| part.len())
+                    .map(|(_, folder)| folder)
+            })
+            .map(String::as_str)
+    }
+}
+
+/// The directory a song's output goes in, by the folder mapped to its tag or else one named
+/// after the tag
+pub fn output_dir_for(
+    song: &SongInfo,
+    route_by: Option<RouteBy>,
+    route_map: &RouteMap,
+    output_dir: &Path,
+) -> PathBuf {
+    match route_by {
+        None => output_dir.to_path_buf(),
+        Some(route_by) => {
+            let folder = route_by
+                .tags()
+                .iter()
+                .find_map(|t| song.get_tag(t))
+                .map(|v| route_by.value(v).trim())
+                .filter(|v| !v.is_empty())
+                .map(|v| route_map.folder(v).unwrap_or(v))
+                .unwrap_or_else(|| route_by.fallback());
+            // A tag value must not be able to escape the output directory
+            output_dir.join(folder.replace(['/', '\\'], "-").trim_start_matches('.'))
+        }
+    }
+}
+
+/// The directory in the output that mirrors a song's directory under the source root. Songs
+/// outside the root go straight into the output directory.
+pub fn mirrored_dir(song: &SongInfo, source_root: &Path, output_dir: &Path) -> PathBuf {
+    match song
+        .get_song_path()
+        .parent()
+        .and_then(|dir| dir.strip_prefix(source_root).ok())
+    {
+        Some(relative) => output_dir.join(relative),
+        None => output_dir.to_path_buf(),
+    }
+}
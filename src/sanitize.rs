@@ -0,0 +1,95 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Characters FAT32 and exFAT don't allow in file names
+const FORBIDDEN: [char; 9] = ['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Makes output file and folder names safe to copy to FAT32 and exFAT USB sticks
+#[derive(Clone, Debug)]
+pub struct NameSanitizer {
+    /// Replaces characters those file systems don't allow
+    pub replacement: char,
+    /// Longest name, in the UTF-16 code units the file systems count in
+    pub max_length: usize,
+}
+
+impl Default for NameSanitizer {
+    fn default() -> Self {
+        NameSanitizer {
+            replacement: '_',
+            max_length: 255,
+        }
+    }
+}
+
+/// Cuts a string down to at most max UTF-16 code units, without splitting a character
+fn truncate_utf16(s: &str, max: usize) -> &str {
+    let mut length = 0;
+    for (i, c) in s.char_indices() {
+        length += c.len_utf16();
+        if length > max {
+            return &s[..i];
+        }
+    }
+    s
+}
+
+impl NameSanitizer {
+    /// Replaces forbidden and control characters, and the trailing dots and spaces Windows
+    /// drops, in a name
+    fn replace_forbidden(&self, name: &str) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if FORBIDDEN.contains(&c) || c.is_control() {
+                    self.replacement
+                } else {
+                    c
+                }
+            })
+            .collect();
+        name.trim_end_matches(['.', ' ']).to_string()
+    }
+
+    /// Sanitizes a name that will have `reserved` more code units appended to it, such as an
+    /// extension
+    pub fn name_with_reserve(&self, name: &str, reserved: usize) -> String {
+        let name = self.replace_forbidden(name);
+        let max = self.max_length.saturating_sub(reserved).max(1);
+        truncate_utf16(&name, max)
+            .trim_end_matches(['.', ' '])
+            .to_string()
+    }
+
+    /// Sanitizes a file or folder name
+    pub fn component(&self, name: &str) -> String {
+        self.name_with_reserve(name, 0)
+    }
+
+    /// Sanitizes a file name, keeping its extension when it has to be shortened
+    pub fn file_name(&self, file_name: &str) -> String {
+        match file_name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                let extension = self.replace_forbidden(extension);
+                format!(
+                    "{}.{}",
+                    self.name_with_reserve(stem, extension.encode_utf16().count() + 1),
+                    extension
+                )
+            }
+            _ => self.component(file_name),
+        }
+    }
+
+    /// Sanitizes the folders of a directory below a base directory, which is left as it is
+    pub fn dir(&self, base: &Path, dir: &Path) -> PathBuf {
+        match dir.strip_prefix(base) {
+            Ok(relative) => relative
+                .components()
+                .fold(base.to_path_buf(), |path, component| match component {
+                    Component::Normal(name) => path.join(self.component(&name.to_string_lossy())),
+                    other => path.join(other.as_os_str()),
+                }),
+            Err(_) => dir.to_path_buf(),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::converter::{self, ConversionJob, ConvertOptions};
+use crate::song_info::{self, SongInfo};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Something a scan found out, as it finds it out. Every file is first found, then probed, and
+/// if it is audio, classified.
+#[derive(Clone, Debug)]
+pub enum ScanEvent {
+    /// A file the converter would pick, while walking the directory
+    Found(PathBuf),
+    /// A file was probed, with the song, or None if it isn't audio
+    Probed(PathBuf, Option<SongInfo>),
+    /// What converting a song would do. Picking between explicit and clean versions or between
+    /// edits needs every song, so it is left to planning the whole batch.
+    Classified(ConversionJob),
+}
+
+/// The events of a scan running in the background, to show a library as it is scanned rather
+/// than after. Files are probed in parallel while the directory is still being walked. The
+/// iterator ends when every file is classified; dropping it stops the scan.
+pub struct ScanEvents {
+    events: Receiver<ScanEvent>,
+}
+
+impl Iterator for ScanEvents {
+    type Item = ScanEvent;
+
+    fn next(&mut self) -> Option<ScanEvent> {
+        self.events.recv().ok()
+    }
+}
+
+/// Starts scanning a directory in the background
+pub(crate) fn start(dir: PathBuf, options: Arc<ConvertOptions>) -> ScanEvents {
+    let (events, receiver) = mpsc::channel();
+    thread::spawn(move || scan(&dir, options, events));
+    ScanEvents { events: receiver }
+}
+
+fn scan(dir: &Path, options: Arc<ConvertOptions>, events: Sender<ScanEvent>) {
+    let (queue, files) = mpsc::channel::<PathBuf>();
+    let files = Arc::new(Mutex::new(files));
+    let probers: Vec<_> = (0..options.probe_jobs.max(1))
+        .map(|_| {
+            let files = files.clone();
+            let events = events.clone();
+            let options = options.clone();
+            thread::spawn(move || loop {
+                let file = match files.lock().unwrap().recv() {
+                    Ok(file) => file,
+                    Err(_) => break,
+                };
+                if !probe(file, &options, &events) {
+                    break;
+                }
+            })
+        })
+        .collect();
+    let walked = converter::walk_files(dir, &mut |file| {
+        let picked = match &options.playlist {
+            Some(playlist) => playlist.contains(&file),
+            None => true,
+        };
+        // Stops walking once nobody listens to the events anymore
+        !picked || (events.send(ScanEvent::Found(file.clone())).is_ok() && queue.send(file).is_ok())
+    });
+    if let Err(e) = walked {
+        tracing::error!(?e, "Could not scan {}", dir.display());
+    }
+    drop(queue);
+    for prober in probers {
+        let _ = prober.join();
+    }
+    if let Some(cache) = &options.probe_cache {
+        if let Err(e) = cache.save() {
+            tracing::warn!(?e, "Could not save probe cache");
+        }
+    }
+}
+
+/// Probes and classifies a file, returning whether anyone still listens to the events
+fn probe(file: PathBuf, options: &ConvertOptions, events: &Sender<ScanEvent>) -> bool {
+    let song = song_info::from_file_cached(
+        file.as_path(),
+        options.probe_cache.as_ref(),
+        options.probe_backend,
+    )
+    .ok();
+    if events.send(ScanEvent::Probed(file, song.clone())).is_err() {
+        return false;
+    }
+    match song {
+        Some(song) => events
+            .send(ScanEvent::Classified(ConversionJob {
+                action: converter::plan_song(&song, options),
+                output_format: converter::output_format(&song, options),
+                song,
+            }))
+            .is_ok(),
+        None => true,
+    }
+}
@@ -0,0 +1,115 @@
+use crate::converter::stderr_excerpt;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Folder rehearsal versions are written to in the output directory, unless given another
+pub const REHEARSAL_DIR: &str = "Rehearsal";
+
+/// Tool separating vocals from the rest of a song
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SeparationTool {
+    /// Meta's Demucs, with its default model
+    Demucs,
+    /// Deezer's Spleeter, with its 2 stems model
+    Spleeter,
+}
+
+/// A version of a song with part of it separated out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Part {
+    Acapella,
+    Instrumental,
+}
+
+impl Part {
+    /// Name the part's files and tags are suffixed with
+    pub fn name(&self) -> &'static str {
+        match self {
+            Part::Acapella => "Acapella",
+            Part::Instrumental => "Instrumental",
+        }
+    }
+}
+
+impl SeparationTool {
+    fn program(&self) -> &'static str {
+        match self {
+            SeparationTool::Demucs => "demucs",
+            SeparationTool::Spleeter => "spleeter",
+        }
+    }
+
+    /// Whether the tool is installed
+    pub fn check(&self) -> Result<()> {
+        let found = Command::new(self.program())
+            .arg("--help")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if found {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} isn't installed, install it to write rehearsal versions",
+                self.program()
+            ))
+        }
+    }
+
+    /// Separates a song into an acapella and an instrumental, written as WAV files into `dir`.
+    /// Returns each part with its file.
+    pub fn separate(&self, song_path: &Path, dir: &Path) -> Result<Vec<(Part, PathBuf)>> {
+        let mut command = Command::new(self.program());
+        match self {
+            SeparationTool::Demucs => command
+                .arg("--two-stems")
+                .arg("vocals")
+                .arg("-n")
+                .arg("htdemucs")
+                .arg("-o")
+                .arg(dir)
+                .arg(song_path),
+            SeparationTool::Spleeter => command
+                .arg("separate")
+                .arg("-p")
+                .arg("spleeter:2stems")
+                .arg("-o")
+                .arg(dir)
+                .arg(song_path),
+        };
+        let output = command.output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed to separate {}: {}",
+                self.program(),
+                song_path.display(),
+                stderr_excerpt(&output.stderr)
+            ));
+        }
+        // Both write a folder named after the song, Demucs inside one named after its model
+        let stem = song_path
+            .file_stem()
+            .ok_or_else(|| anyhow!("Song path has no file name: {:?}", song_path))?;
+        let (song_dir, instrumental) = match self {
+            SeparationTool::Demucs => (dir.join("htdemucs").join(stem), "no_vocals.wav"),
+            SeparationTool::Spleeter => (dir.join(stem), "accompaniment.wav"),
+        };
+        let parts = vec![
+            (Part::Acapella, song_dir.join("vocals.wav")),
+            (Part::Instrumental, song_dir.join(instrumental)),
+        ];
+        for (_, path) in parts.iter() {
+            if !path.is_file() {
+                return Err(anyhow!(
+                    "{} didn't write {}",
+                    self.program(),
+                    path.display()
+                ));
+            }
+        }
+        Ok(parts)
+    }
+}
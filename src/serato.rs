@@ -0,0 +1,172 @@
+use crate::exporters::{self, ExportedFile, Exporter, Playlist, TrackRecord};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Folder crates are kept in, in the _Serato_ folder at the root of a drive
+pub const SUBCRATES_DIR: &str = "_Serato_/Subcrates";
+
+/// Folder Serato keeps its library in, at the root of each drive with tracks in it
+const SERATO_DIR: &str = "_Serato_";
+
+/// What Serato puts between the names of a crate and the crates it is in, in its file name
+const SUBCRATE_SEPARATOR: &str = "%%";
+
+/// Version string every crate file starts with
+const CRATE_VERSION: &str = "1.0/Serato ScratchLive Crate";
+
+/// Serato DJ crates, one per playlist. Serato keeps crates in _Serato_/Subcrates at the root of
+/// the drive holding the tracks, which is where the exported folder has to be copied.
+pub struct SeratoCrates;
+
+impl Exporter for SeratoCrates {
+    fn name(&self) -> &'static str {
+        "serato"
+    }
+
+    fn description(&self) -> &'static str {
+        "a Serato crate per playlist, in _Serato_/Subcrates"
+    }
+
+    fn export(&self, tracks: &[TrackRecord], playlists: &[Playlist]) -> Result<Vec<ExportedFile>> {
+        Ok(playlists
+            .iter()
+            .map(|playlist| {
+                let paths: Vec<&Path> = exporters::playlist_tracks(playlist, tracks)
+                    .map(|t| t.path.as_path())
+                    .collect();
+                ExportedFile {
+                    path: Path::new(SUBCRATES_DIR)
+                        .join(exporters::file_name(&playlist.name, "crate")),
+                    contents: crate_file(&paths),
+                }
+            })
+            .collect())
+    }
+}
+
+/// A crate file listing tracks. Crates are a series of fields, each a 4 letter tag, the length
+/// of its contents as a big endian u32, and the contents: UTF-16BE text, or more fields.
+pub fn crate_file(paths: &[&Path]) -> Vec<u8> {
+    let mut contents = field(b"vrsn", &utf16(CRATE_VERSION));
+    for path in paths {
+        let track = field(b"ptrk", &utf16(&crate_path(path)));
+        contents.extend(field(b"otrk", &track));
+    }
+    contents
+}
+
+/// How a crate refers to a track: its path from the root of its drive, without a leading
+/// separator or drive letter, separated by slashes
+fn crate_path(path: &Path) -> String {
+    let components: Vec<String> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            Component::ParentDir => Some("..".to_string()),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => None,
+        })
+        .collect();
+    components.join("/")
+}
+
+/// A crate read from a crate file
+#[derive(Clone, Debug)]
+pub struct Crate {
+    /// The crate, with the crates it is in, like "Gigs/Prep"
+    pub name: String,
+    /// Where the tracks are on this computer, in crate order
+    pub tracks: Vec<PathBuf>,
+}
+
+/// Reads a crate file. Tracks are stored relative to the root of a drive: the one whose
+/// _Serato_ folder has the crate for external drives, and the root of the filesystem for the
+/// library in the home folder. Each is resolved against whichever of those has it.
+pub fn read_crate(path: &Path) -> Result<Crate> {
+    let data = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let filesystem_root = path
+        .ancestors()
+        .last()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let drive_root = path
+        .ancestors()
+        .find(|a| a.file_name().is_some_and(|n| n == SERATO_DIR))
+        .and_then(Path::parent)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| filesystem_root.clone());
+    let resolve = |track: &str| {
+        let on_drive = drive_root.join(track);
+        if on_drive.exists() {
+            return on_drive;
+        }
+        let on_filesystem = filesystem_root.join(track);
+        if on_filesystem.exists() {
+            on_filesystem
+        } else {
+            on_drive
+        }
+    };
+    let mut tracks = vec![];
+    for (tag, contents) in
+        fields(&data).with_context(|| format!("{} isn't a crate", path.display()))?
+    {
+        if tag != b"otrk" {
+            continue;
+        }
+        let track_path = fields(contents)?
+            .into_iter()
+            .find(|(tag, _)| tag == b"ptrk")
+            .map(|(_, path)| from_utf16(path));
+        match track_path {
+            Some(track_path) => tracks.push(resolve(&track_path)),
+            None => tracing::warn!(?path, "Leaving out a crate entry without a path"),
+        }
+    }
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().replace(SUBCRATE_SEPARATOR, "/"))
+        .unwrap_or_default();
+    Ok(Crate { name, tracks })
+}
+
+/// Splits crate data into its fields' tags and contents
+fn fields(data: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    let mut fields = vec![];
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            return Err(anyhow!("Field header is cut off"));
+        }
+        let length = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let contents = rest
+            .get(8..8 + length)
+            .ok_or_else(|| anyhow!("Field {} is cut off", String::from_utf8_lossy(&rest[..4])))?;
+        fields.push((&rest[..4], contents));
+        rest = &rest[8 + length..];
+    }
+    Ok(fields)
+}
+
+fn from_utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn field(tag: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+    let mut field = Vec::with_capacity(8 + contents.len());
+    field.extend_from_slice(tag);
+    field.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+    field.extend_from_slice(contents);
+    field
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .flat_map(|unit| unit.to_be_bytes())
+        .collect()
+}
@@ -0,0 +1,331 @@
+use crate::exporters::TrackRecord;
+use crate::song_info::{self, SongInfo};
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Description of the GEOB frame Serato keeps cue points and loops in
+const MARKERS2_DESCRIPTION: &str = "Serato Markers2";
+/// Tags the same data is kept in by formats without ID3: a Vorbis comment in FLAC and Ogg, and
+/// the name of a freeform atom in MP4
+const MARKERS2_TAGS: [&str; 2] = ["SERATO_MARKERS_V2", "markersv2"];
+
+/// Whether a marker is a cue point or a loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarkerKind {
+    Cue,
+    Loop,
+}
+
+/// A hot cue or saved loop set in Serato
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    /// Which of the pads it is on, from 0
+    pub index: u8,
+    /// Where it starts, in seconds
+    pub start: f64,
+    /// Where a loop ends, in seconds
+    pub end: Option<f64>,
+    pub name: String,
+    pub color: (u8, u8, u8),
+}
+
+/// The hot cues and loops Serato saved in a song, empty if it has none or they can't be read
+pub fn read(song: &SongInfo) -> Vec<Marker> {
+    let path = song.get_song_path();
+    let markers = match markers2_payload(song) {
+        Ok(Some(payload)) => parse_markers2(&payload),
+        Ok(None) => return vec![],
+        Err(e) => Err(e),
+    };
+    markers.unwrap_or_else(|e| {
+        tracing::warn!(?path, ?e, "Could not read Serato cue points");
+        vec![]
+    })
+}
+
+/// The hot cues and loops of a converted track: those in the converted file, or else those of
+/// its source, as ffmpeg leaves GEOB frames out when converting
+pub fn of_track(track: &TrackRecord) -> Vec<Marker> {
+    let markers = read(&track.song);
+    if !markers.is_empty() {
+        return markers;
+    }
+    let source = &track.source;
+    if !source.is_file() {
+        return vec![];
+    }
+    match song_info::from_file(source) {
+        Ok(song) => read(&song),
+        Err(e) => {
+            tracing::warn!(?source, ?e, "Could not read the source's Serato cue points");
+            vec![]
+        }
+    }
+}
+
+/// The Markers2 data of a song, starting with its version and base64 encoded: from an ID3 GEOB
+/// frame, or from a tag that base64 encodes it again behind a GEOB-like header
+fn markers2_payload(song: &SongInfo) -> Result<Option<Vec<u8>>> {
+    let tagged = song
+        .get_tags()
+        .as_ref()
+        .and_then(|tags| tags.as_object())
+        .and_then(|tags| {
+            tags.iter()
+                .find(|(key, _)| MARKERS2_TAGS.iter().any(|t| t.eq_ignore_ascii_case(key)))
+                .and_then(|(_, value)| value.as_str())
+        });
+    if let Some(tagged) = tagged {
+        let envelope = base64_decode(tagged)?;
+        let header = format!("{}\0", MARKERS2_DESCRIPTION);
+        let start = envelope
+            .windows(header.len())
+            .position(|w| w == header.as_bytes())
+            .ok_or_else(|| anyhow!("Markers2 tag has no {} header", MARKERS2_DESCRIPTION))?;
+        return Ok(Some(envelope[start + header.len()..].to_vec()));
+    }
+    Ok(read_id3_tag(song.get_song_path())?.and_then(|tag| geob_markers2(&tag)))
+}
+
+/// The ID3v2 tag of an MP3, or of an AIFF or WAV file, which keep it in a chunk. Only the
+/// headers of the chunks before it are read, not the audio.
+fn read_id3_tag(path: &Path) -> Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 12];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    if header.starts_with(b"ID3") {
+        let size = 10 + syncsafe(&header[6..10]);
+        let mut tag = vec![0u8; size];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut tag)?;
+        return Ok(Some(tag));
+    }
+    // IFF chunks have big endian sizes, RIFF chunks little endian ones, and both are padded to
+    // an even length
+    let big_endian = match &header[..4] {
+        b"FORM" => true,
+        b"RIFF" => false,
+        _ => return Ok(None),
+    };
+    let mut chunk = [0u8; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let size_bytes = [chunk[4], chunk[5], chunk[6], chunk[7]];
+        let size = if big_endian {
+            u32::from_be_bytes(size_bytes)
+        } else {
+            u32::from_le_bytes(size_bytes)
+        } as u64;
+        if chunk[..4].eq_ignore_ascii_case(b"ID3 ") {
+            let mut tag = vec![];
+            file.take(size).read_to_end(&mut tag)?;
+            return Ok(Some(tag));
+        }
+        file.seek(SeekFrom::Current((size + size % 2) as i64))?;
+    }
+    Ok(None)
+}
+
+/// The data of the Markers2 GEOB frame in an ID3v2.3 or 2.4 tag
+fn geob_markers2(tag: &[u8]) -> Option<Vec<u8>> {
+    if !tag.starts_with(b"ID3") {
+        return None;
+    }
+    let version = *tag.get(3)?;
+    let flags = *tag.get(5)?;
+    let size = syncsafe(tag.get(6..10)?);
+    let end = (10 + size).min(tag.len());
+    let mut offset = 10;
+    if flags & 0x40 != 0 {
+        // The extended header counts itself in 2.4 but not in 2.3
+        let extended = tag.get(10..14)?;
+        offset += match version {
+            4 => syncsafe(extended),
+            _ => {
+                4 + u32::from_be_bytes([extended[0], extended[1], extended[2], extended[3]])
+                    as usize
+            }
+        };
+    }
+    while offset + 10 <= end {
+        let id = &tag[offset..offset + 4];
+        if id[0] == 0 {
+            break;
+        }
+        let size_bytes = &tag[offset + 4..offset + 8];
+        let frame_size = match version {
+            4 => syncsafe(size_bytes),
+            _ => u32::from_be_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]])
+                as usize,
+        };
+        let contents = tag.get(offset + 10..(offset + 10 + frame_size).min(end))?;
+        if id == b"GEOB" {
+            if let Some(data) = geob_data(contents, MARKERS2_DESCRIPTION) {
+                return Some(data.to_vec());
+            }
+        }
+        offset += 10 + frame_size;
+    }
+    None
+}
+
+/// The object in a GEOB frame, if it has the description: after an encoding byte, a MIME type,
+/// a file name and the description, each ending in a zero
+fn geob_data<'a>(frame: &'a [u8], description: &str) -> Option<&'a [u8]> {
+    let encoding = *frame.first()?;
+    let mut rest = &frame[1..];
+    rest = &rest[rest.iter().position(|b| *b == 0)? + 1..];
+    let mut strings = vec![];
+    for _ in 0..2 {
+        let (string, after) = match encoding {
+            // UTF-16 strings end in a zero code unit
+            1 | 2 => {
+                let end = rest.chunks_exact(2).position(|unit| unit == [0, 0])? * 2;
+                (&rest[..end], &rest[end + 2..])
+            }
+            _ => {
+                let end = rest.iter().position(|b| *b == 0)?;
+                (&rest[..end], &rest[end + 1..])
+            }
+        };
+        strings.push(string);
+        rest = after;
+    }
+    let matches = match encoding {
+        1 | 2 => {
+            let units: Vec<u16> = strings[1]
+                .chunks_exact(2)
+                .map(|b| match encoding {
+                    2 => u16::from_be_bytes([b[0], b[1]]),
+                    _ => u16::from_le_bytes([b[0], b[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units).trim_start_matches('\u{feff}') == description
+        }
+        _ => strings[1] == description.as_bytes(),
+    };
+    if matches {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |size, byte| size << 7 | (*byte & 0x7f) as usize)
+}
+
+/// Parses Markers2 data: a version, then base64 encoded entries, each a name ending in a zero,
+/// the length of its data as a big endian u32, and the data. Entries other than cues and loops,
+/// such as the track color and BPM lock, are left out.
+fn parse_markers2(payload: &[u8]) -> Result<Vec<Marker>> {
+    let text = payload
+        .get(2..)
+        .ok_or_else(|| anyhow!("Markers2 data is empty"))?;
+    let text = &text[..text.iter().position(|b| *b == 0).unwrap_or(text.len())];
+    let data = base64_decode(&String::from_utf8_lossy(text))?;
+    let mut entries = data
+        .get(2..)
+        .ok_or_else(|| anyhow!("Markers2 entries are cut off"))?;
+    let mut markers = vec![];
+    while let Some(name_end) = entries.iter().position(|b| *b == 0) {
+        if name_end == 0 {
+            break;
+        }
+        let name = &entries[..name_end];
+        let length_bytes = entries
+            .get(name_end + 1..name_end + 5)
+            .ok_or_else(|| anyhow!("Markers2 entry is cut off"))?;
+        let length = u32::from_be_bytes([
+            length_bytes[0],
+            length_bytes[1],
+            length_bytes[2],
+            length_bytes[3],
+        ]) as usize;
+        let body = entries
+            .get(name_end + 5..name_end + 5 + length)
+            .ok_or_else(|| anyhow!("Markers2 entry is cut off"))?;
+        match name {
+            b"CUE" => markers.push(cue(body)?),
+            b"LOOP" => markers.push(saved_loop(body)?),
+            _ => (),
+        }
+        entries = &entries[name_end + 5 + length..];
+    }
+    Ok(markers)
+}
+
+/// A CUE entry: a zero, the index, the position in milliseconds, a zero, the RGB color, two
+/// zeros and the name
+fn cue(body: &[u8]) -> Result<Marker> {
+    if body.len() < 12 {
+        return Err(anyhow!("CUE entry is cut off"));
+    }
+    Ok(Marker {
+        kind: MarkerKind::Cue,
+        index: body[1],
+        start: milliseconds(&body[2..6]),
+        end: None,
+        name: marker_name(&body[12..]),
+        color: (body[7], body[8], body[9]),
+    })
+}
+
+/// A LOOP entry: a zero, the index, the start and end in milliseconds, four 0xff bytes, the
+/// color as ARGB, whether it is locked and the name
+fn saved_loop(body: &[u8]) -> Result<Marker> {
+    if body.len() < 19 {
+        return Err(anyhow!("LOOP entry is cut off"));
+    }
+    Ok(Marker {
+        kind: MarkerKind::Loop,
+        index: body[1],
+        start: milliseconds(&body[2..6]),
+        end: Some(milliseconds(&body[6..10])),
+        name: marker_name(&body[19..]),
+        color: (body[15], body[16], body[17]),
+    })
+}
+
+fn milliseconds(bytes: &[u8]) -> f64 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64 / 1000.0
+}
+
+fn marker_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Decodes base64, skipping line breaks and tolerating missing padding, as Serato leaves it out
+fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut n_bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            c => return Err(anyhow!("{:?} isn't base64", c as char)),
+        };
+        buffer = buffer << 6 | value as u32;
+        n_bits += 6;
+        if n_bits >= 8 {
+            n_bits -= 8;
+            bytes.push((buffer >> n_bits) as u8);
+            buffer &= (1 << n_bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
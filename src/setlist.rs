@@ -0,0 +1,129 @@
+use crate::links::SourceIndex;
+use crate::rekordbox_playlist::PlaylistSelection;
+use crate::report::csv_field;
+use crate::song_info::{self, ProbeBackend, SongInfo};
+use crate::state::ConversionState;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// How a set list is printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SetlistFormat {
+    /// A numbered line per track, for posting
+    Text,
+    /// A row per track, for club reporting
+    Csv,
+}
+
+/// A track played in a set, with what club reporting asks for, read from the tags of the source
+/// it was converted from
+#[derive(Clone, Debug, Default)]
+pub struct SetlistEntry {
+    pub artist: String,
+    pub title: String,
+    pub label: String,
+    pub isrc: String,
+    /// The source the played file was converted from, if it was converted into the output
+    /// directory
+    pub source: Option<PathBuf>,
+    /// The file Rekordbox played
+    pub played: PathBuf,
+}
+
+impl SetlistEntry {
+    fn new(played: &Path, source: Option<&Path>, song: Option<&SongInfo>) -> Self {
+        let tag = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| song?.get_tag(name))
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let title = match tag(&["title"]) {
+            title if title.is_empty() => played
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            title => title,
+        };
+        SetlistEntry {
+            artist: tag(&["artist"]),
+            title,
+            label: tag(&["label", "publisher", "organization"]),
+            isrc: tag(&["isrc", "tsrc"]),
+            source: source.map(Path::to_path_buf),
+            played: played.to_path_buf(),
+        }
+    }
+}
+
+/// The tracks of a history playlist, in the order they were played. Each played file is traced
+/// back to the source it was converted from through the state of the output directory, whose
+/// tags are read, as those of the converted file may have been cleaned up or left out. Files
+/// that weren't converted into the output directory are read as they are.
+pub fn setlist(
+    output_dir: &Path,
+    history: &PlaylistSelection,
+    probe_backend: ProbeBackend,
+) -> Result<Vec<SetlistEntry>> {
+    let state = ConversionState::load(output_dir)?;
+    let index = SourceIndex::new(&state);
+    let mut entries = vec![];
+    for played in history.locations.iter() {
+        let source = index.source(played).filter(|source| source.is_file());
+        if source.is_none() {
+            tracing::warn!(
+                ?played,
+                "Played track wasn't converted into the output directory"
+            );
+        }
+        let read = source.unwrap_or(played);
+        let song = match song_info::from_file_cached(read, None, probe_backend) {
+            Ok(song) => Some(song),
+            Err(e) => {
+                tracing::warn!(path = ?read, ?e, "Could not read the tags of a played track");
+                None
+            }
+        };
+        entries.push(SetlistEntry::new(played, source, song.as_ref()));
+    }
+    Ok(entries)
+}
+
+/// A set list as numbered lines like "1. Artist – Title – Label – ISRC", leaving out what
+/// isn't tagged, or as CSV
+pub fn format(entries: &[SetlistEntry], format: SetlistFormat) -> String {
+    let mut out = String::new();
+    match format {
+        SetlistFormat::Text => {
+            for (i, entry) in entries.iter().enumerate() {
+                let fields: Vec<&str> = [&entry.artist, &entry.title, &entry.label, &entry.isrc]
+                    .iter()
+                    .map(|f| f.as_str())
+                    .filter(|f| !f.is_empty())
+                    .collect();
+                let _ = writeln!(out, "{}. {}", i + 1, fields.join(" – "));
+            }
+        }
+        SetlistFormat::Csv => {
+            out.push_str("position,artist,title,label,isrc,source\n");
+            for (i, entry) in entries.iter().enumerate() {
+                let source = entry.source.as_deref().unwrap_or(&entry.played);
+                let _ = writeln!(
+                    out,
+                    "{},{},{},{},{},{}",
+                    i + 1,
+                    csv_field(&entry.artist),
+                    csv_field(&entry.title),
+                    csv_field(&entry.label),
+                    csv_field(&entry.isrc),
+                    csv_field(&source.to_string_lossy()),
+                );
+            }
+        }
+    }
+    out
+}
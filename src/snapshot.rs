@@ -0,0 +1,192 @@
+use crate::analyzers::TrackAnalysis;
+use crate::arrow_ipc::{self, Column};
+use crate::key;
+use crate::report;
+use crate::song_info::{AudioFormatType, SongInfo};
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Tags given a column of their own, by column name and the tag names they are read from, in
+/// order. Every tag is in the tags column as well.
+const TAG_COLUMNS: [(&str, &[&str]); 10] = [
+    ("title", &["title"]),
+    ("artist", &["artist"]),
+    ("album", &["album"]),
+    ("album_artist", &["album_artist", "albumartist"]),
+    ("genre", &["genre"]),
+    ("label", &["label", "publisher", "organization"]),
+    ("date", &["date", "year"]),
+    ("track", &["track"]),
+    ("comment", &["comment"]),
+    ("bpm_tag", &["bpm", "tbpm"]),
+];
+
+/// A table of songs, a row per song, with everything probing found about it and what the
+/// analyzers measured, if they ran
+pub fn columns(songs: &[(SongInfo, Option<TrackAnalysis>)]) -> Vec<(&'static str, Column)> {
+    let strings = |value: &dyn Fn(&SongInfo, Option<&TrackAnalysis>) -> Option<String>| {
+        Column::Utf8(
+            songs
+                .iter()
+                .map(|(song, analysis)| value(song, analysis.as_ref()))
+                .collect(),
+        )
+    };
+    let floats = |value: &dyn Fn(&TrackAnalysis) -> Option<f64>| {
+        Column::Float64(
+            songs
+                .iter()
+                .map(|(_, analysis)| analysis.as_ref().and_then(value))
+                .collect(),
+        )
+    };
+    let ints = |value: &dyn Fn(&SongInfo) -> Option<i64>| {
+        Column::Int64(songs.iter().map(|(song, _)| value(song)).collect())
+    };
+    let bools = |value: &dyn Fn(&SongInfo) -> bool| {
+        Column::Bool(songs.iter().map(|(song, _)| Some(value(song))).collect())
+    };
+    let metadata: Vec<Option<fs::Metadata>> = songs
+        .iter()
+        .map(|(song, _)| fs::metadata(song.get_song_path()).ok())
+        .collect();
+    let is_lossless = |song: &SongInfo| matches!(song.get_format(), AudioFormatType::Lossless(_));
+    let is_lossy = |song: &SongInfo| matches!(song.get_format(), AudioFormatType::Lossy(_));
+
+    let mut columns = vec![
+        (
+            "path",
+            strings(&|song, _| Some(song.get_song_path().display().to_string())),
+        ),
+        (
+            "format",
+            strings(&|song, _| Some(report::format_name(song))),
+        ),
+        (
+            "codec",
+            strings(&|song, _| Some(song.get_codec().to_string())),
+        ),
+        (
+            "lossless",
+            Column::Bool(
+                songs
+                    .iter()
+                    .map(|(song, _)| match song.get_format() {
+                        AudioFormatType::Unsupported => None,
+                        _ => Some(is_lossless(song)),
+                    })
+                    .collect(),
+            ),
+        ),
+        (
+            "rekordbox_format",
+            bools(&|song| song.is_rekordbox_format()),
+        ),
+        (
+            "sample_rate",
+            ints(&|song| Some(*song.get_sample_rate() as i64).filter(|rate| *rate > 0)),
+        ),
+        (
+            "bit_depth",
+            ints(&|song| Some(*song.get_bit_info() as i64).filter(|_| is_lossless(song))),
+        ),
+        (
+            "bit_rate",
+            ints(&|song| Some(*song.get_bit_info() as i64).filter(|_| is_lossy(song))),
+        ),
+        (
+            "duration",
+            Column::Float64(
+                songs
+                    .iter()
+                    .map(|(song, _)| Some(song.get_duration()).filter(|d| *d > 0.0))
+                    .collect(),
+            ),
+        ),
+        (
+            "size",
+            Column::Int64(
+                metadata
+                    .iter()
+                    .map(|m| m.as_ref().map(|m| m.len() as i64))
+                    .collect(),
+            ),
+        ),
+        (
+            "modified",
+            Column::Timestamp(
+                metadata
+                    .iter()
+                    .map(|m| {
+                        let modified = m.as_ref()?.modified().ok()?;
+                        Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+                    })
+                    .collect(),
+            ),
+        ),
+        ("has_artwork", bools(&|song| song.has_artwork())),
+        (
+            "artwork_codec",
+            strings(&|song, _| song.get_artwork_codec().map(str::to_string)),
+        ),
+        ("is_stem", bools(&|song| song.is_stem())),
+    ];
+    for (column, tags) in TAG_COLUMNS {
+        columns.push((
+            column,
+            strings(&|song, _| {
+                tags.iter()
+                    .find_map(|tag| song.get_tag(tag))
+                    .map(str::to_string)
+            }),
+        ));
+    }
+    columns.extend([
+        (
+            "key_tag",
+            strings(&|song, _| key::tagged_key(song).map(str::to_string)),
+        ),
+        (
+            "tags",
+            strings(&|song, _| song.get_tags().as_ref().map(|tags| tags.to_string())),
+        ),
+        ("loudness", floats(&|analysis| analysis.loudness)),
+        ("peak", floats(&|analysis| analysis.peak)),
+        ("cutoff_hz", floats(&|analysis| analysis.cutoff_hz)),
+        ("bpm", floats(&|analysis| analysis.bpm)),
+        ("onset_density", floats(&|analysis| analysis.onset_density)),
+        (
+            "key",
+            strings(&|_, analysis| analysis.and_then(|a| a.key.clone())),
+        ),
+        (
+            "key_confidence",
+            floats(&|analysis| analysis.key_confidence),
+        ),
+        (
+            "fingerprint",
+            strings(&|_, analysis| analysis.and_then(|a| a.fingerprint.clone())),
+        ),
+    ]);
+    columns
+}
+
+/// Writes a table of songs as an Arrow IPC file, to load into Polars with `pl.read_ipc` or
+/// into pandas with `pd.read_feather`
+pub fn write(path: &Path, songs: &[(SongInfo, Option<TrackAnalysis>)]) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if extension == "parquet" {
+        return Err(anyhow!(
+            "Snapshots are written as Arrow IPC, name the file .arrow or .feather and convert \
+             it with pl.read_ipc(...).write_parquet(...) if you need Parquet"
+        ));
+    }
+    arrow_ipc::write(path, &columns(songs))?;
+    tracing::info!(n_songs = songs.len(), ?path, "Wrote library snapshot");
+    Ok(())
+}
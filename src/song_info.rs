@@ -1,4 +1,7 @@
+use crate::native_probe;
+use crate::probe_cache::ProbeCache;
 use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -20,6 +23,11 @@ pub enum SupportedAudioFormat {
     MP3,
     OGG,
     AAC,
+    ALAC,
+    OPUS,
+    WAVPACK,
+    APE,
+    TTA,
 }
 
 impl FromStr for SupportedAudioFormat {
@@ -33,6 +41,12 @@ impl FromStr for SupportedAudioFormat {
             "mp3" => Ok(SupportedAudioFormat::MP3),
             "ogg" => Ok(SupportedAudioFormat::OGG),
             "aac" => Ok(SupportedAudioFormat::AAC),
+            "alac" => Ok(SupportedAudioFormat::ALAC),
+            "opus" => Ok(SupportedAudioFormat::OPUS),
+            // ffprobe calls the WavPack container wv and the codec wavpack
+            "wv" | "wavpack" => Ok(SupportedAudioFormat::WAVPACK),
+            "ape" => Ok(SupportedAudioFormat::APE),
+            "tta" => Ok(SupportedAudioFormat::TTA),
             _ => Err(anyhow!("Not a supported file format")),
         }
     }
@@ -48,12 +62,17 @@ impl std::fmt::Display for SupportedAudioFormat {
 impl From<SupportedAudioFormat> for AudioFormatType {
     fn from(value: SupportedAudioFormat) -> Self {
         match &value {
-            SupportedAudioFormat::AIFF | SupportedAudioFormat::FLAC | SupportedAudioFormat::WAV => {
-                AudioFormatType::Lossless(value)
-            }
-            SupportedAudioFormat::MP3 | SupportedAudioFormat::OGG | SupportedAudioFormat::AAC => {
-                AudioFormatType::Lossy(value)
-            }
+            SupportedAudioFormat::AIFF
+            | SupportedAudioFormat::FLAC
+            | SupportedAudioFormat::WAV
+            | SupportedAudioFormat::ALAC
+            | SupportedAudioFormat::WAVPACK
+            | SupportedAudioFormat::APE
+            | SupportedAudioFormat::TTA => AudioFormatType::Lossless(value),
+            SupportedAudioFormat::MP3
+            | SupportedAudioFormat::OGG
+            | SupportedAudioFormat::AAC
+            | SupportedAudioFormat::OPUS => AudioFormatType::Lossy(value),
         }
     }
 }
@@ -83,6 +102,9 @@ pub struct SongInfo {
     sample_rate: usize,
     bit_info: usize,
     tags: Option<serde_json::Value>,
+    audio_streams: usize,
+    artwork_codec: Option<String>,
+    duration: f64,
 }
 
 /// Helper struct that represents initial read from ffprobe
@@ -97,6 +119,10 @@ struct Probe {
 struct ProbeStream {
     codec_name: String,
     codec_type: String,
+    // Actual bit depth of lossless streams, e.g. 24 for 24 bit audio decoded to s32
+    #[serde(default)]
+    #[serde(deserialize_with = "from_string")]
+    bits_per_raw_sample: Option<usize>,
     #[serde(default)]
     #[serde(deserialize_with = "from_string")]
     sample_rate: Option<usize>,
@@ -117,15 +143,25 @@ where
 {
     let mut s: String = Deserialize::deserialize(deserializer)?;
     s = s.replace("s", "");
-    // See if we can parse the sample_fmt to get the bit depth. If not return 0.
-    Ok(s.parse::<usize>().ok())
+    // See if we can parse the sample_fmt to get the bit depth. If not return 0. Planar sample
+    // formats, such as ALAC's s16p, end in a p.
+    Ok(s.trim_end_matches('p').parse::<usize>().ok())
+}
+
+/// Same as from_string, for values with a fractional part such as the duration
+fn from_float_string<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    Ok(s.parse::<f64>().ok())
 }
 
 fn from_format_name_string<'de, D>(deserializer: D) -> Result<AudioFormatType, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let mut s: String = Deserialize::deserialize(deserializer)?;
+    let s: String = Deserialize::deserialize(deserializer)?;
     // See if we can parse the sample_fmt to get the bit depth. If not return 0.
     s.parse::<AudioFormatType>().map_err(serde::de::Error::custom)
 }
@@ -137,11 +173,14 @@ struct ProbeFormat {
     format_name: AudioFormatType,
     #[serde(default)]
     tags: Option<serde_json::Value>,
+    #[serde(default)]
+    #[serde(deserialize_with = "from_float_string")]
+    duration: Option<f64>,
 }
 
 /// Executes the ffprobe command to get the stream and format info.
 #[tracing::instrument(level = "info", ret)]
-fn run_ffprobe(path: &Path) -> Result<Probe> {
+fn run_ffprobe(path: &Path) -> Result<serde_json::Value> {
     // Run ffprobe
     let output = Command::new("ffprobe")
         .arg(path.to_path_buf())
@@ -154,29 +193,106 @@ fn run_ffprobe(path: &Path) -> Result<Probe> {
     Ok(serde_json::from_slice(&output.stdout)?)
 }
 
+/// Whether a path is named like a Native Instruments stem file
+fn is_stem_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| {
+            let n = n.to_lowercase();
+            n.ends_with(".stem.mp4") || n.ends_with(".stem.m4a")
+        })
+        .unwrap_or(false)
+}
+
+/// How files are read to find out their format and tags
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ProbeBackend {
+    /// Run ffprobe on every file
+    Ffprobe,
+    /// Read files in-process, which is much faster, falling back to ffprobe for formats that
+    /// can't be read that way and for stem files
+    Native,
+}
+
 /// Initializes a Song struct
 pub fn from_file(path: &Path) -> Result<SongInfo> {
-    let probe_result = run_ffprobe(path)?;
+    from_file_cached(path, None, ProbeBackend::Ffprobe)
+}
+
+/// Initializes a Song struct, reusing what was found out about the file last time if the cache
+/// has it and the file hasn't changed since
+pub fn from_file_cached(
+    path: &Path,
+    cache: Option<&ProbeCache>,
+    backend: ProbeBackend,
+) -> Result<SongInfo> {
+    let output = match cache.and_then(|c| c.get(path)) {
+        Some(output) => output,
+        None => {
+            // Stem files need every audio stream counted, which only ffprobe does
+            let native = match backend {
+                ProbeBackend::Native if !is_stem_file(path) => native_probe::probe(path),
+                _ => None,
+            };
+            let output = match native {
+                Some(output) => output,
+                None => run_ffprobe(path)?,
+            };
+            if let Some(cache) = cache {
+                cache.insert(path, &output);
+            }
+            output
+        }
+    };
+    let probe_result: Probe = serde_json::from_value(output)?;
     match (probe_result.streams, probe_result.format) {
         (Some(s), Some(f)) => {
-            // splitting the path will return the full file name
-            // then extract the name before the period
-            // since this part of code only runs if a valid path was found
-            // unwraps are guaranteed to work, so this will not panic
+            // Embedded artwork shows up as a video stream, which may come before the audio
+            let audio: Vec<&ProbeStream> = s.iter().filter(|s| s.codec_type == "audio").collect();
+            let stream = audio
+                .first()
+                .ok_or_else(|| anyhow!("No audio stream in {:?}", path))?;
+            // mp4 containers (m4a files, and NI stem files holding the stereo master followed by
+            // four stems) can hold AAC or ALAC, so the format has to come from the codec
+            let codec_format = stream
+                .codec_name
+                .parse::<AudioFormatType>()
+                .unwrap_or(AudioFormatType::Unsupported);
+            let format = match f.format_name {
+                _ if is_stem_file(path) => codec_format,
+                AudioFormatType::Unsupported => codec_format,
+                // Ogg holds Opus as well as Vorbis, which is what the container name stands for
+                AudioFormatType::Lossy(SupportedAudioFormat::OGG)
+                    if stream.codec_name == "opus" =>
+                {
+                    codec_format
+                }
+                format => format,
+            };
 
             // based on the format type, bit info will either be the sample_fmt, or bit_rate
-            let bit_info = match f.format_name {
-                AudioFormatType::Lossless(_) => s[0].sample_fmt.unwrap_or(0),
-                AudioFormatType::Lossy(_) => s[0].bit_rate.unwrap_or(0),
+            let bit_info = match format {
+                AudioFormatType::Lossless(_) => stream
+                    .bits_per_raw_sample
+                    .filter(|bits| *bits > 0)
+                    .or(stream.sample_fmt)
+                    .unwrap_or(0),
+                AudioFormatType::Lossy(_) => stream.bit_rate.unwrap_or(0),
                 _ => 0,
             };
             Ok(SongInfo {
-                codec: s[0].codec_name.clone(),
-                format: f.format_name,
+                codec: stream.codec_name.clone(),
+                format,
                 song_path: path.to_path_buf(),
-                sample_rate: s[0].sample_rate.unwrap_or(0),
+                sample_rate: stream.sample_rate.unwrap_or(0),
                 bit_info,
                 tags: f.tags,
+                audio_streams: audio.len(),
+                artwork_codec: s
+                    .iter()
+                    .find(|s| s.codec_type == "video")
+                    .map(|s| s.codec_name.clone()),
+                duration: f.duration.unwrap_or(0.0),
             })
         }
         _ => Err(anyhow!("Missing streams or format for {:?}", path)),
@@ -221,10 +337,40 @@ impl SongInfo {
         &self.bit_info
     }
 
+    /// Length of the song in seconds, or 0 if ffprobe couldn't tell
+    pub fn get_duration(&self) -> f64 {
+        self.duration
+    }
+
     pub fn get_tags(&self) -> &Option<serde_json::Value> {
         &self.tags
     }
 
+    /// Looks up a tag by name. Containers disagree on capitalization (ARTIST in FLAC,
+    /// artist in MP3), so the match is case-insensitive.
+    pub fn get_tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .as_ref()?
+            .as_object()?
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.as_str())
+    }
+
+    /// Whether the song has embedded artwork, which ffprobe reports as a video stream
+    pub fn has_artwork(&self) -> bool {
+        self.artwork_codec.is_some()
+    }
+
+    pub fn get_artwork_codec(&self) -> Option<&str> {
+        self.artwork_codec.as_deref()
+    }
+
+    /// Whether the song is an NI stem file, i.e. a master plus four stems
+    pub fn is_stem(&self) -> bool {
+        is_stem_file(&self.song_path) && self.audio_streams == 5
+    }
+
     pub fn is_rekordbox_format(&self) -> bool {
         match &self.format {
             AudioFormatType::Lossless(format) | AudioFormatType::Lossy(format) => match format {
@@ -0,0 +1,141 @@
+use crate::analyzers::{Analysis, Analyzer, TrackAnalysis, CHANNELS, SAMPLE_RATE};
+use std::f64::consts::PI;
+
+/// Samples in each analyzed window, a power of two for the FFT
+const WINDOW: usize = 4096;
+/// Only every this many windows is analyzed; the cutoff doesn't change over a song, so this
+/// saves time without changing the result
+const WINDOW_STRIDE: usize = 4;
+/// The cutoff is the highest frequency within this many dB of the level at the low mids
+const CUTOFF_RANGE_DB: f64 = 60.0;
+/// Frequencies the reference level is taken between, in Hz
+const REFERENCE_BAND: (f64, f64) = (200.0, 2000.0);
+
+/// Where the spectrum of a song ends, to tell lossless files made from lossy ones
+pub struct Spectrum;
+
+impl Analyzer for Spectrum {
+    fn name(&self) -> &'static str {
+        "spectrum"
+    }
+
+    fn description(&self) -> &'static str {
+        "the frequency the spectrum is cut off at"
+    }
+
+    fn start(&self) -> Box<dyn Analysis> {
+        Box::new(SpectrumAnalysis {
+            window: Vec::with_capacity(WINDOW),
+            n_windows: 0,
+            power: vec![0.0; WINDOW / 2],
+            n_analyzed: 0,
+        })
+    }
+}
+
+struct SpectrumAnalysis {
+    /// The mono samples of the window being filled
+    window: Vec<f64>,
+    n_windows: usize,
+    /// Summed power of each frequency bin over the analyzed windows
+    power: Vec<f64>,
+    n_analyzed: usize,
+}
+
+impl SpectrumAnalysis {
+    fn analyze_window(&mut self) {
+        let mut re: Vec<f64> = self
+            .window
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| sample * hann(i, WINDOW))
+            .collect();
+        let mut im = vec![0.0; WINDOW];
+        fft(&mut re, &mut im);
+        for (bin, power) in self.power.iter_mut().enumerate() {
+            *power += re[bin] * re[bin] + im[bin] * im[bin];
+        }
+        self.n_analyzed += 1;
+    }
+}
+
+impl Analysis for SpectrumAnalysis {
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            let mono = frame.iter().map(|s| *s as f64).sum::<f64>() / CHANNELS as f64;
+            self.window.push(mono);
+            if self.window.len() == WINDOW {
+                if self.n_windows.is_multiple_of(WINDOW_STRIDE) {
+                    self.analyze_window();
+                }
+                self.n_windows += 1;
+                self.window.clear();
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>, analysis: &mut TrackAnalysis) {
+        if self.n_analyzed == 0 {
+            return;
+        }
+        let bin_hz = SAMPLE_RATE as f64 / WINDOW as f64;
+        let to_db = |power: f64| 10.0 * (power / self.n_analyzed as f64).max(1e-20).log10();
+        let reference_bins =
+            (REFERENCE_BAND.0 / bin_hz) as usize..(REFERENCE_BAND.1 / bin_hz) as usize;
+        // The level of the mean power, which the quiet bins between partials don't drag down
+        let reference = to_db(
+            self.power[reference_bins.clone()].iter().sum::<f64>() / reference_bins.len() as f64,
+        );
+        // Silence has no spectrum to cut off
+        if reference < -100.0 {
+            return;
+        }
+        if let Some(bin) = self
+            .power
+            .iter()
+            .rposition(|power| to_db(*power) > reference - CUTOFF_RANGE_DB)
+        {
+            analysis.cutoff_hz = Some(((bin + 1) as f64 * bin_hz).round());
+        }
+    }
+}
+
+/// Weight of the i-th sample of a Hann window `length` samples long
+pub(crate) fn hann(i: usize, length: usize) -> f64 {
+    0.5 - 0.5 * (2.0 * PI * i as f64 / (length - 1) as f64).cos()
+}
+
+/// In-place radix-2 FFT of a signal whose length is a power of two
+pub(crate) fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
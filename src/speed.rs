@@ -0,0 +1,164 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File in the output directory where the speed correction of single songs can be overridden
+pub const REVIEW_FILE_NAME: &str = "speed-review.json";
+
+/// Correction for a rip recorded at the wrong turntable speed. Like a turntable's pitch control,
+/// it changes speed and pitch together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedCorrection {
+    factor: f64,
+}
+
+impl FromStr for SpeedCorrection {
+    type Err = anyhow::Error;
+
+    /// Parses either a speed factor ("0.9972") or a pitch shift in cents ("-4.85c", "+12cents")
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let factor = if let Some(cents) = s
+            .strip_suffix("cents")
+            .or_else(|| s.strip_suffix("cent"))
+            .or_else(|| s.strip_suffix('c'))
+        {
+            let cents = cents.trim().parse::<f64>()?;
+            2f64.powf(cents / 1200.0)
+        } else {
+            s.parse::<f64>()?
+        };
+        if factor <= 0.5 || factor >= 2.0 {
+            return Err(anyhow!("Speed correction {} is out of range", s));
+        }
+        Ok(SpeedCorrection { factor })
+    }
+}
+
+impl SpeedCorrection {
+    /// Whether the correction changes the speed at all, which an override of 1 doesn't
+    pub fn changes_speed(&self) -> bool {
+        self.factor != 1.0
+    }
+
+    /// Filters that play the song back at the corrected speed, keeping the original sample rate
+    pub fn filter(&self, sample_rate: usize) -> String {
+        let corrected_rate = (sample_rate as f64 * self.factor).round() as usize;
+        format!("asetrate={},aresample={}", corrected_rate, sample_rate)
+    }
+}
+
+impl Serialize for SpeedCorrection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.factor)
+    }
+}
+
+impl<'de> Deserialize<'de> for SpeedCorrection {
+    /// Reads a speed factor as a number or a string, or a pitch shift in cents as a string
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Factor(f64),
+            Text(String),
+        }
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Factor(factor) => factor.to_string(),
+            Raw::Text(text) => text,
+        };
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A song in the review file, with the correction to use for it instead of --speed-correct
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReviewEntry {
+    #[serde(rename = "override")]
+    pub override_correction: SpeedCorrection,
+}
+
+/// Speed corrections of single songs, by source path, like {"/rips/side-a.wav": {"override":
+/// "-4.85c"}}, for rips recorded on another turntable or at another speed than the rest. An
+/// override of 1 leaves a song as it is.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Review {
+    pub songs: BTreeMap<PathBuf, ReviewEntry>,
+}
+
+impl Review {
+    /// The review file of an output directory, empty if there is none yet
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(REVIEW_FILE_NAME);
+        if !path.is_file() {
+            return Ok(Review::default());
+        }
+        let contents = fs::read(&path)?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("{} isn't a speed review file", path.display()))
+    }
+
+    /// The correction a song was overridden to
+    pub fn override_for(&self, source: &Path) -> Option<SpeedCorrection> {
+        Some(self.songs.get(source)?.override_correction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn factor(s: &str) -> f64 {
+        s.parse::<SpeedCorrection>().unwrap().factor
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(factor("0.9972"), 0.9972);
+        assert_eq!(factor(" 1.0028 "), 1.0028);
+        assert_eq!(factor("1"), 1.0);
+    }
+
+    #[test]
+    fn cents() {
+        let up = factor("+12cents");
+        assert!((up - 2f64.powf(12.0 / 1200.0)).abs() < 1e-12);
+        let down = factor("-4.85c");
+        assert!((down - 2f64.powf(-4.85 / 1200.0)).abs() < 1e-12);
+        assert_eq!(factor("0c"), 1.0);
+        assert_eq!(factor("-100 Cent"), factor("-100cents"));
+        // A semitone down is a ratio below 1, not a negative one
+        assert!(down < 1.0 && down > 0.99);
+    }
+
+    #[test]
+    fn out_of_range_or_malformed() {
+        assert!("0.5".parse::<SpeedCorrection>().is_err());
+        assert!("2".parse::<SpeedCorrection>().is_err());
+        assert!("-0.9972".parse::<SpeedCorrection>().is_err());
+        assert!("1200c".parse::<SpeedCorrection>().is_err());
+        assert!("fast".parse::<SpeedCorrection>().is_err());
+        assert!("c".parse::<SpeedCorrection>().is_err());
+    }
+
+    #[test]
+    fn review_overrides() {
+        let review: Review = serde_json::from_str(
+            r#"{"/rips/a.wav": {"override": 0.9972}, "/rips/b.wav": {"override": "-4.85c"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            review.override_for(Path::new("/rips/a.wav")),
+            Some(SpeedCorrection { factor: 0.9972 })
+        );
+        assert_eq!(
+            review.override_for(Path::new("/rips/b.wav")),
+            Some("-4.85c".parse().unwrap())
+        );
+        assert_eq!(review.override_for(Path::new("/rips/c.wav")), None);
+    }
+}
@@ -0,0 +1,502 @@
+use crate::read_only;
+use crate::throttle::ReadThrottle;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the state file kept in the output directory
+pub const STATE_FILE_NAME: &str = ".rekordbox-conversion-state.json";
+/// Name of the journal of songs converted since the state file was last written
+pub const JOURNAL_FILE_NAME: &str = ".rekordbox-conversion-state.journal";
+
+/// Replaces a file's contents so that it holds either the old or the new contents, never a mix,
+/// even if the machine loses power halfway: the contents go to a temporary file that is synced
+/// and then renamed over the file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    read_only::check_writable(path)?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Path has no file name: {:?}", path))?;
+    let temp_path = path.with_file_name(format!("{}.tmp", file_name.to_string_lossy()));
+    {
+        let mut temp = File::create(&temp_path)?;
+        temp.write_all(contents)?;
+        temp.sync_all()?;
+    }
+    fs::rename(&temp_path, path)?;
+    // The rename itself only survives a power loss once the directory is synced. Directories
+    // can't be opened as files on Windows, where renames are durable anyway.
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Highest TrackID given out
+const MAX_TRACK_ID: u32 = i32::MAX as u32;
+
+/// What a source file looked like when it was converted
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SourceRecord {
+    /// Modification time in seconds since the Unix epoch
+    pub modified: u64,
+    pub size: u64,
+    /// FNV-1a hash of the file contents, as hex
+    pub hash: String,
+    /// Files the source was converted or copied into
+    #[serde(default)]
+    pub outputs: Vec<PathBuf>,
+    /// TrackID of the source in exported rekordbox.xml files, kept so re-imports update the
+    /// same Rekordbox entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_id: Option<u32>,
+    /// The track this source is a version of together with its acapella, instrumental or dub
+    /// versions, like "Artist - Title", linking the versions that were converted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant_group: Option<String>,
+    /// The clean or explicit versions of the same track and edit as this source, whether or not
+    /// they were converted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_versions: Vec<PathBuf>,
+    /// Average hash of the source's artwork, as hex, so sharing artwork between songs doesn't
+    /// need to decode it again while the source is unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artwork_hash: Option<String>,
+    /// Hash of the settings the source was converted with that change its outputs' audio, such
+    /// as the sample rate and bit depth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<String>,
+    /// When the source was first converted, in seconds since the Unix epoch, kept when it is
+    /// converted again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converted: Option<u64>,
+    /// How often the track was played in Rekordbox, as last imported from its library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub play_count: Option<u32>,
+    /// Stars from 0 to 5 the track was rated with in Rekordbox, as last imported from its
+    /// library
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<u8>,
+}
+
+impl SourceRecord {
+    /// What a source file looks like now, read through the read throttle if there is one
+    pub fn from_file(path: &Path, throttle: Option<&ReadThrottle>) -> Result<Self> {
+        let (modified, size) = modified_and_size(path)?;
+        Ok(SourceRecord {
+            modified,
+            size,
+            hash: hash_file_throttled(path, throttle)?,
+            outputs: vec![],
+            track_id: None,
+            variant_group: None,
+            other_versions: vec![],
+            artwork_hash: None,
+            settings: None,
+            converted: Some(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+            play_count: None,
+            rating: None,
+        })
+    }
+}
+
+/// Source files converted into an output directory by previous runs, so later runs can skip the
+/// ones that haven't changed since
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ConversionState {
+    sources: BTreeMap<PathBuf, SourceRecord>,
+}
+
+impl ConversionState {
+    /// Reads the state file of an output directory and replays the journal of songs converted
+    /// since, or starts a new state if there is neither
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(STATE_FILE_NAME);
+        let mut state = if path.exists() {
+            serde_json::from_slice(&fs::read(path)?)?
+        } else {
+            ConversionState::default()
+        };
+        let journal_path = output_dir.join(JOURNAL_FILE_NAME);
+        if journal_path.exists() {
+            for line in BufReader::new(File::open(journal_path)?).lines() {
+                // A power loss can cut off the last entry, which is then lost like an
+                // unfinished conversion
+                match serde_json::from_str::<JournalEntry>(&line?) {
+                    Ok(JournalEntry::Finished { path, record }) => state.insert(path, record),
+                    Ok(JournalEntry::Started { .. }) => {}
+                    Err(e) => tracing::warn!(?e, "Skipping damaged journal entry"),
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Writes the state file atomically, after which the journal is no longer needed
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        let path = output_dir.join(STATE_FILE_NAME);
+        write_atomic(&path, &serde_json::to_vec_pretty(self)?)?;
+        let journal_path = output_dir.join(JOURNAL_FILE_NAME);
+        if journal_path.exists() {
+            fs::remove_file(journal_path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a source file was converted before and is unchanged since, and converting it now
+    /// would give the same outputs: the ones it was converted into, all still there, with the
+    /// same settings. The contents are only hashed when the modification time changed but the
+    /// size didn't, e.g. after a touch or a copy, through the read throttle if there is one.
+    pub fn is_up_to_date(
+        &self,
+        path: &Path,
+        outputs: &[PathBuf],
+        settings: &str,
+        throttle: Option<&ReadThrottle>,
+    ) -> bool {
+        let record = match self.sources.get(path) {
+            Some(record) => record,
+            None => return false,
+        };
+        let mut recorded = record.outputs.clone();
+        let mut planned = outputs.to_vec();
+        recorded.sort();
+        planned.sort();
+        // Records from before settings were kept are taken to match
+        recorded == planned
+            && planned.iter().all(|output| output.exists())
+            && record.settings.as_deref().is_none_or(|s| s == settings)
+            && self.is_unchanged(path, throttle)
+    }
+
+    /// Whether a source file was converted before and is unchanged since. The contents are only
+    /// hashed when the modification time changed but the size didn't, e.g. after a touch or a
+    /// copy, through the read throttle if there is one.
+    pub fn is_unchanged(&self, path: &Path, throttle: Option<&ReadThrottle>) -> bool {
+        let record = match self.sources.get(path) {
+            Some(record) => record,
+            None => return false,
+        };
+        match modified_and_size(path) {
+            Ok((modified, size)) if size == record.size => {
+                modified == record.modified
+                    || hash_file_throttled(path, throttle)
+                        .map(|h| h == record.hash)
+                        .unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    /// Remembers that a source file was converted into some outputs with some settings
+    pub fn record(&mut self, path: &Path, outputs: &[PathBuf], settings: &str) -> Result<()> {
+        let mut record = SourceRecord::from_file(path, None)?;
+        record.outputs = outputs.to_vec();
+        record.settings = Some(settings.to_string());
+        self.insert(path.to_path_buf(), record);
+        Ok(())
+    }
+
+    /// Replaces the record of a source file, keeping the TrackID it was exported with, when it
+    /// was first converted, how it was played and rated, and its artwork hash if its contents are
+    /// the same
+    fn insert(&mut self, path: PathBuf, mut record: SourceRecord) {
+        if let Some(old) = self.sources.get(&path) {
+            record.track_id = record.track_id.or(old.track_id);
+            record.converted = old.converted.or(record.converted);
+            record.play_count = record.play_count.or(old.play_count);
+            record.rating = record.rating.or(old.rating);
+            if old.hash == record.hash {
+                record.artwork_hash = record.artwork_hash.or_else(|| old.artwork_hash.clone());
+            }
+        }
+        self.sources.insert(path, record);
+    }
+
+    /// What a source file looked like when it was last converted
+    pub fn get(&self, path: &Path) -> Option<&SourceRecord> {
+        self.sources.get(path)
+    }
+
+    /// Every source file converted into the output directory, and how it looked then
+    pub fn sources(&self) -> impl Iterator<Item = (&PathBuf, &SourceRecord)> {
+        self.sources.iter()
+    }
+
+    /// Links a source to the group of versions of a track it is in, or unlinks it. Returns
+    /// whether that changed anything, in which case the state needs saving.
+    pub fn set_variant_group(&mut self, path: &Path, group: Option<String>) -> bool {
+        match self.sources.get_mut(path) {
+            Some(record) if record.variant_group != group => {
+                record.variant_group = group;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Links a source to its clean or explicit versions. Returns whether that changed anything,
+    /// in which case the state needs saving.
+    pub fn set_other_versions(&mut self, path: &Path, other_versions: Vec<PathBuf>) -> bool {
+        match self.sources.get_mut(path) {
+            Some(record) if record.other_versions != other_versions => {
+                record.other_versions = other_versions;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remembers the hash of a source's artwork. Returns whether that changed anything, in which
+    /// case the state needs saving.
+    pub fn set_artwork_hash(&mut self, path: &Path, hash: String) -> bool {
+        match self.sources.get_mut(path) {
+            Some(record) if record.artwork_hash.as_ref() != Some(&hash) => {
+                record.artwork_hash = Some(hash);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remembers how often a source's track was played and how it was rated in Rekordbox.
+    /// Returns whether that changed anything, in which case the state needs saving.
+    pub fn set_play_history(&mut self, path: &Path, play_count: u32, rating: u8) -> bool {
+        match self.sources.get_mut(path) {
+            Some(record)
+                if record.play_count != Some(play_count) || record.rating != Some(rating) =>
+            {
+                record.play_count = Some(play_count);
+                record.rating = Some(rating);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Gives every source file that still exists a TrackID, which it keeps from then on. A
+    /// source that was moved or renamed takes over the ID of the record it left behind, found by
+    /// its contents; others get one derived from their path. Returns whether any ID was given
+    /// out, in which case the state needs saving.
+    pub fn assign_track_ids(&mut self) -> bool {
+        let mut taken: BTreeSet<u32> = self.sources.values().filter_map(|r| r.track_id).collect();
+        // IDs of sources that are gone, by their contents, for the files they moved to
+        let mut vacated: BTreeMap<String, u32> = self
+            .sources
+            .iter()
+            .filter(|(path, _)| !path.exists())
+            .filter_map(|(_, record)| Some((record.hash.clone(), record.track_id?)))
+            .collect();
+        let mut assigned = false;
+        for (path, record) in self.sources.iter_mut() {
+            if record.track_id.is_some() || !path.exists() {
+                continue;
+            }
+            let id = match vacated.remove(&record.hash) {
+                Some(id) => id,
+                None => {
+                    // Rekordbox reads TrackIDs as positive 32 bit integers. A collision moves on
+                    // to the next free one
+                    let hash = hash_bytes(path.to_string_lossy().as_bytes());
+                    let mut id = (hash as u32 & MAX_TRACK_ID).max(1);
+                    while taken.contains(&id) {
+                        id = if id == MAX_TRACK_ID { 1 } else { id + 1 };
+                    }
+                    id
+                }
+            };
+            taken.insert(id);
+            record.track_id = Some(id);
+            assigned = true;
+        }
+        // A moved source's old record would export as a second track with the same ID
+        if assigned {
+            let moved: BTreeSet<u32> = self
+                .sources
+                .iter()
+                .filter(|(path, _)| path.exists())
+                .filter_map(|(_, record)| record.track_id)
+                .collect();
+            for (path, record) in self.sources.iter_mut() {
+                if !path.exists() && record.track_id.is_some_and(|id| moved.contains(&id)) {
+                    record.track_id = None;
+                }
+            }
+        }
+        assigned
+    }
+}
+
+/// A line of the journal
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum JournalEntry {
+    /// A source file was converted
+    Finished { path: PathBuf, record: SourceRecord },
+    /// Converting a source file into these outputs began
+    Started {
+        started: PathBuf,
+        outputs: Vec<PathBuf>,
+    },
+}
+
+/// What a run that was cut short left behind, read from its journal
+#[derive(Clone, Debug, Default)]
+pub struct InterruptedRun {
+    /// Source files it finished converting
+    pub finished: BTreeSet<PathBuf>,
+    /// Outputs of songs it started converting but didn't finish, which may be half written
+    pub partial_outputs: Vec<PathBuf>,
+}
+
+/// Reads what the last run into an output directory left behind if it was cut short, which is
+/// the case when its journal is still there
+pub fn interrupted_run(output_dir: &Path) -> Result<Option<InterruptedRun>> {
+    let journal_path = output_dir.join(JOURNAL_FILE_NAME);
+    if !journal_path.exists() {
+        return Ok(None);
+    }
+    let mut run = InterruptedRun::default();
+    let mut started = BTreeMap::new();
+    for line in BufReader::new(File::open(journal_path)?).lines() {
+        match serde_json::from_str::<JournalEntry>(&line?) {
+            Ok(JournalEntry::Started {
+                started: path,
+                outputs,
+            }) => {
+                started.insert(path, outputs);
+            }
+            Ok(JournalEntry::Finished { path, .. }) => {
+                started.remove(&path);
+                run.finished.insert(path);
+            }
+            Err(e) => tracing::warn!(?e, "Skipping damaged journal entry"),
+        }
+    }
+    run.partial_outputs = started.into_values().flatten().collect();
+    Ok(Some(run))
+}
+
+/// Append-only log of songs converted during a run, synced after every song, so the progress of
+/// a run that is cut short isn't lost. It is folded into the state file when the run ends.
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn open(output_dir: &Path) -> Result<Self> {
+        let path = output_dir.join(JOURNAL_FILE_NAME);
+        read_only::check_writable(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Remembers that converting a source file into these outputs is about to begin, so the
+    /// outputs can be cleaned up if the run is cut short
+    pub fn start(&self, path: &Path, outputs: &[PathBuf]) -> Result<()> {
+        self.write(&JournalEntry::Started {
+            started: path.to_path_buf(),
+            outputs: outputs.to_vec(),
+        })
+    }
+
+    /// Remembers that a source file was converted into some outputs with some settings, hashing
+    /// it through the read throttle if there is one
+    pub fn append(
+        &self,
+        path: &Path,
+        outputs: &[PathBuf],
+        settings: &str,
+        throttle: Option<&ReadThrottle>,
+    ) -> Result<()> {
+        let mut record = SourceRecord::from_file(path, throttle)?;
+        record.outputs = outputs.to_vec();
+        record.settings = Some(settings.to_string());
+        self.write(&JournalEntry::Finished {
+            path: path.to_path_buf(),
+            record,
+        })
+    }
+
+    fn write(&self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+pub(crate) fn modified_and_size(path: &Path) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    Ok((modified, metadata.len()))
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A 64 bit FNV-1a hash fed a piece at a time
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Fnv(FNV_OFFSET_BASIS)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// The hash as 16 hex digits
+    pub(crate) fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Hashes a file with 64 bit FNV-1a
+pub(crate) fn hash_file(path: &Path) -> Result<String> {
+    hash_file_throttled(path, None)
+}
+
+/// Hashes a file with 64 bit FNV-1a, reading it through the read throttle if there is one
+pub(crate) fn hash_file_throttled(path: &Path, throttle: Option<&ReadThrottle>) -> Result<String> {
+    match throttle {
+        Some(throttle) => hash_reader(throttle.open(path)?),
+        None => hash_reader(File::open(path)?),
+    }
+}
+
+fn hash_reader(reader: impl Read) -> Result<String> {
+    let mut reader = BufReader::new(reader);
+    let mut buffer = [0u8; 64 * 1024];
+    let mut hash = Fnv::new();
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hash.update(&buffer[..n]);
+    }
+    Ok(hash.hex())
+}
+
+/// Hashes bytes with 64 bit FNV-1a, like hash_file
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash = Fnv::new();
+    hash.update(bytes);
+    hash.0
+}
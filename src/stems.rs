@@ -0,0 +1,47 @@
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+
+/// What to do with Native Instruments stem files
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum StemMode {
+    /// Only convert the stereo master
+    Master,
+    /// Convert the four stems into separate files
+    Split,
+}
+
+/// Stems in the order NI stem files store them, after the master
+pub const STEM_NAMES: [&str; 4] = ["Drums", "Bass", "Other", "Vocals"];
+
+/// One file to write out of a song: the audio stream to map if not left up to ffmpeg, the stem
+/// name if it isn't the master, and where to write it
+pub struct StemOutput {
+    pub stream: Option<String>,
+    pub stem: Option<&'static str>,
+    pub path: PathBuf,
+}
+
+/// Lists the files to write for a stem file named song_name
+pub fn outputs(
+    mode: StemMode,
+    output_dir: &Path,
+    song_name: &str,
+    extension: &str,
+) -> Vec<StemOutput> {
+    match mode {
+        StemMode::Master => vec![StemOutput {
+            stream: Some("0:a:0".to_string()),
+            stem: None,
+            path: output_dir.join(format!("{}.{}", song_name, extension)),
+        }],
+        StemMode::Split => STEM_NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, stem)| StemOutput {
+                stream: Some(format!("0:a:{}", i + 1)),
+                stem: Some(*stem),
+                path: output_dir.join(format!("{} ({}).{}", song_name, stem, extension)),
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,346 @@
+use crate::song_info::SongInfo;
+use anyhow::{anyhow, Result};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// An expression over a song's tags picking the songs to convert, like
+/// `REKORDBOX=1 && ENERGY>=7 && genre~"techno"`. Tags are compared with `=`, `!=`, `<`, `<=`,
+/// `>`, `>=`, as numbers when both sides are numbers and else as text ignoring case, and
+/// matched with `~` (contains, ignoring case) and `!~`. A tag name on its own is true when the
+/// song has the tag. Expressions combine with `&&`, `||`, `!` and parentheses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagFilter {
+    Compare { tag: String, op: Op, value: String },
+    Has(String),
+    Not(Box<TagFilter>),
+    And(Box<TagFilter>, Box<TagFilter>),
+    Or(Box<TagFilter>, Box<TagFilter>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+}
+
+impl Op {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Contains => "~",
+            Op::NotContains => "!~",
+        }
+    }
+}
+
+impl TagFilter {
+    pub fn parse(expression: &str) -> Result<TagFilter> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, next: 0 };
+        let filter = parser.or()?;
+        match parser.tokens.get(parser.next) {
+            None => Ok(filter),
+            Some(token) => Err(anyhow!("Unexpected {} in filter {:?}", token, expression)),
+        }
+    }
+
+    /// Whether a song's tags match the filter. Comparisons with a tag the song doesn't have are
+    /// false, except != and !~.
+    pub fn matches(&self, song: &SongInfo) -> bool {
+        self.matches_tags(&|tag| song.get_tag(tag))
+    }
+
+    fn matches_tags<'a>(&self, get_tag: &dyn Fn(&str) -> Option<&'a str>) -> bool {
+        match self {
+            TagFilter::Compare { tag, op, value } => match get_tag(tag) {
+                Some(tagged) => compare(tagged.trim(), *op, value),
+                None => matches!(op, Op::Ne | Op::NotContains),
+            },
+            TagFilter::Has(tag) => get_tag(tag).is_some_and(|v| !v.trim().is_empty()),
+            TagFilter::Not(filter) => !filter.matches_tags(get_tag),
+            TagFilter::And(a, b) => a.matches_tags(get_tag) && b.matches_tags(get_tag),
+            TagFilter::Or(a, b) => a.matches_tags(get_tag) || b.matches_tags(get_tag),
+        }
+    }
+}
+
+fn compare(tagged: &str, op: Op, value: &str) -> bool {
+    let contains = || tagged.to_lowercase().contains(&value.to_lowercase());
+    let ordering = match (tagged.parse::<f64>(), value.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b),
+        _ => Some(tagged.to_lowercase().cmp(&value.to_lowercase())),
+    };
+    match op {
+        Op::Contains => contains(),
+        Op::NotContains => !contains(),
+        Op::Eq => ordering == Some(Ordering::Equal),
+        Op::Ne => ordering != Some(Ordering::Equal),
+        Op::Lt => ordering == Some(Ordering::Less),
+        Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        Op::Gt => ordering == Some(Ordering::Greater),
+        Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A tag name or an unquoted value
+    Word(String),
+    /// A quoted value
+    Quoted(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(value) => write!(f, "{:?}", value),
+            Token::Op(op) => write!(f, "{}", op.symbol()),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<CharIndices> = expression.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let mut followed_by = |next: char| chars.next_if(|(_, c)| *c == next).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if followed_by('&') => Token::And,
+            '|' if followed_by('|') => Token::Or,
+            '=' => {
+                followed_by('=');
+                Token::Op(Op::Eq)
+            }
+            '!' if followed_by('=') => Token::Op(Op::Ne),
+            '!' if followed_by('~') => Token::Op(Op::NotContains),
+            '!' => Token::Not,
+            '<' if followed_by('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if followed_by('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '~' => Token::Op(Op::Contains),
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => value.extend(chars.next().map(|(_, c)| c)),
+                        Some((_, q)) if q == c => break,
+                        Some((_, c)) => value.push(c),
+                        None => return Err(anyhow!("Unclosed quote in filter {:?}", expression)),
+                    }
+                }
+                Token::Quoted(value)
+            }
+            c if is_word_char(c) => {
+                let mut end = i + c.len_utf8();
+                while let Some((j, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                    end = j + c.len_utf8();
+                }
+                Token::Word(expression[i..end].to_string())
+            }
+            c => return Err(anyhow!("Unexpected {:?} in filter {:?}", c, expression)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '#')
+}
+
+/// Parses tokens by precedence: || binds loosest, then &&, then !
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn take_if(&mut self, token: &Token) -> bool {
+        let taken = self.tokens.get(self.next) == Some(token);
+        if taken {
+            self.next += 1;
+        }
+        taken
+    }
+
+    fn or(&mut self) -> Result<TagFilter> {
+        let mut filter = self.and()?;
+        while self.take_if(&Token::Or) {
+            filter = TagFilter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<TagFilter> {
+        let mut filter = self.not()?;
+        while self.take_if(&Token::And) {
+            filter = TagFilter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<TagFilter> {
+        if self.take_if(&Token::Not) {
+            return Ok(TagFilter::Not(Box::new(self.not()?)));
+        }
+        match self.take() {
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                if !self.take_if(&Token::Close) {
+                    return Err(anyhow!("Missing ) in filter"));
+                }
+                Ok(filter)
+            }
+            Some(Token::Word(tag)) => {
+                let op = match self.tokens.get(self.next) {
+                    Some(Token::Op(op)) => *op,
+                    _ => return Ok(TagFilter::Has(tag)),
+                };
+                self.next += 1;
+                match self.take() {
+                    Some(Token::Word(value)) | Some(Token::Quoted(value)) => {
+                        Ok(TagFilter::Compare { tag, op, value })
+                    }
+                    _ => Err(anyhow!("Missing a value to compare {} with in filter", tag)),
+                }
+            }
+            Some(token) => Err(anyhow!("Expected a tag in filter, not {}", token)),
+            None => Err(anyhow!("Filter ends where a tag was expected")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expression: &str, tags: &[(&str, &str)]) -> bool {
+        let filter = TagFilter::parse(expression).unwrap();
+        filter.matches_tags(&|tag| {
+            tags.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+                .map(|(_, value)| *value)
+        })
+    }
+
+    fn comparison(tag: &str, op: Op, value: &str) -> TagFilter {
+        TagFilter::Compare {
+            tag: tag.to_string(),
+            op,
+            value: value.to_string(),
+        }
+    }
+
+    fn has(tag: &str) -> Box<TagFilter> {
+        Box::new(TagFilter::Has(tag.to_string()))
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            TagFilter::parse("a || b && c").unwrap(),
+            TagFilter::Or(has("a"), Box::new(TagFilter::And(has("b"), has("c"))))
+        );
+        assert_eq!(
+            TagFilter::parse("a && b || c").unwrap(),
+            TagFilter::Or(Box::new(TagFilter::And(has("a"), has("b"))), has("c"))
+        );
+    }
+
+    #[test]
+    fn not_binds_tightest() {
+        assert_eq!(
+            TagFilter::parse("!a && b").unwrap(),
+            TagFilter::And(Box::new(TagFilter::Not(has("a"))), has("b"))
+        );
+        assert_eq!(
+            TagFilter::parse("!(a && b)").unwrap(),
+            TagFilter::Not(Box::new(TagFilter::And(has("a"), has("b"))))
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert!(!matches("(a || b) && c", &[("a", "1")]));
+        assert!(matches("a || (b && c)", &[("a", "1")]));
+    }
+
+    #[test]
+    fn quoted_values() {
+        assert_eq!(
+            TagFilter::parse(r#"genre~"deep house" && label='Hot && Fresh'"#).unwrap(),
+            TagFilter::And(
+                Box::new(comparison("genre", Op::Contains, "deep house")),
+                Box::new(comparison("label", Op::Eq, "Hot && Fresh"))
+            )
+        );
+        assert_eq!(
+            TagFilter::parse(r#"title="Say \"Yes\"""#).unwrap(),
+            comparison("title", Op::Eq, r#"Say "Yes""#)
+        );
+        assert!(TagFilter::parse(r#"title="unclosed"#).is_err());
+    }
+
+    #[test]
+    fn numbers_compare_as_numbers_and_text_ignoring_case() {
+        assert!(matches("ENERGY>=7", &[("energy", "10")]));
+        assert!(!matches("ENERGY>=7", &[("energy", "6.5")]));
+        assert!(matches("genre=TECHNO", &[("genre", " techno ")]));
+        assert!(matches("genre~tech", &[("genre", "Tech House")]));
+    }
+
+    #[test]
+    fn missing_tags() {
+        assert!(!matches("REKORDBOX=1", &[]));
+        assert!(!matches("ENERGY<5", &[]));
+        assert!(!matches("genre~house", &[]));
+        assert!(matches("genre!=techno", &[]));
+        assert!(matches("genre!~techno", &[]));
+        assert!(!matches("REKORDBOX", &[]));
+        assert!(!matches("REKORDBOX", &[("rekordbox", " ")]));
+        assert!(matches("!REKORDBOX", &[]));
+    }
+
+    #[test]
+    fn broken_filters_are_rejected() {
+        assert!(TagFilter::parse("").is_err());
+        assert!(TagFilter::parse("(a && b").is_err());
+        assert!(TagFilter::parse("a &&").is_err());
+        assert!(TagFilter::parse("genre=").is_err());
+        assert!(TagFilter::parse("a b").is_err());
+    }
+}
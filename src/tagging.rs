@@ -0,0 +1,176 @@
+use crate::read_only;
+use anyhow::{anyhow, Result};
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::file::{AudioFile, FileType};
+use lofty::flac::FlacFile;
+use lofty::id3::v2::{ExtendedTextFrame, Frame, Id3v2Tag, Id3v2Version};
+use lofty::iff::aiff::AiffFile;
+use lofty::iff::wav::WavFile;
+use lofty::mp4::{Atom, AtomData, AtomIdent, Ilst, Mp4File};
+use lofty::mpeg::MpegFile;
+use lofty::ogg::{OpusFile, VorbisComments, VorbisFile};
+use lofty::tag::TagExt;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+
+/// Changes a song's tags in place without re-encoding it. ffmpeg copies the streams into a
+/// temporary file next to the song, which then replaces it.
+pub fn write_tags(path: &Path, tags: &[(String, String)]) -> Result<()> {
+    read_only::check_writable(path)?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .ok_or_else(|| anyhow!("Song has no file extension: {:?}", path))?;
+    let temp_path = path.with_extension(format!("tagging.{}", extension));
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-y")
+        .arg("-i")
+        .arg(path)
+        .arg("-map")
+        .arg("0")
+        .arg("-c")
+        .arg("copy");
+    // AIFF only keeps tags in an ID3 chunk
+    if extension.eq_ignore_ascii_case("aiff") || extension.eq_ignore_ascii_case("aif") {
+        command.arg("-write_id3v2").arg("1");
+    }
+    for (key, value) in tags {
+        command.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    let output = command.arg(&temp_path).output()?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&temp_path);
+        return Err(anyhow!(
+            "Could not write tags to {:?}: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Sets a tag a song already has to a new value, rewriting only the tag block that holds it.
+/// Unlike `write_tags` nothing is remuxed, so the audio and every other tag and frame are left
+/// as they are, such as the cue points DJ software keeps in binary ID3 frames. The tag is found
+/// by name ignoring case, as an ID3 user text frame, a Vorbis comment or an iTunes MP4 atom.
+/// Fails if the song doesn't have it.
+pub fn replace_tag(path: &Path, key: &str, value: &str) -> Result<()> {
+    read_only::check_writable(path)?;
+    let options = ParseOptions::new().read_properties(false);
+    let mut file = File::open(path)?;
+    let replaced = match FileType::from_path(path) {
+        Some(FileType::Mpeg) => {
+            let mut song = MpegFile::read_from(&mut file, options)?;
+            replace_id3v2(song.id3v2_mut(), path, key, value)?
+        }
+        Some(FileType::Wav) => {
+            let mut song = WavFile::read_from(&mut file, options)?;
+            replace_id3v2(song.id3v2_mut(), path, key, value)?
+        }
+        Some(FileType::Aiff) => {
+            let mut song = AiffFile::read_from(&mut file, options)?;
+            replace_id3v2(song.id3v2_mut(), path, key, value)?
+        }
+        Some(FileType::Flac) => {
+            let mut song = FlacFile::read_from(&mut file, options)?;
+            replace_vorbis(song.vorbis_comments_mut(), path, key, value)?
+                | replace_id3v2(song.id3v2_mut(), path, key, value)?
+        }
+        Some(FileType::Vorbis) => {
+            let mut song = VorbisFile::read_from(&mut file, options)?;
+            replace_vorbis(Some(song.vorbis_comments_mut()), path, key, value)?
+        }
+        Some(FileType::Opus) => {
+            let mut song = OpusFile::read_from(&mut file, options)?;
+            replace_vorbis(Some(song.vorbis_comments_mut()), path, key, value)?
+        }
+        Some(FileType::Mp4) => {
+            let mut song = Mp4File::read_from(&mut file, options)?;
+            replace_ilst(song.ilst_mut(), path, key, value)?
+        }
+        _ => return Err(anyhow!("Can't edit the tags of {:?} in place", path)),
+    };
+    if !replaced {
+        return Err(anyhow!("{:?} has no {} tag", path, key));
+    }
+    Ok(())
+}
+
+fn replace_id3v2(tag: Option<&mut Id3v2Tag>, path: &Path, key: &str, value: &str) -> Result<bool> {
+    let tag = match tag {
+        Some(tag) => tag,
+        None => return Ok(false),
+    };
+    let description = (&*tag).into_iter().find_map(|frame| match frame {
+        Frame::UserText(ExtendedTextFrame { description, .. })
+            if description.eq_ignore_ascii_case(key) =>
+        {
+            Some(description.clone())
+        }
+        _ => None,
+    });
+    let description = match description {
+        Some(description) => description,
+        None => return Ok(false),
+    };
+    tag.insert_user_text(description, value.to_string());
+    // Written back in the version it was read in, as lofty writes ID3v2.4 otherwise
+    let mut write_options = WriteOptions::default();
+    if tag.original_version() == Id3v2Version::V3 {
+        write_options = write_options.use_id3v23(true);
+    }
+    tag.save_to_path(path, write_options)?;
+    Ok(true)
+}
+
+fn replace_vorbis(
+    tag: Option<&mut VorbisComments>,
+    path: &Path,
+    key: &str,
+    value: &str,
+) -> Result<bool> {
+    let tag = match tag {
+        Some(tag) => tag,
+        None => return Ok(false),
+    };
+    let name = match tag.items().find(|(name, _)| name.eq_ignore_ascii_case(key)) {
+        Some((name, _)) => name.to_string(),
+        None => return Ok(false),
+    };
+    tag.insert(name, value.to_string());
+    tag.save_to_path(path, WriteOptions::default())?;
+    Ok(true)
+}
+
+fn replace_ilst(tag: Option<&mut Ilst>, path: &Path, key: &str, value: &str) -> Result<bool> {
+    let tag = match tag {
+        Some(tag) => tag,
+        None => return Ok(false),
+    };
+    let ident = (&*tag).into_iter().find_map(|atom| match atom.ident() {
+        AtomIdent::Freeform { mean, name }
+            if mean == "com.apple.iTunes" && name.eq_ignore_ascii_case(key) =>
+        {
+            Some(atom.ident().clone().into_owned())
+        }
+        _ => None,
+    });
+    let ident = match ident {
+        Some(ident) => ident,
+        None => return Ok(false),
+    };
+    tag.replace_atom(Atom::new(ident, AtomData::UTF8(value.to_string())));
+    tag.save_to_path(path, WriteOptions::default())?;
+    Ok(true)
+}
+
+/// Parses a KEY=VALUE pair as given on the command line
+pub fn parse_tag(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow!("Expected KEY=VALUE, got {:?}", s)),
+    }
+}
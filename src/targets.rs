@@ -0,0 +1,123 @@
+use crate::song_info::SupportedAudioFormat;
+use clap::ValueEnum;
+
+/// Format lossless songs are converted to
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LosslessTarget {
+    Aiff,
+    Wav,
+    Flac,
+}
+
+impl LosslessTarget {
+    pub fn format(&self) -> SupportedAudioFormat {
+        match self {
+            LosslessTarget::Aiff => SupportedAudioFormat::AIFF,
+            LosslessTarget::Wav => SupportedAudioFormat::WAV,
+            LosslessTarget::Flac => SupportedAudioFormat::FLAC,
+        }
+    }
+}
+
+/// Format lossy songs are converted to
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LossyTarget {
+    Mp3,
+    Aac,
+}
+
+impl LossyTarget {
+    pub fn format(&self) -> SupportedAudioFormat {
+        match self {
+            LossyTarget::Mp3 => SupportedAudioFormat::MP3,
+            LossyTarget::Aac => SupportedAudioFormat::AAC,
+        }
+    }
+}
+
+/// How songs are stored in the archive, the target kept for the long term next to the DJ ones
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ArchiveTarget {
+    /// Every song untouched
+    Original,
+    /// 16 and 24 bit WAV files as FLAC, checked to decode to the same samples, which takes
+    /// about half the space and keeps tags. Everything else untouched
+    Flac,
+}
+
+/// The ffmpeg encoder that writes a format at a bit depth
+pub fn codec(format: &SupportedAudioFormat, bit_depth: usize) -> &'static str {
+    match format {
+        SupportedAudioFormat::FLAC => "flac",
+        SupportedAudioFormat::AAC => "aac",
+        SupportedAudioFormat::MP3 => "mp3",
+        _ if bit_depth > 16 => "pcm_s24le",
+        _ => "pcm_s16le",
+    }
+}
+
+/// The sample format ffmpeg hands the encoder for a bit depth. 24 bit audio travels as s32.
+pub fn sample_fmt(bit_depth: usize) -> &'static str {
+    if bit_depth > 16 {
+        "s32"
+    } else {
+        "s16"
+    }
+}
+
+/// The file extension of a format. AAC goes in an mp4 container, which Rekordbox reads tags and
+/// artwork from, rather than a bare ADTS stream.
+pub fn extension(format: &SupportedAudioFormat) -> String {
+    match format {
+        SupportedAudioFormat::AAC | SupportedAudioFormat::ALAC => "m4a".to_string(),
+        SupportedAudioFormat::WAVPACK => "wv".to_string(),
+        format => format.to_string(),
+    }
+}
+
+/// Muxer options for writing tags to a format
+pub fn muxer_args(format: &SupportedAudioFormat) -> &'static [&'static str] {
+    match format {
+        SupportedAudioFormat::AIFF | SupportedAudioFormat::MP3 => &["-write_id3v2", "1"],
+        _ => &[],
+    }
+}
+
+/// Encoder and muxer options that make the same input always encode to the same bytes: no
+/// encoder version or creation time in the container, bit-exact encoders, and settings whose
+/// defaults could change between ffmpeg versions pinned
+pub fn deterministic_args(format: &SupportedAudioFormat) -> &'static [&'static str] {
+    match format {
+        SupportedAudioFormat::FLAC => &[
+            "-fflags",
+            "+bitexact",
+            "-flags",
+            "+bitexact",
+            "-compression_level",
+            "5",
+        ],
+        _ => &["-fflags", "+bitexact", "-flags", "+bitexact"],
+    }
+}
+
+/// The tag a format keeps the tempo in: the ID3 frame where tags are ID3, BPM elsewhere
+pub fn bpm_tag(format: &SupportedAudioFormat) -> &'static str {
+    match format {
+        SupportedAudioFormat::AIFF | SupportedAudioFormat::MP3 => "TBPM",
+        _ => "BPM",
+    }
+}
+
+/// The tag a format keeps the musical key in: the ID3 frame where tags are ID3, INITIALKEY
+/// elsewhere
+pub fn key_tag(format: &SupportedAudioFormat) -> &'static str {
+    match format {
+        SupportedAudioFormat::AIFF | SupportedAudioFormat::MP3 => "TKEY",
+        _ => "INITIALKEY",
+    }
+}
+
+/// Whether a format can hold embedded artwork
+pub fn embeds_artwork(format: &SupportedAudioFormat) -> bool {
+    !matches!(format, SupportedAudioFormat::WAV)
+}
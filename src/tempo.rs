@@ -0,0 +1,161 @@
+use crate::analyzers::{Analysis, Analyzer, TrackAnalysis, CHANNELS, SAMPLE_RATE};
+use crate::spectrum;
+
+/// Samples in each window of the onset detector, and how far windows advance
+const WINDOW: usize = 1024;
+const HOP: usize = 512;
+/// Tempos looked for, in BPM
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+/// Tempo the search leans towards when several fit, and how fast the lean falls off, in
+/// octaves. Without it a 128 BPM track could come out as 64.
+const PREFERRED_BPM: f64 = 120.0;
+const PREFERENCE_WIDTH: f64 = 1.0;
+
+/// Tempo, from how regularly onsets recur: the onset strength of every window is the rise in
+/// its spectrum since the last one, and the tempo is the beat length at which that strength
+/// correlates with itself best
+pub struct Tempo;
+
+impl Analyzer for Tempo {
+    fn name(&self) -> &'static str {
+        "bpm"
+    }
+
+    fn description(&self) -> &'static str {
+        "the tempo in BPM"
+    }
+
+    fn start(&self) -> Box<dyn Analysis> {
+        Box::new(TempoAnalysis {
+            samples: Vec::with_capacity(WINDOW),
+            previous: vec![0.0; WINDOW / 2],
+            onsets: vec![],
+        })
+    }
+}
+
+struct TempoAnalysis {
+    /// The mono samples of the window being filled
+    samples: Vec<f64>,
+    /// Log magnitude spectrum of the last window
+    previous: Vec<f64>,
+    /// Onset strength of every window
+    onsets: Vec<f64>,
+}
+
+impl TempoAnalysis {
+    fn analyze_window(&mut self) {
+        let mut re: Vec<f64> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, sample)| sample * spectrum::hann(i, WINDOW))
+            .collect();
+        let mut im = vec![0.0; WINDOW];
+        spectrum::fft(&mut re, &mut im);
+        let mut flux = 0.0;
+        for (bin, previous) in self.previous.iter_mut().enumerate() {
+            let magnitude = (1.0 + 1000.0 * (re[bin] * re[bin] + im[bin] * im[bin]).sqrt()).ln();
+            flux += (magnitude - *previous).max(0.0);
+            *previous = magnitude;
+        }
+        self.onsets.push(flux);
+    }
+}
+
+impl Analysis for TempoAnalysis {
+    fn feed(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            let mono = frame.iter().map(|s| *s as f64).sum::<f64>() / CHANNELS as f64;
+            self.samples.push(mono);
+            if self.samples.len() == WINDOW {
+                self.analyze_window();
+                self.samples.drain(..HOP);
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>, analysis: &mut TrackAnalysis) {
+        let rate = SAMPLE_RATE as f64 / HOP as f64;
+        analysis.bpm = estimate(&self.onsets, rate);
+        analysis.onset_density = onset_density(&self.onsets, rate);
+    }
+}
+
+/// Onsets a second in an onset strength envelope with `rate` values per second: the peaks
+/// standing out a standard deviation above the mean
+pub fn onset_density(onsets: &[f64], rate: f64) -> Option<f64> {
+    let onsets = onsets.get(1..).filter(|o| o.len() >= 3)?;
+    let mean = onsets.iter().sum::<f64>() / onsets.len() as f64;
+    let variance = onsets.iter().map(|o| (o - mean).powi(2)).sum::<f64>() / onsets.len() as f64;
+    let threshold = mean + variance.sqrt();
+    let n_peaks = onsets
+        .windows(3)
+        .filter(|w| w[1] > threshold && w[1] > w[0] && w[1] >= w[2])
+        .count();
+    Some(n_peaks as f64 * rate / onsets.len() as f64)
+}
+
+/// The tempo of an onset strength envelope with `rate` values per second, rounded to a
+/// hundredth of a BPM, or None if it has no regular beat
+pub fn estimate(onsets: &[f64], rate: f64) -> Option<f64> {
+    // The first window's flux is the whole spectrum rising from nothing
+    let onsets = onsets.get(1..)?;
+    let mean = onsets.iter().sum::<f64>() / onsets.len() as f64;
+    let centered: Vec<f64> = onsets.iter().map(|o| o - mean).collect();
+    let min_lag = (60.0 * rate / MAX_BPM).floor() as usize;
+    let max_lag = (60.0 * rate / MIN_BPM).ceil() as usize;
+    if centered.len() < 2 * max_lag {
+        return None;
+    }
+    let correlation = |lag: usize| -> f64 {
+        centered
+            .iter()
+            .zip(&centered[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f64>()
+            / (centered.len() - lag) as f64
+    };
+    let correlations: Vec<f64> = (min_lag - 1..=max_lag + 1).map(correlation).collect();
+    let weighted = |i: usize| {
+        let bpm = 60.0 * rate / (min_lag - 1 + i) as f64;
+        let octaves = (bpm / PREFERRED_BPM).log2() / PREFERENCE_WIDTH;
+        correlations[i] * (-0.5 * octaves * octaves).exp()
+    };
+    let best = (1..correlations.len() - 1).max_by(|a, b| weighted(*a).total_cmp(&weighted(*b)))?;
+    if correlations[best] <= 0.0 {
+        return None;
+    }
+    // One lag is several BPM at these rates, so the beat length is measured where the envelope
+    // correlates with itself a few beats later, which is the same number of lags but that many
+    // times fewer BPM apart
+    let lag = (min_lag - 1 + best) as f64;
+    let beats = [8.0, 4.0, 1.0]
+        .iter()
+        .copied()
+        .find(|beats| (lag * beats * 1.05).round() as usize + 2 < centered.len() / 2)?;
+    let lag = peak_near(&correlation, lag * beats)? / beats;
+    Some((6000.0 * rate / lag).round() / 100.0)
+}
+
+/// The lag, to a fraction, where a correlation peaks near a lag: the best whole lag within 5%
+/// of it, with a parabola through it and its neighbours finding the peak between lags
+fn peak_near<F: Fn(usize) -> f64>(correlation: &F, lag: f64) -> Option<f64> {
+    let radius = (lag / 20.0).ceil().max(1.0) as usize;
+    let center = lag.round() as usize;
+    let best = (center.checked_sub(radius)?.max(1)..=center + radius)
+        .max_by(|a, b| correlation(*a).total_cmp(&correlation(*b)))?;
+    let (left, middle, right) = (
+        correlation(best - 1),
+        correlation(best),
+        correlation(best + 1),
+    );
+    let denominator = left - 2.0 * middle + right;
+    let shift = if denominator.abs() > f64::EPSILON {
+        (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    Some(best as f64 + shift)
+}
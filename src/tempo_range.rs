@@ -0,0 +1,117 @@
+use crate::anlz;
+use crate::exporters::{Playlist, TrackRecord};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn default_tolerance() -> f64 {
+    5.0
+}
+
+/// The tempos a playlist is meant to hold, declared in a BPM ranges file
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct BpmRange {
+    pub min: f64,
+    pub max: f64,
+    /// How far outside the range, in percent, a track may be before it is flagged, so a track
+    /// pitched a little to fit isn't
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+impl BpmRange {
+    fn contains(&self, bpm: f64) -> bool {
+        let margin = self.tolerance / 100.0;
+        (self.min * (1.0 - margin)..=self.max * (1.0 + margin)).contains(&bpm)
+    }
+}
+
+/// Reads the BPM ranges declared in a JSON file, an object from playlist names to ranges like
+/// {"Peak time": {"min": 124, "max": 130}}
+pub fn load(path: &Path) -> Result<HashMap<String, BpmRange>> {
+    let contents = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let ranges: HashMap<String, BpmRange> = serde_json::from_slice(&contents)
+        .with_context(|| format!("{} isn't an object of BPM ranges", path.display()))?;
+    for (playlist, range) in ranges.iter() {
+        if range.min <= 0.0 || range.min > range.max || range.tolerance < 0.0 {
+            return Err(anyhow!(
+                "The BPM range of {} must go from a positive min up to its max, with a tolerance \
+                 of at least 0",
+                playlist
+            ));
+        }
+    }
+    Ok(ranges)
+}
+
+/// What a track far outside its playlist's range likely is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Suspect {
+    /// Detected at half its tempo: doubled, it fits
+    HalfTime,
+    /// Detected at double its tempo: halved, it fits
+    DoubleTime,
+    /// Neither fits, so it may be in the wrong playlist
+    Misfiled,
+}
+
+/// A track whose BPM is far outside the range of a playlist it is in
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoWarning {
+    pub playlist: String,
+    pub path: PathBuf,
+    pub bpm: f64,
+    pub range: BpmRange,
+    pub suspect: Suspect,
+}
+
+/// The tracks of the playlists with a declared range whose BPM is far outside it. A track's BPM
+/// is the one an analyzer detected, or else its BPM tag; tracks with neither aren't checked.
+pub fn check(
+    ranges: &HashMap<String, BpmRange>,
+    tracks: &[TrackRecord],
+    playlists: &[Playlist],
+) -> Vec<TempoWarning> {
+    let by_id: HashMap<u32, &TrackRecord> = tracks.iter().map(|t| (t.id, t)).collect();
+    let mut warnings = vec![];
+    for playlist in playlists {
+        let range = match ranges.get(&playlist.name) {
+            Some(range) => *range,
+            None => continue,
+        };
+        for track in playlist.track_ids.iter().filter_map(|id| by_id.get(id)) {
+            let bpm = match track.analysis.bpm.or_else(|| anlz::tagged_bpm(&track.song)) {
+                Some(bpm) => bpm,
+                None => continue,
+            };
+            if range.contains(bpm) {
+                continue;
+            }
+            let suspect = if range.contains(bpm * 2.0) {
+                Suspect::HalfTime
+            } else if range.contains(bpm / 2.0) {
+                Suspect::DoubleTime
+            } else {
+                Suspect::Misfiled
+            };
+            tracing::debug!(?track.path, bpm, ?suspect, "Track is outside its playlist's BPM range");
+            warnings.push(TempoWarning {
+                playlist: playlist.name.clone(),
+                path: track.path.clone(),
+                bpm,
+                range,
+                suspect,
+            });
+        }
+    }
+    let unused: Vec<&String> = ranges
+        .keys()
+        .filter(|name| !playlists.iter().any(|p| &p.name == *name))
+        .collect();
+    if !unused.is_empty() {
+        tracing::warn!(?unused, "No playlist is exported under these names");
+    }
+    warnings
+}
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Size of the chunks songs are read in
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// A local copy of a song, removed again when dropped
+#[derive(Debug)]
+pub struct StagedFile {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Drop for StagedFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A file read at the limited rate, taking up one of the concurrent reads until dropped
+pub struct ThrottledFile<'a> {
+    file: File,
+    throttle: &'a ReadThrottle,
+}
+
+impl Read for ThrottledFile<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        self.throttle.wait_turn(buffer.len());
+        let n = self.file.read(buffer)?;
+        self.throttle
+            .bytes_read
+            .fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl Drop for ThrottledFile<'_> {
+    fn drop(&mut self) {
+        self.throttle.release();
+    }
+}
+
+/// One of the concurrent reads, taken up by another program reading a song, like ffprobe,
+/// until dropped
+pub struct ReadSlot<'a> {
+    throttle: &'a ReadThrottle,
+}
+
+impl Drop for ReadSlot<'_> {
+    fn drop(&mut self) {
+        self.throttle.release();
+    }
+}
+
+/// Limits how fast and how many songs at once are read from the source, e.g. a NAS over Wi-Fi.
+/// Songs are copied to a local temporary directory at the limited rate, and all the reads ffmpeg
+/// does while converting them go to the local copy. Songs read for other reasons, like hashing
+/// them or probing their tags, are read through it as well.
+#[derive(Debug)]
+pub struct ReadThrottle {
+    /// Bytes per second, or None for no rate limit
+    bytes_per_sec: Option<f64>,
+    max_concurrent_reads: usize,
+    active_reads: Mutex<usize>,
+    read_finished: Condvar,
+    /// When the next chunk may be read, shared by every reader so the limit is global
+    next_read: Mutex<Instant>,
+    bytes_read: AtomicU64,
+    started: Instant,
+    n_staged: AtomicUsize,
+}
+
+impl ReadThrottle {
+    pub fn new(max_read_mbps: Option<f64>, max_concurrent_reads: usize) -> Self {
+        ReadThrottle {
+            bytes_per_sec: max_read_mbps.map(|mbps| mbps * 1e6),
+            max_concurrent_reads: max_concurrent_reads.max(1),
+            active_reads: Mutex::new(0),
+            read_finished: Condvar::new(),
+            next_read: Mutex::new(Instant::now()),
+            bytes_read: AtomicU64::new(0),
+            started: Instant::now(),
+            n_staged: AtomicUsize::new(0),
+        }
+    }
+
+    /// Average read throughput so far, in MB/s
+    pub fn throughput_mbps(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.bytes_read.load(Ordering::Relaxed) as f64 / 1e6 / elapsed
+    }
+
+    /// Copies a song to a temporary directory, keeping its file name
+    pub fn stage(&self, path: &Path) -> Result<StagedFile> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Song path has no file name: {:?}", path))?;
+        let dir = std::env::temp_dir().join(format!(
+            "rekordbox-staging-{}-{}",
+            std::process::id(),
+            self.n_staged.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let staged = StagedFile {
+            path: dir.join(file_name),
+            dir,
+        };
+        self.acquire();
+        let result = self.copy(path, &staged.path);
+        self.release();
+        result?;
+        Ok(staged)
+    }
+
+    /// Opens a file to read at the limited rate, once fewer than the maximum number of reads are
+    /// going on
+    pub fn open(&self, path: &Path) -> Result<ThrottledFile<'_>> {
+        let file = File::open(path)?;
+        self.acquire();
+        Ok(ThrottledFile {
+            file,
+            throttle: self,
+        })
+    }
+
+    /// Waits until fewer than the maximum number of reads are going on, for a program that reads
+    /// a song itself
+    pub fn slot(&self) -> ReadSlot<'_> {
+        self.acquire();
+        ReadSlot { throttle: self }
+    }
+
+    fn acquire(&self) {
+        let mut active = self.active_reads.lock().unwrap();
+        while *active >= self.max_concurrent_reads {
+            active = self.read_finished.wait(active).unwrap();
+        }
+        *active += 1;
+    }
+
+    fn release(&self) {
+        *self.active_reads.lock().unwrap() -= 1;
+        self.read_finished.notify_one();
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut source = File::open(from)?;
+        let mut destination = File::create(to)?;
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        loop {
+            self.wait_turn(CHUNK_SIZE);
+            let n = source.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            destination.write_all(&buffer[..n])?;
+            self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Waits until reading another chunk keeps the overall rate under the limit
+    fn wait_turn(&self, n_bytes: usize) {
+        let bytes_per_sec = match self.bytes_per_sec {
+            Some(bytes_per_sec) => bytes_per_sec,
+            None => return,
+        };
+        let start = {
+            let mut next_read = self.next_read.lock().unwrap();
+            let start = (*next_read).max(Instant::now());
+            *next_read = start + Duration::from_secs_f64(n_bytes as f64 / bytes_per_sec);
+            start
+        };
+        let now = Instant::now();
+        if start > now {
+            thread::sleep(start - now);
+        }
+    }
+}
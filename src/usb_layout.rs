@@ -0,0 +1,119 @@
+use crate::anlz;
+use crate::exporters::TrackRecord;
+use crate::pdb::{self, PdbTrack};
+use crate::read_only;
+use crate::song_info::SongInfo;
+use anyhow::Result;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Folder of a Rekordbox USB stick the tracks are in, by artist and album
+pub const CONTENTS_DIR: &str = "Contents";
+/// Folder of a Rekordbox USB stick Rekordbox keeps the device library and analysis files in
+pub const PIONEER_DIR: &str = "PIONEER";
+/// Folders in PIONEER_DIR Rekordbox writes its database and analysis files into when it exports
+/// to the stick
+const PIONEER_FOLDERS: [&str; 2] = ["rekordbox", "USBANLZ"];
+
+/// Folders Rekordbox puts tracks without an artist or album tag in
+const UNKNOWN_ARTIST: &str = "UnknownArtist";
+const UNKNOWN_ALBUM: &str = "UnknownAlbum";
+
+/// The folder a song goes in on a Rekordbox USB stick: Contents/<Artist>/<Album>
+pub fn song_dir(song: &SongInfo, output_dir: &Path) -> PathBuf {
+    let folder = |tag: &str, unknown: &str| {
+        song.get_tag(tag)
+            .map(folder_name)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| unknown.to_string())
+    };
+    output_dir
+        .join(CONTENTS_DIR)
+        .join(folder("artist", UNKNOWN_ARTIST))
+        .join(folder("album", UNKNOWN_ALBUM))
+}
+
+/// A tag value made into a folder name FAT32, which USB sticks for players are formatted with,
+/// allows, and that can't escape the output directory
+fn folder_name(value: &str) -> String {
+    let name: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    name.trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+/// Creates the PIONEER folder of a Rekordbox USB stick in the output directory, for Rekordbox
+/// to fill in when the tracks are exported to the stick
+pub fn scaffold(output_dir: &Path) -> Result<()> {
+    for folder in PIONEER_FOLDERS {
+        let path = output_dir.join(PIONEER_DIR).join(folder);
+        if !path.is_dir() {
+            read_only::check_writable(&path)?;
+            fs::create_dir_all(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a path relative to the output directory is in the PIONEER folder, which Rekordbox
+/// rewrites whenever it exports to the stick
+pub fn is_in_pioneer_dir(relative: &Path) -> bool {
+    relative.components().next() == Some(Component::Normal(PIONEER_DIR.as_ref()))
+}
+
+/// Where a file in the output directory is on the stick it is copied to the root of, like
+/// /Contents/Artist/Album/song.mp3
+pub fn device_path(path: &Path, output_dir: &Path) -> String {
+    let relative = path.strip_prefix(output_dir).unwrap_or(path);
+    let components: Vec<String> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    format!("/{}", components.join("/"))
+}
+
+/// Writes a device library of the converted tracks into PIONEER/rekordbox/export.pdb, each
+/// track pointing to the ANLZ files written for it under PIONEER/USBANLZ, so players list the
+/// tracks and show their waveforms without Rekordbox having exported to the stick. Tracks
+/// without ANLZ files are listed without waveforms. Exporting to the stick from Rekordbox
+/// replaces the library and its analysis.
+pub fn write_device_library(output_dir: &Path, tracks: &[TrackRecord]) -> Result<PathBuf> {
+    let pdb_tracks: Vec<PdbTrack> = tracks
+        .iter()
+        .map(|track| {
+            let path = device_path(&track.path, output_dir);
+            let (dat_path, _) = anlz::paths(output_dir, &path);
+            let analyze_path = if dat_path.is_file() {
+                anlz::device_analysis_path(&path)
+            } else {
+                String::new()
+            };
+            PdbTrack {
+                id: track.id,
+                title: track.title(),
+                artist: track.artist().to_string(),
+                duration: track.song.get_duration().round() as u32,
+                tempo: anlz::tagged_bpm(&track.song).map_or(0, |bpm| (bpm * 100.0).round() as u32),
+                sample_rate: *track.song.get_sample_rate() as u32,
+                file_size: track.size.min(u32::MAX as u64) as u32,
+                file_type: pdb::file_type(&track.path),
+                path,
+                analyze_path,
+            }
+        })
+        .collect();
+    let pdb_path = output_dir.join(pdb::DEVICE_PDB_PATH);
+    pdb::write(&pdb_path, &pdb_tracks)?;
+    Ok(pdb_path)
+}
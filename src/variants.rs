@@ -0,0 +1,304 @@
+use crate::record_pool::{self, Explicitness, GenericParser, PoolEdit, PoolInfo, PoolParser};
+use crate::song_info::SongInfo;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Which versions to keep when a clean and an explicit version of the same track are found
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExplicitPolicy {
+    /// Convert both versions
+    Both,
+    /// Only convert the clean version
+    PreferClean,
+    /// Only convert the explicit version
+    PreferDirty,
+}
+
+/// Works out which version of a track a song is, from its file name and its title tag
+pub fn variant_info(song: &SongInfo) -> PoolInfo {
+    let mut info = GenericParser
+        .parse(song.get_song_path())
+        .unwrap_or_default();
+    if let Some(tag_info) = song.get_tag("title").and_then(record_pool::parse_title) {
+        info.title = tag_info.title;
+        info.explicitness = tag_info.explicitness.or(info.explicitness);
+        info.edit = tag_info.edit.or(info.edit);
+    }
+    if let Some(artist) = song.get_tag("artist") {
+        info.artist = Some(artist.to_string());
+    }
+    if info.explicitness.is_none() {
+        info.explicitness = song.get_tag("VERSION").and_then(|v| v.parse().ok());
+    }
+    info
+}
+
+/// Key that is equal for all versions of the same track
+fn base_key(info: &PoolInfo) -> (String, String) {
+    (
+        info.artist.clone().unwrap_or_default().to_lowercase(),
+        info.title.clone().unwrap_or_default().to_lowercase(),
+    )
+}
+
+/// Drops the clean or explicit version of tracks that were found in both versions, according to
+/// the policy.
+pub fn select_explicitness(songs: Vec<SongInfo>, policy: ExplicitPolicy) -> Vec<SongInfo> {
+    let unwanted = match policy {
+        ExplicitPolicy::Both => return songs,
+        ExplicitPolicy::PreferClean => Explicitness::Dirty,
+        ExplicitPolicy::PreferDirty => Explicitness::Clean,
+    };
+    let infos: Vec<PoolInfo> = songs.iter().map(variant_info).collect();
+    // Pair up versions of the same track with the same edit, since the clean intro edit is the
+    // counterpart of the dirty intro edit, not of the dirty original.
+    let mut versions: HashMap<_, Vec<&Option<Explicitness>>> = HashMap::new();
+    for info in infos.iter().filter(|i| i.title.is_some()) {
+        let key = (base_key(info), info.edit.clone());
+        versions.entry(key).or_default().push(&info.explicitness);
+    }
+    songs
+        .into_iter()
+        .zip(infos.iter())
+        .filter(|(song, info)| {
+            if info.explicitness.as_ref() != Some(&unwanted) {
+                return true;
+            }
+            let key = (base_key(info), info.edit.clone());
+            let paired = versions
+                .get(&key)
+                .map(|v| {
+                    v.iter()
+                        .any(|e| e.is_some() && e.as_ref() != Some(&unwanted))
+                })
+                .unwrap_or(false);
+            if paired {
+                tracing::info!(
+                    song = ?song.get_song_path(),
+                    ?policy,
+                    "Skipping version, the other version of this track will be converted"
+                );
+            }
+            !paired
+        })
+        .map(|(song, _)| song)
+        .collect()
+}
+
+/// Links the clean and explicit versions of the same track and edit among songs. Returns the
+/// path of each song with the paths of its other versions, which are none for songs found in
+/// one version only.
+pub fn explicitness_links<'a>(
+    songs: impl IntoIterator<Item = &'a SongInfo>,
+) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let songs: Vec<(&PathBuf, PoolInfo)> = songs
+        .into_iter()
+        .map(|song| (song.get_song_path(), variant_info(song)))
+        .collect();
+    let mut versions: HashMap<_, Vec<(&PathBuf, &Explicitness)>> = HashMap::new();
+    for (path, info) in songs.iter().filter(|(_, i)| i.title.is_some()) {
+        if let Some(explicitness) = &info.explicitness {
+            let key = (base_key(info), info.edit.clone());
+            versions.entry(key).or_default().push((path, explicitness));
+        }
+    }
+    songs
+        .iter()
+        .map(|(path, info)| {
+            let key = (base_key(info), info.edit.clone());
+            let others = match (&info.explicitness, versions.get(&key)) {
+                (Some(explicitness), Some(versions)) if info.title.is_some() => versions
+                    .iter()
+                    .filter(|(_, e)| *e != explicitness)
+                    .map(|(other, _)| (*other).clone())
+                    .collect(),
+                _ => vec![],
+            };
+            ((*path).clone(), others)
+        })
+        .collect()
+}
+
+/// Which edit to keep when a pool delivered the same track in several edits
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum EditPolicy {
+    /// Convert every edit
+    All,
+    /// Only convert the original, unedited version
+    Original,
+    /// Only convert the intro edit
+    Intro,
+    /// Only convert the outro edit
+    Outro,
+    /// Only convert the quick hit edit
+    QuickHit,
+}
+
+impl EditPolicy {
+    fn wanted_edit(&self) -> Option<PoolEdit> {
+        match self {
+            EditPolicy::Intro => Some(PoolEdit::Intro),
+            EditPolicy::Outro => Some(PoolEdit::Outro),
+            EditPolicy::QuickHit => Some(PoolEdit::QuickHit),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps only the preferred edit of tracks that were found in several edits. If the preferred
+/// edit doesn't exist for a track, the original is kept instead, or every edit if there is no
+/// original either. Which edit was picked is logged for each track so it's clear what ends up
+/// on the stick.
+pub fn select_edits(songs: Vec<SongInfo>, policy: EditPolicy) -> Vec<SongInfo> {
+    if policy == EditPolicy::All {
+        return songs;
+    }
+    let wanted = policy.wanted_edit();
+    let infos: Vec<PoolInfo> = songs.iter().map(variant_info).collect();
+    // Clean and dirty versions are chosen between separately, so they are kept apart here
+    let mut edits: HashMap<_, Vec<&Option<PoolEdit>>> = HashMap::new();
+    for info in infos.iter().filter(|i| i.title.is_some()) {
+        let key = (base_key(info), info.explicitness.clone());
+        edits.entry(key).or_default().push(&info.edit);
+    }
+    songs
+        .into_iter()
+        .zip(infos.iter())
+        .filter(|(song, info)| {
+            if info.title.is_none() {
+                return true;
+            }
+            let key = (base_key(info), info.explicitness.clone());
+            let available = &edits[&key];
+            let chosen = if available.contains(&&wanted) {
+                Some(&wanted)
+            } else if available.contains(&&None) {
+                Some(&None)
+            } else {
+                None
+            };
+            let keep = chosen.map(|edit| *edit == info.edit).unwrap_or(true);
+            let edit = info
+                .edit
+                .as_ref()
+                .map(|e| e.to_string())
+                .unwrap_or_else(|| "Original".to_string());
+            if keep {
+                tracing::info!(song = ?song.get_song_path(), %edit, "Selected edit");
+            } else {
+                tracing::debug!(song = ?song.get_song_path(), %edit, ?policy, "Skipping edit");
+            }
+            keep
+        })
+        .map(|(song, _)| song)
+        .collect()
+}
+
+/// A version of a track made from the same recording, with parts of it taken out or mixed
+/// differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrangement {
+    Acapella,
+    Instrumental,
+    Dub,
+}
+
+/// Words in a bracketed group of a title that say which arrangement a track is
+fn arrangement_word(word: &str) -> Option<Arrangement> {
+    match word {
+        "acapella" | "acappella" | "accapella" | "capella" | "cappella" => {
+            Some(Arrangement::Acapella)
+        }
+        "instrumental" | "inst" | "karaoke" => Some(Arrangement::Instrumental),
+        "dub" => Some(Arrangement::Dub),
+        _ => None,
+    }
+}
+
+/// Splits a title into the title of the track it is an arrangement of and the arrangement,
+/// e.g. "Song (Acapella)" into "Song" and Acapella. None if no bracketed group names one.
+pub fn arrangement(title: &str) -> Option<(String, Arrangement)> {
+    let mut rest = title;
+    let mut base = String::new();
+    let mut found = None;
+    while let Some(start) = rest.find(['(', '[']) {
+        let close = if rest[start..].starts_with('(') {
+            ')'
+        } else {
+            ']'
+        };
+        let end = match rest[start..].find(close) {
+            Some(end) => start + end,
+            None => break,
+        };
+        let group = rest[start + 1..end].to_lowercase();
+        let named = group
+            .split(|c: char| !c.is_alphanumeric())
+            .find_map(arrangement_word);
+        base.push_str(&rest[..start]);
+        match named {
+            Some(arrangement) if found.is_none() => found = Some(arrangement),
+            _ => base.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    base.push_str(rest);
+    let base = base.split_whitespace().collect::<Vec<_>>().join(" ");
+    found.filter(|_| !base.is_empty()).map(|a| (base, a))
+}
+
+/// A song as grouping arrangements sees it
+struct ArrangementMember {
+    /// Artist and title of the track it is a version of, to group by
+    key: (String, String),
+    arranged: bool,
+    /// Name of the group, as the song writes its artist and title
+    name: String,
+}
+
+/// Groups the acapella, instrumental and dub versions of a track with the track, by artist and
+/// the title without the arrangement. Returns the name of each song's group, like
+/// "Artist - Title", or None for songs that are in no group of two or more.
+pub fn arrangement_groups(songs: &[&SongInfo]) -> Vec<Option<String>> {
+    let members: Vec<Option<ArrangementMember>> = songs
+        .iter()
+        .map(|song| {
+            let mut info = variant_info(song);
+            let title = info.title.clone()?;
+            let (base, arranged) = match arrangement(&title) {
+                Some((base, _)) => (base, true),
+                None => (title, false),
+            };
+            let artist = info.artist.clone().unwrap_or_default();
+            let name = if artist.is_empty() {
+                base.clone()
+            } else {
+                format!("{} - {}", artist, base)
+            };
+            info.title = Some(base);
+            Some(ArrangementMember {
+                key: base_key(&info),
+                arranged,
+                name,
+            })
+        })
+        .collect();
+    // Songs in each group, and whether any is an arrangement
+    let mut groups: HashMap<&(String, String), (usize, bool)> = HashMap::new();
+    for member in members.iter().flatten() {
+        let group = groups.entry(&member.key).or_default();
+        group.0 += 1;
+        group.1 |= member.arranged;
+    }
+    members
+        .iter()
+        .map(|member| {
+            let member = member.as_ref()?;
+            match groups[&member.key] {
+                (n_songs, true) if n_songs > 1 => Some(member.name.clone()),
+                _ => None,
+            }
+        })
+        .collect()
+}
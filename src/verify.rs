@@ -0,0 +1,72 @@
+use crate::song_info;
+use crate::workers;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Results of verifying a set of songs
+#[derive(Clone, Debug, Default)]
+pub struct VerifySummary {
+    pub n_verified: usize,
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+/// Decodes a song from start to finish, failing if ffmpeg reports any error along the way
+pub fn verify_file(path: &Path) -> Result<()> {
+    let output = Command::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(path)
+        .arg("-map")
+        .arg("0:a")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() && stderr.trim().is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{}", stderr.trim()))
+    }
+}
+
+/// How far, in seconds, a converted file's length may be off from its source's. Encoders pad
+/// lossy formats by a few milliseconds.
+const DURATION_TOLERANCE: f64 = 1.0;
+
+/// Checks that a converted file decodes cleanly and, if the length of its source is given, that
+/// it is as long, catching outputs that were truncated or corrupted while being written
+pub fn verify_output(path: &Path, expected_duration: Option<f64>) -> Result<()> {
+    verify_file(path).with_context(|| format!("{} does not decode cleanly", path.display()))?;
+    if let Some(expected) = expected_duration {
+        let duration = song_info::from_file(path)?.get_duration();
+        if (duration - expected).abs() > DURATION_TOLERANCE {
+            return Err(anyhow!(
+                "{} is {:.1}s long, but its source is {:.1}s",
+                path.display(),
+                duration,
+                expected
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Verifies every song in a list, decoding `n_jobs` songs at once
+pub fn verify_files(paths: &[PathBuf], n_jobs: usize) -> VerifySummary {
+    let results = workers::map_parallel(paths.to_vec(), n_jobs, |path| {
+        let result = verify_file(&path);
+        (path, result)
+    });
+    let mut summary = VerifySummary::default();
+    for (path, result) in results {
+        summary.n_verified += 1;
+        if let Err(e) = result {
+            tracing::error!(?path, %e, "Song does not decode cleanly!");
+            summary.failures.push((path, e.to_string()));
+        }
+    }
+    summary
+}
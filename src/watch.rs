@@ -0,0 +1,106 @@
+use crate::converter::{walk_files, ConversionSummary, Converter};
+use anyhow::{anyhow, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Converts everything in the input directory, then keeps watching it and converts songs as
+/// they are added or changed, calling `on_batch` after every run. A file is only converted once
+/// it has gone `settle` without changing, so songs still being copied in aren't converted
+/// half-written. Runs until the directory can't be watched anymore.
+pub fn watch<F>(
+    converter: &Converter,
+    input_dir: &Path,
+    output_dir: &Path,
+    settle: Duration,
+    mut on_batch: F,
+) -> Result<()>
+where
+    F: FnMut(&ConversionSummary),
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // Watching starts before the first run, so songs dropped in during it aren't missed
+    watcher.watch(input_dir, RecursiveMode::Recursive)?;
+    on_batch(&converter.run(input_dir, output_dir)?);
+    // The output directory may be inside the input directory, and what is written there
+    // mustn't be picked up as new songs
+    let output_dir = output_dir.canonicalize()?;
+    tracing::info!(?input_dir, "Watching for new songs");
+    // Files that changed, and when they last did
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        match receiver.recv_timeout(settle) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+            }
+            Ok(Err(e)) => tracing::warn!(?e, "Error while watching"),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("Stopped watching {}", input_dir.display()))
+            }
+        }
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= settle)
+            .map(|(path, _)| path.clone())
+            .collect();
+        if settled.is_empty() {
+            continue;
+        }
+        for path in settled.iter() {
+            pending.remove(path);
+        }
+        // Moving or copying a folder in may only report the folder, so the songs in it are
+        // found by walking it
+        let mut files = Vec::with_capacity(settled.len());
+        for path in settled {
+            if path.is_dir() {
+                let walked = walk_files(&path, &mut |file| {
+                    files.push(file);
+                    true
+                });
+                if let Err(e) = walked {
+                    tracing::warn!(?path, ?e, "Could not look through new folder");
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        files.sort();
+        files.dedup();
+        // Songs in a folder may still be being written, in which case they wait until they
+        // have settled too
+        files.retain(|path| {
+            let changed = path
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if pending.contains_key(path) || changed.is_some_and(|changed| changed < settle) {
+                pending.entry(path.clone()).or_insert_with(Instant::now);
+                return false;
+            }
+            path.is_file()
+                && converter.selects(path)
+                && !path
+                    .canonicalize()
+                    .map(|p| p.starts_with(&output_dir))
+                    .unwrap_or(true)
+        });
+        if files.is_empty() {
+            continue;
+        }
+        tracing::info!(n_files = files.len(), "Converting new songs");
+        match converter.run_files(&files, &output_dir) {
+            Ok(summary) => on_batch(&summary),
+            Err(e) => tracing::error!(?e),
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::read_only;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Size of rendered waveforms, wide and short so they fit in a catalog's track list
+const WAVEFORM_SIZE: &str = "1200x120";
+
+/// Renders a compact waveform PNG of a song with ffmpeg's showwavespic filter
+pub fn render(song_path: &Path, png_path: &Path) -> Result<()> {
+    read_only::check_writable(png_path)?;
+    if let Some(dir) = png_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let output = Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-i")
+        .arg(song_path)
+        .arg("-filter_complex")
+        .arg(format!(
+            "[0:a:0]aformat=channel_layouts=mono,showwavespic=s={}:colors=#3a7bd5",
+            WAVEFORM_SIZE
+        ))
+        .arg("-frames:v")
+        .arg("1")
+        .arg(png_path)
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Could not render waveform of {:?}: {}",
+            song_path,
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
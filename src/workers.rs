@@ -0,0 +1,132 @@
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Number of workers to use when none is given: one per CPU
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Runs `f` on every item using at most `n_workers` threads, returning the results in the
+/// order of the items
+pub fn map_parallel<T, R, F>(items: Vec<T>, n_workers: usize, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let n_items = items.len();
+    let queue = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let results = Arc::new(Mutex::new((0..n_items).map(|_| None).collect::<Vec<_>>()));
+    let f = Arc::new(f);
+    let handles: Vec<JoinHandle<()>> = (0..n_workers.clamp(1, n_items.max(1)))
+        .map(|_| {
+            let queue = queue.clone();
+            let results = results.clone();
+            let f = f.clone();
+            thread::spawn(move || loop {
+                // The lock is released before the item is worked on
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, item)) => {
+                        let result = f(item);
+                        results.lock().unwrap()[i] = Some(result);
+                    }
+                    None => break,
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let results = Arc::try_unwrap(results)
+        .ok()
+        .expect("Should not have more than one reference to the results")
+        .into_inner()
+        .unwrap();
+    results
+        .into_iter()
+        .map(|r| r.expect("Every item has a result"))
+        .collect()
+}
+
+/// How long a waiting worker goes without looking at the load again
+const LOAD_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lets fewer workers run while the machine is busy with other things, and up to the maximum
+/// when it is idle, based on the load average. Only Linux reports a load average this way;
+/// elsewhere every worker is allowed to run.
+pub struct AdaptiveLimit {
+    max: usize,
+    n_cpus: usize,
+    busy: Mutex<usize>,
+    changed: Condvar,
+}
+
+/// Releases a worker's slot when dropped
+pub struct AdaptiveSlot<'a> {
+    limit: &'a AdaptiveLimit,
+}
+
+impl Drop for AdaptiveSlot<'_> {
+    fn drop(&mut self) {
+        *self.limit.busy.lock().unwrap() -= 1;
+        self.limit.changed.notify_all();
+    }
+}
+
+impl AdaptiveLimit {
+    pub fn new(max: usize) -> Self {
+        AdaptiveLimit {
+            max: max.max(1),
+            n_cpus: default_jobs(),
+            busy: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Number of workers allowed to run, given how many are running now. Load from our own
+    /// workers is taken out, so only what else runs on the machine holds workers back.
+    fn allowed(&self, busy: usize) -> usize {
+        match load_average() {
+            Some(load) => {
+                let other_load = (load - busy as f64).max(0.0);
+                let free_cpus = (self.n_cpus as f64 - other_load).floor().max(1.0) as usize;
+                free_cpus.min(self.max)
+            }
+            None => self.max,
+        }
+    }
+
+    /// Waits until the machine has room for another worker
+    pub fn acquire(&self) -> AdaptiveSlot<'_> {
+        let mut busy = self.busy.lock().unwrap();
+        loop {
+            let allowed = self.allowed(*busy);
+            if *busy < allowed {
+                *busy += 1;
+                tracing::debug!(busy = *busy, allowed, "Starting worker");
+                return AdaptiveSlot { limit: self };
+            }
+            busy = self
+                .changed
+                .wait_timeout(busy, LOAD_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// One minute load average
+fn load_average() -> Option<f64> {
+    fs::read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
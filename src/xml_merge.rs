@@ -0,0 +1,302 @@
+use crate::exporters::{self, TrackRecord};
+use crate::file_uri;
+use crate::i18n;
+use crate::rekordbox_xml::{self, xml_escape};
+use crate::song_info::ProbeBackend;
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Highest TrackID Rekordbox reads, as a positive 32 bit integer
+const MAX_TRACK_ID: u32 = i32::MAX as u32;
+
+/// What merging the converted tracks into an existing library changed
+#[derive(Clone, Debug, Default)]
+pub struct MergeDiff {
+    /// Names of tracks that weren't in the library yet
+    pub added: Vec<String>,
+    /// Names of tracks whose entries changed, with the attributes that did
+    pub updated: Vec<(String, Vec<String>)>,
+    /// Number of converted tracks whose entries were already up to date
+    pub unchanged: usize,
+    /// Number of entries in the library that aren't converted tracks, which are left alone
+    pub kept: usize,
+}
+
+impl std::fmt::Display for MergeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for name in &self.added {
+            writeln!(
+                f,
+                "{}",
+                i18n::message("merge-added", &[("name", name.as_str().into())])
+            )?;
+        }
+        for (name, attributes) in &self.updated {
+            let args = [
+                ("name", name.as_str().into()),
+                ("attributes", attributes.join(", ").into()),
+            ];
+            writeln!(f, "{}", i18n::message("merge-updated", &args))?;
+        }
+        let args = [
+            ("added", self.added.len().into()),
+            ("updated", self.updated.len().into()),
+            ("unchanged", self.unchanged.into()),
+            ("kept", self.kept.into()),
+        ];
+        write!(f, "{}", i18n::message("merge-summary", &args))
+    }
+}
+
+/// An element of a Rekordbox library, which is made of nothing but elements and their
+/// attributes
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn new(name: &str, attributes: Vec<(String, String)>) -> Self {
+        Element {
+            name: name.to_string(),
+            attributes,
+            children: vec![],
+        }
+    }
+
+    fn from_node(node: roxmltree::Node) -> Self {
+        Element {
+            name: node.tag_name().name().to_string(),
+            attributes: node
+                .attributes()
+                .map(|a| (a.name().to_string(), a.value().to_string()))
+                .collect(),
+            children: node
+                .children()
+                .filter(|c| c.is_element())
+                .map(Element::from_node)
+                .collect(),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets an attribute, returning whether its value changed
+    fn set_attribute(&mut self, name: &str, value: &str) -> bool {
+        match self.attributes.iter_mut().find(|(n, _)| n == name) {
+            Some((_, old)) if old == value => false,
+            Some((_, old)) => {
+                *old = value.to_string();
+                true
+            }
+            None => {
+                self.attributes.push((name.to_string(), value.to_string()));
+                true
+            }
+        }
+    }
+
+    /// The first child with this name, added if there is none
+    fn child(&mut self, name: &str, attributes: &[(&str, &str)]) -> &mut Element {
+        let position = match self.children.iter().position(|c| {
+            c.name == name && attributes.iter().all(|(n, v)| c.attribute(n) == Some(v))
+        }) {
+            Some(position) => position,
+            None => {
+                let attributes = attributes
+                    .iter()
+                    .map(|(n, v)| (n.to_string(), v.to_string()))
+                    .collect();
+                self.children.push(Element::new(name, attributes));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[position]
+    }
+
+    fn write(&self, depth: usize, xml: &mut String) {
+        let indent = "  ".repeat(depth);
+        let _ = write!(xml, "{}<{}", indent, self.name);
+        for (name, value) in &self.attributes {
+            let _ = write!(xml, " {}=\"{}\"", name, xml_escape(value));
+        }
+        if self.children.is_empty() {
+            xml.push_str("/>\n");
+            return;
+        }
+        xml.push_str(">\n");
+        for child in &self.children {
+            child.write(depth + 1, xml);
+        }
+        let _ = writeln!(xml, "{}</{}>", indent, self.name);
+    }
+}
+
+fn position_mark_elements(track: &TrackRecord) -> Vec<Element> {
+    rekordbox_xml::position_marks(track)
+        .into_iter()
+        .map(|mark| {
+            let attributes = mark.into_iter().map(|(n, v)| (n.to_string(), v)).collect();
+            Element::new("POSITION_MARK", attributes)
+        })
+        .collect()
+}
+
+/// The exported TrackID of a track, or the next one after it the library doesn't use yet
+fn free_track_id(id: u32, taken: &BTreeSet<u32>) -> u32 {
+    let mut id = id.clamp(1, MAX_TRACK_ID);
+    while taken.contains(&id) {
+        id = if id == MAX_TRACK_ID { 1 } else { id + 1 };
+    }
+    id
+}
+
+/// Merges the tracks converted into an output directory into an existing Rekordbox library,
+/// writing the result to `xml_path`, which may be the library itself. Tracks already in it,
+/// found by Location, have their attributes updated while keeping everything else, such as
+/// cue points added in Rekordbox; new tracks are added under a TrackID the library doesn't use
+/// yet. Other entries and playlists are left as they are, apart from the playlist of the output
+/// directory, which is made to hold exactly the converted tracks, and the one of tracks converted
+/// in the last `new_playlist_days` days if given, which is made to hold exactly those, so older
+/// tracks drop out of it.
+pub fn merge(
+    output_dir: &Path,
+    existing: &Path,
+    xml_path: &Path,
+    probe_backend: ProbeBackend,
+    new_playlist_days: Option<u64>,
+) -> Result<MergeDiff> {
+    let text = fs::read_to_string(existing)
+        .with_context(|| format!("Could not read {}", existing.display()))?;
+    let document = roxmltree::Document::parse(&text)
+        .with_context(|| format!("{} isn't valid XML", existing.display()))?;
+    let mut library = Element::from_node(document.root_element());
+    if library.name != "DJ_PLAYLISTS" {
+        return Err(anyhow!("{} isn't a Rekordbox library", existing.display()));
+    }
+    let tracks = exporters::tracks(output_dir, probe_backend)?;
+    let mut diff = MergeDiff::default();
+
+    let collection = library.child("COLLECTION", &[]);
+    let n_existing = collection.children.len();
+    let mut matched = vec![false; n_existing];
+    // TrackIDs in use in the library, which added tracks mustn't take
+    let mut taken_ids: BTreeSet<u32> = collection
+        .children
+        .iter()
+        .filter(|e| e.name == "TRACK")
+        .filter_map(|e| e.attribute("TrackID")?.parse().ok())
+        .collect();
+    // TrackIDs of the converted tracks in the library, which may differ from the exported ones
+    let mut library_ids: HashMap<u32, String> = HashMap::new();
+    for track in &tracks {
+        let attributes = rekordbox_xml::track_attributes(track);
+        let value = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let name = value("Name").unwrap_or_default().to_string();
+        // Rekordbox may escape Locations differently, so they are compared as paths too
+        let location = value("Location");
+        let path = location.and_then(|l| file_uri::from_uri(l).ok());
+        let is_at_location = |e: &Element| {
+            let other = e.attribute("Location");
+            e.name == "TRACK"
+                && other.is_some()
+                && (other == location
+                    || (path.is_some() && other.and_then(|l| file_uri::from_uri(l).ok()) == path))
+        };
+        // Only the Location says an entry is this track: our TrackIDs come from hashing paths,
+        // so an unrelated track of the library can have the same one
+        let existing =
+            (0..n_existing).find(|&i| !matched[i] && is_at_location(&collection.children[i]));
+        match existing {
+            Some(i) => {
+                matched[i] = true;
+                let entry = &mut collection.children[i];
+                let mut changed: Vec<String> = attributes
+                    .iter()
+                    .filter(|(n, _)| *n != "TrackID")
+                    .filter(|(n, v)| entry.set_attribute(n, v))
+                    .map(|(n, _)| n.to_string())
+                    .collect();
+                // Cues set in Rekordbox are kept, so Serato's are only added to tracks without any
+                if !entry.children.iter().any(|c| c.name == "POSITION_MARK") {
+                    let marks = position_mark_elements(track);
+                    if !marks.is_empty() {
+                        entry.children.extend(marks);
+                        changed.push("POSITION_MARK".to_string());
+                    }
+                }
+                let library_id = entry.attribute("TrackID").unwrap_or_default().to_string();
+                library_ids.insert(track.id, library_id);
+                if changed.is_empty() {
+                    diff.unchanged += 1;
+                } else {
+                    diff.updated.push((name, changed));
+                }
+            }
+            None => {
+                let id = free_track_id(track.id, &taken_ids);
+                taken_ids.insert(id);
+                let attributes = attributes
+                    .iter()
+                    .map(|(n, v)| match *n {
+                        "TrackID" => (n.to_string(), id.to_string()),
+                        _ => (n.to_string(), v.clone()),
+                    })
+                    .collect();
+                let mut entry = Element::new("TRACK", attributes);
+                entry.children = position_mark_elements(track);
+                collection.children.push(entry);
+                library_ids.insert(track.id, id.to_string());
+                diff.added.push(name);
+            }
+        }
+    }
+    diff.kept = collection.children[..n_existing]
+        .iter()
+        .zip(&matched)
+        .filter(|(e, matched)| e.name == "TRACK" && !**matched)
+        .count();
+    let n_tracks = collection
+        .children
+        .iter()
+        .filter(|e| e.name == "TRACK")
+        .count();
+    collection.set_attribute("Entries", &n_tracks.to_string());
+
+    let root = library
+        .child("PLAYLISTS", &[])
+        .child("NODE", &[("Type", "0"), ("Name", "ROOT")]);
+    for exported in exporters::playlists(output_dir, &tracks, new_playlist_days)? {
+        let playlist = root.child("NODE", &[("Name", &exported.name), ("Type", "1")]);
+        playlist.set_attribute("KeyType", "0");
+        playlist.set_attribute("Entries", &exported.track_ids.len().to_string());
+        playlist.children = exported
+            .track_ids
+            .iter()
+            .filter_map(|id| library_ids.get(id))
+            .map(|id| Element::new("TRACK", vec![("Key".to_string(), id.clone())]))
+            .collect();
+    }
+    let n_children = root.children.len();
+    root.set_attribute("Count", &n_children.to_string());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    library.write(0, &mut xml);
+    rekordbox_xml::write_validated(xml_path, &xml)?;
+    Ok(diff)
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeSet;
+
+/// How badly a problem breaks importing a library into Rekordbox
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// Rekordbox refuses the file, or imports the track wrong
+    Error,
+    /// The track imports, but with something missing, such as its length
+    Warning,
+}
+
+/// Something in a Rekordbox library that Rekordbox is known to trip over
+#[derive(Clone, Debug)]
+pub struct XmlProblem {
+    pub severity: Severity,
+    /// The element the problem is in, such as "TRACK 1234"
+    pub element: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for XmlProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} in {}: {}", severity, self.element, self.message)
+    }
+}
+
+/// Checks a Rekordbox library for what makes the import dialog fail or import tracks wrong:
+/// missing attributes, counts that don't match, dates and numbers in the wrong format, and
+/// Locations that aren't properly escaped file URLs
+pub fn validate(xml: &str) -> Vec<XmlProblem> {
+    let mut problems = Problems::default();
+    let document = match roxmltree::Document::parse(xml) {
+        Ok(document) => document,
+        Err(e) => {
+            problems.error("document", e.to_string());
+            return problems.0;
+        }
+    };
+    let library = document.root_element();
+    if !library.has_tag_name("DJ_PLAYLISTS") {
+        problems.error("document", "the root element isn't DJ_PLAYLISTS");
+        return problems.0;
+    }
+    if library.attribute("Version").is_none() {
+        problems.error("DJ_PLAYLISTS", "Version is missing");
+    }
+    let child = |name: &str| library.children().find(|c| c.has_tag_name(name));
+    if child("PRODUCT").is_none() {
+        problems.warning("DJ_PLAYLISTS", "PRODUCT is missing");
+    }
+    let mut track_ids = BTreeSet::new();
+    match child("COLLECTION") {
+        Some(collection) => {
+            let tracks: Vec<_> = collection
+                .children()
+                .filter(|c| c.has_tag_name("TRACK"))
+                .collect();
+            check_count(
+                &mut problems,
+                "COLLECTION",
+                collection,
+                "Entries",
+                tracks.len(),
+            );
+            for track in tracks {
+                check_track(&mut problems, track, &mut track_ids);
+            }
+        }
+        None => problems.error("DJ_PLAYLISTS", "COLLECTION is missing"),
+    }
+    match child("PLAYLISTS").and_then(|p| p.children().find(|c| c.has_tag_name("NODE"))) {
+        Some(root) => {
+            if root.attribute("Type") != Some("0") || root.attribute("Name") != Some("ROOT") {
+                problems.error("PLAYLISTS", "the first NODE isn't the ROOT folder");
+            }
+            check_node(&mut problems, root, &track_ids);
+        }
+        None => problems.error("DJ_PLAYLISTS", "PLAYLISTS has no ROOT node"),
+    }
+    problems.0
+}
+
+#[derive(Default)]
+struct Problems(Vec<XmlProblem>);
+
+impl Problems {
+    fn error(&mut self, element: &str, message: impl Into<String>) {
+        self.push(Severity::Error, element, message.into());
+    }
+
+    fn warning(&mut self, element: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, element, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, element: &str, message: String) {
+        self.0.push(XmlProblem {
+            severity,
+            element: element.to_string(),
+            message,
+        });
+    }
+}
+
+fn check_track(problems: &mut Problems, track: roxmltree::Node, track_ids: &mut BTreeSet<u32>) {
+    let id = track.attribute("TrackID").unwrap_or_default();
+    let element = format!("TRACK {}", id);
+    match id.parse::<u32>() {
+        Ok(0) | Err(_) => problems.error(&element, "TrackID isn't a positive integer"),
+        Ok(id) if !track_ids.insert(id) => problems.error(&element, "TrackID is used twice"),
+        Ok(_) => {}
+    }
+    if track.attribute("Name").is_none() {
+        problems.error(&element, "Name is missing");
+    }
+    match track.attribute("Location") {
+        Some(location) => check_location(problems, &element, location),
+        None => problems.error(&element, "Location is missing"),
+    }
+    // Rekordbox reads these as integers, and drops the track if one has a fraction or a unit
+    for name in ["Size", "TotalTime", "SampleRate", "BitRate", "TrackNumber"] {
+        if let Some(value) = track.attribute(name) {
+            if value.parse::<u64>().is_err() {
+                problems.error(
+                    &element,
+                    format!("{} isn't a whole number: {:?}", name, value),
+                );
+            }
+        }
+    }
+    if track.attribute("TotalTime") == Some("0") {
+        problems.warning(&element, "TotalTime is 0, the track will show no length");
+    }
+    if let Some(year) = track.attribute("Year") {
+        let valid = year.is_empty() || (year.len() == 4 && year.parse::<u16>().is_ok());
+        if !valid && year != "0" {
+            problems.error(&element, format!("Year isn't a 4 digit year: {:?}", year));
+        }
+    }
+    if let Some(date) = track.attribute("DateAdded") {
+        if !is_date(date) {
+            problems.error(
+                &element,
+                format!("DateAdded isn't like 2024-01-31: {:?}", date),
+            );
+        }
+    }
+}
+
+/// Checks that a Location is a file URL, on localhost, with everything that needs escaping
+/// percent-encoded. Rekordbox can't find tracks whose Location has a raw space, #, ? or
+/// non-ASCII character, or a % that doesn't start an escape.
+fn check_location(problems: &mut Problems, element: &str, location: &str) {
+    if !location.starts_with("file://") {
+        problems.error(element, format!("Location isn't a file URL: {}", location));
+    } else if !location.starts_with("file://localhost/") {
+        problems.warning(
+            element,
+            format!(
+                "Location is on a network share, which Rekordbox may not reach: {}",
+                location
+            ),
+        );
+    }
+    let bytes = location.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let escape = bytes.get(i + 1..i + 3);
+                if !escape.is_some_and(|e| e.iter().all(u8::is_ascii_hexdigit)) {
+                    problems.error(element, format!("Location has a stray %: {}", location));
+                    return;
+                }
+                i += 3;
+                continue;
+            }
+            b if b.is_ascii_alphanumeric() || b"-._~/:!$&'()*+,;=@".contains(&b) => {}
+            _ => {
+                problems.error(
+                    element,
+                    format!("Location has unescaped characters: {}", location),
+                );
+                return;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Checks a playlist folder or playlist, and the ones in it
+fn check_node(problems: &mut Problems, node: roxmltree::Node, track_ids: &BTreeSet<u32>) {
+    let element = format!("NODE {:?}", node.attribute("Name").unwrap_or_default());
+    if node.attribute("Name").is_none() {
+        problems.error(&element, "Name is missing");
+    }
+    match node.attribute("Type") {
+        // A folder
+        Some("0") => {
+            let children: Vec<_> = node.children().filter(|c| c.has_tag_name("NODE")).collect();
+            check_count(problems, &element, node, "Count", children.len());
+            for child in children {
+                check_node(problems, child, track_ids);
+            }
+        }
+        Some("1") => {
+            let keys: Vec<_> = node
+                .children()
+                .filter(|c| c.has_tag_name("TRACK"))
+                .filter_map(|c| c.attribute("Key"))
+                .collect();
+            check_count(problems, &element, node, "Entries", keys.len());
+            // Keys are TrackIDs, unless KeyType says they are Locations
+            if node.attribute("KeyType") == Some("0") {
+                for key in keys {
+                    if !key.parse().is_ok_and(|id| track_ids.contains(&id)) {
+                        problems.error(&element, format!("no TRACK has the TrackID {}", key));
+                    }
+                }
+            }
+        }
+        _ => problems.error(&element, "Type is neither 0 (folder) nor 1 (playlist)"),
+    }
+}
+
+/// Checks that a count attribute, which Rekordbox relies on instead of counting, is right
+fn check_count(
+    problems: &mut Problems,
+    element: &str,
+    node: roxmltree::Node,
+    attribute: &str,
+    count: usize,
+) {
+    match node.attribute(attribute) {
+        Some(value) if value == count.to_string() => {}
+        Some(value) => problems.error(
+            element,
+            format!("{} is {}, but there are {}", attribute, value, count),
+        ),
+        None => problems.error(element, format!("{} is missing", attribute)),
+    }
+}
+
+/// Whether a date is in the yyyy-mm-dd form Rekordbox expects
+fn is_date(date: &str) -> bool {
+    let parts: Vec<&str> = date.split('-').collect();
+    match parts.as_slice() {
+        [year, month, day] => {
+            year.len() == 4
+                && month.len() == 2
+                && day.len() == 2
+                && year.parse::<u16>().is_ok()
+                && month.parse::<u8>().is_ok_and(|m| (1..=12).contains(&m))
+                && day.parse::<u8>().is_ok_and(|d| (1..=31).contains(&d))
+        }
+        _ => false,
+    }
+}